@@ -20,7 +20,7 @@ fn main() {
     let ref interface_name = os::args()[1];
 
     // Find the network interface with the provided name
-    let interfaces = get_network_interfaces();
+    let interfaces = get_network_interfaces().unwrap();
     let interface = interfaces.iter()
                               .filter(|iface| iface.name == *interface_name)
                               .next()
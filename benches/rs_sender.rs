@@ -72,7 +72,7 @@ fn main() {
     let ref interface_name = os::args()[1];
     let destination = from_str(os::args()[2].as_slice()).unwrap();
     // Find the network interface with the provided name
-    let interfaces = get_network_interfaces();
+    let interfaces = get_network_interfaces().unwrap();
     let interface = interfaces.iter()
                               .filter(|iface| iface.name == *interface_name)
                               .next()
@@ -88,7 +88,7 @@ fn main() {
     let mut mut_ethernet_header = MutableEthernetHeader::new(buffer);
     {
         mut_ethernet_header.set_destination(destination);
-        mut_ethernet_header.set_source(interface.mac_address());
+        mut_ethernet_header.set_source(interface.mac_address().unwrap());
         mut_ethernet_header.set_ethertype(EtherTypes::Ipv4);
         build_udp4_packet(mut_ethernet_header.payload_mut(), "rmesg");
     }
@@ -109,7 +109,10 @@ fn main() {
     let ref interface_name = os::args()[1];
 
     // Find the network interface with the provided name
-    let interfaces = get_network_interfaces();
+    let interfaces = match get_network_interfaces() {
+        Ok(interfaces) => interfaces,
+        Err(e) => fail!("packetdump: unable to list network interfaces: {}", e)
+    };
     let interface = interfaces.iter()
                               .filter(|iface| iface.name == *interface_name)
                               .next()
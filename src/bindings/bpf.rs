@@ -19,11 +19,21 @@ const IFNAMSIZ: uint = IF_NAMESIZE;
 const IOC_IN: libc::c_ulong = 0x80000000;
 const IOC_OUT: libc::c_ulong = 0x40000000;
 const IOC_INOUT: libc::c_ulong = IOC_IN | IOC_OUT;
+const IOC_VOID: libc::c_ulong = 0x20000000;
 const IOCPARM_SHIFT: libc::c_ulong = 13;
 const IOCPARM_MASK: libc::c_ulong = (1 << (IOCPARM_SHIFT as uint)) - 1;
 
 const SIZEOF_IFREQ: libc::c_ulong = 32;
 const SIZEOF_C_UINT: libc::c_ulong = 4;
+#[cfg(target_word_size = "64")]
+const SIZEOF_TIMEVAL: libc::c_ulong = 16;
+#[cfg(target_word_size = "32")]
+const SIZEOF_TIMEVAL: libc::c_ulong = 8;
+#[cfg(target_word_size = "64")]
+const SIZEOF_BPF_PROGRAM: libc::c_ulong = 16;
+#[cfg(target_word_size = "32")]
+const SIZEOF_BPF_PROGRAM: libc::c_ulong = 8;
+const SIZEOF_BPF_STAT: libc::c_ulong = 8;
 #[cfg(target_os = "freebsd")]
 const SIZEOF_C_LONG: libc::c_int = 8;
 
@@ -48,11 +58,27 @@ pub const BIOCSBLEN: libc::c_ulong = IOC_INOUT |
                                       ((SIZEOF_C_UINT & IOCPARM_MASK) << 16) |
                                       ('B' as libc::c_ulong << 8) |
                                       102;
+pub const BIOCPROMISC: libc::c_ulong = IOC_VOID |
+                                        ('B' as libc::c_ulong << 8) |
+                                        105;
+pub const BIOCSETF: libc::c_ulong = IOC_IN |
+                                     ((SIZEOF_BPF_PROGRAM & IOCPARM_MASK) << 16) |
+                                     ('B' as libc::c_ulong << 8) |
+                                     103;
+pub const BIOCSRTIMEOUT: libc::c_ulong = IOC_IN |
+                                          ((SIZEOF_TIMEVAL & IOCPARM_MASK) << 16) |
+                                          ('B' as libc::c_ulong << 8) |
+                                          109;
 pub const BIOCSHDRCMPLT: libc::c_ulong = IOC_IN |
                                           ((SIZEOF_C_UINT & IOCPARM_MASK) << 16) |
                                           ('B' as libc::c_ulong << 8) |
                                           117;
 
+pub const BIOCGSTATS: libc::c_ulong = IOC_OUT |
+                                      ((SIZEOF_BPF_STAT & IOCPARM_MASK) << 16) |
+                                      ('B' as libc::c_ulong << 8) |
+                                      111;
+
 #[cfg(target_os = "freebsd")]
 pub const BIOCFEEDBACK: libc::c_ulong = IOC_IN |
                                           ((SIZEOF_C_UINT & IOCPARM_MASK) << 16) |
@@ -62,6 +88,106 @@ pub const BIOCFEEDBACK: libc::c_ulong = IOC_IN |
 
 pub const DLT_NULL: libc::c_uint = 0;
 
+pub const SOL_SOCKET: libc::c_int = 0xffff;
+pub const SO_RCVTIMEO: libc::c_int = 0x1006;
+
+// See /usr/include/net/route.h
+pub const PF_ROUTE: libc::c_int = 17;
+pub const RTM_NEWADDR: u8 = 0xc;
+pub const RTM_DELADDR: u8 = 0xd;
+pub const RTM_IFINFO: u8 = 0xe;
+#[cfg(target_os = "freebsd")]
+pub const RTM_IFANNOUNCE: u8 = 0x11;
+pub const RTM_VERSION: u8 = 5;
+pub const RTM_GET: u8 = 0x4;
+pub const RTF_UP: libc::c_int = 0x1;
+pub const RTF_GATEWAY: libc::c_int = 0x2;
+pub const RTA_DST: libc::c_int = 0x1;
+pub const RTA_GATEWAY: libc::c_int = 0x2;
+pub const RTA_NETMASK: libc::c_int = 0x4;
+pub const RTA_IFP: libc::c_int = 0x10;
+pub const RTAX_IFP: uint = 4;
+pub const RTAX_MAX: uint = 8;
+
+#[cfg(target_os = "freebsd")]
+const RT_ALIGNMENT: uint = SIZEOF_C_LONG as uint;
+#[cfg(any(target_os = "macos", windows))]
+const RT_ALIGNMENT: uint = 4;
+
+// The space taken by a socket address of length `len` in a routing message
+pub fn RT_ROUNDUP(len: uint) -> uint {
+    if len == 0 {
+        RT_ALIGNMENT
+    } else {
+        1 + ((len - 1) | (RT_ALIGNMENT - 1))
+    }
+}
+
+// See /usr/include/net/route.h
+#[cfg(target_os = "freebsd")]
+pub struct rt_metrics {
+    pub rmx_locks: libc::c_ulong,
+    pub rmx_mtu: libc::c_ulong,
+    pub rmx_hopcount: libc::c_ulong,
+    pub rmx_expire: libc::c_ulong,
+    pub rmx_recvpipe: libc::c_ulong,
+    pub rmx_sendpipe: libc::c_ulong,
+    pub rmx_ssthresh: libc::c_ulong,
+    pub rmx_rtt: libc::c_ulong,
+    pub rmx_rttvar: libc::c_ulong,
+    pub rmx_pksent: libc::c_ulong,
+    pub rmx_weight: libc::c_ulong,
+    pub rmx_filler: [libc::c_ulong, ..3],
+}
+
+#[cfg(target_os = "macos")]
+pub struct rt_metrics {
+    pub rmx_locks: u32,
+    pub rmx_mtu: u32,
+    pub rmx_hopcount: u32,
+    pub rmx_expire: i32,
+    pub rmx_recvpipe: u32,
+    pub rmx_sendpipe: u32,
+    pub rmx_ssthresh: u32,
+    pub rmx_rtt: u32,
+    pub rmx_rttvar: u32,
+    pub rmx_pksent: u32,
+    pub rmx_state: u32,
+    pub rmx_filler: [u32, ..3],
+}
+
+#[cfg(target_os = "freebsd")]
+pub struct rt_msghdr {
+    pub rtm_msglen: libc::c_ushort,
+    pub rtm_version: libc::c_uchar,
+    pub rtm_type: libc::c_uchar,
+    pub rtm_index: libc::c_ushort,
+    pub rtm_flags: libc::c_int,
+    pub rtm_addrs: libc::c_int,
+    pub rtm_pid: libc::pid_t,
+    pub rtm_seq: libc::c_int,
+    pub rtm_errno: libc::c_int,
+    pub rtm_fmask: libc::c_int,
+    pub rtm_inits: libc::c_ulong,
+    pub rtm_rmx: rt_metrics,
+}
+
+#[cfg(target_os = "macos")]
+pub struct rt_msghdr {
+    pub rtm_msglen: libc::c_ushort,
+    pub rtm_version: libc::c_uchar,
+    pub rtm_type: libc::c_uchar,
+    pub rtm_index: libc::c_ushort,
+    pub rtm_flags: libc::c_int,
+    pub rtm_addrs: libc::c_int,
+    pub rtm_pid: libc::pid_t,
+    pub rtm_seq: libc::c_int,
+    pub rtm_errno: libc::c_int,
+    pub rtm_use: libc::c_int,
+    pub rtm_inits: u32,
+    pub rtm_rmx: rt_metrics,
+}
+
 #[cfg(target_os = "freebsd")]
 const BPF_ALIGNMENT: libc::c_int = SIZEOF_C_LONG;
 #[cfg(any(target_os = "macos", windows))]
@@ -81,6 +207,36 @@ pub struct ifreq {
     pub ifru_addr: libc::sockaddr, // NOTE Should be a union
 }
 
+// See /usr/include/net/if.h
+// NOTE Only the fields up to and including ifi_mtu are included
+#[cfg(target_os = "freebsd")]
+pub struct if_data {
+    pub ifi_type: libc::c_uchar,
+    pub ifi_physical: libc::c_uchar,
+    pub ifi_addrlen: libc::c_uchar,
+    pub ifi_hdrlen: libc::c_uchar,
+    pub ifi_link_state: libc::c_uchar,
+    pub ifi_spare_char1: libc::c_uchar,
+    pub ifi_spare_char2: libc::c_uchar,
+    pub ifi_datalen: libc::c_uchar,
+    pub ifi_mtu: libc::c_ulong,
+}
+
+// See /usr/include/net/if_var.h
+// NOTE Only the fields up to and including ifi_mtu are included
+#[cfg(target_os = "macos")]
+pub struct if_data {
+    pub ifi_type: libc::c_uchar,
+    pub ifi_typelen: libc::c_uchar,
+    pub ifi_physical: libc::c_uchar,
+    pub ifi_addrlen: libc::c_uchar,
+    pub ifi_hdrlen: libc::c_uchar,
+    pub ifi_recvquota: libc::c_uchar,
+    pub ifi_xmitquota: libc::c_uchar,
+    pub ifi_unused1: libc::c_uchar,
+    pub ifi_mtu: u32,
+}
+
 // See /usr/include/net/if_dl.h
 #[cfg(any(target_os = "freebsd", target_os = "macos"))]
 pub struct sockaddr_dl {
@@ -116,6 +272,24 @@ pub struct bpf_hdr {
     pub bh_hdrlen: libc::c_ushort,
 }
 
+// See man 4 bpf or /usr/include/net/bpf.h
+pub struct bpf_insn {
+    pub code: libc::c_ushort,
+    pub jt: libc::c_uchar,
+    pub jf: libc::c_uchar,
+    pub k: u32,
+}
+
+pub struct bpf_program {
+    pub bf_len: libc::c_uint,
+    pub bf_insns: *mut bpf_insn,
+}
+
+pub struct bpf_stat {
+    pub bs_recv: libc::c_uint,
+    pub bs_drop: libc::c_uint,
+}
+
 #[cfg(not(windows))]
 extern {
     pub fn ioctl(d: libc::c_int, request: libc::c_ulong, ...) -> libc::c_int;
@@ -4,7 +4,8 @@ extern crate libc;
 
 pub use libc::{c_uint, c_int, c_char, c_void, sockaddr, sockaddr_storage, socklen_t,
                setsockopt, IPPROTO_IP, socket, AF_INET, AF_INET6, c_uchar, c_ushort, bind,
-               SOCK_DGRAM, sa_family_t};
+               SOCK_DGRAM, sa_family_t, size_t, getsockopt, timeval, sockaddr_in,
+               sockaddr_in6, ENOSYS, getpid};
 
 #[cfg(target_os = "linux")]
 #[repr(C)]
@@ -60,15 +61,49 @@ pub const SOCK_RAW: c_int = 3;
 #[cfg(windows)]
 pub const IP_HDRINCL: c_int = 2;
 #[cfg(windows)]
+pub const IP_TTL: c_int = 4;
+#[cfg(windows)]
+pub const IPPROTO_IPV6: c_int = 41;
+#[cfg(windows)]
+pub const IPV6_UNICAST_HOPS: c_int = 4;
+#[cfg(windows)]
+pub const IPV6_CHECKSUM: c_int = 26;
+#[cfg(windows)]
 pub const IFF_LOOPBACK: c_int = 4;
+#[cfg(windows)]
+pub const IFF_UP: c_int = 1;
+#[cfg(windows)]
+pub const IFF_BROADCAST: c_int = 2;
+#[cfg(windows)]
+pub const IFF_POINTOPOINT: c_int = 8;
+#[cfg(windows)]
+pub const IFF_MULTICAST: c_int = 0x10;
 
 #[cfg(target_os = "linux")]
 pub const SOCK_RAW: c_int = 3;
 #[cfg(target_os = "linux")]
 pub const IP_HDRINCL: c_int = 3;
 #[cfg(target_os = "linux")]
+pub const IP_TTL: c_int = 2;
+#[cfg(target_os = "linux")]
+pub const IPPROTO_IPV6: c_int = 41;
+#[cfg(target_os = "linux")]
+pub const IPV6_UNICAST_HOPS: c_int = 16;
+#[cfg(target_os = "linux")]
+pub const IPV6_CHECKSUM: c_int = 7;
+#[cfg(target_os = "linux")]
 pub const IFF_LOOPBACK: c_int = 0x8;
 #[cfg(target_os = "linux")]
+pub const IFF_UP: c_int = 0x1;
+#[cfg(target_os = "linux")]
+pub const IFF_RUNNING: c_int = 0x40;
+#[cfg(target_os = "linux")]
+pub const IFF_BROADCAST: c_int = 0x2;
+#[cfg(target_os = "linux")]
+pub const IFF_POINTOPOINT: c_int = 0x10;
+#[cfg(target_os = "linux")]
+pub const IFF_MULTICAST: c_int = 0x1000;
+#[cfg(target_os = "linux")]
 pub const AF_PACKET : c_int = 17;
 
 #[cfg(target_os = "freebsd")]
@@ -76,14 +111,50 @@ pub const SOCK_RAW: c_int = 3;
 #[cfg(target_os = "freebsd")]
 pub const IP_HDRINCL: c_int = 2;
 #[cfg(target_os = "freebsd")]
+pub const IP_TTL: c_int = 4;
+#[cfg(target_os = "freebsd")]
+pub const IPPROTO_IPV6: c_int = 41;
+#[cfg(target_os = "freebsd")]
+pub const IPV6_UNICAST_HOPS: c_int = 4;
+#[cfg(target_os = "freebsd")]
+pub const IPV6_CHECKSUM: c_int = 26;
+#[cfg(target_os = "freebsd")]
 pub const IFF_LOOPBACK: c_int = 0x8;
+#[cfg(target_os = "freebsd")]
+pub const IFF_UP: c_int = 0x1;
+#[cfg(target_os = "freebsd")]
+pub const IFF_RUNNING: c_int = 0x40;
+#[cfg(target_os = "freebsd")]
+pub const IFF_BROADCAST: c_int = 0x2;
+#[cfg(target_os = "freebsd")]
+pub const IFF_POINTOPOINT: c_int = 0x10;
+#[cfg(target_os = "freebsd")]
+pub const IFF_MULTICAST: c_int = 0x8000;
 
 #[cfg(target_os = "macos")]
 pub const SOCK_RAW: c_int = 3;
 #[cfg(target_os = "macos")]
 pub const IP_HDRINCL: c_int = 2;
 #[cfg(target_os = "macos")]
+pub const IP_TTL: c_int = 4;
+#[cfg(target_os = "macos")]
+pub const IPPROTO_IPV6: c_int = 41;
+#[cfg(target_os = "macos")]
+pub const IPV6_UNICAST_HOPS: c_int = 4;
+#[cfg(target_os = "macos")]
+pub const IPV6_CHECKSUM: c_int = 26;
+#[cfg(target_os = "macos")]
 pub const IFF_LOOPBACK: c_int = 0x8;
+#[cfg(target_os = "macos")]
+pub const IFF_UP: c_int = 0x1;
+#[cfg(target_os = "macos")]
+pub const IFF_RUNNING: c_int = 0x40;
+#[cfg(target_os = "macos")]
+pub const IFF_BROADCAST: c_int = 0x2;
+#[cfg(target_os = "macos")]
+pub const IFF_POINTOPOINT: c_int = 0x10;
+#[cfg(target_os = "macos")]
+pub const IFF_MULTICAST: c_int = 0x8000;
 
 #[cfg(not(windows))]
 extern "system" {
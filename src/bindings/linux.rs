@@ -11,9 +11,27 @@
 
 extern crate libc;
 
+pub const SOL_SOCKET: libc::c_int = 1;
+pub const SO_SNDBUF: libc::c_int = 7;
+pub const SO_RCVBUF: libc::c_int = 8;
+pub const SO_RCVTIMEO: libc::c_int = 20;
+pub const SO_BINDTODEVICE: libc::c_int = 25;
+pub const SO_TIMESTAMP: libc::c_int = 29;
+pub const SCM_TIMESTAMP: libc::c_int = SO_TIMESTAMP;
+pub const SO_ATTACH_FILTER: libc::c_int = 26;
 pub const SOL_PACKET: libc::c_int = 263;
 pub const PACKET_ADD_MEMBERSHIP: libc::c_int = 1;
 pub const PACKET_MR_PROMISC: libc::c_int = 1;
+pub const PACKET_STATISTICS: libc::c_int = 6;
+pub const SIOCGIFMTU: libc::c_ulong = 0x8921;
+
+pub const AF_NETLINK: libc::c_int = 16;
+pub const NETLINK_ROUTE: libc::c_int = 0;
+pub const RTMGRP_LINK: u32 = 0x1;
+pub const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+pub const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+
+const IFNAMSIZ: uint = 16;
 
 // man 7 packet
 pub struct packet_mreq {
@@ -23,3 +41,72 @@ pub struct packet_mreq {
     pub mr_address: [libc::c_uchar, ..8]
 }
 
+pub struct tpacket_stats {
+    pub tp_packets: libc::c_uint,
+    pub tp_drops: libc::c_uint,
+}
+
+// man 7 netlink
+pub struct sockaddr_nl {
+    pub nl_family: libc::sa_family_t,
+    pub nl_pad: libc::c_ushort,
+    pub nl_pid: u32,
+    pub nl_groups: u32,
+}
+
+// man 7 netdevice
+pub struct ifreq {
+    pub ifr_name: [libc::c_char, ..IFNAMSIZ],
+    pub ifr_mtu: libc::c_int, // NOTE Should be a union
+    pub ifr_padding: [u8, ..20],
+}
+
+// man 7 socket, see also linux/filter.h
+pub struct sock_filter {
+    pub code: libc::c_ushort,
+    pub jt: libc::c_uchar,
+    pub jf: libc::c_uchar,
+    pub k: u32,
+}
+
+pub struct sock_fprog {
+    pub len: libc::c_ushort,
+    pub filter: *const sock_filter,
+}
+
+// man 2 recvmsg
+pub struct iovec {
+    pub iov_base: *mut libc::c_void,
+    pub iov_len: libc::size_t,
+}
+
+pub struct msghdr {
+    pub msg_name: *mut libc::c_void,
+    pub msg_namelen: libc::socklen_t,
+    pub msg_iov: *mut iovec,
+    pub msg_iovlen: libc::size_t,
+    pub msg_control: *mut libc::c_void,
+    pub msg_controllen: libc::size_t,
+    pub msg_flags: libc::c_int,
+}
+
+// man 3 cmsg
+pub struct cmsghdr {
+    pub cmsg_len: libc::size_t,
+    pub cmsg_level: libc::c_int,
+    pub cmsg_type: libc::c_int,
+}
+
+// man 2 sendmmsg
+pub struct mmsghdr {
+    pub msg_hdr: msghdr,
+    pub msg_len: libc::c_uint,
+}
+
+extern {
+    pub fn recvmsg(sockfd: libc::c_int, msg: *mut msghdr, flags: libc::c_int) -> libc::ssize_t;
+    pub fn sendmmsg(sockfd: libc::c_int, msgvec: *mut mmsghdr, vlen: libc::c_uint,
+                    flags: libc::c_int) -> libc::c_int;
+    pub fn ioctl(d: libc::c_int, request: libc::c_ulong, ...) -> libc::c_int;
+}
+
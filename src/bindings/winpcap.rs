@@ -49,8 +49,18 @@ const MAX_ADAPTER_DESCRIPTION_LENGTH: uint = 128;
 const MAX_ADAPTER_NAME_LENGTH: uint = 256;
 const MAX_ADAPTER_ADDRESS_LENGTH: uint = 8;
 
+// from WinSock2.h
+pub const AF_UNSPEC: ULONG = 0;
+
+// from IPTypes.h
+pub const GAA_FLAG_SKIP_UNICAST: ULONG = 0x0001;
+pub const GAA_FLAG_SKIP_ANYCAST: ULONG = 0x0002;
+pub const GAA_FLAG_SKIP_MULTICAST: ULONG = 0x0004;
+pub const GAA_FLAG_SKIP_DNS_SERVER: ULONG = 0x0008;
+
 // from ntddndis.h
 pub const NDIS_PACKET_TYPE_PROMISCUOUS: ULONG = 0x00000020;
+pub const NDIS_PACKET_TYPE_ALL_LOCAL: ULONG = 0x00000080;
 
 // from IPTypes.h
 #[repr(C)]
@@ -364,5 +374,9 @@ extern {
     pub fn PacketSetMinToCopy(AdapterObject: LPADAPTER, nbytes: libc::c_int) -> win::BOOLEAN;
     pub fn PacketSetBuff(AdapterObject: LPADAPTER, dim: libc::c_int) -> win::BOOLEAN;
     pub fn PacketSetReadTimeout(AdapterObject: LPADAPTER, timeout: libc::c_int) -> win::BOOLEAN;
+    pub fn PacketSetBpf(AdapterObject: LPADAPTER, fp: *mut super::bpf::bpf_program)
+        -> win::BOOLEAN;
+    pub fn PacketGetStats(AdapterObject: LPADAPTER, s: *mut super::bpf::bpf_stat)
+        -> win::BOOLEAN;
 }
 
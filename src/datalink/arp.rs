@@ -0,0 +1,220 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Resolving the MAC address of an on-link IPv4 host using ARP
+
+extern crate time;
+
+use std::cmp;
+use std::default::Default;
+use std::io::{IoResult, IoError, InvalidInput, TimedOut};
+use std::io::net::ip::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use datalink::{datalink_channel_with_options, DataLinkOptions};
+use packet::{Packet, MutablePacket};
+use packet::arp::{ArpHeader, ArpPacket, MutableArpHeader, ArpHardwareTypes, ArpOperations};
+use packet::ethernet::{EthernetHeader, EthernetPacket, MutableEthernetHeader, EtherTypes};
+use util::{MacAddr, NetworkInterface};
+
+// Ethernet header, plus an ARP packet for IPv4 over Ethernet
+const ARP_REQUEST_LEN: uint = 14 + 28;
+
+// How often to check whether the timeout has expired while no packets are being received
+const POLL_INTERVAL_MS: i64 = 100;
+
+/// Send an ARP request for `target` out of `iface`, and wait for the matching reply. Returns
+/// the sender hardware address of the reply.
+///
+/// The request is sent from the interface's MAC address, and from an IPv4 address of the
+/// interface on the same network as `target` if there is one. Other ARP traffic is ignored.
+///
+/// If no reply is received within `timeout`, an error with kind `TimedOut` is returned. An error
+/// with kind `InvalidInput` is returned if `target` is not an IPv4 address, or the interface has
+/// no MAC address or IPv4 address. As with any data link channel, permission to open raw
+/// sockets is required.
+pub fn arp_resolve(iface: &NetworkInterface, target: IpAddr, timeout: Duration)
+    -> IoResult<MacAddr> {
+    match target {
+        Ipv4Addr(..) => (),
+        _ => return Err(invalid_input("ARP can only resolve IPv4 addresses"))
+    }
+    let mac = match iface.mac {
+        Some(mac) => mac,
+        None => return Err(invalid_input("the interface has no MAC address"))
+    };
+    let source = match source_address(iface, target) {
+        Some(source) => source,
+        None => return Err(invalid_input("the interface has no IPv4 address"))
+    };
+
+    let options = DataLinkOptions {
+        read_timeout: Some(cmp::min(timeout, Duration::milliseconds(POLL_INTERVAL_MS))),
+        ethertype_filter: Some(EtherTypes::Arp),
+        ..Default::default()
+    };
+    let (mut tx, mut rx) = try!(datalink_channel_with_options(iface, &options));
+
+    let mut buffer = [0u8, ..ARP_REQUEST_LEN];
+    {
+        let mut ethernet = MutableEthernetHeader::new(buffer.as_mut_slice());
+        ethernet.set_destination(MacAddr::broadcast());
+        ethernet.set_source(mac);
+        ethernet.set_ethertype(EtherTypes::Arp);
+        let mut request = MutableArpHeader::new(ethernet.payload_mut());
+        request.set_hardware_type(ArpHardwareTypes::Ethernet);
+        request.set_protocol_type(EtherTypes::Ipv4);
+        request.set_hw_addr_len(6);
+        request.set_proto_addr_len(4);
+        request.set_operation(ArpOperations::Request);
+        request.set_sender_hw_addr(mac);
+        request.set_sender_proto_addr(source);
+        request.set_target_hw_addr(MacAddr(0, 0, 0, 0, 0, 0));
+        request.set_target_proto_addr(target);
+    }
+    let start = time::precise_time_ns();
+    match tx.send_to(EthernetHeader::new(buffer.as_slice()), None) {
+        Some(res) => try!(res),
+        None => return Err(invalid_input("the ARP request is larger than the write buffer"))
+    }
+
+    let mut iter = rx.iter();
+    loop {
+        let elapsed = Duration::nanoseconds((time::precise_time_ns() - start) as i64);
+        if elapsed >= timeout {
+            return Err(arp_timed_out());
+        }
+        let received = match iter.next() {
+            Ok(packet) => reply_sender(&packet, source, target),
+            Err(ref e) if e.kind == TimedOut => None,
+            Err(e) => return Err(e)
+        };
+        match received {
+            Some(mac) => return Ok(mac),
+            None => ()
+        }
+    }
+}
+
+fn arp_timed_out() -> IoError {
+    IoError {
+        kind: TimedOut,
+        desc: "timed out waiting for an ARP reply",
+        detail: None,
+    }
+}
+
+fn invalid_input(desc: &'static str) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: desc,
+        detail: None,
+    }
+}
+
+// Pick the IPv4 address of the interface to send the request from, preferring one on the same
+// network as the target
+fn source_address(iface: &NetworkInterface, target: IpAddr) -> Option<IpAddr> {
+    match iface.on_link_address(target) {
+        Some(ip) => Some(ip),
+        None => iface.ipv4_addresses().into_iter().next()
+    }
+}
+
+// If the packet is a reply to an ARP request for `target` sent from `source`, get the sender
+// hardware address
+fn reply_sender(packet: &EthernetHeader, source: IpAddr, target: IpAddr) -> Option<MacAddr> {
+    if packet.get_ethertype() != EtherTypes::Arp {
+        return None;
+    }
+    match ArpHeader::new_checked(packet.payload()) {
+        Some(ref reply) if reply.get_hw_addr_len() == 6 &&
+                           reply.get_proto_addr_len() == 4 &&
+                           reply.get_operation() == ArpOperations::Reply &&
+                           reply.get_sender_proto_addr() == target &&
+                           reply.get_target_proto_addr() == source => {
+            Some(reply.get_sender_hw_addr())
+        },
+        _ => None
+    }
+}
+
+#[cfg(test)]
+fn build_arp(buffer: &mut [u8], operation: ::packet::arp::ArpOperation, sender: IpAddr,
+             target: IpAddr) {
+    let mut ethernet = MutableEthernetHeader::new(buffer);
+    ethernet.set_ethertype(EtherTypes::Arp);
+    let mut arp = MutableArpHeader::new(ethernet.payload_mut());
+    arp.set_hardware_type(ArpHardwareTypes::Ethernet);
+    arp.set_protocol_type(EtherTypes::Ipv4);
+    arp.set_hw_addr_len(6);
+    arp.set_proto_addr_len(4);
+    arp.set_operation(operation);
+    arp.set_sender_hw_addr(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55));
+    arp.set_sender_proto_addr(sender);
+    arp.set_target_proto_addr(target);
+}
+
+#[test]
+fn arp_reply_sender() {
+    let source = Ipv4Addr(192, 168, 0, 2);
+    let target = Ipv4Addr(192, 168, 0, 1);
+    let mut buffer = [0u8, ..ARP_REQUEST_LEN];
+
+    build_arp(buffer.as_mut_slice(), ArpOperations::Reply, target, source);
+    assert_eq!(reply_sender(&EthernetHeader::new(buffer.as_slice()), source, target),
+               Some(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55)));
+
+    // Requests, and replies about or to other hosts, are ignored
+    build_arp(buffer.as_mut_slice(), ArpOperations::Request, target, source);
+    assert!(reply_sender(&EthernetHeader::new(buffer.as_slice()), source, target).is_none());
+    build_arp(buffer.as_mut_slice(), ArpOperations::Reply, Ipv4Addr(192, 168, 0, 3), source);
+    assert!(reply_sender(&EthernetHeader::new(buffer.as_slice()), source, target).is_none());
+    build_arp(buffer.as_mut_slice(), ArpOperations::Reply, target, Ipv4Addr(192, 168, 0, 3));
+    assert!(reply_sender(&EthernetHeader::new(buffer.as_slice()), source, target).is_none());
+
+    // As are truncated packets
+    build_arp(buffer.as_mut_slice(), ArpOperations::Reply, target, source);
+    assert!(reply_sender(&EthernetHeader::new(buffer.slice_to(40)), source, target).is_none());
+
+    // And replies whose addresses aren't the lengths of a MAC and an IPv4 address
+    {
+        let mut ethernet = MutableEthernetHeader::new(buffer.as_mut_slice());
+        MutableArpHeader::new(ethernet.payload_mut()).set_hw_addr_len(8);
+    }
+    assert!(reply_sender(&EthernetHeader::new(buffer.as_slice()), source, target).is_none());
+    build_arp(buffer.as_mut_slice(), ArpOperations::Reply, target, source);
+    {
+        let mut ethernet = MutableEthernetHeader::new(buffer.as_mut_slice());
+        MutableArpHeader::new(ethernet.payload_mut()).set_proto_addr_len(16);
+    }
+    assert!(reply_sender(&EthernetHeader::new(buffer.as_slice()), source, target).is_none());
+}
+
+#[test]
+fn arp_source_address() {
+    let mut iface = NetworkInterface {
+        name: "eth0".to_string(),
+        index: 2,
+        mac: None,
+        ips: Some(vec![Ipv4Addr(10, 0, 0, 2), Ipv4Addr(192, 168, 0, 2)]),
+        ip_prefixes: Some(vec![(Ipv4Addr(10, 0, 0, 2), 8), (Ipv4Addr(192, 168, 0, 2), 24)]),
+        broadcast_ips: None,
+        mtu: None,
+        flags: 0,
+    };
+    assert_eq!(source_address(&iface, Ipv4Addr(192, 168, 0, 1)), Some(Ipv4Addr(192, 168, 0, 2)));
+    assert_eq!(source_address(&iface, Ipv4Addr(10, 1, 2, 3)), Some(Ipv4Addr(10, 0, 0, 2)));
+
+    // Off-link targets fall back to the first IPv4 address
+    assert_eq!(source_address(&iface, Ipv4Addr(172, 16, 0, 1)), Some(Ipv4Addr(10, 0, 0, 2)));
+
+    iface.ips = None;
+    iface.ip_prefixes = None;
+    assert_eq!(source_address(&iface, Ipv4Addr(192, 168, 0, 1)), None);
+}
@@ -14,19 +14,19 @@ use std::io::{IoResult, IoError};
 use std::mem;
 use std::option::{Option, Some};
 use std::sync::Arc;
+use std::time::Duration;
 
 use bindings::bpf;
 use packet::Packet;
 use packet::ethernet::{EthernetHeader, MutableEthernetHeader};
-use datalink::{DataLinkChannelType, Layer2, Layer3};
+use datalink;
+use datalink::{BpfInstruction, CaptureStats, DataLinkOptions, Layer2, Layer3};
 use internal;
 use util::NetworkInterface;
 
 // NOTE buffer must be word aligned.
 pub fn datalink_channel(network_interface: &NetworkInterface,
-                        write_buffer_size: uint,
-                        read_buffer_size: uint,
-                        channel_type: DataLinkChannelType)
+                        options: &DataLinkOptions)
     -> IoResult<(DataLinkSenderImpl, DataLinkReceiverImpl)> {
     #[cfg(target_os = "freebsd")]
     fn get_fd() -> libc::c_int {
@@ -51,22 +51,17 @@ pub fn datalink_channel(network_interface: &NetworkInterface,
     }
 
     #[cfg(target_os = "freebsd")]
-    fn set_feedback(fd: libc::c_int) -> Result<(), IoError> {
+    fn set_feedback(fd: &internal::FileDesc) -> IoResult<()> {
         let one: libc::c_uint = 1;
-        if unsafe { bpf::ioctl(fd, bpf::BIOCFEEDBACK, &one) } == -1 {
-            let err = IoError::last_error();
-            unsafe { libc::close(fd); }
-            return Err(err);
-        }
-        Ok(())
+        ioctl_result(unsafe { bpf::ioctl(fd.fd, bpf::BIOCFEEDBACK, &one) })
     }
 
     #[cfg(target_os = "macos")]
-    fn set_feedback(_fd: libc::c_int) -> Result<(), IoError> {
+    fn set_feedback(_fd: &internal::FileDesc) -> IoResult<()> {
         Ok(())
     }
 
-    match channel_type {
+    match options.channel_type {
         Layer2 => (),
         Layer3(_) => unimplemented!(),
     }
@@ -75,6 +70,8 @@ pub fn datalink_channel(network_interface: &NetworkInterface,
     if fd == -1 {
         return Err(IoError::last_error());
     }
+    // Closed when dropped, including on any of the error paths below
+    let fd = internal::FileDesc { fd: fd };
     let mut iface: bpf::ifreq = unsafe { mem::zeroed() };
     let mut i = 0;
     for c in network_interface.name.as_slice().bytes() {
@@ -82,75 +79,86 @@ pub fn datalink_channel(network_interface: &NetworkInterface,
         i += 1;
     }
 
-    let buflen = read_buffer_size as libc::c_uint;
+    let mut buflen = options.read_buffer_size as libc::c_uint;
     // NOTE Buffer length must be set before binding to an interface
     //      otherwise this will return Invalid Argument
-    if unsafe { bpf::ioctl(fd, bpf::BIOCSBLEN, &buflen) } == -1 {
-        let err = IoError::last_error();
-        unsafe { libc::close(fd); }
-        return Err(err);
-    }
+    try!(ioctl_result(unsafe { bpf::ioctl(fd.fd, bpf::BIOCSBLEN, &mut buflen) }));
 
     // Set the interface to use
-    if unsafe { bpf::ioctl(fd, bpf::BIOCSETIF, &iface) } == -1 {
-        let err = IoError::last_error();
-        unsafe { libc::close(fd); }
-        return Err(err);
+    try!(ioctl_result(unsafe { bpf::ioctl(fd.fd, bpf::BIOCSETIF, &iface) }));
+
+    // The kernel may have adjusted the buffer length, and reads must use exactly that length
+    try!(ioctl_result(unsafe { bpf::ioctl(fd.fd, bpf::BIOCGBLEN, &mut buflen) }));
+
+    // Enable promiscuous capture
+    if options.promiscuous {
+        try!(ioctl_result(unsafe { bpf::ioctl(fd.fd, bpf::BIOCPROMISC) }));
+    }
+
+    // Set the read timeout
+    match options.read_timeout {
+        Some(timeout) => {
+            let tv = internal::duration_to_timeval(timeout);
+            try!(ioctl_result(unsafe { bpf::ioctl(fd.fd, bpf::BIOCSRTIMEOUT, &tv) }));
+        },
+        None => ()
     }
 
     // Return from read as soon as packets are available - don't wait to fill the buffer
     let one: libc::c_uint = 1;
-    if unsafe { bpf::ioctl(fd, bpf::BIOCIMMEDIATE, &one) } == -1 {
-        let err = IoError::last_error();
-        unsafe { libc::close(fd); }
-        return Err(err);
-    }
+    try!(ioctl_result(unsafe { bpf::ioctl(fd.fd, bpf::BIOCIMMEDIATE, &one) }));
 
     let mut header_size = 0;
 
     // Get the device type
     let mut dlt: libc::c_uint = 0;
-    if unsafe { bpf::ioctl(fd, bpf::BIOCGDLT, &mut dlt) } == -1 {
-        let err = IoError::last_error();
-        unsafe { libc::close(fd); }
-        return Err(err);
-    }
+    try!(ioctl_result(unsafe { bpf::ioctl(fd.fd, bpf::BIOCGDLT, &mut dlt) }));
 
-    // The loopback IoError::last_error()device does weird things
+    // The loopback device does weird things
     // FIXME This should really just be another L2 packet header type
     if dlt == bpf::DLT_NULL {
         header_size = 4;
 
         // Allow packets to be read back after they are written
-        match set_feedback(fd) {
-            Err(e) => return Err(e),
-            _ => ()
-        }
-
+        try!(set_feedback(&fd));
     } else {
         // Don't fill in source MAC
-        if unsafe { bpf::ioctl(fd, bpf::BIOCSHDRCMPLT, &one) } == -1 {
-            let err = IoError::last_error();
-            unsafe { libc::close(fd); }
-            return Err(err);
-        }
+        try!(ioctl_result(unsafe { bpf::ioctl(fd.fd, bpf::BIOCSHDRCMPLT, &one) }));
     }
 
-    let fd = Arc::new(internal::FileDesc { fd: fd });
+    let fd = Arc::new(fd);
     let sender = DataLinkSenderImpl {
         fd: fd.clone(),
-        write_buffer: Vec::from_elem(write_buffer_size, 0u8),
+        write_buffer: Vec::from_elem(options.write_buffer_size, 0u8),
         header_size: header_size,
     };
-    let receiver = DataLinkReceiverImpl {
+    let mut receiver = DataLinkReceiverImpl {
         fd: fd,
-        read_buffer: Vec::from_elem(read_buffer_size, 0u8),
+        read_buffer: Vec::from_elem(buflen as uint, 0u8),
         header_size: header_size,
     };
 
+    // BPF has no way of filtering by EtherType when binding, so attach a filter instead
+    match options.ethertype_filter {
+        Some(ethertype) => {
+            let program = datalink::ethertype_program(ethertype, header_size + 12);
+            try!(receiver.set_filter(program.as_slice()));
+        },
+        None => ()
+    }
+
     Ok((sender, receiver))
 }
 
+// Turn the return value of an ioctl into an IoResult
+fn ioctl_result(res: libc::c_int) -> IoResult<()> {
+    if res == -1 {
+        Err(IoError::last_error())
+    } else {
+        Ok(())
+    }
+}
+
 pub struct DataLinkSenderImpl {
     fd: Arc<internal::FileDesc>,
     write_buffer: Vec<u8>,
@@ -161,7 +169,7 @@ impl DataLinkSenderImpl {
     pub fn build_and_send(&mut self, num_packets: uint, packet_size: uint,
                           func: |MutableEthernetHeader| -> ()) -> Option<IoResult<()>> {
         let len = num_packets * (packet_size + self.header_size);
-        if len >= self.write_buffer.len() {
+        if len > self.write_buffer.len() {
             None
         } else {
             let min = cmp::min(self.write_buffer.len(), len);
@@ -191,6 +199,14 @@ impl DataLinkSenderImpl {
 
     pub fn send_to(&mut self, packet: EthernetHeader, _dst: Option<NetworkInterface>)
         -> Option<IoResult<()>> {
+        // The loopback device needs the address family written before the packet, which
+        // build_and_send takes care of
+        if self.header_size != 0 {
+            use packet::MutablePacket;
+            return self.build_and_send(1, packet.packet().len(), |mut eh| {
+                eh.clone_from(packet);
+            });
+        }
         match unsafe { libc::write(self.fd.fd,
                                    packet.packet().as_ptr() as *const libc::c_void,
                                    packet.packet().len() as libc::size_t) } {
@@ -198,6 +214,10 @@ impl DataLinkSenderImpl {
             _ => Some(Ok(()))
         }
     }
+
+    pub fn send_batch(&mut self, packets: &[EthernetHeader]) -> IoResult<uint> {
+        datalink::send_each(packets, |packet| self.send_to(packet, None))
+    }
 }
 
 pub struct DataLinkReceiverImpl {
@@ -207,6 +227,28 @@ pub struct DataLinkReceiverImpl {
 }
 
 impl DataLinkReceiverImpl {
+    pub fn set_filter(&mut self, filter: &[BpfInstruction]) -> IoResult<()> {
+        let prog = bpf::bpf_program {
+            bf_len: filter.len() as libc::c_uint,
+            bf_insns: filter.as_ptr() as *mut bpf::bpf_insn,
+        };
+        if unsafe { bpf::ioctl(self.fd.fd, bpf::BIOCSETF, &prog) } == -1 {
+            return Err(IoError::last_error());
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> IoResult<CaptureStats> {
+        let mut stats: bpf::bpf_stat = unsafe { mem::zeroed() };
+        if unsafe { bpf::ioctl(self.fd.fd, bpf::BIOCGSTATS, &mut stats) } == -1 {
+            return Err(IoError::last_error());
+        }
+        Ok(CaptureStats {
+            received: stats.bs_recv as u64,
+            dropped: stats.bs_drop as u64,
+        })
+    }
+
     pub fn iter<'a>(&'a mut self) -> DataLinkChannelIteratorImpl<'a> {
         let buflen = self.read_buffer.len();
         DataLinkChannelIteratorImpl {
@@ -219,37 +261,37 @@ impl DataLinkReceiverImpl {
 
 pub struct DataLinkChannelIteratorImpl<'a> {
     pc: &'a mut DataLinkReceiverImpl,
-    packets: RingBuf<(uint, uint)>,
+    packets: RingBuf<(uint, uint, Duration)>,
 }
 
 impl<'a> DataLinkChannelIteratorImpl<'a> {
     pub fn next<'c>(&'c mut self) -> IoResult<EthernetHeader<'c>> {
-        if self.packets.is_empty() {
+        match self.next_with_timestamp() {
+            Ok((_, eh)) => Ok(eh),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub fn next_with_timestamp<'c>(&'c mut self) -> IoResult<(Duration, EthernetHeader<'c>)> {
+        // A single read can return several packets; they're handed out one at a time, and the
+        // device is only read again once they've all been returned
+        while self.packets.is_empty() {
             let buflen = match unsafe {
                 libc::read(self.pc.fd.fd,
                            self.pc.read_buffer.as_ptr() as *mut libc::c_void,
                            self.pc.read_buffer.len() as libc::size_t)
             } {
-                len if len > 0 => len,
+                len if len > 0 => len as uint,
+                // Nothing is read if the read timeout expires
+                0 => return Err(datalink::timed_out()),
                 _ => return Err(IoError::last_error())
             };
-            let mut ptr = self.pc.read_buffer.as_mut_ptr();
-            let end = unsafe { self.pc.read_buffer.as_ptr().offset(buflen as int) };
-            while (ptr as *const u8) < end {
-                unsafe {
-                    let packet: *const bpf::bpf_hdr = mem::transmute(ptr);
-                    let start = ptr as int +
-                                (*packet).bh_hdrlen as int -
-                                self.pc.read_buffer.as_ptr() as int;
-                    self.packets.push((start as uint + self.pc.header_size,
-                                      (*packet).bh_caplen as uint - self.pc.header_size));
-                    let offset = (*packet).bh_hdrlen as int + (*packet).bh_caplen as int;
-                    ptr = ptr.offset(bpf::BPF_WORDALIGN(offset));
-                }
-            }
+            datalink::split_packets(self.pc.read_buffer.slice_to(buflen),
+                                    self.pc.header_size,
+                                    &mut self.packets);
         }
-        let (start, len) = self.packets.pop_front().unwrap();
-        Ok(EthernetHeader::new(self.pc.read_buffer.slice(start, start + len)))
+        let (start, len, timestamp) = self.packets.pop_front().unwrap();
+        Ok((timestamp, EthernetHeader::new(self.pc.read_buffer.slice(start, start + len))))
     }
 }
 
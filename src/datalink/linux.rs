@@ -6,15 +6,21 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::cell::Cell;
 use std::cmp;
-use std::io::{IoResult, IoError};
+use std::io::{IoResult, IoError, OtherIoError, ResourceUnavailable};
 use std::mem;
+use std::os;
+use std::ptr;
 use std::option::{Option, Some};
 use std::sync::Arc;
+use std::time::Duration;
 
 use bindings::libc;
 use bindings::linux;
-use datalink::{DataLinkChannelType, Layer2, Layer3};
+use datalink;
+use datalink::{BpfInstruction, CaptureStats, DataLinkChannelType, DataLinkOptions, Layer2,
+               Layer3};
 use internal;
 use packet::Packet;
 use packet::ethernet::{EtherType, EthernetHeader, MutableEthernetHeader};
@@ -37,6 +43,18 @@ fn network_addr_to_sockaddr(ni: &NetworkInterface,
     }
 }
 
+// Make sure the kernel's buffer for the socket is at least `size` bytes. The buffer is never
+// shrunk, since the small sizes used for userspace buffers would hurt throughput.
+fn grow_socket_buffer(socket: &internal::FileDesc, option: libc::c_int, size: uint)
+    -> IoResult<()> {
+    let current: libc::c_int = try!(socket.getsockopt(linux::SOL_SOCKET, option));
+    if size as libc::c_int <= current {
+        return Ok(());
+    }
+    let size = size as libc::c_int;
+    socket.setsockopt(linux::SOL_SOCKET, option, &size)
+}
+
 pub struct DataLinkSenderImpl {
     socket: Arc<internal::FileDesc>,
     write_buffer: Vec<u8>,
@@ -50,7 +68,7 @@ impl DataLinkSenderImpl {
     pub fn build_and_send(&mut self, num_packets: uint, packet_size: uint,
                           func: |MutableEthernetHeader| -> ()) -> Option<IoResult<()>> {
         let len = num_packets * packet_size;
-        if len < self.write_buffer.as_slice().len() {
+        if len <= self.write_buffer.as_slice().len() {
             let min = cmp::min(self.write_buffer.as_slice().len(), len);
             let ref mut mut_slice = self.write_buffer;
             for chunk in mut_slice.as_mut_slice().slice_to_mut(min)
@@ -83,15 +101,77 @@ impl DataLinkSenderImpl {
             Ok(_) => Some(Ok(()))
         }
     }
+
+    pub fn send_batch(&mut self, packets: &[EthernetHeader]) -> IoResult<uint> {
+        if packets.is_empty() {
+            return Ok(0);
+        }
+        let mut iovecs: Vec<linux::iovec> = packets.iter().map(|packet| {
+            linux::iovec {
+                iov_base: packet.packet().as_ptr() as *mut libc::c_void,
+                iov_len: packet.packet().len() as libc::size_t,
+            }
+        }).collect();
+        let send_addr = (&mut self.send_addr as *mut libc::sockaddr_ll) as *mut libc::c_void;
+        let send_addr_len = self.send_addr_len as libc::socklen_t;
+        let mut msgs: Vec<linux::mmsghdr> = iovecs.iter_mut().map(|iov| {
+            linux::mmsghdr {
+                msg_hdr: linux::msghdr {
+                    msg_name: send_addr,
+                    msg_namelen: send_addr_len,
+                    msg_iov: iov as *mut linux::iovec,
+                    msg_iovlen: 1,
+                    msg_control: ptr::null_mut(),
+                    msg_controllen: 0,
+                    msg_flags: 0,
+                },
+                msg_len: 0,
+            }
+        }).collect();
+        let fd = self.socket.fd;
+        let sent = internal::retry(|| unsafe {
+            linux::sendmmsg(fd, msgs.as_mut_ptr(), msgs.len() as libc::c_uint, 0)
+        });
+        if sent >= 0 {
+            return Ok(sent as uint);
+        }
+        // sendmmsg() is only available from Linux 3.0
+        if os::errno() as int == libc::ENOSYS as int {
+            return datalink::send_each(packets, |packet| self.send_to(packet, None));
+        }
+        Err(IoError::last_error())
+    }
 }
 
 pub struct DataLinkReceiverImpl {
     socket: Arc<internal::FileDesc>,
     read_buffer: Vec<u8>,
     _channel_type: DataLinkChannelType,
+    // PACKET_STATISTICS resets the kernel's counters, so keep running totals here
+    received: Cell<u64>,
+    dropped: Cell<u64>,
 }
 
 impl DataLinkReceiverImpl {
+    pub fn set_filter(&mut self, filter: &[BpfInstruction]) -> IoResult<()> {
+        let prog = linux::sock_fprog {
+            len: filter.len() as libc::c_ushort,
+            filter: filter.as_ptr() as *const linux::sock_filter,
+        };
+        self.socket.setsockopt(linux::SOL_SOCKET, linux::SO_ATTACH_FILTER, &prog)
+    }
+
+    pub fn stats(&self) -> IoResult<CaptureStats> {
+        let stats: linux::tpacket_stats =
+            try!(self.socket.getsockopt(linux::SOL_PACKET, linux::PACKET_STATISTICS));
+        self.received.set(self.received.get() + stats.tp_packets as u64);
+        self.dropped.set(self.dropped.get() + stats.tp_drops as u64);
+        Ok(CaptureStats {
+            received: self.received.get(),
+            dropped: self.dropped.get(),
+        })
+    }
+
     // FIXME Layer 3
     pub fn iter<'a>(&'a mut self) -> DataLinkChannelIteratorImpl<'a> {
         DataLinkChannelIteratorImpl {
@@ -100,65 +180,74 @@ impl DataLinkReceiverImpl {
     }
 }
 
-pub fn datalink_channel (network_interface: &NetworkInterface,
-                         write_buffer_size: uint,
-                         read_buffer_size: uint,
-                         channel_type: DataLinkChannelType)
+pub fn datalink_channel(network_interface: &NetworkInterface,
+                        options: &DataLinkOptions)
     -> IoResult<(DataLinkSenderImpl, DataLinkReceiverImpl)> {
+    let channel_type = options.channel_type;
     let eth_p_all = 0x0003;
-    let (typ, proto) = match channel_type {
-        Layer2 => (libc::SOCK_RAW, eth_p_all),
-        Layer3(EtherType(proto)) => (libc::SOCK_DGRAM, proto),
+    // Have the kernel only deliver packets with the requested EtherType
+    let (typ, proto) = match (channel_type, options.ethertype_filter) {
+        (Layer2, Some(EtherType(proto))) => (libc::SOCK_RAW, proto),
+        (Layer2, None) => (libc::SOCK_RAW, eth_p_all),
+        (Layer3(EtherType(proto)), _) => (libc::SOCK_DGRAM, proto),
     };
-    let socket = unsafe { libc::socket(libc::AF_PACKET, typ, proto.to_be() as i32) };
-    if socket != -1 {
-        let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
-        let len = network_addr_to_sockaddr(network_interface,
-                                           &mut addr,
-                                           proto as i32);
+    let socket = try!(internal::FileDesc::socket(libc::AF_PACKET, typ, proto.to_be() as i32));
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = network_addr_to_sockaddr(network_interface,
+                                       &mut addr,
+                                       proto as i32);
 
-        let send_addr = (&addr as *const libc::sockaddr_storage) as *const libc::sockaddr;
+    let send_addr = (&addr as *const libc::sockaddr_storage) as *const libc::sockaddr;
 
-        // Bind to interface
-        if unsafe { libc::bind(socket, send_addr, len as libc::socklen_t) } == -1 {
-            let err = IoError::last_error();
-            unsafe { internal::close(socket); }
-            return Err(err);
-        }
+    // Bind to interface
+    if unsafe { libc::bind(socket.fd, send_addr, len as libc::socklen_t) } == -1 {
+        return Err(IoError::last_error());
+    }
 
+    // Enable promiscuous capture
+    if options.promiscuous {
         let mut pmr: linux::packet_mreq = unsafe { mem::zeroed() };
         pmr.mr_ifindex = network_interface.index as i32;
         pmr.mr_type = linux::PACKET_MR_PROMISC as u16;
 
-        // Enable promiscuous capture
-        if unsafe { libc::setsockopt(socket,
-                                     linux::SOL_PACKET,
-                                     linux::PACKET_ADD_MEMBERSHIP,
-                                     (&pmr as *const linux::packet_mreq)
-                                           as *const libc::c_void,
-                                     mem::size_of::<linux::packet_mreq>() as u32) } == -1 {
-            let err = IoError::last_error();
-            unsafe { internal::close(socket); }
-            return Err(err);
-        }
+        try!(socket.setsockopt(linux::SOL_PACKET, linux::PACKET_ADD_MEMBERSHIP, &pmr));
+    }
 
-        let fd = Arc::new(internal::FileDesc { fd: socket });
-        let sender = DataLinkSenderImpl {
-            socket: fd.clone(),
-            write_buffer: Vec::from_elem(write_buffer_size, 0u8),
-            _channel_type: channel_type,
-            send_addr: unsafe { *(send_addr as *const libc::sockaddr_ll) },
-            send_addr_len: len,
-        };
-        let receiver = DataLinkReceiverImpl {
-            socket: fd,
-            read_buffer: Vec::from_elem(read_buffer_size, 0u8),
-            _channel_type: channel_type
-        };
-        Ok((sender, receiver))
-    } else {
-        Err(IoError::last_error())
+    // Set the kernel buffer sizes
+    try!(grow_socket_buffer(&socket, linux::SO_RCVBUF, options.read_buffer_size));
+    try!(grow_socket_buffer(&socket, linux::SO_SNDBUF, options.write_buffer_size));
+
+    // Have the kernel timestamp received packets
+    if options.timestamping {
+        let one: libc::c_int = 1;
+        try!(socket.setsockopt(linux::SOL_SOCKET, linux::SO_TIMESTAMP, &one));
     }
+
+    // Set the read timeout
+    match options.read_timeout {
+        Some(timeout) => {
+            let tv = internal::duration_to_timeval(timeout);
+            try!(socket.setsockopt(linux::SOL_SOCKET, linux::SO_RCVTIMEO, &tv));
+        },
+        None => ()
+    }
+
+    let fd = Arc::new(socket);
+    let sender = DataLinkSenderImpl {
+        socket: fd.clone(),
+        write_buffer: Vec::from_elem(options.write_buffer_size, 0u8),
+        _channel_type: channel_type,
+        send_addr: unsafe { *(send_addr as *const libc::sockaddr_ll) },
+        send_addr_len: len,
+    };
+    let receiver = DataLinkReceiverImpl {
+        socket: fd,
+        read_buffer: Vec::from_elem(options.read_buffer_size, 0u8),
+        _channel_type: channel_type,
+        received: Cell::new(0),
+        dropped: Cell::new(0),
+    };
+    Ok((sender, receiver))
 }
 
 pub struct DataLinkChannelIteratorImpl<'a> {
@@ -171,8 +260,76 @@ impl<'a> DataLinkChannelIteratorImpl<'a> {
         let res = internal::recv_from(self.pc.socket.fd, self.pc.read_buffer.as_mut_slice(), &mut caddr);
         match res {
             Ok(len) => Ok(EthernetHeader::new(self.pc.read_buffer.as_slice().slice(0, len))),
+            // Reads on a blocking socket only fail with EAGAIN when SO_RCVTIMEO expires
+            Err(ref e) if e.kind == ResourceUnavailable => Err(datalink::timed_out()),
             Err(e) => Err(e),
         }
     }
+
+    pub fn next_with_timestamp<'c>(&'c mut self) -> IoResult<(Duration, EthernetHeader<'c>)> {
+        let mut iov = linux::iovec {
+            iov_base: self.pc.read_buffer.as_mut_ptr() as *mut libc::c_void,
+            iov_len: self.pc.read_buffer.len() as libc::size_t,
+        };
+        // NOTE u64s so the control messages are suitably aligned
+        let mut control = [0u64, ..8];
+        let mut msg = linux::msghdr {
+            msg_name: ptr::null_mut(),
+            msg_namelen: 0,
+            msg_iov: &mut iov,
+            msg_iovlen: 1,
+            msg_control: control.as_mut_ptr() as *mut libc::c_void,
+            msg_controllen: mem::size_of_val(&control) as libc::size_t,
+            msg_flags: 0,
+        };
+        let fd = self.pc.socket.fd;
+        let len = internal::retry(|| unsafe { linux::recvmsg(fd, &mut msg, 0) });
+        if len < 0 {
+            let err = IoError::last_error();
+            return if err.kind == ResourceUnavailable {
+                Err(datalink::timed_out())
+            } else {
+                Err(err)
+            };
+        }
+
+        // Find the SCM_TIMESTAMP control message
+        let align = |len: uint| {
+            let size = mem::size_of::<libc::size_t>();
+            (len + size - 1) & !(size - 1)
+        };
+        let hdr_len = align(mem::size_of::<linux::cmsghdr>());
+        let mut offset = 0u;
+        let mut timestamp = None;
+        while offset + hdr_len <= msg.msg_controllen as uint {
+            unsafe {
+                let base = (control.as_ptr() as *const u8).offset(offset as int);
+                let cmsg = base as *const linux::cmsghdr;
+                if (*cmsg).cmsg_level == linux::SOL_SOCKET &&
+                   (*cmsg).cmsg_type == linux::SCM_TIMESTAMP {
+                    let tv = base.offset(hdr_len as int) as *const libc::timeval;
+                    timestamp = Some(Duration::seconds((*tv).tv_sec as i64) +
+                                     Duration::microseconds((*tv).tv_usec as i64));
+                    break;
+                }
+                if (*cmsg).cmsg_len == 0 {
+                    break;
+                }
+                offset = offset + align((*cmsg).cmsg_len as uint);
+            }
+        }
+
+        match timestamp {
+            Some(timestamp) => {
+                let packet = self.pc.read_buffer.as_slice().slice_to(len as uint);
+                Ok((timestamp, EthernetHeader::new(packet)))
+            },
+            None => Err(IoError {
+                kind: OtherIoError,
+                desc: "no timestamp was received with the packet",
+                detail: None,
+            })
+        }
+    }
 }
 
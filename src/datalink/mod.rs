@@ -8,12 +8,20 @@
 
 //! Support for sending and receiving data link layer packets
 
-use std::io::{IoResult};
+#[cfg(not(target_os = "linux"))]
+use std::collections::{RingBuf, Deque};
+use std::default::Default;
+use std::io::{IoResult, IoError, InvalidInput, TimedOut};
 use std::iter::Iterator;
 use std::option::{Option};
+use std::time::Duration;
 
-use packet::ethernet::{EtherType, EthernetHeader, MutableEthernetHeader};
-use util::NetworkInterface;
+use packet::{Packet, MutablePacket};
+use packet::ethernet::{EtherType, EtherTypes, EthernetHeader, EthernetPacket,
+                       MutableEthernetHeader, ETHERNET_HEADER_LEN, VLAN_HEADER_LEN};
+use util::{MacAddr, NetworkInterface};
+
+pub mod arp;
 
 #[cfg(windows)]
 #[path = "winpcap.rs"]
@@ -36,6 +44,110 @@ pub enum DataLinkChannelType {
     Layer3(EtherType)
 }
 
+/// A single instruction of a BPF program, as used by `DataLinkReceiver::set_filter()`
+///
+/// This has the same layout as `struct bpf_insn` on BSD and Windows, and `struct sock_filter`
+/// on Linux. Programs can be assembled with `tcpdump -dd <expression>`.
+#[repr(C)]
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct BpfInstruction {
+    /// The opcode
+    pub code: u16,
+    /// The offset to jump by if a conditional jump is true
+    pub jt: u8,
+    /// The offset to jump by if a conditional jump is false
+    pub jf: u8,
+    /// The generic field, used for constants, offsets and return values
+    pub k: u32,
+}
+
+impl BpfInstruction {
+    /// Construct a new BPF instruction
+    pub fn new(code: u16, jt: u8, jf: u8, k: u32) -> BpfInstruction {
+        BpfInstruction { code: code, jt: jt, jf: jf, k: k }
+    }
+}
+
+/// Options for creating a data link channel
+pub struct DataLinkOptions {
+    /// The size of the buffer used when writing packets. This should be large enough to hold the
+    /// largest packet, or batch of packets, you wish to send.
+    ///
+    /// On Linux the socket's send buffer (SO_SNDBUF) is also grown to at least this size.
+    pub write_buffer_size: uint,
+
+    /// The size of the buffer used when reading packets. This should be large enough to hold the
+    /// largest packet you wish to receive. Increasing it reduces the chance of packets being
+    /// dropped at high rates.
+    ///
+    /// On Linux the socket's receive buffer (SO_RCVBUF) is also grown to at least this size. On
+    /// BSD and OS X this sets the BPF buffer length (BIOCSBLEN), which must be done before the
+    /// BPF device is bound to an interface; the kernel may reject sizes it doesn't support. On
+    /// Windows it sets the size of the WinPcap kernel buffer.
+    pub read_buffer_size: uint,
+
+    /// The layer to send and receive packets at, currently only layer 2 is supported
+    pub channel_type: DataLinkChannelType,
+
+    /// Whether to put the interface into promiscuous mode, so that packets which aren't
+    /// addressed to it are received too. Defaults to false.
+    pub promiscuous: bool,
+
+    /// How long to wait for a packet before reading from the channel returns an error with kind
+    /// `TimedOut`. If this is None, reading blocks until a packet is available.
+    ///
+    /// The timeout has millisecond precision, and is rounded up to a whole number of milliseconds.
+    pub read_timeout: Option<Duration>,
+
+    /// Whether the kernel's receive timestamps should be made available, using
+    /// `DataLinkChannelIterator::next_with_timestamp()`. Defaults to false, since it may make
+    /// receiving packets slower.
+    pub timestamping: bool,
+
+    /// Only receive packets with this EtherType. Defaults to None, which receives all packets.
+    ///
+    /// On Linux this is passed to the kernel when the socket is created, so other packets are
+    /// never queued for the channel. On other platforms it's applied as a BPF filter, which is
+    /// replaced if `DataLinkReceiver::set_filter()` is called; on the BSD loopback device it
+    /// matches bytes 12 and 13 of the packets as they are returned by the channel.
+    pub ethertype_filter: Option<EtherType>,
+
+    /// Whether the source address of each sent packet should be replaced with the MAC address of
+    /// the interface the channel is bound to. Packets too short to hold a source address are sent
+    /// unchanged. Defaults to false.
+    ///
+    /// Packets passed to `DataLinkSender::send_to()` and `DataLinkSender::send_batch()` are
+    /// copied before being changed. If the interface has no MAC address, creating the channel
+    /// fails with an error of kind `InvalidInput`.
+    pub fill_source_mac: bool,
+
+    /// Whether packets larger than the interface's MTU may be sent, for instance to send jumbo
+    /// frames. Defaults to false.
+    ///
+    /// Unless this is set, `DataLinkSender::send_to()` and `DataLinkSender::send_batch()` check
+    /// each frame on a layer 2 channel against the MTU of the interface, allowing for the
+    /// Ethernet header and an 802.1Q VLAN tag, and return an error of kind `InvalidInput` for
+    /// frames which are too large rather than passing them to the operating system. Nothing is
+    /// checked if the MTU of the interface is unknown, or for `DataLinkSender::build_and_send()`.
+    pub allow_oversized_frames: bool,
+}
+
+impl Default for DataLinkOptions {
+    fn default() -> DataLinkOptions {
+        DataLinkOptions {
+            write_buffer_size: 4096,
+            read_buffer_size: 4096,
+            channel_type: Layer2,
+            promiscuous: false,
+            read_timeout: None,
+            timestamping: false,
+            ethertype_filter: None,
+            fill_source_mac: false,
+            allow_oversized_frames: false,
+        }
+    }
+}
+
 /// Create a new (DataLinkSender, DataLinkReceiver) pair
 ///
 /// This allows for sending and receiving packets at the data link layer.
@@ -48,23 +160,128 @@ pub enum DataLinkChannelType {
 ///
 /// The channel type specifies what layer to send and receive packets at, currently only layer 2 is
 /// supported.
+///
+/// The interface is put into promiscuous mode. This is equivalent to calling
+/// `datalink_channel_with_options()` with the given buffer sizes and channel type, and
+/// `promiscuous` set to true.
 #[inline]
 pub fn datalink_channel(network_interface: &NetworkInterface,
                         write_buffer_size: uint,
                         read_buffer_size: uint,
                         channel_type: DataLinkChannelType)
     -> IoResult<(DataLinkSender, DataLinkReceiver)> {
-    match backend::datalink_channel(network_interface, write_buffer_size, read_buffer_size,
-                                             channel_type) {
-        Ok((tx, rx)) => Ok((DataLinkSender { dlsi: tx }, DataLinkReceiver { dlri: rx })),
+    let options = DataLinkOptions {
+        write_buffer_size: write_buffer_size,
+        read_buffer_size: read_buffer_size,
+        channel_type: channel_type,
+        promiscuous: true,
+        read_timeout: None,
+        timestamping: false,
+        ethertype_filter: None,
+        fill_source_mac: false,
+        allow_oversized_frames: false,
+    };
+    datalink_channel_with_options(network_interface, &options)
+}
+
+/// Create a new (DataLinkSender, DataLinkReceiver) pair using the given options
+///
+/// A list of network interfaces can be retrieved using util::get_network_interfaces(), and
+/// `DataLinkOptions::default()` provides sensible defaults for the options.
+pub fn datalink_channel_with_options(network_interface: &NetworkInterface,
+                                     options: &DataLinkOptions)
+    -> IoResult<(DataLinkSender, DataLinkReceiver)> {
+    let source_mac = if options.fill_source_mac {
+        match network_interface.mac {
+            Some(mac) => Some(mac),
+            None => return Err(IoError {
+                kind: InvalidInput,
+                desc: "the interface has no MAC address to fill in",
+                detail: None,
+            })
+        }
+    } else {
+        None
+    };
+    let max_frame_len = match (&options.channel_type, network_interface.mtu) {
+        (&Layer2, Some(mtu)) if !options.allow_oversized_frames => {
+            Some(mtu as uint + ETHERNET_HEADER_LEN)
+        },
+        _ => None
+    };
+    match backend::datalink_channel(network_interface, options) {
+        Ok((tx, rx)) => {
+            let sender = DataLinkSender {
+                dlsi: tx,
+                source_mac: source_mac,
+                max_frame_len: max_frame_len,
+            };
+            let receiver = DataLinkReceiver { dlri: rx, timestamping: options.timestamping };
+            Ok((sender, receiver))
+        },
         Err(e) => Err(e)
     }
 }
 
+// The error returned by backends when a read times out
+fn timed_out() -> IoError {
+    IoError {
+        kind: TimedOut,
+        desc: "timed out waiting for a packet",
+        detail: None,
+    }
+}
+
+// Build a BPF program accepting packets with the given EtherType `offset` bytes in, to emulate
+// DataLinkOptions::ethertype_filter where the kernel can't filter by EtherType itself
+#[cfg(not(target_os = "linux"))]
+fn ethertype_program(ethertype: EtherType, offset: uint) -> [BpfInstruction, ..4] {
+    let EtherType(ethertype) = ethertype;
+    // ldh [offset]; jeq #ethertype, accept, drop; accept: ret #-1; drop: ret #0
+    [BpfInstruction::new(0x28, 0, 0, offset as u32),
+     BpfInstruction::new(0x15, 0, 1, ethertype as u32),
+     BpfInstruction::new(0x06, 0, 0, 0xffffffff),
+     BpfInstruction::new(0x06, 0, 0, 0)]
+}
+
+// Find the packets in a buffer filled by reading from a BPF device or a WinPcap adapter, adding
+// the start, length and timestamp of each to `packets`. Each packet is preceded by a bpf_hdr, and
+// padded so the next header is word aligned. `header_size` bytes are skipped at the start of each
+// packet (the address family, on loopback devices). Anything truncated is ignored.
+#[cfg(not(target_os = "linux"))]
+fn split_packets(buffer: &[u8], header_size: uint, packets: &mut RingBuf<(uint, uint, Duration)>) {
+    use std::mem;
+
+    use bindings::bpf;
+
+    let bpf_hdr_len = mem::size_of::<bpf::bpf_hdr>();
+    let mut offset = 0u;
+    while offset + bpf_hdr_len <= buffer.len() {
+        let packet = unsafe { &*(buffer.as_ptr().offset(offset as int) as *const bpf::bpf_hdr) };
+        let start = offset + packet.bh_hdrlen as uint;
+        let caplen = packet.bh_caplen as uint;
+        if packet.bh_hdrlen == 0 || start + caplen > buffer.len() {
+            break;
+        }
+        if caplen >= header_size {
+            let timestamp = Duration::seconds(packet.bh_tstamp.tv_sec as i64) +
+                            Duration::microseconds(packet.bh_tstamp.tv_usec as i64);
+            packets.push((start + header_size, caplen - header_size, timestamp));
+        }
+        offset = offset + bpf::BPF_WORDALIGN(packet.bh_hdrlen as uint + caplen);
+    }
+}
+
 /// Structure for sending packets at the data link layer. Should be constructed using
 /// datalink_channel().
 pub struct DataLinkSender {
-    dlsi: backend::DataLinkSenderImpl
+    dlsi: backend::DataLinkSenderImpl,
+    // The address to overwrite the source of sent packets with, see
+    // DataLinkOptions::fill_source_mac
+    source_mac: Option<MacAddr>,
+    // The largest untagged frame which fits in the interface's MTU, see
+    // DataLinkOptions::allow_oversized_frames
+    max_frame_len: Option<uint>,
 }
 
 impl DataLinkSender {
@@ -77,7 +294,15 @@ impl DataLinkSender {
     #[inline]
     pub fn build_and_send(&mut self, num_packets: uint, packet_size: uint,
                           func: |MutableEthernetHeader| -> ()) -> Option<IoResult<()>> {
-        self.dlsi.build_and_send(num_packets, packet_size, func)
+        match self.source_mac {
+            Some(mac) => {
+                self.dlsi.build_and_send(num_packets, packet_size, |mut packet| {
+                    func(MutableEthernetHeader::new(packet.packet_mut()));
+                    fill_source(packet.packet_mut(), mac);
+                })
+            },
+            None => self.dlsi.build_and_send(num_packets, packet_size, func)
+        }
     }
 
     /// Send a packet
@@ -85,42 +310,338 @@ impl DataLinkSender {
     /// This may require an additional copy compared to `build_and_send`, depending on the
     /// operating system being used. The second parameter is currently ignored, however `None`
     /// should be passed.
+    ///
+    /// An error of kind `InvalidInput` is returned if the packet is larger than the interface's
+    /// MTU allows, see `DataLinkOptions::allow_oversized_frames`.
     #[inline]
     pub fn send_to(&mut self, packet: EthernetHeader, dst: Option<NetworkInterface>)
         -> Option<IoResult<()>> {
-        self.dlsi.send_to(packet, dst)
+        match self.max_frame_len {
+            Some(max_frame_len) => match check_frame_len(&packet, max_frame_len) {
+                Ok(()) => (),
+                Err(e) => return Some(Err(e))
+            },
+            None => ()
+        }
+        match self.source_mac {
+            Some(mac) => {
+                let mut copy = packet.packet().to_vec();
+                fill_source(copy.as_mut_slice(), mac);
+                self.dlsi.send_to(EthernetHeader::new(copy.as_slice()), dst)
+            },
+            None => self.dlsi.send_to(packet, dst)
+        }
+    }
+
+    /// Send a batch of packets, returning the number which were sent
+    ///
+    /// On Linux this uses a single `sendmmsg()` call where the kernel supports it, otherwise the
+    /// packets are sent one at a time. Fewer packets than were given may be sent; the remainder
+    /// should be passed to another call. An error is only returned if no packets were sent.
+    ///
+    /// Sending stops before the first packet which is larger than the interface's MTU allows, see
+    /// `DataLinkOptions::allow_oversized_frames`; if that is the first packet, an error of kind
+    /// `InvalidInput` is returned.
+    #[inline]
+    pub fn send_batch(&mut self, packets: &[EthernetHeader]) -> IoResult<uint> {
+        let packets = match self.max_frame_len {
+            Some(max_frame_len) => packets.slice_to(try!(count_within_mtu(packets, max_frame_len))),
+            None => packets
+        };
+        match self.source_mac {
+            Some(mac) => {
+                let copies: Vec<Vec<u8>> = packets.iter().map(|packet| {
+                    let mut copy = packet.packet().to_vec();
+                    fill_source(copy.as_mut_slice(), mac);
+                    copy
+                }).collect();
+                let headers: Vec<EthernetHeader> = copies.iter().map(|copy| {
+                    EthernetHeader::new(copy.as_slice())
+                }).collect();
+                self.dlsi.send_batch(headers.as_slice())
+            },
+            None => self.dlsi.send_batch(packets)
+        }
     }
 }
 
+// Overwrite the source address of an Ethernet frame, if it's long enough to have one
+fn fill_source(packet: &mut [u8], mac: MacAddr) {
+    if packet.len() >= 12 {
+        MutableEthernetHeader::new(packet).set_source(mac);
+    }
+}
+
+// Check that a frame fits in the MTU of the interface, given the largest untagged frame which
+// does. VLAN tagged frames may be longer by the size of the tag.
+fn check_frame_len(packet: &EthernetHeader, max_frame_len: uint) -> IoResult<()> {
+    let len = packet.packet().len();
+    let tagged = len >= ETHERNET_HEADER_LEN && packet.get_ethertype() == EtherTypes::Vlan;
+    let max_frame_len = if tagged {
+        max_frame_len + VLAN_HEADER_LEN
+    } else {
+        max_frame_len
+    };
+    if len > max_frame_len {
+        Err(IoError {
+            kind: InvalidInput,
+            desc: "packet is larger than the interface's MTU",
+            detail: Some(format!("{} byte frame, at most {} bytes allowed", len, max_frame_len)),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+// Get the number of packets at the start of `packets` which fit in the MTU of the interface.
+// Returns the error for the first packet if it doesn't fit.
+fn count_within_mtu(packets: &[EthernetHeader], max_frame_len: uint) -> IoResult<uint> {
+    for (i, packet) in packets.iter().enumerate() {
+        match check_frame_len(packet, max_frame_len) {
+            Ok(()) => (),
+            Err(e) => return if i == 0 { Err(e) } else { Ok(i) }
+        }
+    }
+    Ok(packets.len())
+}
+
+// Send each packet in turn using `send`, stopping at the first error. Used by backends which
+// have no way to send several packets at once.
+fn send_each(packets: &[EthernetHeader],
+             send: |EthernetHeader| -> Option<IoResult<()>>) -> IoResult<uint> {
+    let mut sent = 0u;
+    for packet in packets.iter() {
+        let res = match send(EthernetHeader::new(packet.packet())) {
+            Some(res) => res,
+            None => Err(IoError {
+                kind: InvalidInput,
+                desc: "packet is larger than the write buffer",
+                detail: None,
+            })
+        };
+        match res {
+            Ok(()) => sent += 1,
+            Err(e) => return if sent == 0 { Err(e) } else { Ok(sent) }
+        }
+    }
+    Ok(sent)
+}
+
+/// Packet counts for a data link channel, as returned by `DataLinkReceiver::stats()`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct CaptureStats {
+    /// The number of packets received by the channel, including those which were dropped
+    pub received: u64,
+    /// The number of packets which were dropped because the channel's buffer was full
+    pub dropped: u64,
+}
+
 /// Structure for receiving packets at the data link layer. Should be constructed using
 /// datalink_channel().
 pub struct DataLinkReceiver {
-    dlri: backend::DataLinkReceiverImpl
+    dlri: backend::DataLinkReceiverImpl,
+    timestamping: bool,
 }
 
 impl DataLinkReceiver {
+    /// Attach a BPF program to the channel, so packets are filtered by the kernel before being
+    /// received. Any previously attached program is replaced.
+    ///
+    /// Packets which were received before the program was attached may still be returned.
+    #[inline]
+    pub fn set_filter(&mut self, filter: &[BpfInstruction]) -> IoResult<()> {
+        self.dlri.set_filter(filter)
+    }
+
+    /// Retrieve the number of packets received and dropped by the kernel for this channel
+    ///
+    /// The operating system's counters are reset each time they are read on Linux, and are
+    /// cumulative elsewhere; the receiver keeps a running total on Linux, so on every platform
+    /// the counts cover the lifetime of the channel.
+    #[inline]
+    pub fn stats(&self) -> IoResult<CaptureStats> {
+        self.dlri.stats()
+    }
+
     /// Returns an iterator over `EthernetHeader`s.
     ///
+    /// The iterator reuses the receiver's read buffer, so no allocation is done per packet. See
+    /// `DataLinkChannelIterator` for how to loop over it.
+    ///
     /// This will likely be removed once other layer two types are supported.
     #[inline]
     #[unstable]
     pub fn iter<'a>(&'a mut self) -> DataLinkChannelIterator<'a> {
         DataLinkChannelIterator {
-            imp: self.dlri.iter()
+            imp: self.dlri.iter(),
+            timestamping: self.timestamping,
         }
     }
 }
 
-/// An iterator over data link layer packets
+/// An iterator over data link layer packets.
+///
+/// Each packet borrows the channel's read buffer, so it is only valid until the next call to
+/// `next()`; copy it if it needs to be kept. Since the borrow cannot be expressed with
+/// `std::iter::Iterator`, use the `pfor!` macro to loop over packets:
+///
+/// ```ignore
+/// pfor!(packet in receiver.iter() {
+///     println!("received {} bytes", packet.packet().len());
+/// } on Err(e) {
+///     fail!("error while receiving packets: {}", e);
+/// })
+/// ```
 pub struct DataLinkChannelIterator<'a> {
     imp: backend::DataLinkChannelIteratorImpl<'a>,
+    timestamping: bool,
 }
 
 impl<'a> DataLinkChannelIterator<'a> {
-    /// Get the next EthernetHeader in the channel
+    /// Get the next EthernetHeader in the channel. The packet is only valid until `next()` is
+    /// called again.
     #[inline]
     pub fn next<'c>(&'c mut self) -> IoResult<EthernetHeader<'c>> {
         self.imp.next()
     }
+
+    /// Get the next EthernetHeader in the channel, along with the time it was received by the
+    /// kernel. The time is given as the duration since the Unix epoch.
+    ///
+    /// The channel must have been created with the `timestamping` option, otherwise an error
+    /// with kind `InvalidInput` is returned.
+    #[inline]
+    pub fn next_with_timestamp<'c>(&'c mut self) -> IoResult<(Duration, EthernetHeader<'c>)> {
+        if !self.timestamping {
+            return Err(IoError {
+                kind: InvalidInput,
+                desc: "timestamping is not enabled for this channel",
+                detail: None,
+            });
+        }
+        self.imp.next_with_timestamp()
+    }
+}
+
+#[test]
+fn datalink_options_default() {
+    let options: DataLinkOptions = Default::default();
+    assert_eq!(options.write_buffer_size, 4096);
+    assert_eq!(options.read_buffer_size, 4096);
+    assert!(!options.promiscuous);
+    assert!(options.read_timeout.is_none());
+    assert!(!options.timestamping);
+    assert!(options.ethertype_filter.is_none());
+    assert!(!options.fill_source_mac);
+    assert!(!options.allow_oversized_frames);
+    match options.channel_type {
+        Layer2 => (),
+        Layer3(_) => fail!("expected a layer 2 channel by default")
+    }
 }
 
+#[test]
+fn datalink_fill_source() {
+    let mac = MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55);
+    let mut packet = [0xffu8, ..14];
+    fill_source(packet.as_mut_slice(), mac);
+    assert_eq!(packet.as_slice(), [0xffu8, 0xff, 0xff, 0xff, 0xff, 0xff,
+                                   0x00, 0x11, 0x22, 0x33, 0x44, 0x55,
+                                   0xff, 0xff].as_slice());
+
+    // Frames without room for a source address are left alone
+    let mut short = [0xffu8, ..11];
+    fill_source(short.as_mut_slice(), mac);
+    assert_eq!(short.as_slice(), [0xffu8, ..11].as_slice());
+}
+
+#[test]
+fn datalink_check_frame_len() {
+    // A full size frame for a 1500 byte MTU
+    let mut packet = [0u8, ..1514];
+    MutableEthernetHeader::new(packet.as_mut_slice()).set_ethertype(EtherTypes::Ipv4);
+    let header = EthernetHeader::new(packet.as_slice());
+    assert!(check_frame_len(&header, 1514).is_ok());
+    match check_frame_len(&header, 1513) {
+        Err(e) => assert_eq!(e.kind, InvalidInput),
+        Ok(()) => fail!("expected an oversized frame to be rejected")
+    }
+
+    // VLAN tagged frames are allowed four more bytes
+    let mut tagged = [0u8, ..1518];
+    MutableEthernetHeader::new(tagged.as_mut_slice()).set_ethertype(EtherTypes::Vlan);
+    assert!(check_frame_len(&EthernetHeader::new(tagged.as_slice()), 1514).is_ok());
+    assert!(check_frame_len(&EthernetHeader::new(tagged.as_slice()), 1513).is_err());
+
+    // Frames too short to have an ethertype are fine
+    assert!(check_frame_len(&EthernetHeader::new(tagged.slice_to(10)), 1514).is_ok());
+
+    // Batches are cut short at the first oversized frame, or rejected if that's the first frame
+    let batch = [EthernetHeader::new(packet.slice_to(60)), EthernetHeader::new(packet.as_slice())];
+    assert_eq!(count_within_mtu(batch.as_slice(), 1514).ok(), Some(2));
+    assert_eq!(count_within_mtu(batch.as_slice(), 1500).ok(), Some(1));
+    assert!(count_within_mtu(batch.slice_from(1), 1500).is_err());
+    assert_eq!(count_within_mtu(batch.slice_to(0), 1500).ok(), Some(0));
+}
+
+#[test]
+#[cfg(not(target_os = "linux"))]
+fn bpf_split_packets() {
+    use std::mem;
+
+    use bindings::bpf;
+
+    let bpf_hdr_len = mem::size_of::<bpf::bpf_hdr>();
+    // NOTE u64s so the headers are suitably aligned
+    let mut storage = [0u64, ..64];
+    let buffer: &mut [u8] = unsafe {
+        mem::transmute(::std::raw::Slice { data: storage.as_mut_ptr() as *const u8, len: 512 })
+    };
+
+    // Three packets of different lengths, each padded to the next word boundary. The last is
+    // chosen so that it ends on a word boundary
+    let mut offset = 0u;
+    let mut expected = Vec::new();
+    for (i, &caplen) in [14u, 61, 24].iter().enumerate() {
+        unsafe {
+            let hdr = buffer.as_mut_ptr().offset(offset as int) as *mut bpf::bpf_hdr;
+            (*hdr).bh_tstamp.tv_sec = ::std::num::from_uint(i).unwrap();
+            (*hdr).bh_tstamp.tv_usec = 0;
+            (*hdr).bh_caplen = caplen as u32;
+            (*hdr).bh_datalen = caplen as u32;
+            (*hdr).bh_hdrlen = bpf_hdr_len as u16;
+        }
+        expected.push((offset + bpf_hdr_len, caplen, Duration::seconds(i as i64)));
+        offset = offset + bpf::BPF_WORDALIGN(bpf_hdr_len + caplen);
+    }
+
+    let mut packets = RingBuf::new();
+    split_packets(buffer.slice_to(offset), 0, &mut packets);
+    assert_eq!(packets.iter().map(|p| *p).collect::<Vec<(uint, uint, Duration)>>(), expected);
+
+    // The loopback header is skipped
+    let mut packets = RingBuf::new();
+    split_packets(buffer.slice_to(offset), 4, &mut packets);
+    let (start, len, _) = packets.pop_front().unwrap();
+    assert_eq!((start, len), (bpf_hdr_len + 4, 10));
+
+    // A truncated packet at the end is ignored
+    let mut packets = RingBuf::new();
+    split_packets(buffer.slice_to(offset - 1), 0, &mut packets);
+    assert_eq!(packets.len(), 2);
+    let mut packets = RingBuf::new();
+    split_packets(buffer.slice_to(bpf_hdr_len - 1), 0, &mut packets);
+    assert!(packets.is_empty());
+}
+
+#[test]
+#[cfg(not(target_os = "linux"))]
+fn datalink_ethertype_program() {
+    // Same as tcpdump -dd arp, except the whole packet is accepted
+    let program = ethertype_program(EtherTypes::Arp, 12);
+    assert_eq!(program.as_slice(), [BpfInstruction::new(0x28, 0, 0, 0x0000000c),
+                                    BpfInstruction::new(0x15, 0, 1, 0x00000806),
+                                    BpfInstruction::new(0x06, 0, 0, 0xffffffff),
+                                    BpfInstruction::new(0x06, 0, 0, 0x00000000)].as_slice());
+    assert_eq!(ethertype_program(EtherTypes::Ipv4, 16)[0].k, 16);
+}
@@ -15,9 +15,12 @@ use std::mem;
 use std::option::{Option, Some};
 use std::raw::Slice;
 use std::sync::Arc;
+use std::time::Duration;
 
 use bindings::{bpf, winpcap};
-use datalink::{DataLinkChannelType};
+use datalink;
+use internal;
+use datalink::{BpfInstruction, CaptureStats, DataLinkOptions};
 use packet::Packet;
 use packet::ethernet::{EthernetHeader, MutableEthernetHeader};
 use util::NetworkInterface;
@@ -47,12 +50,12 @@ impl Drop for WinPcapPacket {
 }
 
 pub fn datalink_channel(network_interface: &NetworkInterface,
-           read_buffer_size: uint,
-           write_buffer_size: uint,
-           channel_type: DataLinkChannelType)
+                        options: &DataLinkOptions)
     -> IoResult<(DataLinkSenderImpl, DataLinkReceiverImpl)> {
+    let read_buffer_size = options.read_buffer_size;
+    let write_buffer_size = options.write_buffer_size;
     let mut read_buffer = Vec::from_elem(read_buffer_size, 0u8);
-    let mut write_buffer = Vec::from_elem(read_buffer_size, 0u8);
+    let mut write_buffer = Vec::from_elem(write_buffer_size, 0u8);
 
     let adapter = unsafe {
         winpcap::PacketOpenAdapter(network_interface.name.to_c_str().as_mut_ptr())
@@ -60,9 +63,16 @@ pub fn datalink_channel(network_interface: &NetworkInterface,
     if adapter.is_null() {
         return Err(IoError::last_error());
     }
+    // Wrap the adapter straight away, so it's closed if any of the following steps fail
+    let adapter = WinPcapAdapter { adapter: adapter };
 
+    let filter = if options.promiscuous {
+        winpcap::NDIS_PACKET_TYPE_PROMISCUOUS
+    } else {
+        winpcap::NDIS_PACKET_TYPE_ALL_LOCAL
+    };
     let ret = unsafe {
-        winpcap::PacketSetHwFilter(adapter, winpcap::NDIS_PACKET_TYPE_PROMISCUOUS)
+        winpcap::PacketSetHwFilter(adapter.adapter, filter)
     };
     if ret == 0 {
         return Err(IoError::last_error());
@@ -70,16 +80,20 @@ pub fn datalink_channel(network_interface: &NetworkInterface,
 
     // Set kernel buffer size
     let ret = unsafe {
-        winpcap::PacketSetBuff(adapter, read_buffer_size as libc::c_int)
+        winpcap::PacketSetBuff(adapter.adapter, read_buffer_size as libc::c_int)
     };
     if ret == 0 {
         return Err(IoError::last_error());
     }
 
-    // FIXME [windows] This shouldn't be here - on Win32 reading seems to block indefinitely
-    //       currently.
+    // FIXME [windows] Without a timeout, on Win32 reading seems to block indefinitely
+    //       currently, so default to 5 seconds.
+    let timeout = match options.read_timeout {
+        Some(timeout) => internal::timeout_millis(timeout),
+        None => 5000
+    };
     let ret = unsafe {
-        winpcap::PacketSetReadTimeout(adapter, 5000)
+        winpcap::PacketSetReadTimeout(adapter.adapter, timeout as libc::c_int)
     };
     if ret == 0 {
         return Err(IoError::last_error());
@@ -87,7 +101,7 @@ pub fn datalink_channel(network_interface: &NetworkInterface,
 
     // Immediate mode
     let ret = unsafe {
-        winpcap::PacketSetMinToCopy(adapter, 1)
+        winpcap::PacketSetMinToCopy(adapter.adapter, 1)
     };
     if ret == 0 {
         return Err(IoError::last_error());
@@ -95,48 +109,55 @@ pub fn datalink_channel(network_interface: &NetworkInterface,
 
     let read_packet = unsafe { winpcap::PacketAllocatePacket() };
     if read_packet.is_null() {
-        unsafe {
-            winpcap::PacketCloseAdapter(adapter);
-        }
         return Err(IoError::last_error());
     }
+    let read_packet = WinPcapPacket { packet: read_packet };
 
     unsafe {
-        winpcap::PacketInitPacket(read_packet,
+        winpcap::PacketInitPacket(read_packet.packet,
                                   read_buffer.as_mut_ptr() as winpcap::PVOID,
                                   read_buffer_size as winpcap::UINT)
     }
 
     let write_packet = unsafe { winpcap::PacketAllocatePacket() };
     if write_packet.is_null() {
-        unsafe {
-            winpcap::PacketFreePacket(read_packet);
-            winpcap::PacketCloseAdapter(adapter);
-        }
         return Err(IoError::last_error());
     }
+    let write_packet = WinPcapPacket { packet: write_packet };
 
     unsafe {
-        winpcap::PacketInitPacket(write_packet,
+        winpcap::PacketInitPacket(write_packet.packet,
                                   write_buffer.as_mut_ptr() as winpcap::PVOID,
                                   write_buffer_size as winpcap::UINT)
     }
 
-    let adapter = Arc::new(WinPcapAdapter { adapter: adapter });
+    let adapter = Arc::new(adapter);
     let sender = DataLinkSenderImpl {
         adapter: adapter.clone(),
         _vec: write_buffer,
-        packet: WinPcapPacket { packet: write_packet }
+        packet: write_packet
     };
-    let receiver = DataLinkReceiverImpl {
+    let mut receiver = DataLinkReceiverImpl {
         adapter: adapter,
         _vec: read_buffer,
-        packet: WinPcapPacket { packet: read_packet }
+        packet: read_packet,
+        timeout: options.read_timeout.is_some(),
     };
+
+    // Emulate filtering by EtherType with a BPF filter
+    match options.ethertype_filter {
+        Some(ethertype) => {
+            let program = datalink::ethertype_program(ethertype, 12);
+            match receiver.set_filter(program.as_slice()) {
+                Err(e) => return Err(e),
+                Ok(()) => ()
+            }
+        },
+        None => ()
+    }
     Ok((sender, receiver))
 }
 
-
 pub struct DataLinkSenderImpl {
     adapter: Arc<WinPcapAdapter>,
     _vec: Vec<u8>,
@@ -147,6 +168,8 @@ pub struct DataLinkReceiverImpl {
     adapter: Arc<WinPcapAdapter>,
     _vec: Vec<u8>,
     packet: WinPcapPacket,
+    // Whether the caller asked for a read timeout, rather than relying on the default one
+    timeout: bool,
 }
 
 impl DataLinkSenderImpl {
@@ -154,7 +177,7 @@ impl DataLinkSenderImpl {
                           func: |MutableEthernetHeader| -> ()) -> Option<IoResult<()>> {
         use std::raw::Slice;
         let len = num_packets * packet_size;
-        if len >= unsafe { (*self.packet.packet).Length } as uint {
+        if len > unsafe { (*self.packet.packet).Length } as uint {
             None
         } else {
             let min = unsafe { cmp::min((*self.packet.packet).Length as uint, len) };
@@ -196,9 +219,35 @@ impl DataLinkSenderImpl {
             eh.clone_from(packet);
         })
     }
+
+    pub fn send_batch(&mut self, packets: &[EthernetHeader]) -> IoResult<uint> {
+        datalink::send_each(packets, |packet| self.send_to(packet, None))
+    }
 }
 
 impl DataLinkReceiverImpl {
+    pub fn set_filter(&mut self, filter: &[BpfInstruction]) -> IoResult<()> {
+        let mut prog = bpf::bpf_program {
+            bf_len: filter.len() as libc::c_uint,
+            bf_insns: filter.as_ptr() as *mut bpf::bpf_insn,
+        };
+        if unsafe { winpcap::PacketSetBpf(self.adapter.adapter, &mut prog) } == 0 {
+            return Err(IoError::last_error());
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> IoResult<CaptureStats> {
+        let mut stats: bpf::bpf_stat = unsafe { mem::zeroed() };
+        if unsafe { winpcap::PacketGetStats(self.adapter.adapter, &mut stats) } == 0 {
+            return Err(IoError::last_error());
+        }
+        Ok(CaptureStats {
+            received: stats.bs_recv as u64,
+            dropped: stats.bs_drop as u64,
+        })
+    }
+
     pub fn iter<'a>(&'a mut self) -> DataLinkChannelIteratorImpl<'a> {
         let buflen = unsafe { (*self.packet.packet).Length } as uint;
         DataLinkChannelIteratorImpl {
@@ -211,13 +260,20 @@ impl DataLinkReceiverImpl {
 
 pub struct DataLinkChannelIteratorImpl<'a> {
     pc: &'a mut DataLinkReceiverImpl,
-    packets: RingBuf<(uint, uint)>,
+    packets: RingBuf<(uint, uint, Duration)>,
 }
 
 impl<'a> DataLinkChannelIteratorImpl<'a> {
     pub fn next<'c>(&'c mut self) -> IoResult<EthernetHeader<'c>> {
+        match self.next_with_timestamp() {
+            Ok((_, eh)) => Ok(eh),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub fn next_with_timestamp<'c>(&'c mut self) -> IoResult<(Duration, EthernetHeader<'c>)> {
         // NOTE Most of the logic here is identical to FreeBSD/OS X
-        if self.packets.is_empty() {
+        while self.packets.is_empty() {
             let ret = unsafe {
                 winpcap::PacketReceivePacket(self.pc.adapter.adapter, self.pc.packet.packet, 0)
             };
@@ -225,26 +281,29 @@ impl<'a> DataLinkChannelIteratorImpl<'a> {
                 0 => return Err(IoError::last_error()),
                 _ => unsafe { (*self.pc.packet.packet).ulBytesReceived },
             };
-            let mut ptr = unsafe { (*self.pc.packet.packet).Buffer };
-            let end = unsafe { (*self.pc.packet.packet).Buffer.offset(buflen as int) };
-            while ptr < end {
-                unsafe {
-                    let packet: *const bpf::bpf_hdr = mem::transmute(ptr);
-                    let start = ptr as int +
-                                (*packet).bh_hdrlen as int -
-                                (*self.pc.packet.packet).Buffer as int;
-                    self.packets.push((start as uint, (*packet).bh_caplen as uint));
-                    let offset = (*packet).bh_hdrlen as int + (*packet).bh_caplen as int;
-                    ptr = ptr.offset(bpf::BPF_WORDALIGN(offset));
+            // Nothing is received if the read timeout expires. Without a timeout from the
+            // caller this is the default one, so keep waiting.
+            if buflen == 0 {
+                if self.pc.timeout {
+                    return Err(datalink::timed_out());
                 }
+                continue;
             }
+            let buffer: &[u8] = unsafe {
+                let packet = self.pc.packet.packet;
+                mem::transmute(Slice {
+                    data: (*packet).Buffer as *const u8,
+                    len: cmp::min(buflen as uint, (*packet).Length as uint),
+                })
+            };
+            datalink::split_packets(buffer, 0, &mut self.packets);
         }
-        let (start, len) = self.packets.pop_front().unwrap();
+        let (start, len, timestamp) = self.packets.pop_front().unwrap();
         let slice = unsafe {
             let data = (*self.pc.packet.packet).Buffer as uint + start;
             mem::transmute(Slice { data: data as *const u8, len: len } )
         };
-        Ok(EthernetHeader::new(slice))
+        Ok((timestamp, EthernetHeader::new(slice)))
     }
 }
 
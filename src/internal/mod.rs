@@ -21,11 +21,58 @@ mod native;
 #[cfg(not(windows))] pub type CSocket = libc::c_int;
 #[cfg(not(windows))] pub type BufLen = u64;
 
-// Any file descriptor on unix, only sockets on Windows.
+// Any file descriptor on unix, only sockets on Windows. The descriptor is closed when the
+// FileDesc is dropped, so it should be wrapped as soon as it is created to avoid leaking it on
+// error paths.
 pub struct FileDesc {
     pub fd: CSocket,
 }
 
+impl FileDesc {
+    /// Create a new socket
+    pub fn socket(domain: libc::c_int, ty: libc::c_int, protocol: libc::c_int)
+        -> IoResult<FileDesc> {
+        let fd = unsafe { libc::socket(domain, ty, protocol) };
+        if fd == -1 {
+            return Err(IoError::last_error());
+        }
+        Ok(FileDesc { fd: fd })
+    }
+
+    /// Set a socket option to `value`
+    pub fn setsockopt<T>(&self, level: libc::c_int, option: libc::c_int, value: &T)
+        -> IoResult<()> {
+        let res = unsafe {
+            libc::setsockopt(self.fd,
+                             level,
+                             option,
+                             (value as *const T) as *const libc::c_void,
+                             mem::size_of::<T>() as libc::socklen_t)
+        };
+        if res == -1 {
+            return Err(IoError::last_error());
+        }
+        Ok(())
+    }
+
+    /// Get the value of a socket option
+    pub fn getsockopt<T>(&self, level: libc::c_int, option: libc::c_int) -> IoResult<T> {
+        let mut value: T = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<T>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(self.fd,
+                             level,
+                             option,
+                             (&mut value as *mut T) as *mut libc::c_void,
+                             &mut len)
+        };
+        if res == -1 {
+            return Err(IoError::last_error());
+        }
+        Ok(value)
+    }
+}
+
 impl Drop for FileDesc {
     fn drop(&mut self) {
         unsafe {
@@ -34,6 +81,62 @@ impl Drop for FileDesc {
     }
 }
 
+// Creating and dropping sockets shouldn't leave descriptors open. Other tests may be opening
+// files at the same time, so this only checks that there are far fewer new descriptors than
+// sockets created.
+#[test]
+#[cfg(target_os = "linux")]
+fn file_desc_no_leak() {
+    use std::io::fs;
+
+    fn open_fds() -> uint {
+        fs::readdir(&Path::new("/proc/self/fd")).unwrap().len()
+    }
+
+    let before = open_fds();
+    for _ in range(0u, 1000) {
+        let socket = FileDesc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0).unwrap();
+        let reuse: libc::c_int = 1;
+        socket.setsockopt(libc::SOL_SOCKET, libc::SO_REUSEADDR, &reuse).unwrap();
+        let value: libc::c_int = socket.getsockopt(libc::SOL_SOCKET, libc::SO_REUSEADDR).unwrap();
+        assert!(value != 0);
+    }
+    assert!(open_fds() < before + 100);
+
+    // Errors are reported rather than leaving a descriptor behind
+    assert!(FileDesc::socket(-1, libc::SOCK_DGRAM, 0).is_err());
+}
+
+/// Convert a Duration to a whole number of milliseconds, as used for timeouts. Sub-millisecond
+/// parts are rounded up, and the result is at least one, since a timeout of zero usually means
+/// wait forever.
+pub fn timeout_millis(dur: ::std::time::Duration) -> i64 {
+    let ms = dur.num_milliseconds();
+    let ms = if ::std::time::Duration::milliseconds(ms) < dur { ms + 1 } else { ms };
+    if ms < 1 { 1 } else { ms }
+}
+
+#[test]
+fn timeout_millis_rounds_up() {
+    use std::time::Duration;
+
+    assert_eq!(timeout_millis(Duration::milliseconds(250)), 250);
+    assert_eq!(timeout_millis(Duration::microseconds(1500)), 2);
+    assert_eq!(timeout_millis(Duration::microseconds(1)), 1);
+    assert_eq!(timeout_millis(Duration::zero()), 1);
+}
+
+/// Convert a Duration to a timeval, as used for socket and BPF timeouts. The timeout is rounded
+/// up as for `timeout_millis()`.
+#[cfg(not(windows))]
+pub fn duration_to_timeval(dur: ::std::time::Duration) -> libc::timeval {
+    let ms = timeout_millis(dur);
+    libc::timeval {
+        tv_sec: (ms / 1000) as libc::time_t,
+        tv_usec: ((ms % 1000) * 1000) as libc::suseconds_t,
+    }
+}
+
 pub fn send_to(socket: CSocket, buffer: &[u8], dst: *const libc::sockaddr, slen: libc::socklen_t)
     -> IoResult<uint> {
 
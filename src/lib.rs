@@ -54,7 +54,7 @@
 //!     let ref interface_name = os::args()[1];
 //!
 //!     // Find the network interface with the provided name
-//!     let interfaces = get_network_interfaces();
+//!     let interfaces = get_network_interfaces().unwrap();
 //!     let interface = interfaces.iter()
 //!                               .filter(|iface| iface.name == *interface_name)
 //!                               .next()
@@ -105,6 +105,7 @@ extern crate libc;
 
 pub mod datalink;
 pub mod packet;
+pub mod pcap;
 pub mod transport;
 pub mod util;
 
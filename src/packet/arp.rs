@@ -0,0 +1,431 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! ARP packet abstraction
+//!
+//! Only ARP for IPv4 over Ethernet is supported.
+
+use std::fmt;
+use std::io::net::ip::{IpAddr, Ipv4Addr};
+
+use packet::{Packet, MutablePacket};
+use packet::ethernet::EtherType;
+use util::MacAddr;
+
+/// The length of an ARP packet for IPv4 over Ethernet, in bytes
+pub const ARP_PACKET_LEN: uint = 28;
+
+/// Structure representing an ARP header
+pub struct ArpHeader<'p> {
+    packet: &'p [u8],
+}
+
+/// Structure representing a mutable ARP header
+pub struct MutableArpHeader<'p> {
+    packet: &'p mut [u8],
+}
+
+// FIXME This should probably be a macro
+impl<'p> PartialEq for ArpHeader<'p> {
+    fn eq(&self, other: &ArpHeader) -> bool {
+        if self.packet.len() != other.packet.len() {
+            return false;
+        }
+        for (b1, b2) in self.packet.iter().zip(other.packet.iter()) {
+            if b1 != b2 {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+impl<'p> Eq for ArpHeader<'p> {}
+
+impl<'p> fmt::Show for ArpHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "ArpHeader {{ hardware type: {}, protocol type: {}, hw addr len: {}, \
+                             proto addr len: {}, operation: {}, sender hw addr: {}, \
+                             sender proto addr: {}, target hw addr: {}, \
+                             target proto addr: {} }}",
+                self.get_hardware_type(),
+                self.get_protocol_type(),
+                self.get_hw_addr_len(),
+                self.get_proto_addr_len(),
+                self.get_operation(),
+                self.get_sender_hw_addr(),
+                self.get_sender_proto_addr(),
+                self.get_target_hw_addr(),
+                self.get_target_proto_addr()
+        )
+    }
+}
+
+impl<'p> fmt::Show for MutableArpHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "MutableArpHeader {{ hardware type: {}, protocol type: {}, hw addr len: {}, \
+                                    proto addr len: {}, operation: {}, sender hw addr: {}, \
+                                    sender proto addr: {}, target hw addr: {}, \
+                                    target proto addr: {} }}",
+                self.get_hardware_type(),
+                self.get_protocol_type(),
+                self.get_hw_addr_len(),
+                self.get_proto_addr_len(),
+                self.get_operation(),
+                self.get_sender_hw_addr(),
+                self.get_sender_proto_addr(),
+                self.get_target_hw_addr(),
+                self.get_target_proto_addr()
+        )
+    }
+}
+
+impl<'p> Packet for ArpHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(ARP_PACKET_LEN) }
+}
+
+impl<'p> Packet for MutableArpHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet.as_slice() }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(ARP_PACKET_LEN) }
+}
+
+impl<'p> MutablePacket for MutableArpHeader<'p> {
+    #[inline(always)]
+    fn packet_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.as_mut_slice() }
+
+    #[inline(always)]
+    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] {
+        self.packet.slice_from_mut(ARP_PACKET_LEN)
+    }
+}
+
+/// Trait implemented by anything which provides an interface to read ARP
+/// packets
+pub trait ArpPacket : Packet {
+    /// Get the hardware type of the packet
+    fn get_hardware_type(&self) -> ArpHardwareType {
+        ArpHardwareType((self.packet()[0] as u16 << 8) | (self.packet()[1] as u16))
+    }
+
+    /// Get the protocol type of the packet
+    fn get_protocol_type(&self) -> EtherType {
+        EtherType((self.packet()[2] as u16 << 8) | (self.packet()[3] as u16))
+    }
+
+    /// Get the length of the hardware addresses in the packet
+    fn get_hw_addr_len(&self) -> u8 {
+        self.packet()[4]
+    }
+
+    /// Get the length of the protocol addresses in the packet
+    fn get_proto_addr_len(&self) -> u8 {
+        self.packet()[5]
+    }
+
+    /// Get the operation of the packet
+    fn get_operation(&self) -> ArpOperation {
+        ArpOperation((self.packet()[6] as u16 << 8) | (self.packet()[7] as u16))
+    }
+
+    /// Get the hardware address of the sender
+    fn get_sender_hw_addr(&self) -> MacAddr {
+        MacAddr(self.packet()[8],
+                self.packet()[9],
+                self.packet()[10],
+                self.packet()[11],
+                self.packet()[12],
+                self.packet()[13])
+    }
+
+    /// Get the protocol address of the sender
+    fn get_sender_proto_addr(&self) -> IpAddr {
+        Ipv4Addr(self.packet()[14],
+                 self.packet()[15],
+                 self.packet()[16],
+                 self.packet()[17])
+    }
+
+    /// Get the hardware address of the target
+    fn get_target_hw_addr(&self) -> MacAddr {
+        MacAddr(self.packet()[18],
+                self.packet()[19],
+                self.packet()[20],
+                self.packet()[21],
+                self.packet()[22],
+                self.packet()[23])
+    }
+
+    /// Get the protocol address of the target
+    fn get_target_proto_addr(&self) -> IpAddr {
+        Ipv4Addr(self.packet()[24],
+                 self.packet()[25],
+                 self.packet()[26],
+                 self.packet()[27])
+    }
+}
+
+impl<'p> ArpPacket for ArpHeader<'p> {}
+impl<'p> ArpPacket for MutableArpHeader<'p> {}
+
+impl<'p> ArpHeader<'p> {
+    /// Construct a new ARP header backed by the given buffer
+    pub fn new(packet: &'p [u8]) -> ArpHeader<'p> {
+        ArpHeader { packet: packet }
+    }
+
+    /// Construct a new ARP header backed by the given buffer, checking that it is an ARP
+    /// packet for IPv4 over Ethernet. Returns None if the buffer is too small, or the address
+    /// lengths are not those of Ethernet and IPv4.
+    pub fn new_checked(packet: &'p [u8]) -> Option<ArpHeader<'p>> {
+        if packet.len() < ARP_PACKET_LEN {
+            return None;
+        }
+        let header = ArpHeader::new(packet);
+        if header.get_hw_addr_len() != 6 || header.get_proto_addr_len() != 4 {
+            return None;
+        }
+
+        Some(header)
+    }
+}
+
+impl<'p> MutableArpHeader<'p> {
+    /// Construct a new mutable ARP header backed by the given buffer
+    pub fn new(packet: &'p mut [u8]) -> MutableArpHeader<'p> {
+        MutableArpHeader { packet: packet }
+    }
+
+    /// Construct a new mutable ARP header backed by the given buffer, checking that it is
+    /// large enough to contain an ARP packet for IPv4 over Ethernet. Returns None if it is not.
+    pub fn new_checked(packet: &'p mut [u8]) -> Option<MutableArpHeader<'p>> {
+        if packet.len() < ARP_PACKET_LEN {
+            None
+        } else {
+            Some(MutableArpHeader::new(packet))
+        }
+    }
+
+    /// Set the hardware type of the packet
+    pub fn set_hardware_type(&mut self, ArpHardwareType(hardware_type): ArpHardwareType) {
+        self.packet[0] = (hardware_type >> 8) as u8;
+        self.packet[1] = (hardware_type & 0xFF) as u8;
+    }
+
+    /// Set the protocol type of the packet
+    pub fn set_protocol_type(&mut self, EtherType(protocol_type): EtherType) {
+        self.packet[2] = (protocol_type >> 8) as u8;
+        self.packet[3] = (protocol_type & 0xFF) as u8;
+    }
+
+    /// Set the length of the hardware addresses in the packet
+    pub fn set_hw_addr_len(&mut self, len: u8) {
+        self.packet[4] = len;
+    }
+
+    /// Set the length of the protocol addresses in the packet
+    pub fn set_proto_addr_len(&mut self, len: u8) {
+        self.packet[5] = len;
+    }
+
+    /// Set the operation of the packet
+    pub fn set_operation(&mut self, ArpOperation(operation): ArpOperation) {
+        self.packet[6] = (operation >> 8) as u8;
+        self.packet[7] = (operation & 0xFF) as u8;
+    }
+
+    /// Set the hardware address of the sender
+    pub fn set_sender_hw_addr(&mut self, mac: MacAddr) {
+        self.set_mac(8, mac);
+    }
+
+    /// Set the protocol address of the sender. Only IPv4 addresses are supported; anything else
+    /// is ignored.
+    pub fn set_sender_proto_addr(&mut self, ip: IpAddr) {
+        self.set_ip(14, ip);
+    }
+
+    /// Set the hardware address of the target
+    pub fn set_target_hw_addr(&mut self, mac: MacAddr) {
+        self.set_mac(18, mac);
+    }
+
+    /// Set the protocol address of the target. Only IPv4 addresses are supported; anything else
+    /// is ignored.
+    pub fn set_target_proto_addr(&mut self, ip: IpAddr) {
+        self.set_ip(24, ip);
+    }
+
+    fn set_mac(&mut self, offset: uint, mac: MacAddr) {
+        match mac {
+            MacAddr(a, b, c, d, e, f) => {
+                self.packet[offset + 0] = a;
+                self.packet[offset + 1] = b;
+                self.packet[offset + 2] = c;
+                self.packet[offset + 3] = d;
+                self.packet[offset + 4] = e;
+                self.packet[offset + 5] = f;
+            }
+        }
+    }
+
+    fn set_ip(&mut self, offset: uint, ip: IpAddr) {
+        match ip {
+            Ipv4Addr(a, b, c, d) => {
+                self.packet[offset + 0] = a;
+                self.packet[offset + 1] = b;
+                self.packet[offset + 2] = c;
+                self.packet[offset + 3] = d;
+            },
+            _ => ()
+        }
+    }
+}
+
+#[test]
+fn arp_request_test() {
+    use packet::ethernet::EtherTypes;
+
+    let mut packet = [0u8, ..28];
+    {
+        let mut arp_header = MutableArpHeader::new_checked(packet.as_mut_slice()).unwrap();
+        arp_header.set_hardware_type(ArpHardwareTypes::Ethernet);
+        assert_eq!(arp_header.get_hardware_type(), ArpHardwareTypes::Ethernet);
+
+        arp_header.set_protocol_type(EtherTypes::Ipv4);
+        assert_eq!(arp_header.get_protocol_type(), EtherTypes::Ipv4);
+
+        arp_header.set_hw_addr_len(6);
+        assert_eq!(arp_header.get_hw_addr_len(), 6);
+
+        arp_header.set_proto_addr_len(4);
+        assert_eq!(arp_header.get_proto_addr_len(), 4);
+
+        arp_header.set_operation(ArpOperations::Request);
+        assert_eq!(arp_header.get_operation(), ArpOperations::Request);
+
+        arp_header.set_sender_hw_addr(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55));
+        assert_eq!(arp_header.get_sender_hw_addr(), MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55));
+
+        arp_header.set_sender_proto_addr(Ipv4Addr(192, 168, 0, 1));
+        assert_eq!(arp_header.get_sender_proto_addr(), Ipv4Addr(192, 168, 0, 1));
+
+        arp_header.set_target_hw_addr(MacAddr(0, 0, 0, 0, 0, 0));
+        assert_eq!(arp_header.get_target_hw_addr(), MacAddr(0, 0, 0, 0, 0, 0));
+
+        arp_header.set_target_proto_addr(Ipv4Addr(192, 168, 0, 2));
+        assert_eq!(arp_header.get_target_proto_addr(), Ipv4Addr(192, 168, 0, 2));
+    }
+
+    let ref_packet = [0x00, 0x01,       /* hardware type */
+                      0x08, 0x00,       /* protocol type */
+                      0x06,             /* hw addr len */
+                      0x04,             /* proto addr len */
+                      0x00, 0x01,       /* operation */
+                      0x00, 0x11, 0x22, 0x33, 0x44, 0x55, /* sender hw addr */
+                      0xc0, 0xa8, 0x00, 0x01, /* sender proto addr */
+                      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* target hw addr */
+                      0xc0, 0xa8, 0x00, 0x02  /* target proto addr */];
+    assert_eq!(ref_packet.as_slice(), packet.as_slice());
+
+    let arp_header = ArpHeader::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(arp_header.get_operation(), ArpOperations::Request);
+    assert_eq!(arp_header.get_target_proto_addr(), Ipv4Addr(192, 168, 0, 2));
+}
+
+#[test]
+fn arp_reply_test() {
+    use packet::ethernet::EtherTypes;
+
+    let request = [0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x01,
+                   0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0xc0, 0xa8, 0x00, 0x01,
+                   0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0xa8, 0x00, 0x02];
+    let request = ArpHeader::new_checked(request.as_slice()).unwrap();
+
+    // Build the reply to the request above
+    let mut packet = [0u8, ..28];
+    {
+        let mut reply = MutableArpHeader::new(packet.as_mut_slice());
+        reply.set_hardware_type(ArpHardwareTypes::Ethernet);
+        reply.set_protocol_type(EtherTypes::Ipv4);
+        reply.set_hw_addr_len(6);
+        reply.set_proto_addr_len(4);
+        reply.set_operation(ArpOperations::Reply);
+        reply.set_sender_hw_addr(MacAddr(0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb));
+        reply.set_sender_proto_addr(request.get_target_proto_addr());
+        reply.set_target_hw_addr(request.get_sender_hw_addr());
+        reply.set_target_proto_addr(request.get_sender_proto_addr());
+    }
+
+    let reply = ArpHeader::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(reply.get_hardware_type(), ArpHardwareTypes::Ethernet);
+    assert_eq!(reply.get_protocol_type(), EtherTypes::Ipv4);
+    assert_eq!(reply.get_operation(), ArpOperations::Reply);
+    assert_eq!(reply.get_sender_hw_addr(), MacAddr(0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb));
+    assert_eq!(reply.get_sender_proto_addr(), Ipv4Addr(192, 168, 0, 2));
+    assert_eq!(reply.get_target_hw_addr(), MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55));
+    assert_eq!(reply.get_target_proto_addr(), Ipv4Addr(192, 168, 0, 1));
+}
+
+#[test]
+fn arp_header_checked() {
+    let mut packet = [0u8, ..28];
+    // The address lengths haven't been set
+    assert!(ArpHeader::new_checked(packet.as_slice()).is_none());
+
+    packet[4] = 6;
+    packet[5] = 4;
+    assert!(ArpHeader::new_checked(packet.as_slice()).is_some());
+    assert!(ArpHeader::new_checked(packet.slice_to(27)).is_none());
+
+    // Not IPv4 over Ethernet
+    packet[5] = 16;
+    assert!(ArpHeader::new_checked(packet.as_slice()).is_none());
+}
+
+/// ARP operations, as defined at:
+/// http://www.iana.org/assignments/arp-parameters/arp-parameters.xhtml
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod ArpOperations {
+    use super::ArpOperation;
+
+    /// ARP request [RFC826]
+    pub const Request: ArpOperation = ArpOperation(1);
+
+    /// ARP reply [RFC826]
+    pub const Reply: ArpOperation   = ArpOperation(2);
+}
+
+/// Represents the ARP operation field
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ArpOperation(pub u16);
+
+/// ARP hardware types, as defined at:
+/// http://www.iana.org/assignments/arp-parameters/arp-parameters.xhtml
+///
+/// FIXME Should include all
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod ArpHardwareTypes {
+    use super::ArpHardwareType;
+
+    /// Ethernet (10Mb)
+    pub const Ethernet: ArpHardwareType = ArpHardwareType(1);
+}
+
+/// Represents the ARP hardware type field
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ArpHardwareType(pub u16);
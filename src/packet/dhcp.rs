@@ -0,0 +1,614 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! DHCP/BOOTP packet abstraction
+
+use std::fmt;
+use std::io::net::ip::{IpAddr, Ipv4Addr};
+
+use packet::{Packet, MutablePacket};
+use util::MacAddr;
+
+/// The length of the fixed BOOTP part of a DHCP message, in bytes. The magic cookie and options
+/// follow it.
+pub const DHCP_HEADER_LEN: uint = 236;
+
+/// The magic cookie which starts the options of a DHCP message
+pub const DHCP_MAGIC_COOKIE: u32 = 0x63825363;
+
+/// Structure representing a DHCP message
+pub struct DhcpHeader<'p> {
+    packet: &'p [u8],
+}
+
+/// Structure representing a mutable DHCP message
+pub struct MutableDhcpHeader<'p> {
+    packet: &'p mut [u8],
+}
+
+// FIXME This should probably be a macro
+impl<'p> PartialEq for DhcpHeader<'p> {
+    fn eq(&self, other: &DhcpHeader) -> bool {
+        if self.packet.len() != other.packet.len() {
+            return false;
+        }
+        for (b1, b2) in self.packet.iter().zip(other.packet.iter()) {
+            if b1 != b2 {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+impl<'p> Eq for DhcpHeader<'p> {}
+
+impl<'p> fmt::Show for DhcpHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "DhcpHeader {{ op: {}, xid: {}, ciaddr: {}, yiaddr: {}, siaddr: {}, giaddr: {}, \
+                chaddr: {} }}",
+                self.get_op(),
+                self.get_xid(),
+                self.get_ciaddr(),
+                self.get_yiaddr(),
+                self.get_siaddr(),
+                self.get_giaddr(),
+                self.get_chaddr()
+        )
+    }
+}
+
+impl<'p> fmt::Show for MutableDhcpHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "MutableDhcpHeader {{ op: {}, xid: {}, ciaddr: {}, yiaddr: {}, siaddr: {}, \
+                giaddr: {}, chaddr: {} }}",
+                self.get_op(),
+                self.get_xid(),
+                self.get_ciaddr(),
+                self.get_yiaddr(),
+                self.get_siaddr(),
+                self.get_giaddr(),
+                self.get_chaddr()
+        )
+    }
+}
+
+impl<'p> Packet for DhcpHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(DHCP_HEADER_LEN) }
+}
+
+impl<'p> Packet for MutableDhcpHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet.as_slice() }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(DHCP_HEADER_LEN) }
+}
+
+impl<'p> MutablePacket for MutableDhcpHeader<'p> {
+    #[inline(always)]
+    fn packet_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.as_mut_slice() }
+
+    #[inline(always)]
+    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] {
+        self.packet.slice_from_mut(DHCP_HEADER_LEN)
+    }
+}
+
+/// Trait implemented by anything which provides an interface to read DHCP
+/// messages
+pub trait DhcpPacket : Packet {
+    /// Get the operation of the message
+    fn get_op(&self) -> DhcpOperation {
+        DhcpOperation(self.packet()[0])
+    }
+
+    /// Get the hardware address type, 1 for Ethernet
+    fn get_htype(&self) -> u8 {
+        self.packet()[1]
+    }
+
+    /// Get the hardware address length
+    fn get_hlen(&self) -> u8 {
+        self.packet()[2]
+    }
+
+    /// Get the number of relay agents the message has passed through
+    fn get_hops(&self) -> u8 {
+        self.packet()[3]
+    }
+
+    /// Get the transaction ID
+    fn get_xid(&self) -> u32 {
+        let xid1 = self.packet()[4] as u32 << 24;
+        let xid2 = self.packet()[5] as u32 << 16;
+        let xid3 = self.packet()[6] as u32 << 8;
+        let xid4 = self.packet()[7] as u32;
+        xid1 | xid2 | xid3 | xid4
+    }
+
+    /// Get the number of seconds since the client began acquiring an address
+    fn get_secs(&self) -> u16 {
+        let secs1 = self.packet()[8] as u16 << 8;
+        let secs2 = self.packet()[9] as u16;
+        secs1 | secs2
+    }
+
+    /// Get the flags field
+    fn get_flags(&self) -> u16 {
+        let flags1 = self.packet()[10] as u16 << 8;
+        let flags2 = self.packet()[11] as u16;
+        flags1 | flags2
+    }
+
+    /// Should the server broadcast its replies?
+    fn is_broadcast(&self) -> bool {
+        self.get_flags() & 0x8000 != 0
+    }
+
+    /// Get the client's current IP address
+    fn get_ciaddr(&self) -> IpAddr {
+        ipv4_addr_at(self.packet(), 12)
+    }
+
+    /// Get the IP address offered to or assigned to the client
+    fn get_yiaddr(&self) -> IpAddr {
+        ipv4_addr_at(self.packet(), 16)
+    }
+
+    /// Get the IP address of the next server to use in bootstrap
+    fn get_siaddr(&self) -> IpAddr {
+        ipv4_addr_at(self.packet(), 20)
+    }
+
+    /// Get the IP address of the relay agent
+    fn get_giaddr(&self) -> IpAddr {
+        ipv4_addr_at(self.packet(), 24)
+    }
+
+    /// Get the client's hardware address. This assumes an Ethernet address; see `get_htype()`.
+    fn get_chaddr(&self) -> MacAddr {
+        MacAddr(
+            self.packet()[28],
+            self.packet()[29],
+            self.packet()[30],
+            self.packet()[31],
+            self.packet()[32],
+            self.packet()[33]
+        )
+    }
+
+    /// Get the server host name field, which is null terminated
+    fn get_sname<'a>(&'a self) -> &'a [u8] {
+        self.packet().slice(44, 108)
+    }
+
+    /// Get the boot file name field, which is null terminated
+    fn get_file<'a>(&'a self) -> &'a [u8] {
+        self.packet().slice(108, 236)
+    }
+
+    /// Does the message start its options with the DHCP magic cookie?
+    fn is_magic_cookie_valid(&self) -> bool {
+        let payload = self.payload();
+        if payload.len() < 4 {
+            return false;
+        }
+        let cookie = (payload[0] as u32 << 24) | (payload[1] as u32 << 16) |
+                     (payload[2] as u32 << 8) | payload[3] as u32;
+        cookie == DHCP_MAGIC_COOKIE
+    }
+
+    /// Returns an iterator over the options of the message. Pad options are skipped, and
+    /// iteration stops at the End option.
+    ///
+    /// No options are returned if the magic cookie is invalid, and iteration stops early if an
+    /// option is truncated.
+    fn options<'a>(&'a self) -> DhcpOptionIterator<'a> {
+        let options = self.packet().slice_from(DHCP_HEADER_LEN);
+        DhcpOptionIterator {
+            options: options,
+            offset: if self.is_magic_cookie_valid() { 4 } else { options.len() },
+        }
+    }
+
+    /// Get the type of the message from the DHCP Message Type option, if it is present
+    fn get_message_type(&self) -> Option<DhcpMessageType> {
+        for option in self.options() {
+            if option.code == DhcpOptionCodes::MessageType && option.data.len() == 1 {
+                return Some(DhcpMessageType(option.data[0]));
+            }
+        }
+        None
+    }
+}
+
+impl<'p> DhcpPacket for DhcpHeader<'p> {}
+impl<'p> DhcpPacket for MutableDhcpHeader<'p> {}
+
+impl<'p> DhcpHeader<'p> {
+    /// Construct a new DHCP header backed by the given buffer
+    pub fn new(packet: &'p [u8]) -> DhcpHeader<'p> {
+        DhcpHeader { packet: packet }
+    }
+
+    /// Construct a new DHCP header backed by the given buffer, checking that it is large enough
+    /// to contain a DHCP header. Returns None if it is not.
+    pub fn new_checked(packet: &'p [u8]) -> Option<DhcpHeader<'p>> {
+        if packet.len() < DHCP_HEADER_LEN {
+            None
+        } else {
+            Some(DhcpHeader::new(packet))
+        }
+    }
+}
+
+impl<'p> MutableDhcpHeader<'p> {
+    /// Construct a new mutable DHCP header backed by the given buffer
+    pub fn new(packet: &'p mut [u8]) -> MutableDhcpHeader<'p> {
+        MutableDhcpHeader { packet: packet }
+    }
+
+    /// Construct a new mutable DHCP header backed by the given buffer, checking that it is
+    /// large enough to contain a DHCP header. Returns None if it is not.
+    pub fn new_checked(packet: &'p mut [u8]) -> Option<MutableDhcpHeader<'p>> {
+        if packet.len() < DHCP_HEADER_LEN {
+            None
+        } else {
+            Some(MutableDhcpHeader::new(packet))
+        }
+    }
+
+    /// Set the operation of the message
+    pub fn set_op(&mut self, DhcpOperation(op): DhcpOperation) {
+        self.packet[0] = op;
+    }
+
+    /// Set the hardware address type
+    pub fn set_htype(&mut self, htype: u8) {
+        self.packet[1] = htype;
+    }
+
+    /// Set the hardware address length
+    pub fn set_hlen(&mut self, hlen: u8) {
+        self.packet[2] = hlen;
+    }
+
+    /// Set the number of relay agents the message has passed through
+    pub fn set_hops(&mut self, hops: u8) {
+        self.packet[3] = hops;
+    }
+
+    /// Set the transaction ID
+    pub fn set_xid(&mut self, xid: u32) {
+        self.packet[4] = (xid >> 24) as u8;
+        self.packet[5] = (xid >> 16) as u8;
+        self.packet[6] = (xid >> 8) as u8;
+        self.packet[7] = xid as u8;
+    }
+
+    /// Set the number of seconds since the client began acquiring an address
+    pub fn set_secs(&mut self, secs: u16) {
+        self.packet[8] = (secs >> 8) as u8;
+        self.packet[9] = (secs & 0xFF) as u8;
+    }
+
+    /// Set the flags field
+    pub fn set_flags(&mut self, flags: u16) {
+        self.packet[10] = (flags >> 8) as u8;
+        self.packet[11] = (flags & 0xFF) as u8;
+    }
+
+    /// Set the client's current IP address
+    pub fn set_ciaddr(&mut self, ip: IpAddr) {
+        set_ipv4_addr_at(self.packet, 12, ip);
+    }
+
+    /// Set the IP address offered to or assigned to the client
+    pub fn set_yiaddr(&mut self, ip: IpAddr) {
+        set_ipv4_addr_at(self.packet, 16, ip);
+    }
+
+    /// Set the IP address of the next server to use in bootstrap
+    pub fn set_siaddr(&mut self, ip: IpAddr) {
+        set_ipv4_addr_at(self.packet, 20, ip);
+    }
+
+    /// Set the IP address of the relay agent
+    pub fn set_giaddr(&mut self, ip: IpAddr) {
+        set_ipv4_addr_at(self.packet, 24, ip);
+    }
+
+    /// Set the client's hardware address
+    pub fn set_chaddr(&mut self, mac: MacAddr) {
+        match mac {
+            MacAddr(a, b, c, d, e, f) => {
+                self.packet[28] = a;
+                self.packet[29] = b;
+                self.packet[30] = c;
+                self.packet[31] = d;
+                self.packet[32] = e;
+                self.packet[33] = f;
+            }
+        }
+    }
+
+    /// Write the DHCP magic cookie at the start of the options
+    pub fn set_magic_cookie(&mut self) {
+        self.packet[236] = (DHCP_MAGIC_COOKIE >> 24) as u8;
+        self.packet[237] = (DHCP_MAGIC_COOKIE >> 16) as u8;
+        self.packet[238] = (DHCP_MAGIC_COOKIE >> 8) as u8;
+        self.packet[239] = DHCP_MAGIC_COOKIE as u8;
+    }
+}
+
+fn ipv4_addr_at(packet: &[u8], offset: uint) -> IpAddr {
+    Ipv4Addr(packet[offset], packet[offset + 1], packet[offset + 2], packet[offset + 3])
+}
+
+fn set_ipv4_addr_at(packet: &mut [u8], offset: uint, ip: IpAddr) {
+    match ip {
+        Ipv4Addr(a, b, c, d) => {
+            packet[offset] = a;
+            packet[offset + 1] = b;
+            packet[offset + 2] = c;
+            packet[offset + 3] = d;
+        },
+        _ => ()
+    }
+}
+
+/// A DHCP option
+#[deriving(Show, PartialEq, Eq)]
+pub struct DhcpOption<'p> {
+    /// The option code
+    pub code: DhcpOptionCode,
+    /// The option data, excluding the code and length
+    pub data: &'p [u8],
+}
+
+/// An iterator over the options of a DHCP message
+pub struct DhcpOptionIterator<'p> {
+    options: &'p [u8],
+    offset: uint,
+}
+
+impl<'p> Iterator<DhcpOption<'p>> for DhcpOptionIterator<'p> {
+    fn next(&mut self) -> Option<DhcpOption<'p>> {
+        loop {
+            if self.offset >= self.options.len() {
+                return None;
+            }
+            let code = DhcpOptionCode(self.options[self.offset]);
+            if code == DhcpOptionCodes::Pad {
+                self.offset += 1;
+                continue;
+            }
+            if code == DhcpOptionCodes::End || self.offset + 1 >= self.options.len() {
+                self.offset = self.options.len();
+                return None;
+            }
+            let start = self.offset + 2;
+            let end = start + self.options[self.offset + 1] as uint;
+            if end > self.options.len() {
+                self.offset = self.options.len();
+                return None;
+            }
+            self.offset = end;
+            return Some(DhcpOption { code: code, data: self.options.slice(start, end) });
+        }
+    }
+}
+
+#[cfg(test)]
+fn build_dhcp_packet(packet: &mut [u8], op: DhcpOperation, options: &[u8]) {
+    {
+        let mut dhcp_header = MutableDhcpHeader::new_checked(packet.as_mut_slice()).unwrap();
+        dhcp_header.set_op(op);
+        dhcp_header.set_htype(1);
+        dhcp_header.set_hlen(6);
+        dhcp_header.set_xid(0x3903f326);
+        dhcp_header.set_flags(0x8000);
+        dhcp_header.set_chaddr(MacAddr(0x00, 0x05, 0x3c, 0x04, 0x8d, 0x59));
+        dhcp_header.set_magic_cookie();
+    }
+    packet.slice_from_mut(DHCP_HEADER_LEN + 4).clone_from_slice(options);
+}
+
+#[test]
+fn dhcp_discover_test() {
+    let mut packet = [0u8, ..DHCP_HEADER_LEN + 16];
+    build_dhcp_packet(packet.as_mut_slice(),
+                      DhcpOperations::Request,
+                      &[53, 1, 1,             /* message type */
+                        0, 0,                 /* padding */
+                        55, 3, 1, 3, 6,       /* parameter request list */
+                        255                   /* end */]);
+    assert_eq!(packet.slice(DHCP_HEADER_LEN, DHCP_HEADER_LEN + 4),
+               [0x63u8, 0x82, 0x53, 0x63].as_slice());
+
+    let dhcp_header = DhcpHeader::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(dhcp_header.get_op(), DhcpOperations::Request);
+    assert_eq!(dhcp_header.get_htype(), 1);
+    assert_eq!(dhcp_header.get_hlen(), 6);
+    assert_eq!(dhcp_header.get_xid(), 0x3903f326);
+    assert!(dhcp_header.is_broadcast());
+    assert_eq!(dhcp_header.get_ciaddr(), Ipv4Addr(0, 0, 0, 0));
+    assert_eq!(dhcp_header.get_chaddr(), MacAddr(0x00, 0x05, 0x3c, 0x04, 0x8d, 0x59));
+    assert!(dhcp_header.is_magic_cookie_valid());
+    assert_eq!(dhcp_header.get_message_type(), Some(DhcpMessageTypes::Discover));
+
+    let options: Vec<DhcpOption> = dhcp_header.options().collect();
+    assert_eq!(options, vec![
+        DhcpOption { code: DhcpOptionCodes::MessageType, data: [1u8].as_slice() },
+        DhcpOption { code: DhcpOptionCodes::ParameterRequestList, data: [1u8, 3, 6].as_slice() }
+    ]);
+}
+
+#[test]
+fn dhcp_offer_test() {
+    let mut packet = [0u8, ..DHCP_HEADER_LEN + 20];
+    build_dhcp_packet(packet.as_mut_slice(),
+                      DhcpOperations::Reply,
+                      &[53, 1, 2,               /* message type */
+                        1, 4, 255, 255, 255, 0, /* subnet mask */
+                        54, 4, 192, 168, 0, 1,  /* server identifier */
+                        255                     /* end */]);
+    {
+        let mut dhcp_header = MutableDhcpHeader::new(packet.as_mut_slice());
+        dhcp_header.set_yiaddr(Ipv4Addr(192, 168, 0, 10));
+        dhcp_header.set_siaddr(Ipv4Addr(192, 168, 0, 1));
+    }
+
+    let dhcp_header = DhcpHeader::new(packet.as_slice());
+    assert_eq!(dhcp_header.get_op(), DhcpOperations::Reply);
+    assert_eq!(dhcp_header.get_yiaddr(), Ipv4Addr(192, 168, 0, 10));
+    assert_eq!(dhcp_header.get_siaddr(), Ipv4Addr(192, 168, 0, 1));
+    assert_eq!(dhcp_header.get_giaddr(), Ipv4Addr(0, 0, 0, 0));
+    assert_eq!(dhcp_header.get_message_type(), Some(DhcpMessageTypes::Offer));
+
+    let mut options = dhcp_header.options();
+    assert_eq!(options.next().unwrap().code, DhcpOptionCodes::MessageType);
+    let subnet_mask = options.next().unwrap();
+    assert_eq!(subnet_mask.code, DhcpOptionCodes::SubnetMask);
+    assert_eq!(subnet_mask.data, [255u8, 255, 255, 0].as_slice());
+    let server_identifier = options.next().unwrap();
+    assert_eq!(server_identifier.code, DhcpOptionCodes::ServerIdentifier);
+    assert_eq!(server_identifier.data, [192u8, 168, 0, 1].as_slice());
+    assert!(options.next().is_none());
+}
+
+#[test]
+fn dhcp_malformed_options_test() {
+    // Options are ignored without the magic cookie
+    let mut packet = [0u8, ..DHCP_HEADER_LEN + 8];
+    build_dhcp_packet(packet.as_mut_slice(), DhcpOperations::Request, &[53, 1, 1, 255]);
+    packet[DHCP_HEADER_LEN] = 0;
+    let dhcp_header = DhcpHeader::new(packet.as_slice());
+    assert!(!dhcp_header.is_magic_cookie_valid());
+    assert!(dhcp_header.options().next().is_none());
+    assert_eq!(dhcp_header.get_message_type(), None);
+
+    // An option which claims to be longer than the remaining data
+    let mut packet = [0u8, ..DHCP_HEADER_LEN + 8];
+    build_dhcp_packet(packet.as_mut_slice(), DhcpOperations::Request, &[53, 1, 1, 12]);
+    let dhcp_header = DhcpHeader::new(packet.as_slice());
+    assert_eq!(dhcp_header.options().count(), 1);
+
+    assert!(DhcpHeader::new_checked(packet.slice_to(DHCP_HEADER_LEN - 1)).is_none());
+}
+
+/// BOOTP operation codes
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod DhcpOperations {
+    use super::DhcpOperation;
+
+    /// BOOTREQUEST, sent by clients [RFC951]
+    pub const Request: DhcpOperation = DhcpOperation(1);
+
+    /// BOOTREPLY, sent by servers [RFC951]
+    pub const Reply: DhcpOperation   = DhcpOperation(2);
+}
+
+/// Represents the op field of a DHCP message
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DhcpOperation(pub u8);
+
+/// DHCP option codes, as defined at:
+/// http://www.iana.org/assignments/bootp-dhcp-parameters/bootp-dhcp-parameters.xhtml
+///
+/// FIXME Should include all
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod DhcpOptionCodes {
+    use super::DhcpOptionCode;
+
+    /// Pad [RFC2132]
+    pub const Pad: DhcpOptionCode                  = DhcpOptionCode(0);
+
+    /// Subnet Mask [RFC2132]
+    pub const SubnetMask: DhcpOptionCode           = DhcpOptionCode(1);
+
+    /// Router [RFC2132]
+    pub const Router: DhcpOptionCode               = DhcpOptionCode(3);
+
+    /// Domain Name Server [RFC2132]
+    pub const DomainNameServer: DhcpOptionCode     = DhcpOptionCode(6);
+
+    /// Host Name [RFC2132]
+    pub const HostName: DhcpOptionCode             = DhcpOptionCode(12);
+
+    /// Domain Name [RFC2132]
+    pub const DomainName: DhcpOptionCode           = DhcpOptionCode(15);
+
+    /// Requested IP Address [RFC2132]
+    pub const RequestedIpAddress: DhcpOptionCode   = DhcpOptionCode(50);
+
+    /// IP Address Lease Time [RFC2132]
+    pub const IpAddressLeaseTime: DhcpOptionCode   = DhcpOptionCode(51);
+
+    /// DHCP Message Type [RFC2132]
+    pub const MessageType: DhcpOptionCode          = DhcpOptionCode(53);
+
+    /// Server Identifier [RFC2132]
+    pub const ServerIdentifier: DhcpOptionCode     = DhcpOptionCode(54);
+
+    /// Parameter Request List [RFC2132]
+    pub const ParameterRequestList: DhcpOptionCode = DhcpOptionCode(55);
+
+    /// Client Identifier [RFC2132]
+    pub const ClientIdentifier: DhcpOptionCode     = DhcpOptionCode(61);
+
+    /// End [RFC2132]
+    pub const End: DhcpOptionCode                  = DhcpOptionCode(255);
+}
+
+/// Represents a DHCP option code
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DhcpOptionCode(pub u8);
+
+/// DHCP message types, carried in the DHCP Message Type option [RFC2132]
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod DhcpMessageTypes {
+    use super::DhcpMessageType;
+
+    /// DHCPDISCOVER
+    pub const Discover: DhcpMessageType = DhcpMessageType(1);
+
+    /// DHCPOFFER
+    pub const Offer: DhcpMessageType    = DhcpMessageType(2);
+
+    /// DHCPREQUEST
+    pub const Request: DhcpMessageType  = DhcpMessageType(3);
+
+    /// DHCPDECLINE
+    pub const Decline: DhcpMessageType  = DhcpMessageType(4);
+
+    /// DHCPACK
+    pub const Ack: DhcpMessageType      = DhcpMessageType(5);
+
+    /// DHCPNAK
+    pub const Nak: DhcpMessageType      = DhcpMessageType(6);
+
+    /// DHCPRELEASE
+    pub const Release: DhcpMessageType  = DhcpMessageType(7);
+
+    /// DHCPINFORM
+    pub const Inform: DhcpMessageType   = DhcpMessageType(8);
+}
+
+/// Represents the value of the DHCP Message Type option
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DhcpMessageType(pub u8);
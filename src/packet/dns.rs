@@ -0,0 +1,672 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! DNS message abstraction
+
+use std::fmt;
+
+use packet::{Packet, MutablePacket};
+
+/// The length of a DNS header, in bytes
+pub const DNS_HEADER_LEN: uint = 12;
+
+/// The maximum number of compression pointers which will be followed when reading a name. Names
+/// which need more than this are treated as malformed, which prevents pointer loops.
+const MAX_POINTER_JUMPS: uint = 64;
+
+/// Structure representing a DNS message
+pub struct DnsHeader<'p> {
+    packet: &'p [u8],
+}
+
+/// Structure representing a mutable DNS message
+pub struct MutableDnsHeader<'p> {
+    packet: &'p mut [u8],
+}
+
+// FIXME This should probably be a macro
+impl<'p> PartialEq for DnsHeader<'p> {
+    fn eq(&self, other: &DnsHeader) -> bool {
+        if self.packet.len() != other.packet.len() {
+            return false;
+        }
+        for (b1, b2) in self.packet.iter().zip(other.packet.iter()) {
+            if b1 != b2 {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+impl<'p> Eq for DnsHeader<'p> {}
+
+impl<'p> fmt::Show for DnsHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "DnsHeader {{ id: {}, flags: {}, questions: {}, answers: {}, authorities: {}, \
+                additionals: {} }}",
+                self.get_id(),
+                self.get_flags(),
+                self.get_question_count(),
+                self.get_answer_count(),
+                self.get_authority_count(),
+                self.get_additional_count()
+        )
+    }
+}
+
+impl<'p> fmt::Show for MutableDnsHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "MutableDnsHeader {{ id: {}, flags: {}, questions: {}, answers: {}, \
+                authorities: {}, additionals: {} }}",
+                self.get_id(),
+                self.get_flags(),
+                self.get_question_count(),
+                self.get_answer_count(),
+                self.get_authority_count(),
+                self.get_additional_count()
+        )
+    }
+}
+
+impl<'p> Packet for DnsHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(DNS_HEADER_LEN) }
+}
+
+impl<'p> Packet for MutableDnsHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet.as_slice() }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(DNS_HEADER_LEN) }
+}
+
+impl<'p> MutablePacket for MutableDnsHeader<'p> {
+    #[inline(always)]
+    fn packet_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.as_mut_slice() }
+
+    #[inline(always)]
+    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] {
+        self.packet.slice_from_mut(DNS_HEADER_LEN)
+    }
+}
+
+/// Trait implemented by anything which provides an interface to read DNS
+/// messages
+pub trait DnsPacket : Packet {
+    /// Get the identifier, which is used to match responses with queries
+    fn get_id(&self) -> u16 {
+        read_u16(self.packet(), 0).unwrap()
+    }
+
+    /// Get the flags field of the message
+    fn get_flags(&self) -> DnsFlags {
+        DnsFlags(read_u16(self.packet(), 2).unwrap())
+    }
+
+    /// Get the number of entries in the question section
+    fn get_question_count(&self) -> u16 {
+        read_u16(self.packet(), 4).unwrap()
+    }
+
+    /// Get the number of resource records in the answer section
+    fn get_answer_count(&self) -> u16 {
+        read_u16(self.packet(), 6).unwrap()
+    }
+
+    /// Get the number of resource records in the authority section
+    fn get_authority_count(&self) -> u16 {
+        read_u16(self.packet(), 8).unwrap()
+    }
+
+    /// Get the number of resource records in the additional section
+    fn get_additional_count(&self) -> u16 {
+        read_u16(self.packet(), 10).unwrap()
+    }
+
+    /// Returns an iterator over the question section.
+    ///
+    /// Iteration stops early if the message is truncated or malformed; this can be detected by
+    /// comparing the number of questions returned with `get_question_count()`.
+    fn questions<'a>(&'a self) -> DnsQuestionIterator<'a> {
+        DnsQuestionIterator {
+            packet: self.packet(),
+            offset: Some(DNS_HEADER_LEN),
+            remaining: self.get_question_count(),
+        }
+    }
+
+    /// Returns an iterator over the resource records in the answer section. Iteration stops
+    /// early if the message is truncated or malformed.
+    fn answers<'a>(&'a self) -> DnsRecordIterator<'a> {
+        let mut questions = self.questions();
+        while questions.next().is_some() {}
+        DnsRecordIterator {
+            packet: self.packet(),
+            offset: questions.end_offset(),
+            remaining: self.get_answer_count(),
+        }
+    }
+
+    /// Returns an iterator over the resource records in the authority section. Iteration stops
+    /// early if the message is truncated or malformed.
+    fn authorities<'a>(&'a self) -> DnsRecordIterator<'a> {
+        let mut answers = self.answers();
+        while answers.next().is_some() {}
+        DnsRecordIterator {
+            packet: self.packet(),
+            offset: answers.end_offset(),
+            remaining: self.get_authority_count(),
+        }
+    }
+
+    /// Returns an iterator over the resource records in the additional section. Iteration stops
+    /// early if the message is truncated or malformed.
+    fn additionals<'a>(&'a self) -> DnsRecordIterator<'a> {
+        let mut authorities = self.authorities();
+        while authorities.next().is_some() {}
+        DnsRecordIterator {
+            packet: self.packet(),
+            offset: authorities.end_offset(),
+            remaining: self.get_additional_count(),
+        }
+    }
+}
+
+impl<'p> DnsPacket for DnsHeader<'p> {}
+impl<'p> DnsPacket for MutableDnsHeader<'p> {}
+
+impl<'p> DnsHeader<'p> {
+    /// Construct a new DNS header backed by the given buffer
+    pub fn new(packet: &'p [u8]) -> DnsHeader<'p> {
+        DnsHeader { packet: packet }
+    }
+
+    /// Construct a new DNS header backed by the given buffer, checking that it is large enough
+    /// to contain a DNS header. Returns None if it is not.
+    pub fn new_checked(packet: &'p [u8]) -> Option<DnsHeader<'p>> {
+        if packet.len() < DNS_HEADER_LEN {
+            None
+        } else {
+            Some(DnsHeader::new(packet))
+        }
+    }
+}
+
+impl<'p> MutableDnsHeader<'p> {
+    /// Construct a new mutable DNS header backed by the given buffer
+    pub fn new(packet: &'p mut [u8]) -> MutableDnsHeader<'p> {
+        MutableDnsHeader { packet: packet }
+    }
+
+    /// Construct a new mutable DNS header backed by the given buffer, checking that it is large
+    /// enough to contain a DNS header. Returns None if it is not.
+    pub fn new_checked(packet: &'p mut [u8]) -> Option<MutableDnsHeader<'p>> {
+        if packet.len() < DNS_HEADER_LEN {
+            None
+        } else {
+            Some(MutableDnsHeader::new(packet))
+        }
+    }
+
+    /// Set the identifier
+    pub fn set_id(&mut self, id: u16) {
+        self.packet[0] = (id >> 8) as u8;
+        self.packet[1] = (id & 0xFF) as u8;
+    }
+
+    /// Set the flags field of the message
+    pub fn set_flags(&mut self, DnsFlags(flags): DnsFlags) {
+        self.packet[2] = (flags >> 8) as u8;
+        self.packet[3] = (flags & 0xFF) as u8;
+    }
+
+    /// Set the number of entries in the question section
+    pub fn set_question_count(&mut self, count: u16) {
+        self.packet[4] = (count >> 8) as u8;
+        self.packet[5] = (count & 0xFF) as u8;
+    }
+
+    /// Set the number of resource records in the answer section
+    pub fn set_answer_count(&mut self, count: u16) {
+        self.packet[6] = (count >> 8) as u8;
+        self.packet[7] = (count & 0xFF) as u8;
+    }
+
+    /// Set the number of resource records in the authority section
+    pub fn set_authority_count(&mut self, count: u16) {
+        self.packet[8] = (count >> 8) as u8;
+        self.packet[9] = (count & 0xFF) as u8;
+    }
+
+    /// Set the number of resource records in the additional section
+    pub fn set_additional_count(&mut self, count: u16) {
+        self.packet[10] = (count >> 8) as u8;
+        self.packet[11] = (count & 0xFF) as u8;
+    }
+}
+
+/// Represents the flags field of a DNS header
+#[deriving(Show, PartialEq, Eq)]
+pub struct DnsFlags(pub u16);
+
+impl DnsFlags {
+    /// Is the message a response (true) or a query (false)?
+    pub fn qr(&self) -> bool {
+        let DnsFlags(flags) = *self;
+        flags & 0x8000 != 0
+    }
+
+    /// Get the kind of query, 0 for a standard query
+    pub fn opcode(&self) -> u8 {
+        let DnsFlags(flags) = *self;
+        ((flags >> 11) & 0xF) as u8
+    }
+
+    /// Is the responding server an authority for the domain name in question?
+    pub fn aa(&self) -> bool {
+        let DnsFlags(flags) = *self;
+        flags & 0x0400 != 0
+    }
+
+    /// Was the message truncated?
+    pub fn tc(&self) -> bool {
+        let DnsFlags(flags) = *self;
+        flags & 0x0200 != 0
+    }
+
+    /// Is recursion desired?
+    pub fn rd(&self) -> bool {
+        let DnsFlags(flags) = *self;
+        flags & 0x0100 != 0
+    }
+
+    /// Is recursion available on the responding server?
+    pub fn ra(&self) -> bool {
+        let DnsFlags(flags) = *self;
+        flags & 0x0080 != 0
+    }
+
+    /// Get the response code, 0 for no error
+    pub fn rcode(&self) -> u8 {
+        let DnsFlags(flags) = *self;
+        (flags & 0xF) as u8
+    }
+}
+
+/// An entry in the question section of a DNS message
+#[deriving(Show, PartialEq, Eq)]
+pub struct DnsQuestion {
+    /// The domain name being queried, with compression pointers followed. Labels are separated
+    /// by '.', and there is no trailing '.'
+    pub name: String,
+    /// The type of the query
+    pub qtype: DnsType,
+    /// The class of the query
+    pub qclass: DnsClass,
+}
+
+/// A resource record from the answer, authority or additional section of a DNS message
+#[deriving(Show, PartialEq, Eq)]
+pub struct DnsResourceRecord<'p> {
+    /// The domain name the record belongs to, with compression pointers followed
+    pub name: String,
+    /// The type of the record
+    pub rtype: DnsType,
+    /// The class of the record
+    pub rclass: DnsClass,
+    /// The number of seconds the record may be cached for
+    pub ttl: u32,
+    /// The record data. Names within it may contain compression pointers, which can be read
+    /// relative to the whole message
+    pub data: &'p [u8],
+}
+
+/// An iterator over the question section of a DNS message
+pub struct DnsQuestionIterator<'p> {
+    packet: &'p [u8],
+    offset: Option<uint>,
+    remaining: u16,
+}
+
+impl<'p> DnsQuestionIterator<'p> {
+    /// The offset of the end of the section, or None if the section could not be read
+    fn end_offset(&self) -> Option<uint> {
+        if self.remaining == 0 { self.offset } else { None }
+    }
+}
+
+impl<'p> Iterator<DnsQuestion> for DnsQuestionIterator<'p> {
+    fn next(&mut self) -> Option<DnsQuestion> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let offset = match self.offset {
+            Some(offset) => offset,
+            None => return None
+        };
+        let question = match read_name(self.packet, offset) {
+            Some((name, end)) => {
+                match (read_u16(self.packet, end), read_u16(self.packet, end + 2)) {
+                    (Some(qtype), Some(qclass)) => {
+                        self.offset = Some(end + 4);
+                        Some(DnsQuestion {
+                            name: name,
+                            qtype: DnsType(qtype),
+                            qclass: DnsClass(qclass),
+                        })
+                    },
+                    _ => None
+                }
+            },
+            None => None
+        };
+        match question {
+            Some(_) => self.remaining -= 1,
+            None => self.offset = None
+        }
+        question
+    }
+}
+
+/// An iterator over the resource records in a section of a DNS message
+pub struct DnsRecordIterator<'p> {
+    packet: &'p [u8],
+    offset: Option<uint>,
+    remaining: u16,
+}
+
+impl<'p> DnsRecordIterator<'p> {
+    /// The offset of the end of the section, or None if the section could not be read
+    fn end_offset(&self) -> Option<uint> {
+        if self.remaining == 0 { self.offset } else { None }
+    }
+}
+
+impl<'p> Iterator<DnsResourceRecord<'p>> for DnsRecordIterator<'p> {
+    fn next(&mut self) -> Option<DnsResourceRecord<'p>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let offset = match self.offset {
+            Some(offset) => offset,
+            None => return None
+        };
+        let record = match read_name(self.packet, offset) {
+            Some((name, end)) => {
+                match (read_u16(self.packet, end),
+                       read_u16(self.packet, end + 2),
+                       read_u32(self.packet, end + 4),
+                       read_u16(self.packet, end + 8)) {
+                    (Some(rtype), Some(rclass), Some(ttl), Some(rdlength))
+                        if end + 10 + rdlength as uint <= self.packet.len() => {
+                        let data_end = end + 10 + rdlength as uint;
+                        self.offset = Some(data_end);
+                        Some(DnsResourceRecord {
+                            name: name,
+                            rtype: DnsType(rtype),
+                            rclass: DnsClass(rclass),
+                            ttl: ttl,
+                            data: self.packet.slice(end + 10, data_end),
+                        })
+                    },
+                    _ => None
+                }
+            },
+            None => None
+        };
+        match record {
+            Some(_) => self.remaining -= 1,
+            None => self.offset = None
+        }
+        record
+    }
+}
+
+/// Read a big endian u16 from the given offset, if there is room for one
+fn read_u16(packet: &[u8], offset: uint) -> Option<u16> {
+    if offset + 2 > packet.len() {
+        return None;
+    }
+    Some((packet[offset] as u16 << 8) | packet[offset + 1] as u16)
+}
+
+/// Read a big endian u32 from the given offset, if there is room for one
+fn read_u32(packet: &[u8], offset: uint) -> Option<u32> {
+    match (read_u16(packet, offset), read_u16(packet, offset + 2)) {
+        (Some(high), Some(low)) => Some((high as u32 << 16) | low as u32),
+        _ => None
+    }
+}
+
+/// Read the (possibly compressed) domain name starting at the given offset of a DNS message.
+///
+/// Returns the name, and the offset of the first byte after it at its original position, or
+/// None if the name is malformed.
+pub fn read_name(packet: &[u8], offset: uint) -> Option<(String, uint)> {
+    let mut name = Vec::new();
+    let mut pos = offset;
+    let mut end = None;
+    let mut jumps = 0u;
+    loop {
+        if pos >= packet.len() {
+            return None;
+        }
+        let len = packet[pos] as uint;
+        match len & 0xC0 {
+            // A compression pointer to elsewhere in the message
+            0xC0 => {
+                if pos + 1 >= packet.len() || jumps == MAX_POINTER_JUMPS {
+                    return None;
+                }
+                if end.is_none() {
+                    end = Some(pos + 2);
+                }
+                jumps += 1;
+                pos = ((len & 0x3F) << 8) | packet[pos + 1] as uint;
+            },
+            // The root label ends the name
+            0x00 if len == 0 => {
+                if end.is_none() {
+                    end = Some(pos + 1);
+                }
+                break;
+            },
+            0x00 => {
+                if pos + 1 + len > packet.len() {
+                    return None;
+                }
+                if !name.is_empty() {
+                    name.push('.' as u8);
+                }
+                name.push_all(packet.slice(pos + 1, pos + 1 + len));
+                pos += 1 + len;
+            },
+            // Extended and reserved label types are not supported
+            _ => return None
+        }
+    }
+    match String::from_utf8(name) {
+        Ok(name) => Some((name, end.unwrap())),
+        Err(_) => None
+    }
+}
+
+#[cfg(test)]
+static QUERY: [u8, ..29] = [0x12, 0x34, /* id */
+                            0x01, 0x00, /* flags */
+                            0x00, 0x01, /* question count */
+                            0x00, 0x00, /* answer count */
+                            0x00, 0x00, /* authority count */
+                            0x00, 0x00, /* additional count */
+                            0x07, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, /* example */
+                            0x03, 0x63, 0x6f, 0x6d, /* com */
+                            0x00,
+                            0x00, 0x01, /* qtype */
+                            0x00, 0x01  /* qclass */];
+
+#[test]
+fn dns_query_test() {
+    let dns_header = DnsHeader::new_checked(QUERY.as_slice()).unwrap();
+    assert_eq!(dns_header.get_id(), 0x1234);
+    let flags = dns_header.get_flags();
+    assert!(!flags.qr());
+    assert_eq!(flags.opcode(), 0);
+    assert!(flags.rd());
+    assert_eq!(flags.rcode(), 0);
+    assert_eq!(dns_header.get_question_count(), 1);
+    assert_eq!(dns_header.get_answer_count(), 0);
+
+    let questions: Vec<DnsQuestion> = dns_header.questions().collect();
+    assert_eq!(questions, vec![DnsQuestion {
+        name: "example.com".to_string(),
+        qtype: DnsTypes::A,
+        qclass: DnsClasses::In,
+    }]);
+    assert!(dns_header.answers().next().is_none());
+}
+
+#[test]
+fn dns_response_test() {
+    let mut packet = [0u8, ..45];
+    packet.slice_to_mut(29).clone_from_slice(QUERY.as_slice());
+    {
+        let mut dns_header = MutableDnsHeader::new_checked(packet.as_mut_slice()).unwrap();
+        dns_header.set_flags(DnsFlags(0x8180));
+        dns_header.set_answer_count(1);
+    }
+    packet.slice_from_mut(29).clone_from_slice(&[0xc0, 0x0c, /* name pointer */
+                                                0x00, 0x01, /* type */
+                                                0x00, 0x01, /* class */
+                                                0x00, 0x00, 0x0e, 0x10, /* ttl */
+                                                0x00, 0x04, /* data length */
+                                                0x5d, 0xb8, 0xd8, 0x22 /* data */]);
+
+    let dns_header = DnsHeader::new(packet.as_slice());
+    let flags = dns_header.get_flags();
+    assert!(flags.qr());
+    assert!(flags.rd());
+    assert!(flags.ra());
+    assert!(!flags.aa());
+    assert!(!flags.tc());
+    assert_eq!(flags.rcode(), 0);
+    assert_eq!(dns_header.questions().count(), 1);
+
+    let answers: Vec<DnsResourceRecord> = dns_header.answers().collect();
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0].name.as_slice(), "example.com");
+    assert_eq!(answers[0].rtype, DnsTypes::A);
+    assert_eq!(answers[0].rclass, DnsClasses::In);
+    assert_eq!(answers[0].ttl, 3600);
+    assert_eq!(answers[0].data, [93u8, 184, 216, 34].as_slice());
+    assert!(dns_header.authorities().next().is_none());
+    assert!(dns_header.additionals().next().is_none());
+}
+
+#[test]
+fn dns_name_compression_test() {
+    let mut packet = QUERY.as_slice().to_vec();
+    // www, followed by a pointer to example.com
+    packet.push_all(&[0x03, 0x77, 0x77, 0x77, 0xc0, 0x0c]);
+    assert_eq!(read_name(packet.as_slice(), 29), Some(("www.example.com".to_string(), 35)));
+
+    // Pointers which loop back on themselves
+    packet.push_all(&[0xc0, 0x23]);
+    assert_eq!(read_name(packet.as_slice(), 35), None);
+    packet.push_all(&[0x01, 0x61, 0xc0, 0x25]);
+    assert_eq!(read_name(packet.as_slice(), 37), None);
+
+    // Pointers past the end of the message
+    packet.push_all(&[0xc0, 0xff]);
+    assert_eq!(read_name(packet.as_slice(), 41), None);
+}
+
+#[test]
+fn dns_malformed_test() {
+    // The question count claims there are two questions, but there is only one
+    let mut packet = QUERY.as_slice().to_vec();
+    packet.as_mut_slice()[5] = 2;
+    let dns_header = DnsHeader::new(packet.as_slice());
+    assert_eq!(dns_header.questions().count(), 1);
+    assert!(dns_header.answers().next().is_none());
+
+    // Truncated in the middle of the question
+    let dns_header = DnsHeader::new(QUERY.slice_to(27));
+    assert_eq!(dns_header.questions().count(), 0);
+    assert!(DnsHeader::new_checked(QUERY.slice_to(11)).is_none());
+}
+
+/// DNS resource record types, as defined at:
+/// http://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml
+///
+/// FIXME Should include all
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod DnsTypes {
+    use super::DnsType;
+
+    /// A host address [RFC1035]
+    pub const A: DnsType     = DnsType(1);
+
+    /// An authoritative name server [RFC1035]
+    pub const Ns: DnsType    = DnsType(2);
+
+    /// The canonical name for an alias [RFC1035]
+    pub const Cname: DnsType = DnsType(5);
+
+    /// Marks the start of a zone of authority [RFC1035]
+    pub const Soa: DnsType   = DnsType(6);
+
+    /// A domain name pointer [RFC1035]
+    pub const Ptr: DnsType   = DnsType(12);
+
+    /// Mail exchange [RFC1035]
+    pub const Mx: DnsType    = DnsType(15);
+
+    /// Text strings [RFC1035]
+    pub const Txt: DnsType   = DnsType(16);
+
+    /// IPv6 address [RFC3596]
+    pub const Aaaa: DnsType  = DnsType(28);
+
+    /// Server selection [RFC2782]
+    pub const Srv: DnsType   = DnsType(33);
+
+    /// A request for all records [RFC1035]
+    pub const Any: DnsType   = DnsType(255);
+}
+
+/// DNS classes, as defined at:
+/// http://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod DnsClasses {
+    use super::DnsClass;
+
+    /// Internet [RFC1035]
+    pub const In: DnsClass = DnsClass(1);
+
+    /// Chaos [RFC1035]
+    pub const Ch: DnsClass = DnsClass(3);
+
+    /// Hesiod [RFC1035]
+    pub const Hs: DnsClass = DnsClass(4);
+}
+
+/// Represents the type of a DNS question or resource record
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DnsType(pub u16);
+
+/// Represents the class of a DNS question or resource record
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DnsClass(pub u16);
@@ -10,8 +10,14 @@
 
 use std::fmt;
 use packet::{Packet, MutablePacket};
+use packet::arp::ArpHeader;
+use packet::ipv4::Ipv4Header;
+use packet::ipv6::Ipv6Header;
 use util::MacAddr;
 
+/// The length of an Ethernet header, in bytes
+pub const ETHERNET_HEADER_LEN: uint = 14;
+
 /// A structure which represents an Ethernet header
 pub struct EthernetHeader<'p> {
     packet: &'p [u8],
@@ -119,6 +125,44 @@ pub trait EthernetPacket : Packet {
     fn get_ethertype(&self) -> EtherType {
         EtherType((self.packet()[12] as u16 << 8) | (self.packet()[13] as u16))
     }
+
+    /// Get the payload of an Ethernet packet, parsed according to its Ethertype. The inner
+    /// packet is constructed with `new_checked`, so a payload with an unknown Ethertype, or one
+    /// which is truncated or malformed, is returned as `Other`.
+    fn parsed_payload<'a>(&'a self) -> EtherPayload<'a> {
+        let payload = self.payload();
+        let ethertype = self.get_ethertype();
+        let parsed = if ethertype == EtherTypes::Ipv4 {
+            Ipv4Header::new_checked(payload).map(|header| Ipv4(header))
+        } else if ethertype == EtherTypes::Ipv6 {
+            Ipv6Header::new_checked(payload).map(|header| Ipv6(header))
+        } else if ethertype == EtherTypes::Arp {
+            ArpHeader::new_checked(payload).map(|header| Arp(header))
+        } else if ethertype == EtherTypes::Vlan {
+            VlanHeader::new_checked(payload).map(|header| Vlan(header))
+        } else {
+            None
+        };
+        match parsed {
+            Some(parsed) => parsed,
+            None => Other(payload)
+        }
+    }
+}
+
+/// The payload of an Ethernet packet, as returned by `EthernetPacket::parsed_payload()`
+#[deriving(Show)]
+pub enum EtherPayload<'p> {
+    /// An IPv4 packet
+    Ipv4(Ipv4Header<'p>),
+    /// An IPv6 packet
+    Ipv6(Ipv6Header<'p>),
+    /// An ARP packet
+    Arp(ArpHeader<'p>),
+    /// An 802.1Q VLAN tag, followed by the encapsulated payload
+    Vlan(VlanHeader<'p>),
+    /// A payload with an unknown Ethertype, or which could not be parsed
+    Other(&'p [u8]),
 }
 
 impl<'p> EthernetPacket for EthernetHeader<'p> {}
@@ -130,6 +174,16 @@ impl<'p> EthernetHeader<'p> {
     pub fn new(packet: &'p [u8]) -> EthernetHeader<'p> {
         EthernetHeader { packet: packet }
     }
+
+    /// Construct a new Ethernet header backed by the given buffer, checking that it is large
+    /// enough to contain an Ethernet header. Returns None if it is not.
+    pub fn new_checked(packet: &'p [u8]) -> Option<EthernetHeader<'p>> {
+        if packet.len() < ETHERNET_HEADER_LEN {
+            None
+        } else {
+            Some(EthernetHeader::new(packet))
+        }
+    }
 }
 
 impl<'p> MutableEthernetHeader<'p> {
@@ -139,6 +193,16 @@ impl<'p> MutableEthernetHeader<'p> {
         MutableEthernetHeader { packet: packet }
     }
 
+    /// Construct a new mutable Ethernet header backed by the given buffer, checking that it is
+    /// large enough to contain an Ethernet header. Returns None if it is not.
+    pub fn new_checked(packet: &'p mut [u8]) -> Option<MutableEthernetHeader<'p>> {
+        if packet.len() < ETHERNET_HEADER_LEN {
+            None
+        } else {
+            Some(MutableEthernetHeader::new(packet))
+        }
+    }
+
     /// Set the source address for an Ethernet packet
     pub fn set_source(&mut self, mac: MacAddr) {
         match mac {
@@ -174,6 +238,86 @@ impl<'p> MutableEthernetHeader<'p> {
     }
 }
 
+/// An Ethernet packet which owns its buffer, for building frames without managing the lifetime
+/// of a separate buffer
+pub struct OwnedEthernetHeader {
+    packet: Vec<u8>,
+}
+
+impl fmt::Show for OwnedEthernetHeader {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "OwnedEthernetHeader {{ destination: {}, source: {}, ethertype: {} }}",
+               self.get_destination(),
+               self.get_source(),
+               self.get_ethertype())
+    }
+}
+
+impl Packet for OwnedEthernetHeader {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet.as_slice() }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(14) }
+}
+
+impl MutablePacket for OwnedEthernetHeader {
+    #[inline(always)]
+    fn packet_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.as_mut_slice() }
+
+    #[inline(always)]
+    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.slice_from_mut(14) }
+}
+
+impl EthernetPacket for OwnedEthernetHeader {}
+
+impl OwnedEthernetHeader {
+    /// Allocate a zeroed Ethernet packet with room for a payload of the given length
+    pub fn new(payload_len: uint) -> OwnedEthernetHeader {
+        OwnedEthernetHeader { packet: Vec::from_elem(ETHERNET_HEADER_LEN + payload_len, 0u8) }
+    }
+
+    /// Borrow the packet as an EthernetHeader
+    pub fn to_immutable<'p>(&'p self) -> EthernetHeader<'p> {
+        EthernetHeader::new(self.packet.as_slice())
+    }
+
+    /// Borrow the packet as a MutableEthernetHeader, so its fields can be set
+    pub fn to_mutable<'p>(&'p mut self) -> MutableEthernetHeader<'p> {
+        MutableEthernetHeader::new(self.packet.as_mut_slice())
+    }
+
+    /// Consume the packet, returning its buffer
+    pub fn into_vec(self) -> Vec<u8> {
+        self.packet
+    }
+}
+
+#[test]
+fn owned_ethernet_header_test() {
+    let mut owned = OwnedEthernetHeader::new(4);
+    assert_eq!(owned.packet().len(), ETHERNET_HEADER_LEN + 4);
+    {
+        let mut ethernet_header = owned.to_mutable();
+        ethernet_header.set_destination(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55));
+        ethernet_header.set_source(MacAddr(0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb));
+        ethernet_header.set_ethertype(EtherTypes::Ipv4);
+    }
+    owned.payload_mut().clone_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+    assert_eq!(owned.get_destination(), MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55));
+    assert_eq!(owned.get_source(), MacAddr(0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb));
+    assert_eq!(owned.get_ethertype(), EtherTypes::Ipv4);
+    assert_eq!(owned.to_immutable().payload(), [0xdeu8, 0xad, 0xbe, 0xef].as_slice());
+
+    let ref_packet = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, /* destination */
+                      0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, /* source */
+                      0x08, 0x00, /* ethertype */
+                      0xde, 0xad, 0xbe, 0xef /* payload */];
+    assert_eq!(owned.into_vec().as_slice(), ref_packet.as_slice());
+}
+
 #[test]
 fn ethernet_header_test() {
     let mut packet = [0u8, ..14];
@@ -198,6 +342,345 @@ fn ethernet_header_test() {
     assert_eq!(ref_packet.as_slice(), packet.as_slice());
 }
 
+#[test]
+fn ethernet_header_parse() {
+    let packet = [0xde, 0xf0, 0x12, 0x34, 0x45, 0x67, /* destination */
+                  0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, /* source */
+                  0x08, 0x00, /* ethertype */
+                  0xaa, 0xbb /* payload */];
+    let ethernet_header = EthernetHeader::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(ethernet_header.get_destination(), MacAddr(0xde, 0xf0, 0x12, 0x34, 0x45, 0x67));
+    assert_eq!(ethernet_header.get_source(), MacAddr(0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc));
+    assert_eq!(ethernet_header.get_ethertype(), EtherTypes::Ipv4);
+    assert_eq!(ethernet_header.payload(), [0xaa, 0xbb].as_slice());
+
+    // A header with no payload is fine, but anything shorter isn't
+    assert!(EthernetHeader::new_checked(packet.slice_to(14)).is_some());
+    assert!(EthernetHeader::new_checked(packet.slice_to(13)).is_none());
+    assert!(EthernetHeader::new_checked(&[]).is_none());
+}
+
+#[test]
+fn ethernet_header_round_trip() {
+    let mut packet = [0u8, ..18];
+    {
+        let mut ethernet_header = MutableEthernetHeader::new_checked(packet.as_mut_slice())
+                                                        .unwrap();
+        ethernet_header.set_destination(MacAddr(0xff, 0xff, 0xff, 0xff, 0xff, 0xff));
+        ethernet_header.set_source(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55));
+        ethernet_header.set_ethertype(EtherTypes::Arp);
+        let payload = ethernet_header.payload_mut();
+        assert_eq!(payload.len(), 4);
+        payload[0] = 0xde;
+        payload[3] = 0xef;
+    }
+
+    assert_eq!(packet.slice(12, 14), [0x08, 0x06].as_slice());
+
+    let ethernet_header = EthernetHeader::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(ethernet_header.get_destination(), MacAddr(0xff, 0xff, 0xff, 0xff, 0xff, 0xff));
+    assert_eq!(ethernet_header.get_source(), MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55));
+    assert_eq!(ethernet_header.get_ethertype(), EtherTypes::Arp);
+    assert_eq!(ethernet_header.payload(), [0xde, 0x00, 0x00, 0xef].as_slice());
+
+    let mut short = [0u8, ..13];
+    assert!(MutableEthernetHeader::new_checked(short.as_mut_slice()).is_none());
+}
+
+#[cfg(test)]
+fn ethernet_frame(ethertype: EtherType, payload: &[u8]) -> OwnedEthernetHeader {
+    let mut owned = OwnedEthernetHeader::new(payload.len());
+    owned.to_mutable().set_ethertype(ethertype);
+    owned.payload_mut().clone_from_slice(payload);
+    owned
+}
+
+#[test]
+fn ethernet_parsed_payload() {
+    use packet::arp::ArpPacket;
+    use packet::ipv4::Ipv4Packet;
+    use packet::ipv6::Ipv6Packet;
+
+    let mut ipv4 = [0u8, ..20];
+    ipv4[0] = 0x45; /* version 4, header length 5 */
+    ipv4[3] = 20; /* total length */
+    let frame = ethernet_frame(EtherTypes::Ipv4, ipv4.as_slice());
+    match frame.parsed_payload() {
+        Ipv4(header) => assert_eq!(header.get_total_length(), 20),
+        other => fail!("ethernet_parsed_payload: expected IPv4, got {}", other)
+    }
+
+    let mut ipv6 = [0u8, ..42];
+    ipv6[0] = 0x60; /* version 6 */
+    ipv6[5] = 2; /* payload length */
+    let frame = ethernet_frame(EtherTypes::Ipv6, ipv6.as_slice());
+    match frame.parsed_payload() {
+        Ipv6(header) => assert_eq!(header.get_payload_length(), 2),
+        other => fail!("ethernet_parsed_payload: expected IPv6, got {}", other)
+    }
+
+    let mut arp = [0u8, ..28];
+    arp[4] = 6; /* hardware address length */
+    arp[5] = 4; /* protocol address length */
+    let frame = ethernet_frame(EtherTypes::Arp, arp.as_slice());
+    match frame.parsed_payload() {
+        Arp(header) => assert_eq!(header.get_hw_addr_len(), 6),
+        other => fail!("ethernet_parsed_payload: expected ARP, got {}", other)
+    }
+
+    let vlan = [0x00, 0x64, /* vid 100 */
+                0x08, 0x00 /* inner ethertype */];
+    let frame = ethernet_frame(EtherTypes::Vlan, vlan.as_slice());
+    match frame.parsed_payload() {
+        Vlan(header) => {
+            assert_eq!(header.get_vlan_identifier(), 100);
+            assert_eq!(header.get_ethertype(), EtherTypes::Ipv4);
+        },
+        other => fail!("ethernet_parsed_payload: expected VLAN, got {}", other)
+    }
+
+    let frame = ethernet_frame(EtherType(0x1234), [0xaa, 0xbb].as_slice());
+    match frame.parsed_payload() {
+        Other(payload) => assert_eq!(payload, [0xaa, 0xbb].as_slice()),
+        other => fail!("ethernet_parsed_payload: expected other, got {}", other)
+    }
+
+    // Truncated or malformed payloads fall back to Other rather than failing
+    ipv4[3] = 40;
+    let truncated = [(EtherTypes::Ipv4, ipv4.as_slice()),
+                     (EtherTypes::Ipv6, ipv6.slice_to(41)),
+                     (EtherTypes::Arp, arp.slice_to(27)),
+                     (EtherTypes::Vlan, vlan.slice_to(3))];
+    for &(ethertype, payload) in truncated.iter() {
+        let frame = ethernet_frame(ethertype, payload);
+        match frame.parsed_payload() {
+            Other(other) => assert_eq!(other, payload),
+            parsed => fail!("ethernet_parsed_payload: expected other, got {}", parsed)
+        }
+    }
+}
+
+/// The length of an 802.1Q VLAN tag, in bytes
+pub const VLAN_HEADER_LEN: uint = 4;
+
+/// A structure which represents an 802.1Q VLAN tag. This is the payload of an Ethernet frame
+/// with an ethertype of EtherTypes::Vlan, and is followed by the encapsulated payload.
+pub struct VlanHeader<'p> {
+    packet: &'p [u8],
+}
+
+/// A structure representing an 802.1Q VLAN tag which can be mutated
+pub struct MutableVlanHeader<'p> {
+    packet: &'p mut [u8],
+}
+
+impl<'p> PartialEq for VlanHeader<'p> {
+    fn eq(&self, other: &VlanHeader) -> bool {
+        if self.packet.len() != other.packet.len() {
+            return false;
+        }
+        for (b1, b2) in self.packet.iter().zip(other.packet.iter()) {
+            if b1 != b2 {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+impl<'p> Eq for VlanHeader<'p> {}
+
+impl<'p> fmt::Show for VlanHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "VlanHeader {{ priority: {}, drop eligible: {}, vlan identifier: {}, \
+                              ethertype: {} }}",
+               self.get_priority(),
+               self.get_drop_eligible_indicator(),
+               self.get_vlan_identifier(),
+               self.get_ethertype())
+    }
+}
+
+impl<'p> fmt::Show for MutableVlanHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "MutableVlanHeader {{ priority: {}, drop eligible: {}, vlan identifier: {}, \
+                                     ethertype: {} }}",
+               self.get_priority(),
+               self.get_drop_eligible_indicator(),
+               self.get_vlan_identifier(),
+               self.get_ethertype())
+    }
+}
+
+impl<'p> Packet for VlanHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(VLAN_HEADER_LEN) }
+}
+
+impl<'p> Packet for MutableVlanHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet.as_slice() }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(VLAN_HEADER_LEN) }
+}
+
+impl<'p> MutablePacket for MutableVlanHeader<'p> {
+    #[inline(always)]
+    fn packet_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.as_mut_slice() }
+
+    #[inline(always)]
+    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] {
+        self.packet.slice_from_mut(VLAN_HEADER_LEN)
+    }
+}
+
+/// A trait implemented by anything which provides the ability to retrieve
+/// fields of an 802.1Q VLAN tag
+pub trait VlanPacket : Packet {
+    /// Get the priority code point (PCP) of the tag. This is the top three bits of the tag
+    /// control information.
+    fn get_priority(&self) -> u8 {
+        self.packet()[0] >> 5
+    }
+
+    /// Get the drop eligible indicator (DEI) of the tag
+    fn get_drop_eligible_indicator(&self) -> bool {
+        self.packet()[0] & 0x10 != 0
+    }
+
+    /// Get the VLAN identifier (VID) of the tag. This is the bottom 12 bits of the tag control
+    /// information.
+    fn get_vlan_identifier(&self) -> u16 {
+        ((self.packet()[0] & 0x0F) as u16 << 8) | (self.packet()[1] as u16)
+    }
+
+    /// Get the Ethertype of the encapsulated payload
+    fn get_ethertype(&self) -> EtherType {
+        EtherType((self.packet()[2] as u16 << 8) | (self.packet()[3] as u16))
+    }
+}
+
+impl<'p> VlanPacket for VlanHeader<'p> {}
+impl<'p> VlanPacket for MutableVlanHeader<'p> {}
+
+impl<'p> VlanHeader<'p> {
+    /// Construct a new VLAN tag backed by the given buffer
+    pub fn new(packet: &'p [u8]) -> VlanHeader<'p> {
+        VlanHeader { packet: packet }
+    }
+
+    /// Construct a new VLAN tag backed by the given buffer, checking that it is large enough
+    /// to contain the tag. Returns None if it is not.
+    pub fn new_checked(packet: &'p [u8]) -> Option<VlanHeader<'p>> {
+        if packet.len() < VLAN_HEADER_LEN {
+            None
+        } else {
+            Some(VlanHeader::new(packet))
+        }
+    }
+}
+
+impl<'p> MutableVlanHeader<'p> {
+    /// Construct a new mutable VLAN tag backed by the given buffer
+    pub fn new(packet: &'p mut [u8]) -> MutableVlanHeader<'p> {
+        MutableVlanHeader { packet: packet }
+    }
+
+    /// Construct a new mutable VLAN tag backed by the given buffer, checking that it is large
+    /// enough to contain the tag. Returns None if it is not.
+    pub fn new_checked(packet: &'p mut [u8]) -> Option<MutableVlanHeader<'p>> {
+        if packet.len() < VLAN_HEADER_LEN {
+            None
+        } else {
+            Some(MutableVlanHeader::new(packet))
+        }
+    }
+
+    /// Set the priority code point (PCP) of the tag. Only the bottom three bits are used.
+    pub fn set_priority(&mut self, priority: u8) {
+        self.packet[0] = (self.packet[0] & 0x1F) | ((priority & 0x07) << 5);
+    }
+
+    /// Set the drop eligible indicator (DEI) of the tag
+    pub fn set_drop_eligible_indicator(&mut self, dei: bool) {
+        self.packet[0] = if dei {
+            self.packet[0] | 0x10
+        } else {
+            self.packet[0] & !0x10
+        };
+    }
+
+    /// Set the VLAN identifier (VID) of the tag. Only the bottom 12 bits are used.
+    pub fn set_vlan_identifier(&mut self, vid: u16) {
+        self.packet[0] = (self.packet[0] & 0xF0) | ((vid >> 8) & 0x0F) as u8;
+        self.packet[1] = (vid & 0xFF) as u8;
+    }
+
+    /// Set the Ethertype of the encapsulated payload
+    pub fn set_ethertype(&mut self, EtherType(ethertype): EtherType) {
+        self.packet[2] = (ethertype >> 8) as u8;
+        self.packet[3] = (ethertype & 0xFF) as u8;
+    }
+}
+
+#[test]
+fn vlan_header_parse() {
+    let packet = [0xde, 0xf0, 0x12, 0x34, 0x45, 0x67, /* destination */
+                  0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, /* source */
+                  0x81, 0x00, /* ethertype */
+                  0x70, 0x64, /* priority 3, dei, vid 100 */
+                  0x86, 0xdd, /* inner ethertype */
+                  0xaa, 0xbb /* payload */];
+    let ethernet_header = EthernetHeader::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(ethernet_header.get_ethertype(), EtherTypes::Vlan);
+
+    let vlan_header = VlanHeader::new_checked(ethernet_header.payload()).unwrap();
+    assert_eq!(vlan_header.get_priority(), 3);
+    assert!(vlan_header.get_drop_eligible_indicator());
+    assert_eq!(vlan_header.get_vlan_identifier(), 100);
+    assert_eq!(vlan_header.get_ethertype(), EtherTypes::Ipv6);
+    assert_eq!(vlan_header.payload(), [0xaa, 0xbb].as_slice());
+
+    assert!(VlanHeader::new_checked(packet.slice(14, 17)).is_none());
+}
+
+#[test]
+fn vlan_header_test() {
+    let mut packet = [0u8, ..4];
+    {
+        let mut vlan_header = MutableVlanHeader::new(packet.as_mut_slice());
+
+        vlan_header.set_priority(3);
+        assert_eq!(vlan_header.get_priority(), 3);
+
+        vlan_header.set_vlan_identifier(0xfff);
+        assert_eq!(vlan_header.get_vlan_identifier(), 0xfff);
+        vlan_header.set_vlan_identifier(100);
+        assert_eq!(vlan_header.get_vlan_identifier(), 100);
+
+        vlan_header.set_drop_eligible_indicator(true);
+        assert!(vlan_header.get_drop_eligible_indicator());
+        vlan_header.set_drop_eligible_indicator(false);
+        assert!(!vlan_header.get_drop_eligible_indicator());
+
+        // Setting the fields mustn't disturb each other
+        assert_eq!(vlan_header.get_priority(), 3);
+        assert_eq!(vlan_header.get_vlan_identifier(), 100);
+
+        vlan_header.set_ethertype(EtherTypes::Ipv4);
+        assert_eq!(vlan_header.get_ethertype(), EtherTypes::Ipv4);
+    }
+
+    let ref_packet = [0x60, 0x64, /* tci */
+                      0x08, 0x00 /* ethertype */];
+    assert_eq!(ref_packet.as_slice(), packet.as_slice());
+}
+
 /// EtherTypes defined at:
 /// http://www.iana.org/assignments/ieee-802-numbers/ieee-802-numbers.xhtml
 /// These values should be used in the Ethernet EtherType field
@@ -213,10 +696,29 @@ pub mod EtherTypes {
     pub const Arp: EtherType       = EtherType(0x0806);
     pub const WakeOnLan: EtherType = EtherType(0x0842);
     pub const Rarp: EtherType      = EtherType(0x8035);
+    pub const Vlan: EtherType      = EtherType(0x8100);
     pub const Ipv6: EtherType      = EtherType(0x86DD);
+    pub const Lldp: EtherType      = EtherType(0x88CC);
 }
 
 /// Represents the Ethernet ethertype field.
 #[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
 pub struct EtherType(pub u16);
 
+#[test]
+fn ether_types() {
+    let types = [(EtherTypes::Ipv4, 0x0800u16),
+                 (EtherTypes::Arp, 0x0806),
+                 (EtherTypes::Ipv6, 0x86DD),
+                 (EtherTypes::Vlan, 0x8100),
+                 (EtherTypes::Lldp, 0x88CC)];
+    for &(ethertype, value) in types.iter() {
+        let EtherType(v) = ethertype;
+        assert_eq!(v, value);
+        assert_eq!(EtherType(value), ethertype);
+    }
+
+    // Unknown values are still representable
+    let EtherType(unknown) = EtherType(0x1234);
+    assert_eq!(unknown, 0x1234);
+}
@@ -0,0 +1,244 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Extracting flow keys from packets, for building connection tables
+
+use std::io::net::ip::IpAddr;
+
+use packet::ethernet::{EthernetPacket, Ipv4, Ipv6};
+use packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use packet::ipv4::{Ipv4Packet, Ipv4Flags};
+use packet::ipv6::{Ipv6Packet, is_fragment, is_later_fragment};
+use packet::tcp::{TcpHeader, TcpPacket};
+use packet::udp::{UdpHeader, UdpPacket};
+use util;
+
+/// The 5-tuple identifying the flow a packet belongs to. For protocols without ports, such as
+/// ICMP, the ports are zero.
+#[deriving(Show, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    /// The source IP address
+    pub source: IpAddr,
+    /// The destination IP address
+    pub destination: IpAddr,
+    /// The source port, or zero
+    pub source_port: u16,
+    /// The destination port, or zero
+    pub destination_port: u16,
+    /// The transport protocol
+    pub protocol: IpNextHeaderProtocol,
+}
+
+impl FlowKey {
+    /// Get the key with its endpoints in a canonical order, so that packets travelling in either
+    /// direction of a connection have the same canonical key. The endpoint with the lower
+    /// address, or the lower port if the addresses are equal, becomes the source.
+    pub fn canonical(&self) -> FlowKey {
+        let source = (address_key(self.source), self.source_port);
+        let destination = (address_key(self.destination), self.destination_port);
+        if destination < source {
+            FlowKey {
+                source: self.destination,
+                destination: self.source,
+                source_port: self.destination_port,
+                destination_port: self.source_port,
+                protocol: self.protocol,
+            }
+        } else {
+            *self
+        }
+    }
+}
+
+// Order IPv4 addresses before IPv6 addresses, and each family numerically
+fn address_key(ip: IpAddr) -> (u64, u64) {
+    match util::ipv4_to_u32(ip) {
+        Some(v) => (0, v as u64),
+        None => util::ipv6_to_u64s(ip).unwrap()
+    }
+}
+
+/// Get the flow key for an Ethernet frame carrying an IPv4 or IPv6 packet. Returns None for
+/// anything else, such as ARP, or if the IP packet or its TCP or UDP header is truncated.
+///
+/// IPv6 extension headers are skipped as for `Ipv6Packet::get_upper_layer()`. IPv4 and IPv6
+/// fragments other than the first carry no transport header, so their ports are zero. In the
+/// first fragment only the ports are required to be present.
+pub fn flow_key<T: EthernetPacket>(eth: &T) -> Option<FlowKey> {
+    let payload = eth.parsed_payload();
+    let (source, destination, protocol, transport, fragment) = match payload {
+        Ipv4(ref ip) => {
+            // Fragments other than the first carry no transport header
+            let offset = ip.get_fragment_offset();
+            let transport = if offset == 0 { Some(ip.payload()) } else { None };
+            let fragment = offset != 0 || ip.get_flags() & Ipv4Flags::MoreFragments != 0;
+            (ip.get_source(), ip.get_destination(), ip.get_next_level_protocol(), transport,
+             fragment)
+        },
+        Ipv6(ref ip) => {
+            let (protocol, transport) = ip.get_upper_layer();
+            let transport = if is_later_fragment(ip) { None } else { Some(transport) };
+            (ip.get_source(), ip.get_destination(), protocol, transport, is_fragment(ip))
+        },
+        _ => return None
+    };
+
+    let (source_port, destination_port) = match transport {
+        // The lengths in the transport header of a first fragment describe the whole datagram
+        // rather than the fragment, so only check that the ports are there
+        Some(transport) if fragment && (protocol == IpNextHeaderProtocols::Tcp ||
+                                        protocol == IpNextHeaderProtocols::Udp) => {
+            if transport.len() < 4 {
+                return None;
+            }
+            ((transport[0] as u16 << 8) | transport[1] as u16,
+             (transport[2] as u16 << 8) | transport[3] as u16)
+        },
+        Some(transport) if protocol == IpNextHeaderProtocols::Tcp => {
+            match TcpHeader::new_checked(transport) {
+                Some(tcp) => (tcp.get_source(), tcp.get_destination()),
+                None => return None
+            }
+        },
+        Some(transport) if protocol == IpNextHeaderProtocols::Udp => {
+            match UdpHeader::new_checked(transport) {
+                Some(udp) => (udp.get_source(), udp.get_destination()),
+                None => return None
+            }
+        },
+        _ => (0, 0)
+    };
+
+    Some(FlowKey {
+        source: source,
+        destination: destination,
+        source_port: source_port,
+        destination_port: destination_port,
+        protocol: protocol,
+    })
+}
+
+#[test]
+fn flow_key_tcp() {
+    use std::io::net::ip::Ipv4Addr;
+    use packet::ethernet::EthernetHeader;
+
+    let mut packet = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, /* destination */
+                      0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, /* source */
+                      0x08, 0x00, /* ethertype */
+                      0x45, 0x00, 0x00, 0x28, 0x00, 0x00, 0x40, 0x00, /* ipv4 header */
+                      0x40, 0x06, 0x00, 0x00, 0xc0, 0xa8, 0x00, 0x02,
+                      0xc0, 0xa8, 0x00, 0x01,
+                      0xd4, 0x31, 0x00, 0x50, /* source and destination ports */
+                      0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x04, /* tcp header */
+                      0x50, 0x02, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00];
+    let key = flow_key(&EthernetHeader::new(packet.as_slice())).unwrap();
+    assert_eq!(key, FlowKey {
+        source: Ipv4Addr(192, 168, 0, 2),
+        destination: Ipv4Addr(192, 168, 0, 1),
+        source_port: 54321,
+        destination_port: 80,
+        protocol: IpNextHeaderProtocols::Tcp,
+    });
+
+    // Both directions have the same canonical key
+    let reply = FlowKey {
+        source: key.destination,
+        destination: key.source,
+        source_port: key.destination_port,
+        destination_port: key.source_port,
+        protocol: key.protocol,
+    };
+    assert!(reply != key);
+    assert_eq!(reply.canonical(), key.canonical());
+    assert_eq!(key.canonical().source, Ipv4Addr(192, 168, 0, 1));
+    assert_eq!(key.canonical().canonical(), key.canonical());
+
+    // A truncated packet has no key
+    assert!(flow_key(&EthernetHeader::new(packet.slice_to(44))).is_none());
+
+    // Protocols without ports have zero ports
+    packet[23] = 0x01; /* icmp */
+    let key = flow_key(&EthernetHeader::new(packet.as_slice())).unwrap();
+    assert_eq!((key.source_port, key.destination_port), (0, 0));
+    assert_eq!(key.protocol, IpNextHeaderProtocols::Icmp);
+
+    // As do IPv4 fragments other than the first
+    packet[23] = 0x06;
+    packet[21] = 0x10; /* fragment offset */
+    let key = flow_key(&EthernetHeader::new(packet.as_slice())).unwrap();
+    assert_eq!((key.source_port, key.destination_port), (0, 0));
+    assert_eq!(key.protocol, IpNextHeaderProtocols::Tcp);
+
+    // Non-IP frames have no key
+    packet[12] = 0x08;
+    packet[13] = 0x06; /* arp */
+    assert!(flow_key(&EthernetHeader::new(packet.as_slice())).is_none());
+}
+
+#[test]
+fn flow_key_udp() {
+    use std::io::net::ip::Ipv6Addr;
+    use packet::ethernet::EthernetHeader;
+
+    let packet = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, /* destination */
+                  0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, /* source */
+                  0x86, 0xdd, /* ethertype */
+                  0x60, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x11, 0x40, /* ipv6 header */
+                  0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, /* source */
+                  0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+                  0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, /* destination */
+                  0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+                  0x30, 0x39, 0x00, 0x35, 0x00, 0x0c, 0x00, 0x00, /* udp header */
+                  0x74, 0x65, 0x73, 0x74 /* "test" */];
+    let key = flow_key(&EthernetHeader::new(packet.as_slice())).unwrap();
+    assert_eq!(key, FlowKey {
+        source: Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2),
+        destination: Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+        source_port: 12345,
+        destination_port: 53,
+        protocol: IpNextHeaderProtocols::Udp,
+    });
+
+    let canonical = key.canonical();
+    assert_eq!(canonical.source, Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+    assert_eq!(canonical.source_port, 53);
+    assert_eq!(canonical.destination_port, 12345);
+
+    // A longer datagram split into fragments. Only the first has the UDP header, and its length
+    // field covers the whole datagram; the second starts with payload data, which mustn't be
+    // read as ports.
+    let mut fragment = Vec::new();
+    fragment.push_all(packet.slice_to(54));
+    *fragment.get_mut(19) = 0x18; /* payload length */
+    *fragment.get_mut(20) = 0x2c; /* fragment header */
+    fragment.push_all([0x11u8, 0x00, 0x00, 0x01, 0x12, 0x34, 0x56, 0x78].as_slice());
+    fragment.push_all(packet.slice(54, 66));
+    *fragment.get_mut(67) = 0x14; /* udp length */
+    fragment.push_all(packet.slice(62, 66));
+    let key = flow_key(&EthernetHeader::new(fragment.as_slice())).unwrap();
+    assert_eq!((key.source_port, key.destination_port), (12345, 53));
+    assert_eq!(key.protocol, IpNextHeaderProtocols::Udp);
+
+    // A first fragment too short to contain the ports has no key
+    let mut short = Vec::new();
+    short.push_all(fragment.slice_to(64));
+    *short.get_mut(19) = 0x0a;
+    assert!(flow_key(&EthernetHeader::new(short.as_slice())).is_none());
+
+    let mut later = Vec::new();
+    later.push_all(packet.slice_to(54));
+    *later.get_mut(19) = 0x0c;
+    *later.get_mut(20) = 0x2c;
+    later.push_all([0x11u8, 0x00, 0x00, 0x10, 0x12, 0x34, 0x56, 0x78].as_slice());
+    later.push_all([0x74u8, 0x65, 0x73, 0x74].as_slice());
+    let key = flow_key(&EthernetHeader::new(later.as_slice())).unwrap();
+    assert_eq!((key.source_port, key.destination_port), (0, 0));
+    assert_eq!(key.protocol, IpNextHeaderProtocols::Udp);
+    assert_eq!(key.source, Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2));
+}
@@ -0,0 +1,417 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! GRE packet abstraction
+//!
+//! GRE packets are carried over IP protocol 47 (`IpNextHeaderProtocols::Gre`). The header is
+//! described in RFC 2784, with the key and sequence number fields added by RFC 2890. Source
+//! routing from RFC 1701 is not supported.
+
+use std::cmp;
+use std::fmt;
+
+use packet::{Packet, MutablePacket};
+use packet::ethernet::EtherType;
+use util;
+
+/// The minimum length of a GRE header, in bytes, when no optional fields are present
+pub const GRE_HEADER_LEN: uint = 4;
+
+const CHECKSUM_PRESENT: u8 = 0x80;
+const ROUTING_PRESENT: u8 = 0x40;
+const KEY_PRESENT: u8 = 0x20;
+const SEQUENCE_PRESENT: u8 = 0x10;
+
+/// Structure representing a GRE header
+pub struct GreHeader<'p> {
+    packet: &'p [u8],
+}
+
+/// Structure representing a mutable GRE header
+pub struct MutableGreHeader<'p> {
+    packet: &'p mut [u8],
+}
+
+impl<'p> PartialEq for GreHeader<'p> {
+    fn eq(&self, other: &GreHeader) -> bool {
+        if self.packet.len() != other.packet.len() {
+            return false;
+        }
+        for (b1, b2) in self.packet.iter().zip(other.packet.iter()) {
+            if b1 != b2 {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+impl<'p> Eq for GreHeader<'p> {}
+
+impl<'p> fmt::Show for GreHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "GreHeader {{ version: {}, protocol_type: {}, checksum: {}, key: {}, \
+                sequence_number: {} }}",
+                self.get_version(),
+                self.get_protocol_type(),
+                self.get_checksum(),
+                self.get_key(),
+                self.get_sequence_number()
+        )
+    }
+}
+
+impl<'p> fmt::Show for MutableGreHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "MutableGreHeader {{ version: {}, protocol_type: {}, checksum: {}, key: {}, \
+                sequence_number: {} }}",
+                self.get_version(),
+                self.get_protocol_type(),
+                self.get_checksum(),
+                self.get_key(),
+                self.get_sequence_number()
+        )
+    }
+}
+
+impl<'p> Packet for GreHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] {
+        let start = cmp::min(header_length(self.packet), self.packet.len());
+        self.packet.slice_from(start)
+    }
+}
+
+impl<'p> Packet for MutableGreHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet.as_slice() }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] {
+        let start = cmp::min(header_length(self.packet.as_slice()), self.packet.len());
+        self.packet.slice_from(start)
+    }
+}
+
+impl<'p> MutablePacket for MutableGreHeader<'p> {
+    #[inline(always)]
+    fn packet_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.as_mut_slice() }
+
+    #[inline(always)]
+    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] {
+        let start = cmp::min(header_length(self.packet.as_slice()), self.packet.len());
+        self.packet.slice_from_mut(start)
+    }
+}
+
+// The checksum and (unused) offset fields are present if either the checksum or routing present
+// bits are set
+fn checksum_length(packet: &[u8]) -> uint {
+    if packet[0] & (CHECKSUM_PRESENT | ROUTING_PRESENT) != 0 { 4 } else { 0 }
+}
+
+fn key_offset(packet: &[u8]) -> Option<uint> {
+    if packet[0] & KEY_PRESENT != 0 {
+        Some(GRE_HEADER_LEN + checksum_length(packet))
+    } else {
+        None
+    }
+}
+
+fn sequence_offset(packet: &[u8]) -> Option<uint> {
+    if packet[0] & SEQUENCE_PRESENT != 0 {
+        let key_length = if packet[0] & KEY_PRESENT != 0 { 4 } else { 0 };
+        Some(GRE_HEADER_LEN + checksum_length(packet) + key_length)
+    } else {
+        None
+    }
+}
+
+// The length of the header, including whichever optional fields are present
+fn header_length(packet: &[u8]) -> uint {
+    let mut len = GRE_HEADER_LEN + checksum_length(packet);
+    if packet[0] & KEY_PRESENT != 0 {
+        len += 4;
+    }
+    if packet[0] & SEQUENCE_PRESENT != 0 {
+        len += 4;
+    }
+    len
+}
+
+fn read_u32(packet: &[u8], offset: uint) -> u32 {
+    (packet[offset] as u32 << 24) |
+    (packet[offset + 1] as u32 << 16) |
+    (packet[offset + 2] as u32 << 8) |
+    (packet[offset + 3] as u32)
+}
+
+fn write_u32(packet: &mut [u8], offset: uint, value: u32) {
+    packet[offset] = (value >> 24) as u8;
+    packet[offset + 1] = (value >> 16) as u8;
+    packet[offset + 2] = (value >> 8) as u8;
+    packet[offset + 3] = value as u8;
+}
+
+/// Trait implemented by anything which provides an interface to read GRE packets
+pub trait GrePacket : Packet {
+    /// Is the checksum field present?
+    fn is_checksum_present(&self) -> bool {
+        self.packet()[0] & CHECKSUM_PRESENT != 0
+    }
+
+    /// Is the key field present?
+    fn is_key_present(&self) -> bool {
+        self.packet()[0] & KEY_PRESENT != 0
+    }
+
+    /// Is the sequence number field present?
+    fn is_sequence_present(&self) -> bool {
+        self.packet()[0] & SEQUENCE_PRESENT != 0
+    }
+
+    /// Get the version of the packet. This is 0 for GRE, and 1 for the enhanced GRE used by PPTP.
+    fn get_version(&self) -> u8 {
+        self.packet()[1] & 0x07
+    }
+
+    /// Get the EtherType of the encapsulated payload
+    fn get_protocol_type(&self) -> EtherType {
+        let p1 = self.packet()[2] as u16 << 8;
+        let p2 = self.packet()[3] as u16;
+        EtherType(p1 | p2)
+    }
+
+    /// Get the checksum field, if it is present
+    fn get_checksum(&self) -> Option<u16> {
+        if self.is_checksum_present() {
+            let c1 = self.packet()[4] as u16 << 8;
+            let c2 = self.packet()[5] as u16;
+            Some(c1 | c2)
+        } else {
+            None
+        }
+    }
+
+    /// Get the key field, if it is present
+    fn get_key(&self) -> Option<u32> {
+        key_offset(self.packet()).map(|offset| read_u32(self.packet(), offset))
+    }
+
+    /// Get the sequence number field, if it is present
+    fn get_sequence_number(&self) -> Option<u32> {
+        sequence_offset(self.packet()).map(|offset| read_u32(self.packet(), offset))
+    }
+
+    /// Get the length of the header, including any optional fields which are present
+    fn get_header_length(&self) -> uint {
+        header_length(self.packet())
+    }
+
+    /// Calculate the checksum for the packet, which covers the header and payload
+    fn calculate_checksum(&self) -> u16 {
+        util::checksum(self.packet(), Some(2))
+    }
+}
+
+impl<'p> GrePacket for GreHeader<'p> {}
+impl<'p> GrePacket for MutableGreHeader<'p> {}
+
+impl<'p> GreHeader<'p> {
+    /// Construct a new GRE header backed by the given buffer
+    pub fn new(packet: &'p [u8]) -> GreHeader<'p> {
+        GreHeader { packet: packet }
+    }
+
+    /// Construct a new GRE header backed by the given buffer, checking that it is large enough
+    /// to contain the header and the optional fields indicated by its flags. Returns None if it
+    /// is not.
+    pub fn new_checked(packet: &'p [u8]) -> Option<GreHeader<'p>> {
+        if packet.len() < GRE_HEADER_LEN || packet.len() < header_length(packet) {
+            None
+        } else {
+            Some(GreHeader::new(packet))
+        }
+    }
+}
+
+impl<'p> MutableGreHeader<'p> {
+    /// Construct a new mutable GRE header backed by the given buffer
+    pub fn new(packet: &'p mut [u8]) -> MutableGreHeader<'p> {
+        MutableGreHeader { packet: packet }
+    }
+
+    /// Construct a new mutable GRE header backed by the given buffer, checking that it is large
+    /// enough to contain the header and the optional fields indicated by its flags. Returns None
+    /// if it is not.
+    pub fn new_checked(packet: &'p mut [u8]) -> Option<MutableGreHeader<'p>> {
+        if packet.len() < GRE_HEADER_LEN || packet.len() < header_length(packet) {
+            None
+        } else {
+            Some(MutableGreHeader::new(packet))
+        }
+    }
+
+    fn set_flag(&mut self, flag: u8, present: bool) {
+        if present {
+            self.packet[0] |= flag;
+        } else {
+            self.packet[0] &= !flag;
+        }
+    }
+
+    /// Set whether the checksum field is present. Since this moves the fields which follow it,
+    /// the flags should be set before any optional fields or the payload.
+    pub fn set_checksum_present(&mut self, present: bool) {
+        self.set_flag(CHECKSUM_PRESENT, present);
+    }
+
+    /// Set whether the key field is present. Since this moves the fields which follow it, the
+    /// flags should be set before any optional fields or the payload.
+    pub fn set_key_present(&mut self, present: bool) {
+        self.set_flag(KEY_PRESENT, present);
+    }
+
+    /// Set whether the sequence number field is present. Since this moves the payload, the flags
+    /// should be set before it is written.
+    pub fn set_sequence_present(&mut self, present: bool) {
+        self.set_flag(SEQUENCE_PRESENT, present);
+    }
+
+    /// Set the version of the packet
+    pub fn set_version(&mut self, version: u8) {
+        self.packet[1] = (self.packet[1] & 0xF8) | (version & 0x07);
+    }
+
+    /// Set the EtherType of the encapsulated payload
+    pub fn set_protocol_type(&mut self, EtherType(protocol_type): EtherType) {
+        self.packet[2] = (protocol_type >> 8) as u8;
+        self.packet[3] = (protocol_type & 0xFF) as u8;
+    }
+
+    /// Set the checksum field. Does nothing if the checksum present flag is not set.
+    pub fn set_checksum(&mut self, checksum: u16) {
+        if self.is_checksum_present() {
+            self.packet[4] = (checksum >> 8) as u8;
+            self.packet[5] = (checksum & 0xFF) as u8;
+        }
+    }
+
+    /// Set the key field. Does nothing if the key present flag is not set.
+    pub fn set_key(&mut self, key: u32) {
+        match key_offset(self.packet.as_slice()) {
+            Some(offset) => write_u32(self.packet, offset, key),
+            None => ()
+        }
+    }
+
+    /// Set the sequence number field. Does nothing if the sequence number present flag is not
+    /// set.
+    pub fn set_sequence_number(&mut self, sequence_number: u32) {
+        match sequence_offset(self.packet.as_slice()) {
+            Some(offset) => write_u32(self.packet, offset, sequence_number),
+            None => ()
+        }
+    }
+
+    /// Calculate a checksum for the packet, then set the field. Does nothing if the checksum
+    /// present flag is not set.
+    pub fn checksum(&mut self) {
+        let checksum = self.calculate_checksum();
+        self.set_checksum(checksum);
+    }
+}
+
+#[test]
+fn gre_minimal_header() {
+    use packet::ethernet::EtherTypes;
+
+    let packet = [0x00u8, 0x00, 0x08, 0x00, 0x45, 0x00];
+    let header = GreHeader::new_checked(packet.as_slice()).unwrap();
+    assert!(!header.is_checksum_present());
+    assert!(!header.is_key_present());
+    assert!(!header.is_sequence_present());
+    assert_eq!(header.get_version(), 0);
+    assert_eq!(header.get_protocol_type(), EtherTypes::Ipv4);
+    assert_eq!(header.get_checksum(), None);
+    assert_eq!(header.get_key(), None);
+    assert_eq!(header.get_sequence_number(), None);
+    assert_eq!(header.get_header_length(), 4);
+    assert_eq!(header.payload(), [0x45u8, 0x00].as_slice());
+
+    assert!(GreHeader::new_checked(packet.slice_to(4)).is_some());
+    assert!(GreHeader::new_checked(packet.slice_to(3)).is_none());
+
+    // Setting fields which aren't present leaves the packet unchanged
+    let mut copy = packet;
+    {
+        let mut header = MutableGreHeader::new(copy.as_mut_slice());
+        header.set_checksum(0x1234);
+        header.set_key(1234);
+        header.set_sequence_number(1);
+        header.checksum();
+    }
+    assert_eq!(copy.as_slice(), packet.as_slice());
+}
+
+#[test]
+fn gre_key_present() {
+    let packet = [0x20u8, 0x00, 0x65, 0x58, /* flags, version, transparent ethernet bridging */
+                  0x00, 0x00, 0x04, 0xd2, /* key */
+                  0xaa, 0xbb];
+    let header = GreHeader::new_checked(packet.as_slice()).unwrap();
+    assert!(!header.is_checksum_present());
+    assert!(header.is_key_present());
+    assert!(!header.is_sequence_present());
+    assert_eq!(header.get_protocol_type(), EtherType(0x6558));
+    assert_eq!(header.get_key(), Some(1234));
+    assert_eq!(header.get_sequence_number(), None);
+    assert_eq!(header.get_header_length(), 8);
+    assert_eq!(header.payload(), [0xaau8, 0xbb].as_slice());
+
+    // Too short for the key
+    assert!(GreHeader::new_checked(packet.slice_to(8)).is_some());
+    assert!(GreHeader::new_checked(packet.slice_to(7)).is_none());
+}
+
+#[test]
+fn gre_all_fields() {
+    use packet::ethernet::EtherTypes;
+
+    let mut packet = [0u8, ..20];
+    {
+        let mut header = MutableGreHeader::new(packet.as_mut_slice());
+        header.set_checksum_present(true);
+        header.set_key_present(true);
+        header.set_sequence_present(true);
+        header.set_protocol_type(EtherTypes::Ipv6);
+        header.set_key(0x01020304);
+        header.set_sequence_number(7);
+        header.payload_mut().clone_from_slice([0xdeu8, 0xad, 0xbe, 0xef].as_slice());
+        header.checksum();
+    }
+
+    let ref_packet = [0xb0u8, 0x00, 0x86, 0xdd, /* flags, version, protocol type */
+                      0x27, 0x77, 0x00, 0x00, /* checksum, reserved */
+                      0x01, 0x02, 0x03, 0x04, /* key */
+                      0x00, 0x00, 0x00, 0x07, /* sequence number */
+                      0xde, 0xad, 0xbe, 0xef];
+    assert_eq!(packet.as_slice(), ref_packet.as_slice());
+
+    let header = GreHeader::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(header.get_checksum(), Some(0x2777));
+    assert_eq!(header.calculate_checksum(), 0x2777);
+    assert_eq!(header.get_key(), Some(0x01020304));
+    assert_eq!(header.get_sequence_number(), Some(7));
+    assert_eq!(header.get_header_length(), 16);
+    assert!(GreHeader::new_checked(packet.slice_to(15)).is_none());
+}
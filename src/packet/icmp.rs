@@ -0,0 +1,343 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! ICMP packet abstraction
+
+use std::fmt;
+
+use packet::{Packet, MutablePacket};
+use packet::ipv4::Ipv4Header;
+use util;
+
+/// The length of an ICMP header, including the four type specific bytes, in bytes
+pub const ICMP_HEADER_LEN: uint = 8;
+
+/// Structure representing an ICMP header
+pub struct IcmpHeader<'p> {
+    packet: &'p [u8],
+}
+
+/// Structure representing a mutable ICMP header
+pub struct MutableIcmpHeader<'p> {
+    packet: &'p mut [u8],
+}
+
+// FIXME This should probably be a macro
+impl<'p> PartialEq for IcmpHeader<'p> {
+    fn eq(&self, other: &IcmpHeader) -> bool {
+        if self.packet.len() != other.packet.len() {
+            return false;
+        }
+        for (b1, b2) in self.packet.iter().zip(other.packet.iter()) {
+            if b1 != b2 {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+impl<'p> Eq for IcmpHeader<'p> {}
+
+impl<'p> fmt::Show for IcmpHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "IcmpHeader {{ icmp type: {}, icmp code: {}, checksum: {} }}",
+                self.get_icmp_type(),
+                self.get_icmp_code(),
+                self.get_checksum()
+        )
+    }
+}
+
+impl<'p> fmt::Show for MutableIcmpHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "MutableIcmpHeader {{ icmp type: {}, icmp code: {}, checksum: {} }}",
+                self.get_icmp_type(),
+                self.get_icmp_code(),
+                self.get_checksum()
+        )
+    }
+}
+
+impl<'p> Packet for IcmpHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(ICMP_HEADER_LEN) }
+}
+
+impl<'p> Packet for MutableIcmpHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet.as_slice() }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(ICMP_HEADER_LEN) }
+}
+
+impl<'p> MutablePacket for MutableIcmpHeader<'p> {
+    #[inline(always)]
+    fn packet_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.as_mut_slice() }
+
+    #[inline(always)]
+    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] {
+        self.packet.slice_from_mut(ICMP_HEADER_LEN)
+    }
+}
+
+/// Trait implemented by anything which provides an interface to read ICMP
+/// packets
+pub trait IcmpPacket : Packet {
+    /// Get the type of the message
+    fn get_icmp_type(&self) -> IcmpType {
+        IcmpType(self.packet()[0])
+    }
+
+    /// Get the code of the message
+    fn get_icmp_code(&self) -> IcmpCode {
+        IcmpCode(self.packet()[1])
+    }
+
+    /// Get the checksum field for the packet
+    fn get_checksum(&self) -> u16 {
+        let c1 = self.packet()[2] as u16 << 8;
+        let c2 = self.packet()[3] as u16;
+        c1 | c2
+    }
+
+    /// Get the identifier of an Echo Request or Echo Reply message
+    fn get_identifier(&self) -> u16 {
+        let id1 = self.packet()[4] as u16 << 8;
+        let id2 = self.packet()[5] as u16;
+        id1 | id2
+    }
+
+    /// Get the sequence number of an Echo Request or Echo Reply message
+    fn get_sequence_number(&self) -> u16 {
+        let seq1 = self.packet()[6] as u16 << 8;
+        let seq2 = self.packet()[7] as u16;
+        seq1 | seq2
+    }
+
+    /// Get the IP header of the datagram which caused a Destination Unreachable or Time
+    /// Exceeded message. Its payload is the first 8 bytes of the original datagram's payload.
+    fn get_original_header<'a>(&'a self) -> Ipv4Header<'a> {
+        Ipv4Header::new(self.payload())
+    }
+
+    /// Calculate the checksum for the packet, which covers the header and payload
+    fn calculate_checksum(&self) -> u16 {
+        util::checksum(self.packet(), Some(1))
+    }
+
+    /// Does the checksum field of the packet match the checksum calculated for it?
+    fn is_checksum_valid(&self) -> bool {
+        self.calculate_checksum() == self.get_checksum()
+    }
+}
+
+impl<'p> IcmpPacket for IcmpHeader<'p> {}
+impl<'p> IcmpPacket for MutableIcmpHeader<'p> {}
+
+impl<'p> IcmpHeader<'p> {
+    /// Construct a new ICMP header backed by the given buffer
+    pub fn new(packet: &'p [u8]) -> IcmpHeader<'p> {
+        IcmpHeader { packet: packet }
+    }
+
+    /// Construct a new ICMP header backed by the given buffer, checking that it is large enough
+    /// to contain an ICMP header. Returns None if it is not.
+    pub fn new_checked(packet: &'p [u8]) -> Option<IcmpHeader<'p>> {
+        if packet.len() < ICMP_HEADER_LEN {
+            None
+        } else {
+            Some(IcmpHeader::new(packet))
+        }
+    }
+}
+
+impl<'p> MutableIcmpHeader<'p> {
+    /// Construct a new mutable ICMP header backed by the given buffer
+    pub fn new(packet: &'p mut [u8]) -> MutableIcmpHeader<'p> {
+        MutableIcmpHeader { packet: packet }
+    }
+
+    /// Construct a new mutable ICMP header backed by the given buffer, checking that it is
+    /// large enough to contain an ICMP header. Returns None if it is not.
+    pub fn new_checked(packet: &'p mut [u8]) -> Option<MutableIcmpHeader<'p>> {
+        if packet.len() < ICMP_HEADER_LEN {
+            None
+        } else {
+            Some(MutableIcmpHeader::new(packet))
+        }
+    }
+
+    /// Set the type of the message
+    pub fn set_icmp_type(&mut self, IcmpType(icmp_type): IcmpType) {
+        self.packet[0] = icmp_type;
+    }
+
+    /// Set the code of the message
+    pub fn set_icmp_code(&mut self, IcmpCode(icmp_code): IcmpCode) {
+        self.packet[1] = icmp_code;
+    }
+
+    /// Set the checksum field for the packet
+    pub fn set_checksum(&mut self, checksum: u16) {
+        self.packet[2] = (checksum >> 8) as u8;
+        self.packet[3] = (checksum & 0xFF) as u8;
+    }
+
+    /// Set the identifier of an Echo Request or Echo Reply message
+    pub fn set_identifier(&mut self, identifier: u16) {
+        self.packet[4] = (identifier >> 8) as u8;
+        self.packet[5] = (identifier & 0xFF) as u8;
+    }
+
+    /// Set the sequence number of an Echo Request or Echo Reply message
+    pub fn set_sequence_number(&mut self, sequence_number: u16) {
+        self.packet[6] = (sequence_number >> 8) as u8;
+        self.packet[7] = (sequence_number & 0xFF) as u8;
+    }
+
+    /// Calculate a checksum for the packet, then set the field
+    pub fn checksum(&mut self) {
+        let checksum = self.calculate_checksum();
+        self.set_checksum(checksum);
+    }
+}
+
+#[test]
+fn icmp_echo_request_test() {
+    let mut packet = [0u8, ..16];
+    {
+        let mut icmp_header = MutableIcmpHeader::new_checked(packet.as_mut_slice()).unwrap();
+        icmp_header.set_icmp_type(IcmpTypes::EchoRequest);
+        assert_eq!(icmp_header.get_icmp_type(), IcmpTypes::EchoRequest);
+
+        icmp_header.set_icmp_code(IcmpCode(0));
+        assert_eq!(icmp_header.get_icmp_code(), IcmpCode(0));
+
+        icmp_header.set_identifier(0x1234);
+        assert_eq!(icmp_header.get_identifier(), 0x1234);
+
+        icmp_header.set_sequence_number(1);
+        assert_eq!(icmp_header.get_sequence_number(), 1);
+
+        for (i, b) in icmp_header.payload_mut().iter_mut().enumerate() {
+            *b = 'a' as u8 + i as u8;
+        }
+
+        icmp_header.checksum();
+        assert_eq!(icmp_header.get_checksum(), 0x5435);
+        assert!(icmp_header.is_checksum_valid());
+    }
+
+    let ref_packet = [0x08,                   /* type */
+                      0x00,                   /* code */
+                      0x54, 0x35,             /* checksum */
+                      0x12, 0x34,             /* identifier */
+                      0x00, 0x01,             /* sequence number */
+                      0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68 /* payload */];
+    assert_eq!(ref_packet.as_slice(), packet.as_slice());
+
+    // Corrupt the payload
+    packet[15] = 0x00;
+    let icmp_header = IcmpHeader::new(packet.as_slice());
+    assert!(!icmp_header.is_checksum_valid());
+}
+
+#[test]
+fn icmp_time_exceeded_test() {
+    use std::io::net::ip::Ipv4Addr;
+    use packet::ip::IpNextHeaderProtocols;
+    use packet::ipv4::Ipv4Packet;
+
+    let packet = [0x0b,                   /* type */
+                  0x00,                   /* code */
+                  0xd2, 0x3b,             /* checksum */
+                  0x00, 0x00, 0x00, 0x00, /* unused */
+                  0x45, 0x00, 0x00, 0x3c, /* original ip header */
+                  0x1c, 0x46, 0x00, 0x00,
+                  0x01, 0x11, 0xcc, 0xb1,
+                  0xc0, 0xa8, 0x00, 0x02,
+                  0x08, 0x08, 0x08, 0x08,
+                  0xa0, 0x00, 0x82, 0x9b, /* original udp header */
+                  0x00, 0x28, 0x00, 0x00];
+    let icmp_header = IcmpHeader::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(icmp_header.get_icmp_type(), IcmpTypes::TimeExceeded);
+    assert_eq!(icmp_header.get_icmp_code(), IcmpCode(0));
+    assert!(icmp_header.is_checksum_valid());
+
+    let original = icmp_header.get_original_header();
+    assert!(original.is_checksum_valid());
+    assert_eq!(original.get_ttl(), 1);
+    assert_eq!(original.get_next_level_protocol(), IpNextHeaderProtocols::Udp);
+    assert_eq!(original.get_source(), Ipv4Addr(192, 168, 0, 2));
+    assert_eq!(original.get_destination(), Ipv4Addr(8, 8, 8, 8));
+    assert_eq!(original.payload(), packet.slice_from(28));
+}
+
+#[test]
+fn icmp_header_checked() {
+    let packet = [0u8, ..8];
+    assert!(IcmpHeader::new_checked(packet.as_slice()).is_some());
+    assert!(IcmpHeader::new_checked(packet.slice_to(7)).is_none());
+}
+
+/// ICMP message types, as defined at:
+/// http://www.iana.org/assignments/icmp-parameters/icmp-parameters.xhtml
+///
+/// FIXME Should include all
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod IcmpTypes {
+    use super::IcmpType;
+
+    /// Echo Reply [RFC792]
+    pub const EchoReply: IcmpType              = IcmpType(0);
+
+    /// Destination Unreachable [RFC792]
+    pub const DestinationUnreachable: IcmpType = IcmpType(3);
+
+    /// Source Quench [RFC792]
+    pub const SourceQuench: IcmpType           = IcmpType(4);
+
+    /// Redirect [RFC792]
+    pub const Redirect: IcmpType               = IcmpType(5);
+
+    /// Echo [RFC792]
+    pub const EchoRequest: IcmpType            = IcmpType(8);
+
+    /// Router Advertisement [RFC1256]
+    pub const RouterAdvertisement: IcmpType    = IcmpType(9);
+
+    /// Router Solicitation [RFC1256]
+    pub const RouterSolicitation: IcmpType     = IcmpType(10);
+
+    /// Time Exceeded [RFC792]
+    pub const TimeExceeded: IcmpType           = IcmpType(11);
+
+    /// Parameter Problem [RFC792]
+    pub const ParameterProblem: IcmpType       = IcmpType(12);
+
+    /// Timestamp [RFC792]
+    pub const Timestamp: IcmpType              = IcmpType(13);
+
+    /// Timestamp Reply [RFC792]
+    pub const TimestampReply: IcmpType         = IcmpType(14);
+}
+
+/// Represents the ICMP type field
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IcmpType(pub u8);
+
+/// Represents the ICMP code field. Its meaning depends on the type of the message.
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IcmpCode(pub u8);
@@ -0,0 +1,410 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! ICMPv6 packet abstraction
+
+use std::fmt;
+use std::io::net::ip::IpAddr;
+
+use packet::{Packet, MutablePacket};
+use packet::ip::IpNextHeaderProtocols;
+use util;
+
+/// The length of an ICMPv6 header, including the four type specific bytes, in bytes
+pub const ICMPV6_HEADER_LEN: uint = 8;
+
+// The length of a Neighbor Solicitation or Advertisement message, up to the end of the target
+// address
+const NEIGHBOR_MESSAGE_LEN: uint = 24;
+
+/// Structure representing an ICMPv6 header
+pub struct Icmpv6Header<'p> {
+    packet: &'p [u8],
+}
+
+/// Structure representing a mutable ICMPv6 header
+pub struct MutableIcmpv6Header<'p> {
+    packet: &'p mut [u8],
+}
+
+// FIXME This should probably be a macro
+impl<'p> PartialEq for Icmpv6Header<'p> {
+    fn eq(&self, other: &Icmpv6Header) -> bool {
+        if self.packet.len() != other.packet.len() {
+            return false;
+        }
+        for (b1, b2) in self.packet.iter().zip(other.packet.iter()) {
+            if b1 != b2 {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+impl<'p> Eq for Icmpv6Header<'p> {}
+
+impl<'p> fmt::Show for Icmpv6Header<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "Icmpv6Header {{ icmpv6 type: {}, icmpv6 code: {}, checksum: {} }}",
+                self.get_icmpv6_type(),
+                self.get_icmpv6_code(),
+                self.get_checksum()
+        )
+    }
+}
+
+impl<'p> fmt::Show for MutableIcmpv6Header<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "MutableIcmpv6Header {{ icmpv6 type: {}, icmpv6 code: {}, checksum: {} }}",
+                self.get_icmpv6_type(),
+                self.get_icmpv6_code(),
+                self.get_checksum()
+        )
+    }
+}
+
+impl<'p> Packet for Icmpv6Header<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(ICMPV6_HEADER_LEN) }
+}
+
+impl<'p> Packet for MutableIcmpv6Header<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet.as_slice() }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(ICMPV6_HEADER_LEN) }
+}
+
+impl<'p> MutablePacket for MutableIcmpv6Header<'p> {
+    #[inline(always)]
+    fn packet_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.as_mut_slice() }
+
+    #[inline(always)]
+    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] {
+        self.packet.slice_from_mut(ICMPV6_HEADER_LEN)
+    }
+}
+
+/// Trait implemented by anything which provides an interface to read ICMPv6
+/// packets
+pub trait Icmpv6Packet : Packet {
+    /// Get the type of the message
+    fn get_icmpv6_type(&self) -> Icmpv6Type {
+        Icmpv6Type(self.packet()[0])
+    }
+
+    /// Get the code of the message
+    fn get_icmpv6_code(&self) -> Icmpv6Code {
+        Icmpv6Code(self.packet()[1])
+    }
+
+    /// Get the checksum field for the packet
+    fn get_checksum(&self) -> u16 {
+        let c1 = self.packet()[2] as u16 << 8;
+        let c2 = self.packet()[3] as u16;
+        c1 | c2
+    }
+
+    /// Get the identifier of an Echo Request or Echo Reply message
+    fn get_identifier(&self) -> u16 {
+        let id1 = self.packet()[4] as u16 << 8;
+        let id2 = self.packet()[5] as u16;
+        id1 | id2
+    }
+
+    /// Get the sequence number of an Echo Request or Echo Reply message
+    fn get_sequence_number(&self) -> u16 {
+        let seq1 = self.packet()[6] as u16 << 8;
+        let seq2 = self.packet()[7] as u16;
+        seq1 | seq2
+    }
+
+    /// Get the router flag of a Neighbor Advertisement message
+    fn is_router(&self) -> bool {
+        self.packet()[4] & 0x80 != 0
+    }
+
+    /// Get the solicited flag of a Neighbor Advertisement message
+    fn is_solicited(&self) -> bool {
+        self.packet()[4] & 0x40 != 0
+    }
+
+    /// Get the override flag of a Neighbor Advertisement message
+    fn is_override(&self) -> bool {
+        self.packet()[4] & 0x20 != 0
+    }
+
+    /// Get the target address of a Neighbor Solicitation or Neighbor Advertisement message.
+    /// Returns None if the packet is too short to contain one.
+    fn get_target_address(&self) -> Option<IpAddr> {
+        if self.packet().len() < NEIGHBOR_MESSAGE_LEN {
+            return None;
+        }
+        let mut octets = [0u8, ..16];
+        octets.clone_from_slice(self.packet().slice(8, NEIGHBOR_MESSAGE_LEN));
+        Some(util::ipv6_from_octets(octets))
+    }
+
+    /// Calculate the checksum for the packet, including the IPv6 pseudo-header
+    fn calculate_checksum(&self, ipv6_source: IpAddr, ipv6_destination: IpAddr) -> u16 {
+        util::ipv6_checksum(self.packet(), Some(1), ipv6_source, ipv6_destination,
+                            IpNextHeaderProtocols::Ipv6Icmp)
+    }
+
+    /// Does the checksum field of the packet match the checksum calculated for it?
+    fn is_checksum_valid(&self, ipv6_source: IpAddr, ipv6_destination: IpAddr) -> bool {
+        self.calculate_checksum(ipv6_source, ipv6_destination) == self.get_checksum()
+    }
+}
+
+impl<'p> Icmpv6Packet for Icmpv6Header<'p> {}
+impl<'p> Icmpv6Packet for MutableIcmpv6Header<'p> {}
+
+impl<'p> Icmpv6Header<'p> {
+    /// Construct a new ICMPv6 header backed by the given buffer
+    pub fn new(packet: &'p [u8]) -> Icmpv6Header<'p> {
+        Icmpv6Header { packet: packet }
+    }
+
+    /// Construct a new ICMPv6 header backed by the given buffer, checking that it is large
+    /// enough to contain an ICMPv6 header. Returns None if it is not.
+    pub fn new_checked(packet: &'p [u8]) -> Option<Icmpv6Header<'p>> {
+        if packet.len() < ICMPV6_HEADER_LEN {
+            None
+        } else {
+            Some(Icmpv6Header::new(packet))
+        }
+    }
+}
+
+impl<'p> MutableIcmpv6Header<'p> {
+    /// Construct a new mutable ICMPv6 header backed by the given buffer
+    pub fn new(packet: &'p mut [u8]) -> MutableIcmpv6Header<'p> {
+        MutableIcmpv6Header { packet: packet }
+    }
+
+    /// Construct a new mutable ICMPv6 header backed by the given buffer, checking that it is
+    /// large enough to contain an ICMPv6 header. Returns None if it is not.
+    pub fn new_checked(packet: &'p mut [u8]) -> Option<MutableIcmpv6Header<'p>> {
+        if packet.len() < ICMPV6_HEADER_LEN {
+            None
+        } else {
+            Some(MutableIcmpv6Header::new(packet))
+        }
+    }
+
+    /// Set the type of the message
+    pub fn set_icmpv6_type(&mut self, Icmpv6Type(icmpv6_type): Icmpv6Type) {
+        self.packet[0] = icmpv6_type;
+    }
+
+    /// Set the code of the message
+    pub fn set_icmpv6_code(&mut self, Icmpv6Code(icmpv6_code): Icmpv6Code) {
+        self.packet[1] = icmpv6_code;
+    }
+
+    /// Set the checksum field for the packet
+    pub fn set_checksum(&mut self, checksum: u16) {
+        self.packet[2] = (checksum >> 8) as u8;
+        self.packet[3] = (checksum & 0xFF) as u8;
+    }
+
+    /// Set the identifier of an Echo Request or Echo Reply message
+    pub fn set_identifier(&mut self, identifier: u16) {
+        self.packet[4] = (identifier >> 8) as u8;
+        self.packet[5] = (identifier & 0xFF) as u8;
+    }
+
+    /// Set the sequence number of an Echo Request or Echo Reply message
+    pub fn set_sequence_number(&mut self, sequence_number: u16) {
+        self.packet[6] = (sequence_number >> 8) as u8;
+        self.packet[7] = (sequence_number & 0xFF) as u8;
+    }
+
+    /// Set the router, solicited and override flags of a Neighbor Advertisement message
+    pub fn set_advertisement_flags(&mut self, router: bool, solicited: bool, override_flag: bool) {
+        let mut flags = 0u8;
+        if router { flags = flags | 0x80; }
+        if solicited { flags = flags | 0x40; }
+        if override_flag { flags = flags | 0x20; }
+        self.packet[4] = flags;
+    }
+
+    /// Set the target address of a Neighbor Solicitation or Neighbor Advertisement message.
+    /// Only IPv6 addresses are supported, and the packet must be large enough to contain one;
+    /// anything else is ignored.
+    pub fn set_target_address(&mut self, ip: IpAddr) {
+        if self.packet.len() < NEIGHBOR_MESSAGE_LEN {
+            return;
+        }
+        match util::ipv6_octets(ip) {
+            Some(octets) => {
+                self.packet.slice_mut(8, NEIGHBOR_MESSAGE_LEN).clone_from_slice(octets.as_slice());
+            },
+            None => ()
+        }
+    }
+
+    /// Calculate a checksum for the packet, including the IPv6 pseudo-header, then set the
+    /// field
+    pub fn checksum(&mut self, ipv6_source: IpAddr, ipv6_destination: IpAddr) {
+        let checksum = self.calculate_checksum(ipv6_source, ipv6_destination);
+        self.set_checksum(checksum);
+    }
+}
+
+#[test]
+fn icmpv6_echo_request_test() {
+    use std::io::net::ip::Ipv6Addr;
+
+    let source = Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+    let destination = Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+
+    let mut packet = [0u8, ..16];
+    {
+        let mut icmpv6_header = MutableIcmpv6Header::new_checked(packet.as_mut_slice()).unwrap();
+        icmpv6_header.set_icmpv6_type(Icmpv6Types::EchoRequest);
+        assert_eq!(icmpv6_header.get_icmpv6_type(), Icmpv6Types::EchoRequest);
+
+        icmpv6_header.set_icmpv6_code(Icmpv6Code(0));
+        assert_eq!(icmpv6_header.get_icmpv6_code(), Icmpv6Code(0));
+
+        icmpv6_header.set_identifier(0x1234);
+        assert_eq!(icmpv6_header.get_identifier(), 0x1234);
+
+        icmpv6_header.set_sequence_number(1);
+        assert_eq!(icmpv6_header.get_sequence_number(), 1);
+
+        for (i, b) in icmpv6_header.payload_mut().iter_mut().enumerate() {
+            *b = 'a' as u8 + i as u8;
+        }
+
+        icmpv6_header.checksum(source, destination);
+        assert_eq!(icmpv6_header.get_checksum(), 0xdee5);
+        assert!(icmpv6_header.is_checksum_valid(source, destination));
+    }
+
+    let ref_packet = [0x80,                   /* type */
+                      0x00,                   /* code */
+                      0xde, 0xe5,             /* checksum */
+                      0x12, 0x34,             /* identifier */
+                      0x00, 0x01,             /* sequence number */
+                      0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68 /* payload */];
+    assert_eq!(ref_packet.as_slice(), packet.as_slice());
+
+    // The pseudo-header is part of the checksum
+    let icmpv6_header = Icmpv6Header::new(packet.as_slice());
+    assert!(!icmpv6_header.is_checksum_valid(destination, source));
+}
+
+#[test]
+fn icmpv6_neighbor_solicitation_test() {
+    use std::io::net::ip::Ipv6Addr;
+
+    let packet = [0x87,                   /* type */
+                  0x00,                   /* code */
+                  0x15, 0xff,             /* checksum */
+                  0x00, 0x00, 0x00, 0x00, /* reserved */
+                  0xfe, 0x80, 0x00, 0x00, /* target address */
+                  0x00, 0x00, 0x00, 0x00,
+                  0x00, 0x00, 0x00, 0x00,
+                  0x00, 0x00, 0x00, 0x02,
+                  0x01, 0x01,             /* source link-layer address option */
+                  0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    let icmpv6_header = Icmpv6Header::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(icmpv6_header.get_icmpv6_type(), Icmpv6Types::NeighborSolicitation);
+    assert_eq!(icmpv6_header.get_target_address(), Some(Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 2)));
+    assert!(icmpv6_header.is_checksum_valid(Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+                                            Ipv6Addr(0xff02, 0, 0, 0, 0, 1, 0xff00, 2)));
+
+    // A message truncated before the end of the target address has none
+    let truncated = Icmpv6Header::new_checked(packet.slice_to(20)).unwrap();
+    assert_eq!(truncated.get_target_address(), None);
+}
+
+#[test]
+fn icmpv6_neighbor_advertisement_test() {
+    use std::io::net::ip::Ipv6Addr;
+
+    let source = Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+    let destination = Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+
+    let mut packet = [0u8, ..24];
+    {
+        let mut icmpv6_header = MutableIcmpv6Header::new(packet.as_mut_slice());
+        icmpv6_header.set_icmpv6_type(Icmpv6Types::NeighborAdvertisement);
+        icmpv6_header.set_advertisement_flags(false, true, true);
+        icmpv6_header.set_target_address(source);
+        icmpv6_header.checksum(source, destination);
+    }
+
+    let icmpv6_header = Icmpv6Header::new(packet.as_slice());
+    assert_eq!(icmpv6_header.get_icmpv6_type(), Icmpv6Types::NeighborAdvertisement);
+    assert!(!icmpv6_header.is_router());
+    assert!(icmpv6_header.is_solicited());
+    assert!(icmpv6_header.is_override());
+    assert_eq!(icmpv6_header.get_target_address(), Some(source));
+    assert_eq!(icmpv6_header.get_checksum(), 0x1c25);
+}
+
+/// ICMPv6 message types, as defined at:
+/// http://www.iana.org/assignments/icmpv6-parameters/icmpv6-parameters.xhtml
+///
+/// FIXME Should include all
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod Icmpv6Types {
+    use super::Icmpv6Type;
+
+    /// Destination Unreachable [RFC4443]
+    pub const DestinationUnreachable: Icmpv6Type = Icmpv6Type(1);
+
+    /// Packet Too Big [RFC4443]
+    pub const PacketTooBig: Icmpv6Type           = Icmpv6Type(2);
+
+    /// Time Exceeded [RFC4443]
+    pub const TimeExceeded: Icmpv6Type           = Icmpv6Type(3);
+
+    /// Parameter Problem [RFC4443]
+    pub const ParameterProblem: Icmpv6Type       = Icmpv6Type(4);
+
+    /// Echo Request [RFC4443]
+    pub const EchoRequest: Icmpv6Type            = Icmpv6Type(128);
+
+    /// Echo Reply [RFC4443]
+    pub const EchoReply: Icmpv6Type              = Icmpv6Type(129);
+
+    /// Router Solicitation [RFC4861]
+    pub const RouterSolicitation: Icmpv6Type     = Icmpv6Type(133);
+
+    /// Router Advertisement [RFC4861]
+    pub const RouterAdvertisement: Icmpv6Type    = Icmpv6Type(134);
+
+    /// Neighbor Solicitation [RFC4861]
+    pub const NeighborSolicitation: Icmpv6Type   = Icmpv6Type(135);
+
+    /// Neighbor Advertisement [RFC4861]
+    pub const NeighborAdvertisement: Icmpv6Type  = Icmpv6Type(136);
+
+    /// Redirect Message [RFC4861]
+    pub const Redirect: Icmpv6Type               = Icmpv6Type(137);
+}
+
+/// Represents the ICMPv6 type field
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Icmpv6Type(pub u8);
+
+/// Represents the ICMPv6 code field. Its meaning depends on the type of the message.
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Icmpv6Code(pub u8);
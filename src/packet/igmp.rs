@@ -0,0 +1,506 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! IGMP packet abstraction
+//!
+//! IGMPv1 and IGMPv2 messages, and IGMPv3 queries (RFC 3376), share the layout of the first eight
+//! bytes. IGMPv3 queries are longer, and list the sources being queried; IGMPv3 membership
+//! reports use the group field to count a list of group records instead.
+
+use std::fmt;
+use std::io::net::ip::IpAddr;
+
+use packet::{Packet, MutablePacket};
+use util;
+
+/// The length of an IGMPv1 or IGMPv2 message, in bytes
+pub const IGMP_HEADER_LEN: uint = 8;
+
+/// The minimum length of an IGMPv3 query, in bytes
+pub const IGMPV3_QUERY_LEN: uint = 12;
+
+/// Structure representing an IGMP packet
+pub struct IgmpHeader<'p> {
+    packet: &'p [u8],
+}
+
+/// Structure representing a mutable IGMP packet
+pub struct MutableIgmpHeader<'p> {
+    packet: &'p mut [u8],
+}
+
+impl<'p> PartialEq for IgmpHeader<'p> {
+    fn eq(&self, other: &IgmpHeader) -> bool {
+        if self.packet.len() != other.packet.len() {
+            return false;
+        }
+        for (b1, b2) in self.packet.iter().zip(other.packet.iter()) {
+            if b1 != b2 {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+impl<'p> Eq for IgmpHeader<'p> {}
+
+impl<'p> fmt::Show for IgmpHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "IgmpHeader {{ igmp type: {}, max response time: {}, checksum: {}, \
+                group address: {} }}",
+                self.get_igmp_type(),
+                self.get_max_response_time(),
+                self.get_checksum(),
+                self.get_group_address()
+        )
+    }
+}
+
+impl<'p> fmt::Show for MutableIgmpHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "MutableIgmpHeader {{ igmp type: {}, max response time: {}, checksum: {}, \
+                group address: {} }}",
+                self.get_igmp_type(),
+                self.get_max_response_time(),
+                self.get_checksum(),
+                self.get_group_address()
+        )
+    }
+}
+
+impl<'p> Packet for IgmpHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(IGMP_HEADER_LEN) }
+}
+
+impl<'p> Packet for MutableIgmpHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet.as_slice() }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(IGMP_HEADER_LEN) }
+}
+
+impl<'p> MutablePacket for MutableIgmpHeader<'p> {
+    #[inline(always)]
+    fn packet_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.as_mut_slice() }
+
+    #[inline(always)]
+    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] {
+        self.packet.slice_from_mut(IGMP_HEADER_LEN)
+    }
+}
+
+/// Trait implemented by anything which provides an interface to read IGMP packets
+pub trait IgmpPacket : Packet {
+    /// Get the type of the message
+    fn get_igmp_type(&self) -> IgmpType {
+        IgmpType(self.packet()[0])
+    }
+
+    /// Get the maximum response time of a query, in tenths of a second. For IGMPv3 queries this
+    /// is the maximum response code, which is encoded as a floating point value when it is 128 or
+    /// more.
+    fn get_max_response_time(&self) -> u8 {
+        self.packet()[1]
+    }
+
+    /// Get the checksum field for the packet
+    fn get_checksum(&self) -> u16 {
+        (self.packet()[2] as u16 << 8) | (self.packet()[3] as u16)
+    }
+
+    /// Get the group address of the message. This is the unspecified address for general
+    /// queries, and isn't used by IGMPv3 membership reports.
+    fn get_group_address(&self) -> IpAddr {
+        util::ipv4_from_octets([self.packet()[4],
+                                self.packet()[5],
+                                self.packet()[6],
+                                self.packet()[7]])
+    }
+
+    /// Calculate the checksum for the packet, which covers the whole message
+    fn calculate_checksum(&self) -> u16 {
+        util::checksum(self.packet(), Some(1))
+    }
+
+    /// Does the checksum field of the packet match the checksum calculated for it?
+    fn is_checksum_valid(&self) -> bool {
+        self.calculate_checksum() == self.get_checksum()
+    }
+
+    /// Is the message an IGMPv3 query? IGMPv3 queries have the same type as earlier queries,
+    /// but are at least 12 bytes long.
+    fn is_v3_query(&self) -> bool {
+        self.get_igmp_type() == IgmpTypes::MembershipQuery &&
+            self.packet().len() >= IGMPV3_QUERY_LEN
+    }
+
+    /// Get the Suppress Router-Side Processing flag of an IGMPv3 query
+    fn get_suppress_router_processing(&self) -> bool {
+        self.packet()[8] & 0x08 != 0
+    }
+
+    /// Get the Querier's Robustness Variable of an IGMPv3 query
+    fn get_querier_robustness(&self) -> u8 {
+        self.packet()[8] & 0x07
+    }
+
+    /// Get the Querier's Query Interval Code of an IGMPv3 query
+    fn get_querier_query_interval(&self) -> u8 {
+        self.packet()[9]
+    }
+
+    /// Get the number of sources listed in an IGMPv3 query
+    fn get_number_of_sources(&self) -> u16 {
+        (self.packet()[10] as u16 << 8) | (self.packet()[11] as u16)
+    }
+
+    /// Get an iterator over the sources listed in an IGMPv3 query. Iteration finishes early if
+    /// the packet is too short to hold all of them.
+    fn get_sources<'a>(&'a self) -> IgmpAddressIterator<'a> {
+        IgmpAddressIterator {
+            buf: self.packet().slice_from(IGMPV3_QUERY_LEN),
+            remaining: self.get_number_of_sources(),
+        }
+    }
+
+    /// Get the number of group records in an IGMPv3 membership report
+    fn get_number_of_group_records(&self) -> u16 {
+        (self.packet()[6] as u16 << 8) | (self.packet()[7] as u16)
+    }
+
+    /// Get an iterator over the group records of an IGMPv3 membership report. Iteration finishes
+    /// early if a group record is truncated.
+    fn get_group_records<'a>(&'a self) -> IgmpGroupRecordIterator<'a> {
+        IgmpGroupRecordIterator {
+            buf: self.packet().slice_from(IGMP_HEADER_LEN),
+            remaining: self.get_number_of_group_records(),
+        }
+    }
+}
+
+impl<'p> IgmpPacket for IgmpHeader<'p> {}
+impl<'p> IgmpPacket for MutableIgmpHeader<'p> {}
+
+impl<'p> IgmpHeader<'p> {
+    /// Construct a new IGMP packet backed by the given buffer
+    pub fn new(packet: &'p [u8]) -> IgmpHeader<'p> {
+        IgmpHeader { packet: packet }
+    }
+
+    /// Construct a new IGMP packet backed by the given buffer, checking that it is large enough
+    /// to contain an IGMP message. Returns None if it is not.
+    pub fn new_checked(packet: &'p [u8]) -> Option<IgmpHeader<'p>> {
+        if packet.len() < IGMP_HEADER_LEN {
+            None
+        } else {
+            Some(IgmpHeader::new(packet))
+        }
+    }
+}
+
+impl<'p> MutableIgmpHeader<'p> {
+    /// Construct a new mutable IGMP packet backed by the given buffer
+    pub fn new(packet: &'p mut [u8]) -> MutableIgmpHeader<'p> {
+        MutableIgmpHeader { packet: packet }
+    }
+
+    /// Construct a new mutable IGMP packet backed by the given buffer, checking that it is large
+    /// enough to contain an IGMP message. Returns None if it is not.
+    pub fn new_checked(packet: &'p mut [u8]) -> Option<MutableIgmpHeader<'p>> {
+        if packet.len() < IGMP_HEADER_LEN {
+            None
+        } else {
+            Some(MutableIgmpHeader::new(packet))
+        }
+    }
+
+    /// Set the type of the message
+    pub fn set_igmp_type(&mut self, IgmpType(igmp_type): IgmpType) {
+        self.packet[0] = igmp_type;
+    }
+
+    /// Set the maximum response time, in tenths of a second
+    pub fn set_max_response_time(&mut self, max_response_time: u8) {
+        self.packet[1] = max_response_time;
+    }
+
+    /// Set the checksum field for the packet
+    pub fn set_checksum(&mut self, checksum: u16) {
+        self.packet[2] = (checksum >> 8) as u8;
+        self.packet[3] = (checksum & 0xFF) as u8;
+    }
+
+    /// Set the group address of the message. Only IPv4 addresses are supported; anything else
+    /// is ignored.
+    pub fn set_group_address(&mut self, group: IpAddr) {
+        match util::ipv4_octets(group) {
+            Some(octets) => { self.packet.slice_mut(4, 8).clone_from_slice(octets.as_slice()); },
+            None => ()
+        }
+    }
+
+    /// Calculate a checksum for the packet, then set the field
+    pub fn checksum(&mut self) {
+        let checksum = self.calculate_checksum();
+        self.set_checksum(checksum);
+    }
+}
+
+/// A group record from an IGMPv3 membership report
+#[deriving(PartialEq, Eq, Show)]
+pub struct IgmpGroupRecord<'p> {
+    /// The type of the record
+    pub record_type: IgmpGroupRecordType,
+    /// The multicast address the record applies to
+    pub multicast_address: IpAddr,
+    /// The number of sources listed in the record
+    pub number_of_sources: u16,
+    /// The auxiliary data of the record
+    pub aux_data: &'p [u8],
+    sources: &'p [u8],
+}
+
+impl<'p> IgmpGroupRecord<'p> {
+    /// Get an iterator over the sources listed in the record
+    pub fn get_sources(&self) -> IgmpAddressIterator<'p> {
+        IgmpAddressIterator { buf: self.sources, remaining: self.number_of_sources }
+    }
+}
+
+/// An iterator over the group records of an IGMPv3 membership report
+pub struct IgmpGroupRecordIterator<'p> {
+    buf: &'p [u8],
+    remaining: u16,
+}
+
+impl<'p> Iterator<IgmpGroupRecord<'p>> for IgmpGroupRecordIterator<'p> {
+    fn next(&mut self) -> Option<IgmpGroupRecord<'p>> {
+        // Each record has an 8 byte header, followed by the sources and auxiliary data
+        if self.remaining == 0 || self.buf.len() < 8 {
+            self.buf = self.buf.slice_to(0);
+            return None;
+        }
+        let aux_len = self.buf[1] as uint * 4;
+        let number_of_sources = (self.buf[2] as u16 << 8) | (self.buf[3] as u16);
+        let sources_end = 8 + number_of_sources as uint * 4;
+        let end = sources_end + aux_len;
+        if end > self.buf.len() {
+            self.buf = self.buf.slice_to(0);
+            return None;
+        }
+        let record = IgmpGroupRecord {
+            record_type: IgmpGroupRecordType(self.buf[0]),
+            multicast_address: util::ipv4_from_octets([self.buf[4],
+                                                       self.buf[5],
+                                                       self.buf[6],
+                                                       self.buf[7]]),
+            number_of_sources: number_of_sources,
+            aux_data: self.buf.slice(sources_end, end),
+            sources: self.buf.slice(8, sources_end),
+        };
+        self.buf = self.buf.slice_from(end);
+        self.remaining -= 1;
+
+        Some(record)
+    }
+}
+
+/// An iterator over a list of IPv4 addresses in an IGMP message
+pub struct IgmpAddressIterator<'p> {
+    buf: &'p [u8],
+    remaining: u16,
+}
+
+impl<'p> Iterator<IpAddr> for IgmpAddressIterator<'p> {
+    fn next(&mut self) -> Option<IpAddr> {
+        if self.remaining == 0 || self.buf.len() < 4 {
+            self.buf = self.buf.slice_to(0);
+            return None;
+        }
+        let addr = util::ipv4_from_octets([self.buf[0], self.buf[1], self.buf[2], self.buf[3]]);
+        self.buf = self.buf.slice_from(4);
+        self.remaining -= 1;
+
+        Some(addr)
+    }
+}
+
+#[test]
+fn igmp_v2_membership_report() {
+    use std::io::net::ip::{Ipv4Addr, Ipv6Addr};
+
+    let packet = [0x16,                   /* type */
+                  0x00,                   /* max response time */
+                  0x09, 0x04,             /* checksum */
+                  0xe0, 0x00, 0x00, 0xfb  /* group address */];
+    let igmp = IgmpHeader::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(igmp.get_igmp_type(), IgmpTypes::V2MembershipReport);
+    assert_eq!(igmp.get_max_response_time(), 0);
+    assert_eq!(igmp.get_group_address(), Ipv4Addr(224, 0, 0, 251));
+    assert!(igmp.is_checksum_valid());
+    assert!(!igmp.is_v3_query());
+
+    let mut built = [0u8, ..IGMP_HEADER_LEN];
+    {
+        let mut igmp = MutableIgmpHeader::new(built.as_mut_slice());
+        igmp.set_igmp_type(IgmpTypes::V2MembershipReport);
+        igmp.set_group_address(Ipv4Addr(224, 0, 0, 251));
+        igmp.checksum();
+    }
+    assert_eq!(built.as_slice(), packet.as_slice());
+
+    // IPv6 group addresses are ignored
+    {
+        let mut igmp = MutableIgmpHeader::new(built.as_mut_slice());
+        igmp.set_group_address(Ipv6Addr(0xff02, 0, 0, 0, 0, 0, 0, 0xfb));
+    }
+    assert_eq!(built.as_slice(), packet.as_slice());
+
+    assert!(IgmpHeader::new_checked(packet.slice_to(7)).is_none());
+}
+
+#[test]
+fn igmp_v3_query() {
+    use std::io::net::ip::Ipv4Addr;
+
+    let mut packet = [0x11,                   /* type */
+                      0x64,                   /* max response code */
+                      0xe8, 0x16,             /* checksum */
+                      0xef, 0x01, 0x01, 0x01, /* group address */
+                      0x02,                   /* s flag, qrv */
+                      0x7d,                   /* qqic */
+                      0x00, 0x02,             /* number of sources */
+                      0x0a, 0x00, 0x00, 0x01, /* sources */
+                      0x0a, 0x00, 0x00, 0x02];
+    {
+        let igmp = IgmpHeader::new(packet.as_slice());
+        assert_eq!(igmp.get_igmp_type(), IgmpTypes::MembershipQuery);
+        assert!(igmp.is_v3_query());
+        assert!(igmp.is_checksum_valid());
+        assert_eq!(igmp.get_max_response_time(), 100);
+        assert_eq!(igmp.get_group_address(), Ipv4Addr(239, 1, 1, 1));
+        assert!(!igmp.get_suppress_router_processing());
+        assert_eq!(igmp.get_querier_robustness(), 2);
+        assert_eq!(igmp.get_querier_query_interval(), 125);
+        assert_eq!(igmp.get_number_of_sources(), 2);
+        let sources: Vec<IpAddr> = igmp.get_sources().collect();
+        assert_eq!(sources, vec![Ipv4Addr(10, 0, 0, 1), Ipv4Addr(10, 0, 0, 2)]);
+
+        // Sources past the end of the packet are skipped
+        let truncated = IgmpHeader::new(packet.slice_to(18));
+        assert_eq!(truncated.get_sources().count(), 1);
+        assert!(!truncated.is_checksum_valid());
+    }
+
+    packet[19] = 0x03;
+    assert!(!IgmpHeader::new(packet.as_slice()).is_checksum_valid());
+}
+
+#[test]
+fn igmp_v3_membership_report() {
+    use std::io::net::ip::Ipv4Addr;
+
+    let packet = [0x22,                   /* type */
+                  0x00,                   /* reserved */
+                  0x50, 0x55,             /* checksum */
+                  0x00, 0x00,             /* reserved */
+                  0x00, 0x02,             /* number of group records */
+                  0x04,                   /* record type */
+                  0x00,                   /* aux data length */
+                  0x00, 0x00,             /* number of sources */
+                  0xef, 0x01, 0x01, 0x01, /* multicast address */
+                  0x01,                   /* record type */
+                  0x01,                   /* aux data length */
+                  0x00, 0x01,             /* number of sources */
+                  0xef, 0x02, 0x02, 0x02, /* multicast address */
+                  0x0a, 0x00, 0x00, 0x01, /* sources */
+                  0xde, 0xad, 0xbe, 0xef  /* aux data */];
+    let igmp = IgmpHeader::new(packet.as_slice());
+    assert_eq!(igmp.get_igmp_type(), IgmpTypes::V3MembershipReport);
+    assert!(igmp.is_checksum_valid());
+    assert_eq!(igmp.get_number_of_group_records(), 2);
+
+    let records: Vec<IgmpGroupRecord> = igmp.get_group_records().collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].record_type, IgmpGroupRecordTypes::ChangeToExcludeMode);
+    assert_eq!(records[0].multicast_address, Ipv4Addr(239, 1, 1, 1));
+    assert_eq!(records[0].get_sources().count(), 0);
+    assert_eq!(records[1].record_type, IgmpGroupRecordTypes::ModeIsInclude);
+    assert_eq!(records[1].multicast_address, Ipv4Addr(239, 2, 2, 2));
+    assert_eq!(records[1].get_sources().collect::<Vec<IpAddr>>(), vec![Ipv4Addr(10, 0, 0, 1)]);
+    assert_eq!(records[1].aux_data, [0xdeu8, 0xad, 0xbe, 0xef].as_slice());
+
+    // A truncated record finishes iteration
+    let truncated = IgmpHeader::new(packet.slice_to(30));
+    assert_eq!(truncated.get_group_records().count(), 1);
+}
+
+/// IGMP message types, as defined at:
+/// http://www.iana.org/assignments/igmp-type-numbers/igmp-type-numbers.xhtml
+///
+/// FIXME Should include all
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod IgmpTypes {
+    use super::IgmpType;
+
+    /// Membership Query [RFC1112][RFC2236][RFC3376]
+    pub const MembershipQuery: IgmpType    = IgmpType(0x11);
+
+    /// IGMPv1 Membership Report [RFC1112]
+    pub const V1MembershipReport: IgmpType = IgmpType(0x12);
+
+    /// IGMPv2 Membership Report [RFC2236]
+    pub const V2MembershipReport: IgmpType = IgmpType(0x16);
+
+    /// IGMPv2 Leave Group [RFC2236]
+    pub const V2LeaveGroup: IgmpType       = IgmpType(0x17);
+
+    /// IGMPv3 Membership Report [RFC3376]
+    pub const V3MembershipReport: IgmpType = IgmpType(0x22);
+}
+
+/// Represents the IGMP type field
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IgmpType(pub u8);
+
+/// IGMPv3 group record types, as defined in RFC 3376
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod IgmpGroupRecordTypes {
+    use super::IgmpGroupRecordType;
+
+    /// MODE_IS_INCLUDE
+    pub const ModeIsInclude: IgmpGroupRecordType        = IgmpGroupRecordType(1);
+
+    /// MODE_IS_EXCLUDE
+    pub const ModeIsExclude: IgmpGroupRecordType        = IgmpGroupRecordType(2);
+
+    /// CHANGE_TO_INCLUDE_MODE
+    pub const ChangeToIncludeMode: IgmpGroupRecordType  = IgmpGroupRecordType(3);
+
+    /// CHANGE_TO_EXCLUDE_MODE
+    pub const ChangeToExcludeMode: IgmpGroupRecordType  = IgmpGroupRecordType(4);
+
+    /// ALLOW_NEW_SOURCES
+    pub const AllowNewSources: IgmpGroupRecordType      = IgmpGroupRecordType(5);
+
+    /// BLOCK_OLD_SOURCES
+    pub const BlockOldSources: IgmpGroupRecordType      = IgmpGroupRecordType(6);
+}
+
+/// Represents the type of an IGMPv3 group record
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IgmpGroupRecordType(pub u8);
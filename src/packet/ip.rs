@@ -463,6 +463,40 @@ pub mod IpNextHeaderProtocols {
 
 /// Represents an IPv4 next level protocol, or an IPv6 next header protocol,
 /// see `IpNextHeaderProtocols` for a list of values.
-#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct IpNextHeaderProtocol(pub u8);
 
+impl IpNextHeaderProtocol {
+    /// Construct an IpNextHeaderProtocol from its protocol number
+    pub fn new(value: u8) -> IpNextHeaderProtocol {
+        IpNextHeaderProtocol(value)
+    }
+
+    /// Get the protocol number
+    pub fn value(&self) -> u8 {
+        let IpNextHeaderProtocol(value) = *self;
+        value
+    }
+}
+
+#[test]
+fn ip_next_header_protocols() {
+    let protocols = [(IpNextHeaderProtocols::Icmp, 1u8),
+                     (IpNextHeaderProtocols::Tcp, 6),
+                     (IpNextHeaderProtocols::Udp, 17),
+                     (IpNextHeaderProtocols::Ipv6, 41),
+                     (IpNextHeaderProtocols::Ipv6Icmp, 58)];
+    for &(protocol, value) in protocols.iter() {
+        assert_eq!(protocol.value(), value);
+        assert_eq!(IpNextHeaderProtocol::new(value), protocol);
+    }
+
+    // Unassigned values keep their number
+    let unassigned = IpNextHeaderProtocol::new(143);
+    assert_eq!(unassigned.value(), 143);
+    match unassigned {
+        IpNextHeaderProtocols::Tcp | IpNextHeaderProtocols::Udp => fail!("unexpected protocol"),
+        IpNextHeaderProtocol(value) => assert_eq!(value, 143)
+    }
+}
+
@@ -8,11 +8,21 @@
 
 //! IPv4 packet abstraction
 
+use std::cmp;
+use std::collections::HashMap;
 use std::fmt;
 use std::io::net::ip::{IpAddr, Ipv4Addr};
+use std::time::Duration;
 
 use packet::{Packet, MutablePacket};
-use packet::ip::IpNextHeaderProtocol;
+use packet::icmp::{IcmpHeader, IcmpPacket};
+use packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use packet::tcp::{TcpHeader, TcpPacket};
+use packet::udp::{UdpHeader, UdpPacket};
+use util;
+
+/// The length of an IPv4 header without options, in bytes
+pub const IPV4_HEADER_LEN: uint = 20;
 
 /// Structure representing an IPv4 header
 pub struct Ipv4Header<'p> {
@@ -93,7 +103,7 @@ impl<'p> Packet for Ipv4Header<'p> {
     fn packet<'p>(&'p self) -> &'p [u8] { self.packet }
 
     #[inline(always)]
-    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(20) /* FIXME */ }
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(self.payload_offset()) }
 }
 
 impl<'p> Packet for MutableIpv4Header<'p> {
@@ -101,7 +111,7 @@ impl<'p> Packet for MutableIpv4Header<'p> {
     fn packet<'p>(&'p self) -> &'p [u8] { self.packet.as_slice() }
 
     #[inline(always)]
-    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(20) /* FIXME */ }
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(self.payload_offset()) }
 }
 
 impl<'p> MutablePacket for MutableIpv4Header<'p> {
@@ -109,7 +119,10 @@ impl<'p> MutablePacket for MutableIpv4Header<'p> {
     fn packet_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.as_mut_slice() }
 
     #[inline(always)]
-    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.slice_from_mut(20) /* FIXME */ }
+    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] {
+        let offset = self.payload_offset();
+        self.packet.slice_from_mut(offset)
+    }
 }
 
 /// Trait implemented by anything which provides an interface to read IPv4
@@ -120,11 +133,22 @@ pub trait Ipv4Packet : Packet {
         self.packet()[0] >> 4
     }
 
-    /// Get the header length field of the packet
+    /// Get the header length field of the packet. This is in 32-bit words.
     fn get_header_length(&self) -> u8 {
         self.packet()[0] & 0xF
     }
 
+    /// Get the length of the header in bytes, including any options
+    fn get_header_length_bytes(&self) -> uint {
+        self.get_header_length() as uint * 4
+    }
+
+    /// Get the offset of the payload, skipping any options. This is clamped to the length of the
+    /// buffer so that taking the payload of a truncated packet doesn't fail.
+    fn payload_offset(&self) -> uint {
+        cmp::min(self.get_header_length_bytes(), self.packet().len())
+    }
+
     /// Get the DSCP field of the packet
     fn get_dscp(&self) -> u8 {
         (self.packet()[1] & 0xFC) >> 2
@@ -194,20 +218,75 @@ pub trait Ipv4Packet : Packet {
                  self.packet()[19])
     }
 
-    /// Calculate the checksum for the packet
-    fn calculate_checksum(&mut self) -> u16 {
-        let len = self.get_header_length() as uint * 4;
-        let mut sum = 0u32;
-        let mut i = 0;
-        while i < len {
-            let word = self.packet()[i] as u32 << 8 | self.packet()[i + 1] as u32;
-            sum = sum + word;
-            i = i + 2;
+    /// Calculate the checksum for the packet. The checksum field itself is treated as zero.
+    fn calculate_checksum(&self) -> u16 {
+        util::checksum(self.packet().slice_to(self.payload_offset()), Some(5))
+    }
+
+    /// Get an iterator over the options of the packet, if there are any
+    fn get_options<'a>(&'a self) -> Ipv4OptionIterator<'a> {
+        let end = self.payload_offset();
+        let start = cmp::min(IPV4_HEADER_LEN, end);
+        Ipv4OptionIterator { buf: self.packet().slice(start, end) }
+    }
+
+    /// Does the checksum field of the packet match the checksum calculated for the header?
+    fn is_checksum_valid(&self) -> bool {
+        self.calculate_checksum() == self.get_checksum()
+    }
+}
+
+/// An IPv4 option
+#[deriving(PartialEq, Eq, Show)]
+pub struct Ipv4Option<'p> {
+    /// The type of the option
+    pub number: Ipv4OptionNumber,
+    /// The length of the option in bytes, including the type and length fields. This is 1 for
+    /// single byte options, which have no length field.
+    pub length: u8,
+    /// The data for the option, excluding the type and length fields
+    pub data: &'p [u8],
+}
+
+/// An iterator over the options of an IPv4 packet
+///
+/// Iteration finishes at the end of the header or at an end of options list option, which is
+/// not included. Iteration also finishes if an option is malformed.
+pub struct Ipv4OptionIterator<'p> {
+    buf: &'p [u8],
+}
+
+impl<'p> Iterator<Ipv4Option<'p>> for Ipv4OptionIterator<'p> {
+    fn next(&mut self) -> Option<Ipv4Option<'p>> {
+        if self.buf.len() == 0 {
+            return None;
         }
-        while sum >> 16 != 0 {
-            sum = (sum >> 16) + (sum & 0xFFFF);
+        let number = Ipv4OptionNumber(self.buf[0]);
+        if number == Ipv4OptionNumbers::Eol {
+            self.buf = self.buf.slice_to(0);
+            return None;
         }
-        return !sum as u16;
+        if number == Ipv4OptionNumbers::Nop {
+            let option = Ipv4Option { number: number, length: 1, data: self.buf.slice(1, 1) };
+            self.buf = self.buf.slice_from(1);
+            return Some(option);
+        }
+
+        // Any other option has a length field, which includes the type and length fields
+        if self.buf.len() < 2 || (self.buf[1] as uint) < 2 ||
+           self.buf[1] as uint > self.buf.len() {
+            self.buf = self.buf.slice_to(0);
+            return None;
+        }
+        let length = self.buf[1];
+        let option = Ipv4Option {
+            number: number,
+            length: length,
+            data: self.buf.slice(2, length as uint)
+        };
+        self.buf = self.buf.slice_from(length as uint);
+
+        Some(option)
     }
 }
 
@@ -220,6 +299,27 @@ impl<'p> Ipv4Header<'p> {
     pub fn new(packet: &'p [u8]) -> Ipv4Header<'p> {
         Ipv4Header { packet: packet }
     }
+
+    /// Construct a new IPv4 header backed by the given buffer, checking that the buffer is
+    /// large enough to contain the header, including any options. Returns None if it is not, if
+    /// the header length field is less than the minimum of 5, or if the total length field is
+    /// less than the header length or greater than the length of the buffer.
+    pub fn new_checked(packet: &'p [u8]) -> Option<Ipv4Header<'p>> {
+        if packet.len() < IPV4_HEADER_LEN {
+            return None;
+        }
+        let header = Ipv4Header::new(packet);
+        let header_len = header.get_header_length_bytes();
+        if header_len < IPV4_HEADER_LEN || header_len > packet.len() {
+            return None;
+        }
+        let total_len = header.get_total_length() as uint;
+        if total_len < header_len || total_len > packet.len() {
+            return None;
+        }
+
+        Some(header)
+    }
 }
 impl<'p> MutableIpv4Header<'p> {
     /// Construct a new mutable IPv4 header backed by the given buffer with
@@ -228,6 +328,20 @@ impl<'p> MutableIpv4Header<'p> {
         MutableIpv4Header { packet: packet }
     }
 
+    /// Construct a new mutable IPv4 header backed by the given buffer, and initialise it for
+    /// building a packet. The header is zeroed, then the version is set to 4, the header length
+    /// to 5 (no options) and the TTL to 64. The buffer must be at least `IPV4_HEADER_LEN` bytes.
+    pub fn new_with_defaults(packet: &'p mut [u8]) -> MutableIpv4Header<'p> {
+        for b in packet.slice_to_mut(IPV4_HEADER_LEN).iter_mut() {
+            *b = 0;
+        }
+        let mut header = MutableIpv4Header::new(packet);
+        header.set_version(4);
+        header.set_header_length(5);
+        header.set_ttl(64);
+        header
+    }
+
     /// Set the version field for the packet
     pub fn set_version(&mut self, version: u8) {
         let ver = version << 4;
@@ -258,6 +372,14 @@ impl<'p> MutableIpv4Header<'p> {
         self.packet[3] = (len & 0xFF) as u8;
     }
 
+    /// Set the total length field to the length of the buffer backing the packet, which should
+    /// be exactly the size of the packet. Buffers larger than the maximum IPv4 packet size are
+    /// treated as being 65535 bytes long.
+    pub fn set_total_length_auto(&mut self) {
+        let len = cmp::min(self.packet.len(), 0xFFFF) as u16;
+        self.set_total_length(len);
+    }
+
     /// Set the identification field for the packet
     pub fn set_identification(&mut self, identification: u16) {
         self.packet[4] = (identification >> 8) as u8;
@@ -390,3 +512,723 @@ fn ipv4_header_test() {
     assert_eq!(ref_packet.as_slice(), packet.as_slice());
 }
 
+#[test]
+fn ipv4_header_defaults() {
+    use packet::ip::IpNextHeaderProtocols;
+    use packet::udp::{MutableUdpHeader, UdpHeader, UdpPacket};
+
+    // Anything already in the buffer should be overwritten
+    let mut packet = [0xffu8, ..IPV4_HEADER_LEN + 8 + 4];
+    {
+        let mut ip_header = MutableIpv4Header::new_with_defaults(packet.as_mut_slice());
+        ip_header.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        ip_header.set_source(Ipv4Addr(192, 168, 0, 1));
+        ip_header.set_destination(Ipv4Addr(192, 168, 0, 2));
+        ip_header.set_total_length_auto();
+        ip_header.checksum();
+    }
+    {
+        let mut udp_header = MutableUdpHeader::new(packet.slice_from_mut(IPV4_HEADER_LEN));
+        udp_header.set_source(1234);
+        udp_header.set_destination(5678);
+        udp_header.set_length(12);
+    }
+
+    let ip_header = Ipv4Header::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(ip_header.get_version(), 4);
+    assert_eq!(ip_header.get_header_length(), 5);
+    assert_eq!(ip_header.get_total_length(), 32);
+    assert_eq!(ip_header.get_ttl(), 64);
+    assert_eq!(ip_header.get_dscp(), 0);
+    assert_eq!(ip_header.get_flags(), 0);
+    assert_eq!(ip_header.get_fragment_offset(), 0);
+    assert_eq!(ip_header.get_next_level_protocol(), IpNextHeaderProtocols::Udp);
+    assert!(ip_header.is_checksum_valid());
+    assert_eq!(ip_header.get_options().count(), 0);
+
+    let udp_header = UdpHeader::new(ip_header.payload());
+    assert_eq!(udp_header.get_destination(), 5678);
+    // The UDP payload was left alone
+    assert_eq!(udp_header.payload(), [0xffu8, 0xff, 0xff, 0xff].as_slice());
+}
+
+#[test]
+fn ipv4_header_options_payload() {
+    let packet = [0x46,                   /* ver/ihl */
+                  0x00,                   /* dscp/ecn */
+                  0x00, 0x1a,             /* total len */
+                  0x00, 0x00,             /* identification */
+                  0x00, 0x00,             /* flags/frag offset */
+                  0x40,                   /* ttl */
+                  0x11,                   /* proto */
+                  0x00, 0x00,             /* checksum */
+                  0xc0, 0xa8, 0x00, 0x01, /* source ip */
+                  0xc0, 0xa8, 0x00, 0xc7, /* dest ip */
+                  0x01, 0x01, 0x01, 0x00, /* options */
+                  0xaa, 0xbb              /* payload */];
+    let ip_header = Ipv4Header::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(ip_header.get_header_length(), 6);
+    assert_eq!(ip_header.get_header_length_bytes(), 24);
+    assert_eq!(ip_header.payload(), [0xaa, 0xbb].as_slice());
+
+    // Truncated in the options
+    assert!(Ipv4Header::new_checked(packet.slice_to(22)).is_none());
+    assert!(Ipv4Header::new_checked(packet.slice_to(19)).is_none());
+    // Truncated in the payload, so shorter than the total length
+    assert!(Ipv4Header::new_checked(packet.slice_to(24)).is_none());
+
+    // Header length less than the minimum
+    let mut short_ihl = packet;
+    short_ihl[0] = 0x44;
+    assert!(Ipv4Header::new_checked(short_ihl.as_slice()).is_none());
+}
+
+#[test]
+fn ipv4_header_checked_lengths() {
+    let packet = [0x45,                   /* ver/ihl */
+                  0x00,                   /* dscp/ecn */
+                  0x00, 0x18,             /* total len */
+                  0x00, 0x00,             /* identification */
+                  0x00, 0x00,             /* flags/frag offset */
+                  0x40,                   /* ttl */
+                  0x11,                   /* proto */
+                  0x00, 0x00,             /* checksum */
+                  0xc0, 0xa8, 0x00, 0x01, /* source ip */
+                  0xc0, 0xa8, 0x00, 0xc7, /* dest ip */
+                  0xaa, 0xbb, 0xcc, 0xdd, /* payload */
+                  0x00, 0x00              /* trailing padding */];
+    let ip_header = Ipv4Header::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(ip_header.get_total_length(), 24);
+
+    // Every truncation of the packet
+    for len in range(0u, 24) {
+        assert!(Ipv4Header::new_checked(packet.slice_to(len)).is_none());
+    }
+    assert!(Ipv4Header::new_checked(packet.slice_to(24)).is_some());
+
+    // Header lengths which claim more than the buffer
+    for ihl in range(7u8, 16) {
+        let mut over_claiming = packet;
+        over_claiming[0] = 0x40 | ihl;
+        over_claiming[3] = 4 * ihl;
+        assert!(Ipv4Header::new_checked(over_claiming.as_slice()).is_none());
+    }
+
+    // Total lengths which claim more than the buffer, or less than the header
+    for &total_len in [0u8, 19, 27, 0xff].iter() {
+        let mut inconsistent = packet;
+        inconsistent[3] = total_len;
+        assert!(Ipv4Header::new_checked(inconsistent.as_slice()).is_none());
+    }
+}
+
+#[test]
+fn ipv4_header_checksum() {
+    let mut packet = [0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11,
+                      0xb8, 0x61, /* checksum */
+                      0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7];
+    {
+        let ip_header = Ipv4Header::new(packet.as_slice());
+        assert_eq!(ip_header.calculate_checksum(), 0xb861);
+        assert!(ip_header.is_checksum_valid());
+    }
+
+    // Corrupt the TTL
+    packet[8] = 0x3f;
+    {
+        let ip_header = Ipv4Header::new(packet.as_slice());
+        assert!(!ip_header.is_checksum_valid());
+    }
+
+    // Recalculating fixes it, regardless of the old value in the checksum field
+    {
+        let mut ip_header = MutableIpv4Header::new(packet.as_mut_slice());
+        ip_header.checksum();
+        assert_eq!(ip_header.get_checksum(), 0xb961);
+        assert!(ip_header.is_checksum_valid());
+    }
+}
+
+#[test]
+fn ipv4_header_options() {
+    let packet = [0x48,                   /* ver/ihl */
+                  0x00,                   /* dscp/ecn */
+                  0x00, 0x20,             /* total len */
+                  0x00, 0x00,             /* identification */
+                  0x00, 0x00,             /* flags/frag offset */
+                  0x40,                   /* ttl */
+                  0x11,                   /* proto */
+                  0x00, 0x00,             /* checksum */
+                  0xc0, 0xa8, 0x00, 0x01, /* source ip */
+                  0xc0, 0xa8, 0x00, 0xc7, /* dest ip */
+                  0x01,                   /* nop */
+                  0x07, 0x07, 0x04,       /* record route, pointer */
+                  0xc0, 0xa8, 0x00, 0x01, /* route data */
+                  0x00,                   /* end of options */
+                  0x00, 0x00, 0x00        /* padding */];
+    let ip_header = Ipv4Header::new_checked(packet.as_slice()).unwrap();
+    let options: Vec<Ipv4Option> = ip_header.get_options().collect();
+    assert_eq!(options.len(), 2);
+
+    assert_eq!(options[0].number, Ipv4OptionNumbers::Nop);
+    assert_eq!(options[0].length, 1);
+    assert!(options[0].data.is_empty());
+
+    assert_eq!(options[1].number, Ipv4OptionNumbers::Rr);
+    assert_eq!(options[1].length, 7);
+    assert_eq!(options[1].data, [0x04, 0xc0, 0xa8, 0x00, 0x01].as_slice());
+
+    // A header without options
+    let mut no_options = packet;
+    no_options[0] = 0x45;
+    let ip_header = Ipv4Header::new(no_options.as_slice());
+    assert_eq!(ip_header.get_options().count(), 0);
+
+    // An option which runs past the end of the header stops iteration
+    let mut malformed = packet;
+    malformed[22] = 0x20;
+    let ip_header = Ipv4Header::new(malformed.as_slice());
+    let options: Vec<Ipv4Option> = ip_header.get_options().collect();
+    assert_eq!(options.len(), 1);
+    assert_eq!(options[0].number, Ipv4OptionNumbers::Nop);
+
+    // As does an option with an impossible length
+    malformed[22] = 0x01;
+    let ip_header = Ipv4Header::new(malformed.as_slice());
+    assert_eq!(ip_header.get_options().count(), 1);
+}
+
+/// Split an IPv4 packet into fragments of at most `mtu` bytes each, as described in RFC 791.
+///
+/// The payload is split on 8-byte boundaries. Each fragment has the more fragments flag and
+/// fragment offset set, and its checksum recalculated. Only options with the copied flag set are
+/// included in fragments after the first. A packet which already fits is returned as is.
+///
+/// Returns None if the packet needs to be fragmented but has the don't fragment flag set, or if
+/// `mtu` is too small to carry the header and eight bytes of payload.
+pub fn fragment<T: Ipv4Packet>(packet: &T, mtu: uint) -> Option<Vec<Vec<u8>>> {
+    // Anything after the total length, such as Ethernet padding, isn't part of the packet
+    let total_len = cmp::min(packet.get_total_length() as uint, packet.packet().len());
+    let bytes = packet.packet().slice_to(cmp::max(total_len, packet.payload_offset()));
+    if bytes.len() <= mtu {
+        return Some(vec![bytes.to_vec()]);
+    }
+    if packet.get_flags() & Ipv4Flags::DontFragment != 0 {
+        return None;
+    }
+    let header_len = packet.payload_offset();
+    if mtu < header_len + 8 {
+        return None;
+    }
+
+    let first_header = bytes.slice_to(header_len);
+    let mut later_header = bytes.slice_to(IPV4_HEADER_LEN).to_vec();
+    for option in packet.get_options() {
+        let Ipv4OptionNumber(number) = option.number;
+        // The copied flag is the top bit of the option number
+        if number & 0x80 != 0 {
+            later_header.push(number);
+            later_header.push(option.length);
+            later_header.push_all(option.data);
+        }
+    }
+    // Pad with end of options list
+    while later_header.len() % 4 != 0 {
+        later_header.push(0);
+    }
+
+    // Fragmenting a fragment keeps its offset, and the more fragments flag on its last piece
+    let base_offset = packet.get_fragment_offset() as uint;
+    let more_fragments = packet.get_flags() & Ipv4Flags::MoreFragments != 0;
+    let payload = bytes.slice_from(header_len);
+    let mut fragments = Vec::new();
+    let mut start = 0u;
+    while start < payload.len() {
+        let header = if start == 0 { first_header } else { later_header.as_slice() };
+        let end = cmp::min(start + ((mtu - header.len()) & !7), payload.len());
+        let len = header.len() + end - start;
+
+        let mut buf = Vec::from_elem(len, 0u8);
+        buf.slice_to_mut(header.len()).clone_from_slice(header);
+        buf.slice_from_mut(header.len()).clone_from_slice(payload.slice(start, end));
+        {
+            let mut ip_header = MutableIpv4Header::new(buf.as_mut_slice());
+            ip_header.set_header_length((header.len() / 4) as u8);
+            ip_header.set_total_length(len as u16);
+            let mut flags = ip_header.get_flags() & !Ipv4Flags::MoreFragments;
+            if end < payload.len() || more_fragments {
+                flags = flags | Ipv4Flags::MoreFragments;
+            }
+            ip_header.set_flags(flags);
+            ip_header.set_fragment_offset((base_offset + start / 8) as u16);
+            ip_header.checksum();
+        }
+        fragments.push(buf);
+        start = end;
+    }
+
+    Some(fragments)
+}
+
+#[cfg(test)]
+fn reassemble(fragments: &Vec<Vec<u8>>) -> Vec<u8> {
+    let mut payload = Vec::new();
+    for (i, fragment) in fragments.iter().enumerate() {
+        let ip_header = Ipv4Header::new_checked(fragment.as_slice()).unwrap();
+        assert!(ip_header.is_checksum_valid());
+        assert_eq!(ip_header.get_fragment_offset() as uint * 8, payload.len());
+        let last = i == fragments.len() - 1;
+        assert_eq!(ip_header.get_flags() & Ipv4Flags::MoreFragments != 0, !last);
+        payload.push_all(ip_header.payload());
+    }
+    payload
+}
+
+#[test]
+fn ipv4_fragment() {
+    let mut packet = [0u8, ..IPV4_HEADER_LEN + 100];
+    for (i, b) in packet.slice_from_mut(IPV4_HEADER_LEN).iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    {
+        let mut ip_header = MutableIpv4Header::new_with_defaults(packet.as_mut_slice());
+        ip_header.set_identification(0x1234);
+        ip_header.set_total_length_auto();
+        ip_header.checksum();
+    }
+    let ip_header = Ipv4Header::new(packet.as_slice());
+
+    // 24 bytes of payload fit in each fragment
+    let fragments = fragment(&ip_header, 48).unwrap();
+    assert_eq!(fragments.len(), 5);
+    for fragment in fragments.iter() {
+        assert!(fragment.len() <= 48);
+        assert_eq!(Ipv4Header::new(fragment.as_slice()).get_identification(), 0x1234);
+    }
+    assert_eq!(Ipv4Header::new(fragments[4].as_slice()).get_total_length(), 24);
+    assert_eq!(reassemble(&fragments).as_slice(), ip_header.payload());
+
+    // A packet which fits isn't changed
+    let fragments = fragment(&ip_header, 1500).unwrap();
+    assert_eq!(fragments, vec![packet.to_vec()]);
+
+    // The MTU has to fit the header and at least eight bytes
+    assert!(fragment(&ip_header, 27).is_none());
+    assert_eq!(fragment(&ip_header, 28).unwrap().len(), 13);
+}
+
+#[test]
+fn ipv4_fragment_dont_fragment() {
+    let mut packet = [0u8, ..IPV4_HEADER_LEN + 100];
+    {
+        let mut ip_header = MutableIpv4Header::new_with_defaults(packet.as_mut_slice());
+        ip_header.set_flags(Ipv4Flags::DontFragment);
+        ip_header.set_total_length_auto();
+        ip_header.checksum();
+    }
+    let ip_header = Ipv4Header::new(packet.as_slice());
+    assert!(fragment(&ip_header, 48).is_none());
+    assert_eq!(fragment(&ip_header, 120).unwrap(), vec![packet.to_vec()]);
+}
+
+#[test]
+fn ipv4_fragment_options() {
+    let mut packet = [0u8, ..32 + 40];
+    packet.slice_mut(IPV4_HEADER_LEN, 32).clone_from_slice([
+        0x07, 0x03, 0x04,                         /* record route, not copied */
+        0x83, 0x07, 0x04, 0xc0, 0xa8, 0x00, 0x01, /* loose source route, copied */
+        0x00, 0x00                                /* end of options */
+    ].as_slice());
+    {
+        let mut ip_header = MutableIpv4Header::new(packet.as_mut_slice());
+        ip_header.set_version(4);
+        ip_header.set_header_length(8);
+        ip_header.set_total_length_auto();
+        ip_header.checksum();
+    }
+    let ip_header = Ipv4Header::new(packet.as_slice());
+
+    let fragments = fragment(&ip_header, 60).unwrap();
+    assert_eq!(fragments.len(), 2);
+    let first = Ipv4Header::new(fragments[0].as_slice());
+    assert_eq!(first.get_header_length(), 8);
+    assert_eq!(first.get_options().count(), 2);
+    // Only the loose source route option is copied, padded to a multiple of four bytes
+    let second = Ipv4Header::new(fragments[1].as_slice());
+    assert_eq!(second.get_header_length(), 7);
+    let options: Vec<Ipv4Option> = second.get_options().collect();
+    assert_eq!(options.len(), 1);
+    assert_eq!(options[0].number, Ipv4OptionNumbers::Lsrr);
+    assert_eq!(reassemble(&fragments).as_slice(), ip_header.payload());
+}
+
+// The fragments received so far for a datagram
+struct FragmentSet {
+    // When the first fragment was received
+    first_seen: Duration,
+    data: Vec<u8>,
+    // Which bytes of `data` have been received
+    received: Vec<bool>,
+    // The length of the payload, known once the last fragment has been received
+    total_len: Option<uint>,
+}
+
+impl FragmentSet {
+    // Add a fragment's payload at the given byte offset. Returns false if the fragment
+    // contradicts what has already been received, in which case the set should be dropped.
+    fn add(&mut self, offset: uint, payload: &[u8], last: bool) -> bool {
+        let end = offset + payload.len();
+        if last {
+            match self.total_len {
+                Some(len) if len != end => return false,
+                _ => ()
+            }
+            // Nothing may have been received beyond the end of the datagram
+            if self.received.iter().skip(end).any(|&r| r) {
+                return false;
+            }
+            self.total_len = Some(end);
+        } else {
+            match self.total_len {
+                Some(len) if end > len => return false,
+                _ => ()
+            }
+        }
+        while self.data.len() < end {
+            self.data.push(0);
+            self.received.push(false);
+        }
+        // Overlapping data is only accepted if it matches what was already received
+        for (i, &b) in payload.iter().enumerate() {
+            if self.received[offset + i] && self.data[offset + i] != b {
+                return false;
+            }
+            self.data[offset + i] = b;
+            self.received[offset + i] = true;
+        }
+        true
+    }
+
+    fn is_complete(&self) -> bool {
+        match self.total_len {
+            Some(len) => self.received.iter().take(len).all(|&r| r),
+            None => false
+        }
+    }
+}
+
+/// Reassembles fragmented IPv4 datagrams
+///
+/// Fragments are grouped by source, destination, identification and protocol, and may arrive in
+/// any order. Fragments which overlap are only accepted if the overlapping bytes are the same;
+/// otherwise the whole datagram is discarded, since conflicting fragments are usually an attempt
+/// to evade inspection. Datagrams which aren't completed within the timeout are discarded.
+pub struct Reassembler {
+    timeout: Duration,
+    pending: HashMap<(IpAddr, IpAddr, u16, IpNextHeaderProtocol), FragmentSet>,
+}
+
+impl Reassembler {
+    /// Create a new reassembler, which discards incomplete datagrams `timeout` after their first
+    /// fragment was received. RFC 1122 suggests a timeout of between 60 and 120 seconds.
+    pub fn new(timeout: Duration) -> Reassembler {
+        Reassembler {
+            timeout: timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Add a packet, returning the payload of the datagram it belongs to if that datagram is now
+    /// complete. Packets which aren't fragmented are returned straight away.
+    ///
+    /// `now` is the time the packet was received, for example a timestamp from
+    /// `DataLinkChannelIterator::next_with_timestamp()`; it only needs to be consistent between
+    /// calls. Incomplete datagrams which have timed out are discarded first.
+    pub fn add<T: Ipv4Packet>(&mut self, packet: &T, now: Duration) -> Option<Vec<u8>> {
+        self.expire(now);
+
+        let start = packet.payload_offset();
+        let end = cmp::max(start, cmp::min(packet.get_total_length() as uint,
+                                           packet.packet().len()));
+        let payload = packet.packet().slice(start, end);
+        let offset = packet.get_fragment_offset() as uint * 8;
+        let last = packet.get_flags() & Ipv4Flags::MoreFragments == 0;
+        if offset == 0 && last {
+            return Some(payload.to_vec());
+        }
+        // Every fragment but the last must carry a multiple of eight bytes
+        if (!last && payload.len() % 8 != 0) || offset + payload.len() > 0xFFFF {
+            return None;
+        }
+
+        let key = (packet.get_source(), packet.get_destination(), packet.get_identification(),
+                   packet.get_next_level_protocol());
+        let (accepted, complete) = {
+            let set = self.pending.find_or_insert_with(key, |_| FragmentSet {
+                first_seen: now,
+                data: Vec::new(),
+                received: Vec::new(),
+                total_len: None,
+            });
+            let accepted = set.add(offset, payload, last);
+            (accepted, accepted && set.is_complete())
+        };
+        if !accepted {
+            self.pending.remove(&key);
+            return None;
+        }
+        if !complete {
+            return None;
+        }
+        let set = self.pending.pop(&key).unwrap();
+        let mut data = set.data;
+        data.truncate(set.total_len.unwrap());
+        Some(data)
+    }
+
+    /// Discard any incomplete datagrams which have timed out
+    pub fn expire(&mut self, now: Duration) {
+        let timeout = self.timeout;
+        let expired: Vec<(IpAddr, IpAddr, u16, IpNextHeaderProtocol)> =
+            self.pending.iter().filter(|&(_, set)| now - set.first_seen > timeout)
+                               .map(|(key, _)| *key).collect();
+        for key in expired.iter() {
+            self.pending.remove(key);
+        }
+    }
+
+    /// The number of datagrams which are waiting for more fragments
+    pub fn pending(&self) -> uint {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+fn fragments_for_reassembly(id: u16) -> (Vec<u8>, Vec<Vec<u8>>) {
+    let mut packet = [0u8, ..IPV4_HEADER_LEN + 100];
+    for (i, b) in packet.slice_from_mut(IPV4_HEADER_LEN).iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    {
+        let mut ip_header = MutableIpv4Header::new_with_defaults(packet.as_mut_slice());
+        ip_header.set_identification(id);
+        ip_header.set_source(Ipv4Addr(192, 168, 0, 1));
+        ip_header.set_destination(Ipv4Addr(192, 168, 0, 2));
+        ip_header.set_total_length_auto();
+        ip_header.checksum();
+    }
+    let fragments = fragment(&Ipv4Header::new(packet.as_slice()), 48).unwrap();
+    (packet.slice_from(IPV4_HEADER_LEN).to_vec(), fragments)
+}
+
+#[test]
+fn ipv4_reassembly() {
+    let (payload, fragments) = fragments_for_reassembly(1);
+    assert_eq!(fragments.len(), 5);
+    let now = Duration::seconds(0);
+
+    // In order
+    let mut reassembler = Reassembler::new(Duration::seconds(30));
+    for (i, fragment) in fragments.iter().enumerate() {
+        let res = reassembler.add(&Ipv4Header::new(fragment.as_slice()), now);
+        if i < fragments.len() - 1 {
+            assert!(res.is_none());
+        } else {
+            assert_eq!(res, Some(payload.clone()));
+        }
+    }
+    assert_eq!(reassembler.pending(), 0);
+
+    // Out of order, with the last fragment first and a duplicate
+    for &i in [4u, 1, 0, 1, 3].iter() {
+        let res = reassembler.add(&Ipv4Header::new(fragments[i].as_slice()), now);
+        assert!(res.is_none());
+    }
+    assert_eq!(reassembler.add(&Ipv4Header::new(fragments[2].as_slice()), now), Some(payload));
+    assert_eq!(reassembler.pending(), 0);
+
+    // Packets which aren't fragmented are returned as they are
+    let mut packet = [0u8, ..IPV4_HEADER_LEN + 4];
+    {
+        let mut ip_header = MutableIpv4Header::new_with_defaults(packet.as_mut_slice());
+        ip_header.set_total_length_auto();
+    }
+    packet.slice_from_mut(IPV4_HEADER_LEN).clone_from_slice([1u8, 2, 3, 4].as_slice());
+    assert_eq!(reassembler.add(&Ipv4Header::new(packet.as_slice()), now), Some(vec![1, 2, 3, 4]));
+}
+
+#[test]
+fn ipv4_reassembly_incomplete() {
+    let (_, fragments) = fragments_for_reassembly(2);
+    let mut reassembler = Reassembler::new(Duration::seconds(30));
+
+    // Everything but the third fragment
+    for &i in [0u, 1, 3, 4].iter() {
+        let res = reassembler.add(&Ipv4Header::new(fragments[i].as_slice()),
+                                  Duration::seconds(i as i64));
+        assert!(res.is_none());
+    }
+    assert_eq!(reassembler.pending(), 1);
+
+    // Once the set times out, the missing fragment can't complete it
+    reassembler.expire(Duration::seconds(31));
+    assert_eq!(reassembler.pending(), 0);
+    let res = reassembler.add(&Ipv4Header::new(fragments[2].as_slice()), Duration::seconds(32));
+    assert!(res.is_none());
+    assert_eq!(reassembler.pending(), 1);
+}
+
+#[test]
+fn ipv4_reassembly_overlapping() {
+    let (payload, fragments) = fragments_for_reassembly(3);
+    let now = Duration::seconds(0);
+    let mut reassembler = Reassembler::new(Duration::seconds(30));
+
+    // A fragment which overlaps others with the same data is accepted
+    let mut overlap = fragments[1].clone();
+    {
+        let mut ip_header = MutableIpv4Header::new(overlap.as_mut_slice());
+        ip_header.set_fragment_offset(2);
+    }
+    for (i, b) in overlap.slice_from_mut(IPV4_HEADER_LEN).iter_mut().enumerate() {
+        *b = (16 + i) as u8;
+    }
+    assert!(reassembler.add(&Ipv4Header::new(overlap.as_slice()), now).is_none());
+    for i in range(0u, fragments.len() - 1) {
+        assert!(reassembler.add(&Ipv4Header::new(fragments[i].as_slice()), now).is_none());
+    }
+    let last = fragments[fragments.len() - 1].as_slice();
+    assert_eq!(reassembler.add(&Ipv4Header::new(last), now), Some(payload));
+
+    // One which conflicts causes the whole datagram to be dropped
+    for b in overlap.slice_from_mut(IPV4_HEADER_LEN).iter_mut() {
+        *b = 0xff;
+    }
+    assert!(reassembler.add(&Ipv4Header::new(fragments[0].as_slice()), now).is_none());
+    assert!(reassembler.add(&Ipv4Header::new(overlap.as_slice()), now).is_none());
+    assert_eq!(reassembler.pending(), 0);
+}
+
+/// Check the checksum of the TCP, UDP or ICMP packet carried by an IPv4 packet, using the
+/// addresses from the IPv4 header for the pseudo-header where needed.
+///
+/// Returns true if there is nothing to verify: for other protocols, and for fragments, since the
+/// checksum covers the whole reassembled datagram. Returns false if the payload is too short to
+/// hold the transport header.
+pub fn verify_transport_checksum<T: Ipv4Packet>(packet: &T) -> bool {
+    if packet.get_fragment_offset() != 0 || packet.get_flags() & Ipv4Flags::MoreFragments != 0 {
+        return true;
+    }
+
+    // Ignore anything after the end of the packet, such as Ethernet padding
+    let start = packet.payload_offset();
+    let end = cmp::min(packet.get_total_length() as uint, packet.packet().len());
+    if end < start {
+        return false;
+    }
+    let payload = packet.packet().slice(start, end);
+
+    let source = packet.get_source();
+    let destination = packet.get_destination();
+    let protocol = packet.get_next_level_protocol();
+    if protocol == IpNextHeaderProtocols::Tcp {
+        match TcpHeader::new_checked(payload) {
+            Some(tcp) => tcp.is_checksum_valid(source, destination),
+            None => false
+        }
+    } else if protocol == IpNextHeaderProtocols::Udp {
+        match UdpHeader::new_checked(payload) {
+            Some(udp) => udp.is_checksum_valid(source, destination, protocol),
+            None => false
+        }
+    } else if protocol == IpNextHeaderProtocols::Icmp {
+        match IcmpHeader::new_checked(payload) {
+            Some(icmp) => icmp.is_checksum_valid(),
+            None => false
+        }
+    } else {
+        true
+    }
+}
+
+#[test]
+fn ipv4_verify_transport_checksum() {
+    let mut packet = [0x45u8, 0x00, 0x00, 0x20, 0x12, 0x34, 0x40, 0x00,
+                      0x40, 0x11, 0xa7, 0x45, 0xc0, 0xa8, 0x00, 0x01,
+                      0xc0, 0xa8, 0x00, 0x02,
+                      0x30, 0x39, 0x00, 0x35, 0x00, 0x0c, 0x66, 0x3a, /* udp header */
+                      0x74, 0x65, 0x73, 0x74, /* "test" */
+                      0x00, 0x00 /* padding */];
+    assert!(verify_transport_checksum(&Ipv4Header::new(packet.as_slice())));
+
+    // A corrupted payload byte
+    packet[29] ^= 0x01;
+    assert!(!verify_transport_checksum(&Ipv4Header::new(packet.as_slice())));
+    packet[29] ^= 0x01;
+
+    // Fragments can't be checked
+    let mut fragment = packet;
+    fragment[29] ^= 0x01;
+    fragment[6] |= 0x20;
+    assert!(verify_transport_checksum(&Ipv4Header::new(fragment.as_slice())));
+
+    // Nor can unknown protocols
+    let mut unknown = packet;
+    unknown[9] = 0xfd;
+    unknown[29] ^= 0x01;
+    assert!(verify_transport_checksum(&Ipv4Header::new(unknown.as_slice())));
+
+    // Too short to hold a UDP header
+    let mut truncated = packet;
+    truncated[3] = 0x18;
+    assert!(!verify_transport_checksum(&Ipv4Header::new(truncated.as_slice())));
+}
+
+/// Flags for the flags field of IPv4 packets, as returned by `get_flags()`
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod Ipv4Flags {
+    /// Don't fragment [RFC791]
+    pub const DontFragment: u8 = 0b010;
+
+    /// More fragments [RFC791]
+    pub const MoreFragments: u8 = 0b001;
+}
+
+/// IPv4 option numbers, as defined at:
+/// http://www.iana.org/assignments/ip-parameters/ip-parameters.xhtml
+///
+/// FIXME Should include all
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod Ipv4OptionNumbers {
+    use super::Ipv4OptionNumber;
+
+    /// End of Options List [RFC791]
+    pub const Eol: Ipv4OptionNumber  = Ipv4OptionNumber(0);
+
+    /// No Operation [RFC791]
+    pub const Nop: Ipv4OptionNumber  = Ipv4OptionNumber(1);
+
+    /// Record Route [RFC791]
+    pub const Rr: Ipv4OptionNumber   = Ipv4OptionNumber(7);
+
+    /// Time Stamp [RFC791]
+    pub const Ts: Ipv4OptionNumber   = Ipv4OptionNumber(68);
+
+    /// Security [RFC1108]
+    pub const Sec: Ipv4OptionNumber  = Ipv4OptionNumber(130);
+
+    /// Loose Source Route [RFC791]
+    pub const Lsrr: Ipv4OptionNumber = Ipv4OptionNumber(131);
+
+    /// Strict Source Route [RFC791]
+    pub const Ssrr: Ipv4OptionNumber = Ipv4OptionNumber(137);
+
+    /// Router Alert [RFC2113]
+    pub const Rtralt: Ipv4OptionNumber = Ipv4OptionNumber(148);
+}
+
+/// Represents the type field of an IPv4 option, see `Ipv4OptionNumbers` for a list of values.
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ipv4OptionNumber(pub u8);
@@ -8,11 +8,19 @@
 
 //! IPv6 packet abstraction
 
+use std::cmp;
 use std::fmt;
-use std::io::net::ip::{IpAddr, Ipv6Addr};
+use std::io::net::ip::IpAddr;
 
 use packet::{Packet, MutablePacket};
-use packet::ip::IpNextHeaderProtocol;
+use packet::icmpv6::{Icmpv6Header, Icmpv6Packet};
+use packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use packet::tcp::{TcpHeader, TcpPacket};
+use packet::udp::{UdpHeader, UdpPacket};
+use util;
+
+/// The length of an IPv6 header, in bytes
+pub const IPV6_HEADER_LEN: uint = 40;
 
 /// Structure representing an IPv6 header
 pub struct Ipv6Header<'p> {
@@ -126,34 +134,50 @@ pub trait Ipv6Packet : Packet {
 
     /// Get the source IP address for the packet
     fn get_source(&self) -> IpAddr {
-        let packet = self.packet();
-
-        let a = (packet[ 8] as u16 << 8) | packet[ 9] as u16;
-        let b = (packet[10] as u16 << 8) | packet[11] as u16;
-        let c = (packet[12] as u16 << 8) | packet[13] as u16;
-        let d = (packet[14] as u16 << 8) | packet[15] as u16;
-        let e = (packet[16] as u16 << 8) | packet[17] as u16;
-        let f = (packet[18] as u16 << 8) | packet[19] as u16;
-        let g = (packet[20] as u16 << 8) | packet[21] as u16;
-        let h = (packet[22] as u16 << 8) | packet[23] as u16;
-
-        Ipv6Addr(a, b, c, d, e, f, g, h)
+        let mut octets = [0u8, ..16];
+        octets.clone_from_slice(self.packet().slice(8, 24));
+        util::ipv6_from_octets(octets)
     }
 
     /// Get the destination IP address for the packet
     fn get_destination(&self) -> IpAddr {
-        let packet = self.packet();
+        let mut octets = [0u8, ..16];
+        octets.clone_from_slice(self.packet().slice(24, 40));
+        util::ipv6_from_octets(octets)
+    }
 
-        let a = (packet[24] as u16 << 8) | packet[25] as u16;
-        let b = (packet[26] as u16 << 8) | packet[27] as u16;
-        let c = (packet[28] as u16 << 8) | packet[29] as u16;
-        let d = (packet[30] as u16 << 8) | packet[31] as u16;
-        let e = (packet[32] as u16 << 8) | packet[33] as u16;
-        let f = (packet[34] as u16 << 8) | packet[35] as u16;
-        let g = (packet[36] as u16 << 8) | packet[37] as u16;
-        let h = (packet[38] as u16 << 8) | packet[39] as u16;
+    /// Follow the chain of extension headers, returning the protocol of the upper-layer header
+    /// and the buffer it starts at.
+    ///
+    /// Hop-by-Hop Options, Routing, Fragment and Destination Options headers are skipped. The
+    /// walk stops at any other header, or at an extension header which is truncated, and that
+    /// header is returned as-is. Note that for a fragment other than the first, the buffer
+    /// returned is the continuation of the fragmented payload, not an upper-layer header.
+    fn get_upper_layer<'a>(&'a self) -> (IpNextHeaderProtocol, &'a [u8]) {
+        let mut next_header = self.get_next_header();
+        let mut buf = self.payload();
+        loop {
+            let len = if next_header == IpNextHeaderProtocols::Hopopt ||
+                         next_header == IpNextHeaderProtocols::Ipv6Route ||
+                         next_header == IpNextHeaderProtocols::Ipv6Opts {
+                // The length field is in 8-octet units, not including the first 8 octets
+                if buf.len() < 2 {
+                    break;
+                }
+                (buf[1] as uint + 1) * 8
+            } else if next_header == IpNextHeaderProtocols::Ipv6Frag {
+                8
+            } else {
+                break;
+            };
+            if len > buf.len() {
+                break;
+            }
+            next_header = IpNextHeaderProtocol(buf[0]);
+            buf = buf.slice_from(len);
+        }
 
-        Ipv6Addr(a, b, c, d, e, f, g, h)
+        (next_header, buf)
     }
 }
 
@@ -166,6 +190,21 @@ impl<'p> Ipv6Header<'p> {
     pub fn new(packet: &'p [u8]) -> Ipv6Header<'p> {
         Ipv6Header { packet: packet }
     }
+
+    /// Construct a new IPv6 header backed by the given buffer, checking that it is large
+    /// enough to contain an IPv6 header and the payload length it declares. Returns None if it
+    /// is not.
+    pub fn new_checked(packet: &'p [u8]) -> Option<Ipv6Header<'p>> {
+        if packet.len() < IPV6_HEADER_LEN {
+            return None;
+        }
+        let header = Ipv6Header::new(packet);
+        if IPV6_HEADER_LEN + header.get_payload_length() as uint > packet.len() {
+            return None;
+        }
+
+        Some(header)
+    }
 }
 
 impl<'p> MutableIpv6Header<'p> {
@@ -184,7 +223,7 @@ impl<'p> MutableIpv6Header<'p> {
     /// Set the traffic class field for the packet
     pub fn set_traffic_class(&mut self, tc: u8) {
         self.packet[0] = (self.packet[0] & 0xF0) | (tc >> 4);
-        self.packet[1] = ((tc & 0x0F) << 4) | ((self.packet[1] & 0xF0) >> 4);
+        self.packet[1] = ((tc & 0x0F) << 4) | (self.packet[1] & 0x0F);
     }
 
     /// Set the flow label field for the packet
@@ -213,57 +252,24 @@ impl<'p> MutableIpv6Header<'p> {
 
     /// Set the source IP address for the packet
     pub fn set_source(&mut self, ip: IpAddr) {
-        match ip {
-            Ipv6Addr(a, b, c, d, e, f, g, h) => {
-                self.packet[ 8] = (a >> 8) as u8;
-                self.packet[ 9] = (a & 0xFF) as u8;
-                self.packet[10] = (b >> 8) as u8;
-                self.packet[11] = (b & 0xFF) as u8;;
-                self.packet[12] = (c >> 8) as u8;
-                self.packet[13] = (c & 0xFF) as u8;;
-                self.packet[14] = (d >> 8) as u8;
-                self.packet[15] = (d & 0xFF) as u8;;
-                self.packet[16] = (e >> 8) as u8;
-                self.packet[17] = (e & 0xFF) as u8;;
-                self.packet[18] = (f >> 8) as u8;
-                self.packet[19] = (f & 0xFF) as u8;;
-                self.packet[20] = (g >> 8) as u8;
-                self.packet[21] = (g & 0xFF) as u8;;
-                self.packet[22] = (h >> 8) as u8;
-                self.packet[23] = (h & 0xFF) as u8;
-            },
-            _ => ()
+        match util::ipv6_octets(ip) {
+            Some(octets) => { self.packet.slice_mut(8, 24).clone_from_slice(octets.as_slice()); },
+            None => ()
         }
     }
 
     /// Set the destination IP address for the packet
     pub fn set_destination(&mut self, ip: IpAddr) {
-        match ip {
-            Ipv6Addr(a, b, c, d, e, f, g, h) => {
-                self.packet[24] = (a >> 8) as u8;
-                self.packet[25] = (a & 0xFF) as u8;
-                self.packet[26] = (b >> 8) as u8;
-                self.packet[27] = (b & 0xFF) as u8;;
-                self.packet[28] = (c >> 8) as u8;
-                self.packet[29] = (c & 0xFF) as u8;;
-                self.packet[30] = (d >> 8) as u8;
-                self.packet[31] = (d & 0xFF) as u8;;
-                self.packet[32] = (e >> 8) as u8;
-                self.packet[33] = (e & 0xFF) as u8;;
-                self.packet[34] = (f >> 8) as u8;
-                self.packet[35] = (f & 0xFF) as u8;;
-                self.packet[36] = (g >> 8) as u8;
-                self.packet[37] = (g & 0xFF) as u8;;
-                self.packet[38] = (h >> 8) as u8;
-                self.packet[39] = (h & 0xFF) as u8;
-            },
-            _ => ()
+        match util::ipv6_octets(ip) {
+            Some(octets) => { self.packet.slice_mut(24, 40).clone_from_slice(octets.as_slice()); },
+            None => ()
         }
     }
 }
 
 #[test]
 fn ipv6_header_test() {
+    use std::io::net::ip::Ipv6Addr;
     use packet::ip::IpNextHeaderProtocols;
     let mut packet = [0u8, ..40];
     {
@@ -320,3 +326,256 @@ fn ipv6_header_test() {
     assert_eq!(ref_packet.as_slice(), packet.as_slice());
 }
 
+#[test]
+fn ipv6_header_parse() {
+    use std::io::net::ip::Ipv6Addr;
+    use packet::ip::IpNextHeaderProtocols;
+
+    let packet = [0x6a, 0xbc, 0xde, 0xf1, /* ver/traffic class/flow label */
+                  0x00, 0x02,             /* payload length */
+                  0x3a,                   /* next header */
+                  0x40,                   /* hop limit */
+                  0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* source ip */
+                  0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+                  0xff, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* dest ip */
+                  0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+                  0xaa, 0xbb              /* payload */];
+    let ip_header = Ipv6Header::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(ip_header.get_version(), 6);
+    assert_eq!(ip_header.get_traffic_class(), 0xab);
+    assert_eq!(ip_header.get_flow_label(), 0xcdef1);
+    assert_eq!(ip_header.get_payload_length(), 2);
+    assert_eq!(ip_header.get_next_header(), IpNextHeaderProtocols::Ipv6Icmp);
+    assert_eq!(ip_header.get_hop_limit(), 64);
+    assert_eq!(ip_header.get_source(), Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+    assert_eq!(ip_header.get_destination(), Ipv6Addr(0xff02, 0, 0, 0, 0, 0, 0, 2));
+    assert_eq!(ip_header.payload(), [0xaa, 0xbb].as_slice());
+
+    // Every truncation of the packet, including those which only truncate the payload
+    for len in range(0u, packet.len()) {
+        assert!(Ipv6Header::new_checked(packet.slice_to(len)).is_none());
+    }
+
+    // A payload length which claims more than the buffer
+    let mut over_claiming = packet;
+    over_claiming[5] = 3;
+    assert!(Ipv6Header::new_checked(over_claiming.as_slice()).is_none());
+    over_claiming[4] = 0xff;
+    over_claiming[5] = 0xff;
+    assert!(Ipv6Header::new_checked(over_claiming.as_slice()).is_none());
+}
+
+#[test]
+fn ipv6_header_traffic_class_flow_label() {
+    let mut packet = [0u8, ..40];
+    let mut ip_header = MutableIpv6Header::new(packet.as_mut_slice());
+
+    // The traffic class and flow label share a byte, so setting one mustn't disturb the other
+    ip_header.set_version(6);
+    ip_header.set_flow_label(0xfffff);
+    ip_header.set_traffic_class(0x5a);
+    assert_eq!(ip_header.get_version(), 6);
+    assert_eq!(ip_header.get_flow_label(), 0xfffff);
+    assert_eq!(ip_header.get_traffic_class(), 0x5a);
+
+    ip_header.set_flow_label(0x12345);
+    assert_eq!(ip_header.get_flow_label(), 0x12345);
+    assert_eq!(ip_header.get_traffic_class(), 0x5a);
+}
+
+#[test]
+fn ipv6_header_upper_layer() {
+    let mut packet = [0x60, 0x00, 0x00, 0x00, /* ver/traffic class/flow label */
+                      0x00, 0x12,             /* payload length */
+                      0x00,                   /* next header */
+                      0x40,                   /* hop limit */
+                      0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* source ip */
+                      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+                      0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, /* dest ip */
+                      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+                      0x11, 0x00,             /* hop-by-hop next header/length */
+                      0x05, 0x02, 0x00, 0x00, /* router alert */
+                      0x01, 0x00,             /* padn */
+                      0x04, 0xd2, 0x04, 0xd2, /* udp header */
+                      0x00, 0x0a, 0x00, 0x00,
+                      0xaa, 0xbb              /* udp payload */];
+    {
+        let ip_header = Ipv6Header::new(packet.as_slice());
+        let (protocol, buf) = ip_header.get_upper_layer();
+        assert_eq!(protocol, IpNextHeaderProtocols::Udp);
+        assert_eq!(buf, packet.slice_from(48));
+    }
+
+    // A truncated extension header stops the walk
+    packet[41] = 0x04;
+    {
+        let ip_header = Ipv6Header::new(packet.as_slice());
+        let (protocol, buf) = ip_header.get_upper_layer();
+        assert_eq!(protocol, IpNextHeaderProtocols::Hopopt);
+        assert_eq!(buf, packet.slice_from(40));
+    }
+
+    // No extension headers
+    packet[6] = 0x11;
+    {
+        let ip_header = Ipv6Header::new(packet.as_slice());
+        let (protocol, buf) = ip_header.get_upper_layer();
+        assert_eq!(protocol, IpNextHeaderProtocols::Udp);
+        assert_eq!(buf, packet.slice_from(40));
+    }
+}
+
+/// Check the checksum of the TCP, UDP or ICMPv6 packet carried by an IPv6 packet, using the
+/// addresses from the IPv6 header for the pseudo-header. Extension headers are skipped as for
+/// `get_upper_layer()`.
+///
+/// Returns true if there is nothing to verify: for other protocols, and for fragments, since the
+/// checksum covers the whole reassembled datagram. Returns false if the payload is too short to
+/// hold the transport header.
+pub fn verify_transport_checksum<T: Ipv6Packet>(packet: &T) -> bool {
+    if is_fragment(packet) {
+        return true;
+    }
+    let (protocol, upper_layer) = packet.get_upper_layer();
+
+    // Ignore anything after the end of the packet, such as Ethernet padding
+    let end = cmp::min(IPV6_HEADER_LEN + packet.get_payload_length() as uint,
+                       packet.packet().len());
+    let start = packet.packet().len() - upper_layer.len();
+    if end < start {
+        return false;
+    }
+    let payload = packet.packet().slice(start, end);
+
+    let source = packet.get_source();
+    let destination = packet.get_destination();
+    if protocol == IpNextHeaderProtocols::Tcp {
+        match TcpHeader::new_checked(payload) {
+            Some(tcp) => tcp.is_checksum_valid(source, destination),
+            None => false
+        }
+    } else if protocol == IpNextHeaderProtocols::Udp {
+        match UdpHeader::new_checked(payload) {
+            Some(udp) => udp.is_checksum_valid(source, destination, protocol),
+            None => false
+        }
+    } else if protocol == IpNextHeaderProtocols::Icmpv6 {
+        match Icmpv6Header::new_checked(payload) {
+            Some(icmpv6) => icmpv6.is_checksum_valid(source, destination),
+            None => false
+        }
+    } else {
+        true
+    }
+}
+
+/// Is the packet a fragment other than the first, i.e. does it have a Fragment header with a
+/// non-zero offset? The extension headers are walked in the same way as `get_upper_layer()`.
+///
+/// The upper-layer header is only in the first fragment, so for later fragments the buffer
+/// returned by `get_upper_layer()` is the continuation of the fragmented payload.
+pub fn is_later_fragment<T: Ipv6Packet>(packet: &T) -> bool {
+    match fragment_offset_flags(packet) {
+        // The fragment offset is the top 13 bits
+        Some(offset_flags) => offset_flags & 0xfff8 != 0,
+        None => false
+    }
+}
+
+/// Is the packet a fragment, i.e. does it have a Fragment header with a non-zero offset or the
+/// more fragments flag set? The extension headers are walked as for `is_later_fragment()`.
+pub fn is_fragment<T: Ipv6Packet>(packet: &T) -> bool {
+    match fragment_offset_flags(packet) {
+        // The fragment offset is the top 13 bits, and the more fragments flag the lowest
+        Some(offset_flags) => offset_flags & 0xfff9 != 0,
+        None => false
+    }
+}
+
+// Get the offset and flags field of the packet's Fragment header, if it has one. The extension
+// headers are walked in the same way as get_upper_layer().
+fn fragment_offset_flags<T: Ipv6Packet>(packet: &T) -> Option<u16> {
+    let mut next_header = packet.get_next_header();
+    let mut buf = packet.payload();
+    loop {
+        let len = if next_header == IpNextHeaderProtocols::Ipv6Frag {
+            if buf.len() < 8 {
+                return None;
+            }
+            return Some((buf[2] as u16 << 8) | buf[3] as u16);
+        } else if next_header == IpNextHeaderProtocols::Hopopt ||
+                  next_header == IpNextHeaderProtocols::Ipv6Route ||
+                  next_header == IpNextHeaderProtocols::Ipv6Opts {
+            if buf.len() < 2 {
+                return None;
+            }
+            (buf[1] as uint + 1) * 8
+        } else {
+            return None;
+        };
+        if len > buf.len() {
+            return None;
+        }
+        next_header = IpNextHeaderProtocol(buf[0]);
+        buf = buf.slice_from(len);
+    }
+}
+
+#[test]
+fn ipv6_later_fragment() {
+    let mut packet = [0x60u8, 0x00, 0x00, 0x00, 0x00, 0x10, 0x2c, 0x40,
+                      0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, /* source */
+                      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+                      0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, /* destination */
+                      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+                      0x11, 0x00, 0x00, 0x01, 0x12, 0x34, 0x56, 0x78, /* fragment header */
+                      0x30, 0x39, 0x00, 0x35, 0x00, 0x0c, 0x00, 0x00];
+    // The first fragment, with the more fragments flag set
+    assert!(is_fragment(&Ipv6Header::new(packet.as_slice())));
+    assert!(!is_later_fragment(&Ipv6Header::new(packet.as_slice())));
+
+    // A later fragment, at an offset of 8 octets
+    packet[42] = 0x00;
+    packet[43] = 0x08;
+    assert!(is_fragment(&Ipv6Header::new(packet.as_slice())));
+    assert!(is_later_fragment(&Ipv6Header::new(packet.as_slice())));
+
+    // Not a fragment at all
+    packet[6] = 0x11;
+    assert!(!is_fragment(&Ipv6Header::new(packet.as_slice())));
+    assert!(!is_later_fragment(&Ipv6Header::new(packet.as_slice())));
+}
+
+#[test]
+fn ipv6_verify_transport_checksum() {
+    let mut packet = [0x60u8, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x11, 0x40,
+                      0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, /* source */
+                      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+                      0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, /* destination */
+                      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+                      0x30, 0x39, 0x00, 0x35, 0x00, 0x0c, 0x8c, 0x19, /* udp header */
+                      0x74, 0x65, 0x73, 0x74, /* "test" */
+                      0x00, 0x00 /* padding */];
+    assert!(verify_transport_checksum(&Ipv6Header::new(packet.as_slice())));
+
+    // A corrupted payload byte
+    packet[49] ^= 0x01;
+    assert!(!verify_transport_checksum(&Ipv6Header::new(packet.as_slice())));
+
+    // Nothing to check for unknown protocols
+    packet[6] = 0xfd;
+    assert!(verify_transport_checksum(&Ipv6Header::new(packet.as_slice())));
+    packet[6] = 0x11;
+    packet[49] ^= 0x01;
+
+    // The same UDP packet after a Destination Options header
+    let mut with_options = Vec::new();
+    with_options.push_all(packet.slice_to(40));
+    *with_options.get_mut(5) = 0x14;
+    *with_options.get_mut(6) = 0x3c;
+    with_options.push_all([0x11u8, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00].as_slice());
+    with_options.push_all(packet.slice(40, 52));
+    assert!(verify_transport_checksum(&Ipv6Header::new(with_options.as_slice())));
+    *with_options.get_mut(59) ^= 0x01;
+    assert!(!verify_transport_checksum(&Ipv6Header::new(with_options.as_slice())));
+}
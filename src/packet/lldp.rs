@@ -0,0 +1,312 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! LLDP packet abstraction
+//!
+//! LLDP frames have an EtherType of `EtherTypes::Lldp`. The LLDPDU is a list of TLVs, as
+//! described in IEEE 802.1AB, starting with the mandatory Chassis ID, Port ID and Time To Live
+//! TLVs and finishing with an End Of LLDPDU TLV.
+
+use std::fmt;
+
+use packet::Packet;
+
+/// Structure representing an LLDP packet (an LLDPDU)
+pub struct LldpHeader<'p> {
+    packet: &'p [u8],
+}
+
+impl<'p> PartialEq for LldpHeader<'p> {
+    fn eq(&self, other: &LldpHeader) -> bool {
+        if self.packet.len() != other.packet.len() {
+            return false;
+        }
+        for (b1, b2) in self.packet.iter().zip(other.packet.iter()) {
+            if b1 != b2 {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+impl<'p> Eq for LldpHeader<'p> {}
+
+impl<'p> fmt::Show for LldpHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "LldpHeader {{ chassis_id: {}, port_id: {}, ttl: {} }}",
+                self.get_chassis_id(),
+                self.get_port_id(),
+                self.get_ttl()
+        )
+    }
+}
+
+impl<'p> Packet for LldpHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet }
+
+    /// LLDP packets have no fixed header, so the payload is the whole packet
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet }
+}
+
+/// Trait implemented by anything which provides an interface to read LLDP packets
+pub trait LldpPacket : Packet {
+    /// Get an iterator over the TLVs of the packet
+    fn get_tlvs<'a>(&'a self) -> LldpTlvIterator<'a> {
+        LldpTlvIterator { buf: self.packet() }
+    }
+
+    /// Get the chassis ID subtype and chassis ID, from the first Chassis ID TLV. Returns None if
+    /// there isn't a well formed Chassis ID TLV.
+    fn get_chassis_id<'a>(&'a self) -> Option<(u8, &'a [u8])> {
+        self.get_tlvs().filter_map(|tlv| tlv.get_chassis_id()).next()
+    }
+
+    /// Get the port ID subtype and port ID, from the first Port ID TLV. Returns None if there
+    /// isn't a well formed Port ID TLV.
+    fn get_port_id<'a>(&'a self) -> Option<(u8, &'a [u8])> {
+        self.get_tlvs().filter_map(|tlv| tlv.get_port_id()).next()
+    }
+
+    /// Get the time to live in seconds, from the first Time To Live TLV. Returns None if there
+    /// isn't a well formed Time To Live TLV.
+    fn get_ttl(&self) -> Option<u16> {
+        self.get_tlvs().filter_map(|tlv| tlv.get_ttl()).next()
+    }
+}
+
+impl<'p> LldpPacket for LldpHeader<'p> {}
+
+impl<'p> LldpHeader<'p> {
+    /// Construct a new LLDP packet backed by the given buffer
+    pub fn new(packet: &'p [u8]) -> LldpHeader<'p> {
+        LldpHeader { packet: packet }
+    }
+
+    /// Construct a new LLDP packet backed by the given buffer, checking that it starts with the
+    /// mandatory Chassis ID, Port ID and Time To Live TLVs, in that order. Returns None if it
+    /// does not.
+    pub fn new_checked(packet: &'p [u8]) -> Option<LldpHeader<'p>> {
+        let header = LldpHeader::new(packet);
+        let valid = {
+            let mut tlvs = header.get_tlvs();
+            let mandatory = [LldpTlvTypes::ChassisId, LldpTlvTypes::PortId, LldpTlvTypes::Ttl];
+            mandatory.iter().all(|&tlv_type| {
+                match tlvs.next() {
+                    Some(tlv) => tlv.tlv_type == tlv_type,
+                    None => false
+                }
+            })
+        };
+        if valid {
+            Some(header)
+        } else {
+            None
+        }
+    }
+}
+
+/// An LLDP TLV
+#[deriving(PartialEq, Eq, Show)]
+pub struct LldpTlv<'p> {
+    /// The type of the TLV
+    pub tlv_type: LldpTlvType,
+    /// The length of the value, in bytes
+    pub length: u16,
+    /// The value of the TLV
+    pub value: &'p [u8],
+}
+
+impl<'p> LldpTlv<'p> {
+    /// Get the chassis ID subtype and chassis ID, if this is a well formed Chassis ID TLV
+    pub fn get_chassis_id(&self) -> Option<(u8, &'p [u8])> {
+        // The chassis ID is between 1 and 255 bytes
+        if self.tlv_type == LldpTlvTypes::ChassisId && self.value.len() >= 2 &&
+           self.value.len() <= 256 {
+            Some((self.value[0], self.value.slice_from(1)))
+        } else {
+            None
+        }
+    }
+
+    /// Get the port ID subtype and port ID, if this is a well formed Port ID TLV
+    pub fn get_port_id(&self) -> Option<(u8, &'p [u8])> {
+        // The port ID is between 1 and 255 bytes
+        if self.tlv_type == LldpTlvTypes::PortId && self.value.len() >= 2 &&
+           self.value.len() <= 256 {
+            Some((self.value[0], self.value.slice_from(1)))
+        } else {
+            None
+        }
+    }
+
+    /// Get the time to live in seconds, if this is a well formed Time To Live TLV
+    pub fn get_ttl(&self) -> Option<u16> {
+        if self.tlv_type == LldpTlvTypes::Ttl && self.value.len() == 2 {
+            Some((self.value[0] as u16 << 8) | self.value[1] as u16)
+        } else {
+            None
+        }
+    }
+
+    /// Get the OUI, subtype and information string, if this is a well formed Organizationally
+    /// Specific TLV
+    pub fn get_organizationally_specific(&self) -> Option<([u8, ..3], u8, &'p [u8])> {
+        if self.tlv_type == LldpTlvTypes::OrganizationallySpecific && self.value.len() >= 4 {
+            let oui = [self.value[0], self.value[1], self.value[2]];
+            Some((oui, self.value[3], self.value.slice_from(4)))
+        } else {
+            None
+        }
+    }
+}
+
+/// An iterator over the TLVs of an LLDP packet
+///
+/// Iteration finishes at an End Of LLDPDU TLV, which is not included, or at the end of the
+/// buffer. Iteration also finishes if a TLV is truncated.
+pub struct LldpTlvIterator<'p> {
+    buf: &'p [u8],
+}
+
+impl<'p> Iterator<LldpTlv<'p>> for LldpTlvIterator<'p> {
+    fn next(&mut self) -> Option<LldpTlv<'p>> {
+        // The type is the first 7 bits, and the length of the value the next 9
+        if self.buf.len() < 2 {
+            self.buf = self.buf.slice_to(0);
+            return None;
+        }
+        let tlv_type = LldpTlvType(self.buf[0] >> 1);
+        let length = ((self.buf[0] as u16 & 0x1) << 8) | self.buf[1] as u16;
+        if tlv_type == LldpTlvTypes::End || 2 + length as uint > self.buf.len() {
+            self.buf = self.buf.slice_to(0);
+            return None;
+        }
+        let tlv = LldpTlv {
+            tlv_type: tlv_type,
+            length: length,
+            value: self.buf.slice(2, 2 + length as uint),
+        };
+        self.buf = self.buf.slice_from(2 + length as uint);
+
+        Some(tlv)
+    }
+}
+
+#[cfg(test)]
+static TEST_PACKET: [u8, ..52] = [
+    0x02, 0x07, 0x04, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, /* chassis id, mac address */
+    0x04, 0x05, 0x05, 0x65, 0x74, 0x68, 0x30,             /* port id, "eth0" */
+    0x06, 0x02, 0x00, 0x78,                               /* ttl, 120 seconds */
+    0x0a, 0x06, 0x73, 0x77, 0x69, 0x74, 0x63, 0x68,       /* system name, "switch" */
+    0xfe, 0x06, 0x00, 0x80, 0xc2, 0x01, 0x00, 0x01,       /* 802.1 port vlan id, 1 */
+    0x00, 0x00,                                           /* end of lldpdu */
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,       /* padding */
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+];
+
+#[test]
+fn lldp_mandatory_tlvs() {
+    let packet = LldpHeader::new_checked(TEST_PACKET.as_slice()).unwrap();
+    assert_eq!(packet.get_chassis_id(),
+               Some((4u8, [0x00u8, 0x11, 0x22, 0x33, 0x44, 0x55].as_slice())));
+    assert_eq!(packet.get_port_id(), Some((5u8, "eth0".as_bytes())));
+    assert_eq!(packet.get_ttl(), Some(120));
+}
+
+#[test]
+fn lldp_tlvs() {
+    let packet = LldpHeader::new(TEST_PACKET.as_slice());
+    let tlvs: Vec<LldpTlv> = packet.get_tlvs().collect();
+    assert_eq!(tlvs.len(), 5);
+    assert_eq!(tlvs[0].tlv_type, LldpTlvTypes::ChassisId);
+    assert_eq!(tlvs[0].length, 7);
+    assert_eq!(tlvs[3].tlv_type, LldpTlvTypes::SystemName);
+    assert_eq!(tlvs[3].value, "switch".as_bytes());
+
+    // The vendor specific TLV
+    assert_eq!(tlvs[4].tlv_type, LldpTlvTypes::OrganizationallySpecific);
+    assert_eq!(tlvs[4].length, 6);
+    assert_eq!(tlvs[4].get_organizationally_specific(),
+               Some(([0x00u8, 0x80, 0xc2], 1u8, [0x00u8, 0x01].as_slice())));
+    assert_eq!(tlvs[4].get_chassis_id(), None);
+}
+
+#[test]
+fn lldp_malformed() {
+    // A TLV which runs past the end of the buffer finishes iteration
+    let truncated = TEST_PACKET.slice_to(18);
+    let tlvs: Vec<LldpTlv> = LldpHeader::new(truncated).get_tlvs().collect();
+    assert_eq!(tlvs.len(), 2);
+    assert!(LldpHeader::new_checked(truncated).is_none());
+
+    // As does a lone byte
+    let tlvs: Vec<LldpTlv> = LldpHeader::new(TEST_PACKET.slice_to(10)).get_tlvs().collect();
+    assert_eq!(tlvs.len(), 1);
+
+    // Including a length with its top bit in the type byte
+    let mut long = TEST_PACKET;
+    long[16] = 0x07;
+    let tlvs: Vec<LldpTlv> = LldpHeader::new(long.as_slice()).get_tlvs().collect();
+    assert_eq!(tlvs.len(), 2);
+
+    // Mandatory TLVs out of order
+    let mut reordered = TEST_PACKET;
+    reordered[0] = 0x04;
+    assert!(LldpHeader::new_checked(reordered.as_slice()).is_none());
+
+    // Malformed values aren't decoded
+    let mut bad_ttl = TEST_PACKET;
+    bad_ttl[17] = 0x01;
+    let packet = LldpHeader::new(bad_ttl.as_slice());
+    assert_eq!(packet.get_ttl(), None);
+    assert!(LldpHeader::new(TEST_PACKET.slice_to(0)).get_tlvs().next().is_none());
+}
+
+/// LLDP TLV types, as defined in IEEE 802.1AB
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod LldpTlvTypes {
+    use packet::lldp::LldpTlvType;
+
+    /// End Of LLDPDU
+    pub const End: LldpTlvType                      = LldpTlvType(0);
+
+    /// Chassis ID
+    pub const ChassisId: LldpTlvType                = LldpTlvType(1);
+
+    /// Port ID
+    pub const PortId: LldpTlvType                   = LldpTlvType(2);
+
+    /// Time To Live
+    pub const Ttl: LldpTlvType                      = LldpTlvType(3);
+
+    /// Port Description
+    pub const PortDescription: LldpTlvType          = LldpTlvType(4);
+
+    /// System Name
+    pub const SystemName: LldpTlvType               = LldpTlvType(5);
+
+    /// System Description
+    pub const SystemDescription: LldpTlvType        = LldpTlvType(6);
+
+    /// System Capabilities
+    pub const SystemCapabilities: LldpTlvType       = LldpTlvType(7);
+
+    /// Management Address
+    pub const ManagementAddress: LldpTlvType        = LldpTlvType(8);
+
+    /// Organizationally Specific
+    pub const OrganizationallySpecific: LldpTlvType = LldpTlvType(127);
+}
+
+/// Represents the type of an LLDP TLV
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LldpTlvType(pub u8);
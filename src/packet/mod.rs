@@ -12,19 +12,19 @@
 
 /// Represents a generic network packet
 pub trait Packet {
-    /// Retreive the underlying buffer for the packet
+    /// Retrieve the underlying buffer for the packet
     fn packet<'p>(&'p self) -> &'p [u8];
 
-    /// Retreive the payload for the packet
+    /// Retrieve the payload for the packet
     fn payload<'p>(&'p self) -> &'p [u8];
 }
 
 /// Represents a generic, mutable, network packet
 pub trait MutablePacket {
-    /// Retreive the underlying, mutable, buffer for the packet
+    /// Retrieve the underlying, mutable, buffer for the packet
     fn packet_mut<'p>(&'p mut self) -> &'p mut [u8];
 
-    /// Retreive the mutable payload for the packet
+    /// Retrieve the mutable payload for the packet
     fn payload_mut<'p>(&'p mut self) -> &'p mut [u8];
 
     /// Initialize this packet by cloning another
@@ -68,9 +68,55 @@ pub macro_rules! pfor (
     }
 )
 
+#[test]
+fn generic_packet_test() {
+    use packet::ipv4::{Ipv4Header, MutableIpv4Header};
+    use packet::udp::{UdpHeader, MutableUdpHeader};
+    use util;
+
+    // Works with any type of packet
+    fn packet_checksum<T : Packet>(packet: &T) -> u16 {
+        util::checksum(packet.packet(), None)
+    }
+    fn zero_payload<T : MutablePacket>(packet: &mut T) {
+        for b in packet.payload_mut().iter_mut() {
+            *b = 0;
+        }
+    }
+
+    let mut ipv4 = [0x45u8, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x40, 0x00,
+                    0x40, 0x11, 0x00, 0x00, 0x7f, 0x00, 0x00, 0x01,
+                    0x7f, 0x00, 0x00, 0x01, 0xff, 0xff, 0xff, 0xff];
+    let mut udp = [0x00u8, 0x35, 0x00, 0x35, 0x00, 0x0c, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04];
+
+    assert_eq!(packet_checksum(&Ipv4Header::new(ipv4.as_slice())), 0x3ccf);
+    assert_eq!(packet_checksum(&UdpHeader::new(udp.as_slice())), 0xfb83);
+
+    zero_payload(&mut MutableIpv4Header::new(ipv4.as_mut_slice()));
+    assert_eq!(ipv4.slice_from(20), [0u8, 0, 0, 0].as_slice());
+    zero_payload(&mut MutableUdpHeader::new(udp.as_mut_slice()));
+    assert_eq!(udp.slice_from(8), [0u8, 0, 0, 0].as_slice());
+
+    // Mutable packets can be initialised from any other packet
+    let mut copy = [0u8, ..12];
+    MutableUdpHeader::new(copy.as_mut_slice()).clone_from(UdpHeader::new(udp.as_slice()));
+    assert_eq!(copy.as_slice(), udp.as_slice());
+}
+
+pub mod arp;
+pub mod dhcp;
+pub mod dns;
 pub mod ethernet;
+pub mod flow;
+pub mod gre;
+pub mod icmp;
+pub mod icmpv6;
+pub mod igmp;
 pub mod ip;
 pub mod ipv4;
 pub mod ipv6;
+pub mod lldp;
+pub mod sll;
+pub mod tcp;
 pub mod udp;
 
@@ -0,0 +1,308 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Linux "cooked" capture (SLL) packet abstraction
+//!
+//! Packets captured on Linux's `any` pseudo-interface, and captures with a link type of
+//! `pcap::LinkTypes::LinuxSll`, start with an SLL header rather than an Ethernet header.
+
+use std::cmp;
+use std::fmt;
+
+use packet::{Packet, MutablePacket};
+use packet::arp::ArpHardwareType;
+use packet::ethernet::EtherType;
+use util::MacAddr;
+
+/// The length of an SLL header, in bytes
+pub const SLL_HEADER_LEN: uint = 16;
+
+/// Structure representing an SLL header
+pub struct SllHeader<'p> {
+    packet: &'p [u8],
+}
+
+/// Structure representing an SLL header which can be mutated
+pub struct MutableSllHeader<'p> {
+    packet: &'p mut [u8],
+}
+
+impl<'p> PartialEq for SllHeader<'p> {
+    fn eq(&self, other: &SllHeader) -> bool {
+        if self.packet.len() != other.packet.len() {
+            return false;
+        }
+        for (b1, b2) in self.packet.iter().zip(other.packet.iter()) {
+            if b1 != b2 {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+impl<'p> Eq for SllHeader<'p> {}
+
+impl<'p> fmt::Show for SllHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "SllHeader {{ packet_type: {}, link_layer_type: {}, address: {}, protocol: {} }}",
+                self.get_packet_type(),
+                self.get_link_layer_type(),
+                self.get_address(),
+                self.get_protocol()
+        )
+    }
+}
+
+impl<'p> fmt::Show for MutableSllHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "MutableSllHeader {{ packet_type: {}, link_layer_type: {}, address: {}, \
+                protocol: {} }}",
+                self.get_packet_type(),
+                self.get_link_layer_type(),
+                self.get_address(),
+                self.get_protocol()
+        )
+    }
+}
+
+impl<'p> Packet for SllHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(SLL_HEADER_LEN) }
+}
+
+impl<'p> Packet for MutableSllHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet.as_slice() }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(SLL_HEADER_LEN) }
+}
+
+impl<'p> MutablePacket for MutableSllHeader<'p> {
+    #[inline(always)]
+    fn packet_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.as_mut_slice() }
+
+    #[inline(always)]
+    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] {
+        self.packet.slice_from_mut(SLL_HEADER_LEN)
+    }
+}
+
+/// Trait implemented by anything which provides an interface to read SLL packets
+pub trait SllPacket : Packet {
+    /// Get the packet type, which says who the packet was sent to
+    fn get_packet_type(&self) -> SllPacketType {
+        SllPacketType((self.packet()[0] as u16 << 8) | (self.packet()[1] as u16))
+    }
+
+    /// Get the link layer type of the interface the packet was captured on, as an `ARPHRD_`
+    /// value. This is `ArpHardwareTypes::Ethernet` for Ethernet interfaces.
+    fn get_link_layer_type(&self) -> ArpHardwareType {
+        ArpHardwareType((self.packet()[2] as u16 << 8) | (self.packet()[3] as u16))
+    }
+
+    /// Get the length of the link layer address of the sender
+    fn get_address_length(&self) -> u16 {
+        (self.packet()[4] as u16 << 8) | (self.packet()[5] as u16)
+    }
+
+    /// Get the link layer address of the sender. At most 8 bytes of the address are stored, so
+    /// longer addresses are truncated.
+    fn get_address<'a>(&'a self) -> &'a [u8] {
+        let len = cmp::min(self.get_address_length() as uint, 8);
+        self.packet().slice(6, 6 + len)
+    }
+
+    /// Get the link layer address of the sender as a MAC address. Returns None if the address
+    /// isn't 6 bytes long.
+    fn get_mac_address(&self) -> Option<MacAddr> {
+        if self.get_address_length() == 6 {
+            MacAddr::from_bytes(self.get_address())
+        } else {
+            None
+        }
+    }
+
+    /// Get the protocol of the payload. This is usually an EtherType, except for some link layer
+    /// types which don't use EtherTypes.
+    fn get_protocol(&self) -> EtherType {
+        EtherType((self.packet()[14] as u16 << 8) | (self.packet()[15] as u16))
+    }
+}
+
+impl<'p> SllPacket for SllHeader<'p> {}
+impl<'p> SllPacket for MutableSllHeader<'p> {}
+
+impl<'p> SllHeader<'p> {
+    /// Construct a new SLL header backed by the given buffer
+    pub fn new(packet: &'p [u8]) -> SllHeader<'p> {
+        SllHeader { packet: packet }
+    }
+
+    /// Construct a new SLL header backed by the given buffer, checking that it is large enough
+    /// to contain an SLL header. Returns None if it is not.
+    pub fn new_checked(packet: &'p [u8]) -> Option<SllHeader<'p>> {
+        if packet.len() < SLL_HEADER_LEN {
+            None
+        } else {
+            Some(SllHeader::new(packet))
+        }
+    }
+}
+
+impl<'p> MutableSllHeader<'p> {
+    /// Construct a new mutable SLL header backed by the given buffer
+    pub fn new(packet: &'p mut [u8]) -> MutableSllHeader<'p> {
+        MutableSllHeader { packet: packet }
+    }
+
+    /// Construct a new mutable SLL header backed by the given buffer, checking that it is large
+    /// enough to contain an SLL header. Returns None if it is not.
+    pub fn new_checked(packet: &'p mut [u8]) -> Option<MutableSllHeader<'p>> {
+        if packet.len() < SLL_HEADER_LEN {
+            None
+        } else {
+            Some(MutableSllHeader::new(packet))
+        }
+    }
+
+    /// Set the packet type
+    pub fn set_packet_type(&mut self, SllPacketType(packet_type): SllPacketType) {
+        self.packet[0] = (packet_type >> 8) as u8;
+        self.packet[1] = (packet_type & 0xFF) as u8;
+    }
+
+    /// Set the link layer type
+    pub fn set_link_layer_type(&mut self, ArpHardwareType(link_layer_type): ArpHardwareType) {
+        self.packet[2] = (link_layer_type >> 8) as u8;
+        self.packet[3] = (link_layer_type & 0xFF) as u8;
+    }
+
+    /// Set the link layer address of the sender, and the address length. Addresses longer than
+    /// 8 bytes are truncated, but the length is set to the full length.
+    pub fn set_address(&mut self, address: &[u8]) {
+        let len = address.len();
+        self.packet[4] = (len >> 8) as u8;
+        self.packet[5] = (len & 0xFF) as u8;
+        let stored = cmp::min(len, 8);
+        for b in self.packet.slice_mut(6, 14).iter_mut() {
+            *b = 0;
+        }
+        self.packet.slice_mut(6, 6 + stored).clone_from_slice(address.slice_to(stored));
+    }
+
+    /// Set the link layer address of the sender to a MAC address
+    pub fn set_mac_address(&mut self, mac: MacAddr) {
+        self.set_address(mac.octets().as_slice());
+    }
+
+    /// Set the protocol of the payload
+    pub fn set_protocol(&mut self, EtherType(protocol): EtherType) {
+        self.packet[14] = (protocol >> 8) as u8;
+        self.packet[15] = (protocol & 0xFF) as u8;
+    }
+}
+
+#[test]
+fn sll_ipv4() {
+    use packet::arp::ArpHardwareTypes;
+    use packet::ethernet::EtherTypes;
+    use packet::ip::IpNextHeaderProtocols;
+    use packet::ipv4::{Ipv4Header, Ipv4Packet};
+    use std::io::net::ip::Ipv4Addr;
+
+    // A UDP packet received from 00:11:22:33:44:55, as captured on the "any" interface
+    let packet = [0x00, 0x00, /* packet type: to us */
+                  0x00, 0x01, /* link layer type: Ethernet */
+                  0x00, 0x06, /* address length */
+                  0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x00, 0x00, /* address, and padding */
+                  0x08, 0x00, /* protocol: IPv4 */
+                  0x45, 0x00, 0x00, 0x1c, 0x12, 0x34, 0x40, 0x00,
+                  0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8, 0x00, 0x01,
+                  0xc0, 0xa8, 0x00, 0x02, /* ipv4 header */
+                  0x30, 0x39, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00 /* udp header */];
+
+    let sll = SllHeader::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(sll.get_packet_type(), SllPacketTypes::Host);
+    assert_eq!(sll.get_link_layer_type(), ArpHardwareTypes::Ethernet);
+    assert_eq!(sll.get_address_length(), 6);
+    assert_eq!(sll.get_address(), [0x00u8, 0x11, 0x22, 0x33, 0x44, 0x55].as_slice());
+    assert_eq!(sll.get_mac_address(), Some(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55)));
+    assert_eq!(sll.get_protocol(), EtherTypes::Ipv4);
+
+    let ipv4 = Ipv4Header::new(sll.payload());
+    assert_eq!(ipv4.get_next_level_protocol(), IpNextHeaderProtocols::Udp);
+    assert_eq!(ipv4.get_source(), Ipv4Addr(192, 168, 0, 1));
+    assert_eq!(ipv4.get_destination(), Ipv4Addr(192, 168, 0, 2));
+
+    assert!(SllHeader::new_checked(packet.slice_to(SLL_HEADER_LEN - 1)).is_none());
+}
+
+#[test]
+fn sll_mutable() {
+    use packet::ethernet::EtherTypes;
+
+    let mut packet = [0xffu8, ..SLL_HEADER_LEN + 4];
+    {
+        let mut sll = MutableSllHeader::new(packet.as_mut_slice());
+        sll.set_packet_type(SllPacketTypes::Outgoing);
+        // ARPHRD_LOOPBACK
+        sll.set_link_layer_type(ArpHardwareType(772));
+        sll.set_mac_address(MacAddr(0, 0, 0, 0, 0, 0));
+        sll.set_protocol(EtherTypes::Ipv6);
+        assert_eq!(sll.get_packet_type(), SllPacketTypes::Outgoing);
+        assert_eq!(sll.get_mac_address(), Some(MacAddr(0, 0, 0, 0, 0, 0)));
+    }
+    let ref_packet = [0x00, 0x04,
+                      0x03, 0x04,
+                      0x00, 0x06,
+                      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                      0x86, 0xdd,
+                      0xff, 0xff, 0xff, 0xff];
+    assert_eq!(packet.as_slice(), ref_packet.as_slice());
+
+    // Long addresses are truncated, but the full length is kept
+    {
+        let mut sll = MutableSllHeader::new(packet.as_mut_slice());
+        sll.set_address([1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10].as_slice());
+        assert_eq!(sll.get_address_length(), 10);
+        assert_eq!(sll.get_address(), [1u8, 2, 3, 4, 5, 6, 7, 8].as_slice());
+        assert_eq!(sll.get_mac_address(), None);
+    }
+}
+
+/// SLL packet types, as defined in linux/if_packet.h
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod SllPacketTypes {
+    use super::SllPacketType;
+
+    /// The packet was addressed to this host
+    pub const Host: SllPacketType      = SllPacketType(0);
+
+    /// The packet was broadcast
+    pub const Broadcast: SllPacketType = SllPacketType(1);
+
+    /// The packet was multicast
+    pub const Multicast: SllPacketType = SllPacketType(2);
+
+    /// The packet was addressed to another host, and captured in promiscuous mode
+    pub const OtherHost: SllPacketType = SllPacketType(3);
+
+    /// The packet was sent by this host
+    pub const Outgoing: SllPacketType  = SllPacketType(4);
+}
+
+/// Represents the SLL packet type field
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SllPacketType(pub u16);
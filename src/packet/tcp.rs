@@ -0,0 +1,721 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! TCP packet abstraction
+
+use std::cmp;
+use std::fmt;
+use std::io::net::ip::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use packet::{Packet, MutablePacket};
+use packet::ip::IpNextHeaderProtocols;
+use util;
+
+/// The length of a TCP header without options, in bytes
+pub const TCP_HEADER_LEN: uint = 20;
+
+/// Structure representing a TCP header
+pub struct TcpHeader<'p> {
+    packet: &'p [u8],
+}
+
+/// Structure representing a mutable TCP header
+pub struct MutableTcpHeader<'p> {
+    packet: &'p mut [u8],
+}
+
+// FIXME This should probably be a macro
+impl<'p> PartialEq for TcpHeader<'p> {
+    fn eq(&self, other: &TcpHeader) -> bool {
+        if self.packet.len() != other.packet.len() {
+            return false;
+        }
+        for (b1, b2) in self.packet.iter().zip(other.packet.iter()) {
+            if b1 != b2 {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+impl<'p> Eq for TcpHeader<'p> {}
+
+impl<'p> fmt::Show for TcpHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "TcpHeader {{ source: {}, destination: {}, sequence: {}, acknowledgement: {}, \
+                             data offset: {}, flags: {:x}, window: {}, checksum: {}, \
+                             urgent pointer: {} }}",
+                self.get_source(),
+                self.get_destination(),
+                self.get_sequence(),
+                self.get_acknowledgement(),
+                self.get_data_offset(),
+                self.get_flags(),
+                self.get_window(),
+                self.get_checksum(),
+                self.get_urgent_ptr()
+        )
+    }
+}
+
+impl<'p> fmt::Show for MutableTcpHeader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "MutableTcpHeader {{ source: {}, destination: {}, sequence: {}, \
+                                    acknowledgement: {}, data offset: {}, flags: {:x}, \
+                                    window: {}, checksum: {}, urgent pointer: {} }}",
+                self.get_source(),
+                self.get_destination(),
+                self.get_sequence(),
+                self.get_acknowledgement(),
+                self.get_data_offset(),
+                self.get_flags(),
+                self.get_window(),
+                self.get_checksum(),
+                self.get_urgent_ptr()
+        )
+    }
+}
+
+impl<'p> Packet for TcpHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(self.payload_offset()) }
+}
+
+impl<'p> Packet for MutableTcpHeader<'p> {
+    #[inline(always)]
+    fn packet<'p>(&'p self) -> &'p [u8] { self.packet.as_slice() }
+
+    #[inline(always)]
+    fn payload<'p>(&'p self) -> &'p [u8] { self.packet.slice_from(self.payload_offset()) }
+}
+
+impl<'p> MutablePacket for MutableTcpHeader<'p> {
+    #[inline(always)]
+    fn packet_mut<'p>(&'p mut self) -> &'p mut [u8] { self.packet.as_mut_slice() }
+
+    #[inline(always)]
+    fn payload_mut<'p>(&'p mut self) -> &'p mut [u8] {
+        let offset = self.payload_offset();
+        self.packet.slice_from_mut(offset)
+    }
+}
+
+/// Trait implemented by anything which provides an interface to read TCP
+/// packets
+pub trait TcpPacket : Packet {
+    /// Get the source port for the packet
+    fn get_source(&self) -> u16 {
+        (self.packet()[0] as u16 << 8) | self.packet()[1] as u16
+    }
+
+    /// Get the destination port for the packet
+    fn get_destination(&self) -> u16 {
+        (self.packet()[2] as u16 << 8) | self.packet()[3] as u16
+    }
+
+    /// Get the sequence number for the packet
+    fn get_sequence(&self) -> u32 {
+        (self.packet()[4] as u32 << 24) | (self.packet()[5] as u32 << 16) |
+        (self.packet()[6] as u32 << 8) | self.packet()[7] as u32
+    }
+
+    /// Get the acknowledgement number for the packet
+    fn get_acknowledgement(&self) -> u32 {
+        (self.packet()[8] as u32 << 24) | (self.packet()[9] as u32 << 16) |
+        (self.packet()[10] as u32 << 8) | self.packet()[11] as u32
+    }
+
+    /// Get the data offset field for the packet. This is the length of the header in 32-bit
+    /// words.
+    fn get_data_offset(&self) -> u8 {
+        self.packet()[12] >> 4
+    }
+
+    /// Get the length of the header in bytes, including any options
+    fn get_header_length_bytes(&self) -> uint {
+        self.get_data_offset() as uint * 4
+    }
+
+    /// Get the offset of the payload, skipping any options. This is clamped to the length of the
+    /// buffer so that taking the payload of a truncated packet doesn't fail.
+    fn payload_offset(&self) -> uint {
+        cmp::min(self.get_header_length_bytes(), self.packet().len())
+    }
+
+    /// Get the flags for the packet, see `TcpFlags` for the values of each bit
+    fn get_flags(&self) -> u16 {
+        ((self.packet()[12] & 0x01) as u16 << 8) | self.packet()[13] as u16
+    }
+
+    /// Is the FIN flag set?
+    fn is_fin(&self) -> bool {
+        self.get_flags() & TcpFlags::Fin != 0
+    }
+
+    /// Is the SYN flag set?
+    fn is_syn(&self) -> bool {
+        self.get_flags() & TcpFlags::Syn != 0
+    }
+
+    /// Is the RST flag set?
+    fn is_rst(&self) -> bool {
+        self.get_flags() & TcpFlags::Rst != 0
+    }
+
+    /// Is the PSH flag set?
+    fn is_psh(&self) -> bool {
+        self.get_flags() & TcpFlags::Psh != 0
+    }
+
+    /// Is the ACK flag set?
+    fn is_ack(&self) -> bool {
+        self.get_flags() & TcpFlags::Ack != 0
+    }
+
+    /// Is the URG flag set?
+    fn is_urg(&self) -> bool {
+        self.get_flags() & TcpFlags::Urg != 0
+    }
+
+    /// Get the window size for the packet
+    fn get_window(&self) -> u16 {
+        (self.packet()[14] as u16 << 8) | self.packet()[15] as u16
+    }
+
+    /// Get the checksum field for the packet
+    fn get_checksum(&self) -> u16 {
+        (self.packet()[16] as u16 << 8) | self.packet()[17] as u16
+    }
+
+    /// Get the urgent pointer for the packet
+    fn get_urgent_ptr(&self) -> u16 {
+        (self.packet()[18] as u16 << 8) | self.packet()[19] as u16
+    }
+
+    /// Get an iterator over the options of the packet, if there are any
+    fn get_options<'a>(&'a self) -> TcpOptionIterator<'a> {
+        let end = self.payload_offset();
+        let start = cmp::min(TCP_HEADER_LEN, end);
+        TcpOptionIterator { buf: self.packet().slice(start, end) }
+    }
+
+    /// Calculate the checksum for a packet built on IPv4
+    fn calculate_ipv4_checksum(&self, ipv4_source: IpAddr, ipv4_destination: IpAddr) -> u16 {
+        util::ipv4_checksum(self.packet(), Some(8), ipv4_source, ipv4_destination,
+                            IpNextHeaderProtocols::Tcp)
+    }
+
+    /// Calculate the checksum for a packet built on IPv6
+    fn calculate_ipv6_checksum(&self, ipv6_source: IpAddr, ipv6_destination: IpAddr) -> u16 {
+        util::ipv6_checksum(self.packet(), Some(8), ipv6_source, ipv6_destination,
+                            IpNextHeaderProtocols::Tcp)
+    }
+
+    /// Calculate a checksum regardless of IP version
+    fn calculate_checksum(&self, source: IpAddr, destination: IpAddr) -> u16 {
+        match source {
+            Ipv4Addr(..) => self.calculate_ipv4_checksum(source, destination),
+            Ipv6Addr(..) => self.calculate_ipv6_checksum(source, destination),
+        }
+    }
+
+    /// Does the checksum field of the packet match the checksum calculated for it?
+    fn is_checksum_valid(&self, source: IpAddr, destination: IpAddr) -> bool {
+        self.calculate_checksum(source, destination) == self.get_checksum()
+    }
+}
+
+/// A TCP option
+#[deriving(PartialEq, Eq, Show)]
+pub struct TcpOption<'p> {
+    /// The kind of the option
+    pub number: TcpOptionNumber,
+    /// The length of the option in bytes, including the kind and length fields. This is 1 for
+    /// single byte options, which have no length field.
+    pub length: u8,
+    /// The data for the option, excluding the kind and length fields
+    pub data: &'p [u8],
+}
+
+impl<'p> TcpOption<'p> {
+    /// Get the maximum segment size, if this is a well formed MSS option
+    pub fn get_mss(&self) -> Option<u16> {
+        if self.number == TcpOptionNumbers::Mss && self.data.len() == 2 {
+            Some((self.data[0] as u16 << 8) | self.data[1] as u16)
+        } else {
+            None
+        }
+    }
+
+    /// Get the shift count, if this is a well formed window scale option
+    pub fn get_window_scale(&self) -> Option<u8> {
+        if self.number == TcpOptionNumbers::Wscale && self.data.len() == 1 {
+            Some(self.data[0])
+        } else {
+            None
+        }
+    }
+
+    /// Is this a well formed SACK permitted option?
+    pub fn is_sack_permitted(&self) -> bool {
+        self.number == TcpOptionNumbers::SackPermitted && self.data.len() == 0
+    }
+
+    /// Get the timestamp value and timestamp echo reply, if this is a well formed timestamps
+    /// option
+    pub fn get_timestamps(&self) -> Option<(u32, u32)> {
+        if self.number == TcpOptionNumbers::Timestamps && self.data.len() == 8 {
+            let d = self.data;
+            let value = (d[0] as u32 << 24) | (d[1] as u32 << 16) | (d[2] as u32 << 8) |
+                        d[3] as u32;
+            let echo_reply = (d[4] as u32 << 24) | (d[5] as u32 << 16) | (d[6] as u32 << 8) |
+                             d[7] as u32;
+            Some((value, echo_reply))
+        } else {
+            None
+        }
+    }
+}
+
+/// An iterator over the options of a TCP packet
+///
+/// Iteration finishes at the end of the header or at an end of option list option, which is
+/// not included. Iteration also finishes if an option is malformed.
+pub struct TcpOptionIterator<'p> {
+    buf: &'p [u8],
+}
+
+impl<'p> Iterator<TcpOption<'p>> for TcpOptionIterator<'p> {
+    fn next(&mut self) -> Option<TcpOption<'p>> {
+        if self.buf.len() == 0 {
+            return None;
+        }
+        let number = TcpOptionNumber(self.buf[0]);
+        if number == TcpOptionNumbers::Eol {
+            self.buf = self.buf.slice_to(0);
+            return None;
+        }
+        if number == TcpOptionNumbers::Nop {
+            let option = TcpOption { number: number, length: 1, data: self.buf.slice(1, 1) };
+            self.buf = self.buf.slice_from(1);
+            return Some(option);
+        }
+
+        // Any other option has a length field, which includes the kind and length fields
+        if self.buf.len() < 2 || (self.buf[1] as uint) < 2 ||
+           self.buf[1] as uint > self.buf.len() {
+            self.buf = self.buf.slice_to(0);
+            return None;
+        }
+        let length = self.buf[1];
+        let option = TcpOption {
+            number: number,
+            length: length,
+            data: self.buf.slice(2, length as uint)
+        };
+        self.buf = self.buf.slice_from(length as uint);
+
+        Some(option)
+    }
+}
+
+impl<'p> TcpPacket for TcpHeader<'p> {}
+impl<'p> TcpPacket for MutableTcpHeader<'p> {}
+
+impl<'p> TcpHeader<'p> {
+    /// Construct a new TCP header backed by the given buffer
+    pub fn new(packet: &'p [u8]) -> TcpHeader<'p> {
+        TcpHeader { packet: packet }
+    }
+
+    /// Construct a new TCP header backed by the given buffer, checking that the buffer is large
+    /// enough to contain the header, including any options. Returns None if it is not, or if
+    /// the data offset field is less than the minimum of 5.
+    pub fn new_checked(packet: &'p [u8]) -> Option<TcpHeader<'p>> {
+        if packet.len() < TCP_HEADER_LEN {
+            return None;
+        }
+        let header = TcpHeader::new(packet);
+        let header_len = header.get_header_length_bytes();
+        if header_len < TCP_HEADER_LEN || header_len > packet.len() {
+            return None;
+        }
+
+        Some(header)
+    }
+}
+
+impl<'p> MutableTcpHeader<'p> {
+    /// Construct a new mutable TCP header backed by the given buffer
+    pub fn new(packet: &'p mut [u8]) -> MutableTcpHeader<'p> {
+        MutableTcpHeader { packet: packet }
+    }
+
+    /// Construct a new mutable TCP header backed by the given buffer, checking that it is large
+    /// enough to contain a TCP header without options. Returns None if it is not.
+    pub fn new_checked(packet: &'p mut [u8]) -> Option<MutableTcpHeader<'p>> {
+        if packet.len() < TCP_HEADER_LEN {
+            None
+        } else {
+            Some(MutableTcpHeader::new(packet))
+        }
+    }
+
+    /// Set the source port for the packet
+    pub fn set_source(&mut self, port: u16) {
+        self.packet[0] = (port >> 8) as u8;
+        self.packet[1] = (port & 0xFF) as u8;
+    }
+
+    /// Set the destination port for the packet
+    pub fn set_destination(&mut self, port: u16) {
+        self.packet[2] = (port >> 8) as u8;
+        self.packet[3] = (port & 0xFF) as u8;
+    }
+
+    /// Set the sequence number for the packet
+    pub fn set_sequence(&mut self, sequence: u32) {
+        self.packet[4] = (sequence >> 24) as u8;
+        self.packet[5] = (sequence >> 16) as u8;
+        self.packet[6] = (sequence >> 8) as u8;
+        self.packet[7] = sequence as u8;
+    }
+
+    /// Set the acknowledgement number for the packet
+    pub fn set_acknowledgement(&mut self, acknowledgement: u32) {
+        self.packet[8] = (acknowledgement >> 24) as u8;
+        self.packet[9] = (acknowledgement >> 16) as u8;
+        self.packet[10] = (acknowledgement >> 8) as u8;
+        self.packet[11] = acknowledgement as u8;
+    }
+
+    /// Set the data offset field for the packet, in 32-bit words
+    pub fn set_data_offset(&mut self, offset: u8) {
+        self.packet[12] = (self.packet[12] & 0x0F) | ((offset & 0x0F) << 4);
+    }
+
+    /// Set the flags for the packet, see `TcpFlags` for the values of each bit
+    pub fn set_flags(&mut self, flags: u16) {
+        self.packet[12] = (self.packet[12] & 0xFE) | ((flags >> 8) & 0x01) as u8;
+        self.packet[13] = (flags & 0xFF) as u8;
+    }
+
+    /// Set the window size for the packet
+    pub fn set_window(&mut self, window: u16) {
+        self.packet[14] = (window >> 8) as u8;
+        self.packet[15] = (window & 0xFF) as u8;
+    }
+
+    /// Set the checksum field for the packet
+    pub fn set_checksum(&mut self, checksum: u16) {
+        self.packet[16] = (checksum >> 8) as u8;
+        self.packet[17] = (checksum & 0xFF) as u8;
+    }
+
+    /// Set the urgent pointer for the packet
+    pub fn set_urgent_ptr(&mut self, urgent_ptr: u16) {
+        self.packet[18] = (urgent_ptr >> 8) as u8;
+        self.packet[19] = (urgent_ptr & 0xFF) as u8;
+    }
+
+    /// Calculate a checksum for the packet, then set the field. The whole buffer is treated as
+    /// the TCP segment.
+    pub fn checksum(&mut self, source: IpAddr, destination: IpAddr) {
+        let checksum = self.calculate_checksum(source, destination);
+        self.set_checksum(checksum);
+    }
+}
+
+#[test]
+fn tcp_header_test() {
+    let mut packet = [0u8, ..20];
+    {
+        let mut tcp_header = MutableTcpHeader::new(packet.as_mut_slice());
+        tcp_header.set_source(49152);
+        assert_eq!(tcp_header.get_source(), 49152);
+
+        tcp_header.set_destination(80);
+        assert_eq!(tcp_header.get_destination(), 80);
+
+        tcp_header.set_sequence(0x12345678);
+        assert_eq!(tcp_header.get_sequence(), 0x12345678);
+
+        tcp_header.set_acknowledgement(0x9abcdef0);
+        assert_eq!(tcp_header.get_acknowledgement(), 0x9abcdef0);
+
+        tcp_header.set_data_offset(5);
+        assert_eq!(tcp_header.get_data_offset(), 5);
+
+        tcp_header.set_flags(TcpFlags::Ns | TcpFlags::Ack | TcpFlags::Psh);
+        assert_eq!(tcp_header.get_flags(), TcpFlags::Ns | TcpFlags::Ack | TcpFlags::Psh);
+        assert_eq!(tcp_header.get_data_offset(), 5);
+
+        tcp_header.set_window(0xfaf0);
+        assert_eq!(tcp_header.get_window(), 0xfaf0);
+
+        tcp_header.set_checksum(0xbeef);
+        assert_eq!(tcp_header.get_checksum(), 0xbeef);
+
+        tcp_header.set_urgent_ptr(0x0102);
+        assert_eq!(tcp_header.get_urgent_ptr(), 0x0102);
+    }
+
+    let ref_packet = [0xc0, 0x00,             /* source */
+                      0x00, 0x50,             /* destination */
+                      0x12, 0x34, 0x56, 0x78, /* sequence */
+                      0x9a, 0xbc, 0xde, 0xf0, /* acknowledgement */
+                      0x51,                   /* data offset/ns */
+                      0x18,                   /* flags */
+                      0xfa, 0xf0,             /* window */
+                      0xbe, 0xef,             /* checksum */
+                      0x01, 0x02              /* urgent pointer */];
+    assert_eq!(ref_packet.as_slice(), packet.as_slice());
+}
+
+#[test]
+fn tcp_header_syn() {
+    let packet = [0xc0, 0x00,             /* source */
+                  0x00, 0x50,             /* destination */
+                  0x00, 0x00, 0x00, 0x01, /* sequence */
+                  0x00, 0x00, 0x00, 0x00, /* acknowledgement */
+                  0x50,                   /* data offset */
+                  0x02,                   /* flags */
+                  0x72, 0x10,             /* window */
+                  0x00, 0x00,             /* checksum */
+                  0x00, 0x00              /* urgent pointer */];
+    let tcp_header = TcpHeader::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(tcp_header.get_source(), 49152);
+    assert_eq!(tcp_header.get_destination(), 80);
+    assert_eq!(tcp_header.get_sequence(), 1);
+    assert!(tcp_header.is_syn());
+    assert!(!tcp_header.is_ack());
+    assert!(!tcp_header.is_fin());
+    assert!(!tcp_header.is_rst());
+    assert!(!tcp_header.is_psh());
+    assert!(!tcp_header.is_urg());
+    assert_eq!(tcp_header.get_window(), 0x7210);
+    assert_eq!(tcp_header.payload().len(), 0);
+
+    assert!(TcpHeader::new_checked(packet.slice_to(19)).is_none());
+}
+
+#[test]
+fn tcp_header_payload_offset() {
+    let mut packet = [0xc0, 0x00, 0x00, 0x50, /* source/destination */
+                      0x00, 0x00, 0x00, 0x01, /* sequence */
+                      0x00, 0x00, 0x00, 0x01, /* acknowledgement */
+                      0x60,                   /* data offset */
+                      0x18,                   /* flags */
+                      0x72, 0x10, 0x00, 0x00, /* window/checksum */
+                      0x00, 0x00,             /* urgent pointer */
+                      0x01, 0x01, 0x01, 0x00, /* options */
+                      0xaa, 0xbb              /* payload */];
+    {
+        let tcp_header = TcpHeader::new_checked(packet.as_slice()).unwrap();
+        assert!(tcp_header.is_ack());
+        assert!(tcp_header.is_psh());
+        assert_eq!(tcp_header.get_data_offset(), 6);
+        assert_eq!(tcp_header.payload(), [0xaa, 0xbb].as_slice());
+
+        // Truncated in the options
+        assert!(TcpHeader::new_checked(packet.slice_to(23)).is_none());
+    }
+
+    // Without options, the payload starts straight after the fixed header
+    packet[12] = 0x50;
+    {
+        let tcp_header = TcpHeader::new_checked(packet.as_slice()).unwrap();
+        assert_eq!(tcp_header.payload(), packet.slice_from(20));
+    }
+
+    // Data offset less than the minimum
+    packet[12] = 0x40;
+    assert!(TcpHeader::new_checked(packet.as_slice()).is_none());
+}
+
+#[test]
+fn tcp_header_options() {
+    let mut packet = [0xc0, 0x00, 0x00, 0x50, /* source/destination */
+                      0x00, 0x00, 0x00, 0x01, /* sequence */
+                      0x00, 0x00, 0x00, 0x00, /* acknowledgement */
+                      0xa0,                   /* data offset */
+                      0x02,                   /* flags */
+                      0x72, 0x10, 0x00, 0x00, /* window/checksum */
+                      0x00, 0x00,             /* urgent pointer */
+                      0x02, 0x04, 0x05, 0xb4, /* mss */
+                      0x01,                   /* nop */
+                      0x03, 0x03, 0x07,       /* window scale */
+                      0x04, 0x02,             /* sack permitted */
+                      0x08, 0x0a,             /* timestamps */
+                      0x00, 0x01, 0x02, 0x03,
+                      0x00, 0x00, 0x00, 0x00,
+                      0xaa, 0xbb              /* payload */];
+    {
+        let tcp_header = TcpHeader::new_checked(packet.as_slice()).unwrap();
+        let options: Vec<TcpOption> = tcp_header.get_options().collect();
+        assert_eq!(options.len(), 5);
+
+        assert_eq!(options[0].number, TcpOptionNumbers::Mss);
+        assert_eq!(options[0].length, 4);
+        assert_eq!(options[0].get_mss(), Some(1460));
+        assert_eq!(options[0].get_window_scale(), None);
+
+        assert_eq!(options[1].number, TcpOptionNumbers::Nop);
+        assert_eq!(options[1].length, 1);
+
+        assert_eq!(options[2].number, TcpOptionNumbers::Wscale);
+        assert_eq!(options[2].get_window_scale(), Some(7));
+        assert_eq!(options[2].get_mss(), None);
+
+        assert!(options[3].is_sack_permitted());
+
+        assert_eq!(options[4].get_timestamps(), Some((0x00010203, 0)));
+    }
+
+    // A length running past the end of the header ends iteration
+    packet[21] = 0x20;
+    {
+        let tcp_header = TcpHeader::new_checked(packet.as_slice()).unwrap();
+        assert_eq!(tcp_header.get_options().count(), 0);
+    }
+
+    // As does an impossible length, after the preceding options
+    packet[21] = 0x04;
+    packet[26] = 0x00;
+    {
+        let tcp_header = TcpHeader::new_checked(packet.as_slice()).unwrap();
+        let options: Vec<TcpOption> = tcp_header.get_options().collect();
+        assert_eq!(options.len(), 2);
+        assert_eq!(options[1].number, TcpOptionNumbers::Nop);
+    }
+}
+
+#[test]
+fn tcp_header_checksum() {
+    // A SYN with an MSS option
+    let mut packet = [0xc0, 0x00, 0x00, 0x50, /* source/destination */
+                      0x3a, 0x5b, 0x7c, 0x9d, /* sequence */
+                      0x00, 0x00, 0x00, 0x00, /* acknowledgement */
+                      0x60,                   /* data offset */
+                      0x02,                   /* flags */
+                      0xfa, 0xf0,             /* window */
+                      0x2e, 0x05,             /* checksum */
+                      0x00, 0x00,             /* urgent pointer */
+                      0x02, 0x04, 0x05, 0xb4  /* mss */];
+    let ipv4_source = Ipv4Addr(192, 168, 1, 100);
+    let ipv4_destination = Ipv4Addr(93, 184, 216, 34);
+    let ipv6_source = Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+    let ipv6_destination = Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2);
+    {
+        let tcp_header = TcpHeader::new(packet.as_slice());
+        assert_eq!(tcp_header.calculate_checksum(ipv4_source, ipv4_destination), 0x2e05);
+        assert!(tcp_header.is_checksum_valid(ipv4_source, ipv4_destination));
+        assert_eq!(tcp_header.calculate_checksum(ipv6_source, ipv6_destination), 0xca77);
+        assert!(!tcp_header.is_checksum_valid(ipv6_source, ipv6_destination));
+    }
+
+    // Corrupt the sequence number
+    packet[7] = 0x9e;
+    {
+        let tcp_header = TcpHeader::new(packet.as_slice());
+        assert!(!tcp_header.is_checksum_valid(ipv4_source, ipv4_destination));
+    }
+    packet[7] = 0x9d;
+
+    // The existing checksum is ignored when calculating a new one
+    {
+        let mut tcp_header = MutableTcpHeader::new(packet.as_mut_slice());
+        tcp_header.checksum(ipv6_source, ipv6_destination);
+        assert_eq!(tcp_header.get_checksum(), 0xca77);
+        assert!(tcp_header.is_checksum_valid(ipv6_source, ipv6_destination));
+    }
+}
+
+#[test]
+fn tcp_header_checksum_odd_length() {
+    let mut packet = [0xc0, 0x00, 0x00, 0x50, 0x3a, 0x5b, 0x7c, 0x9d,
+                      0x00, 0x00, 0x00, 0x00, 0x60, 0x02, 0xfa, 0xf0,
+                      0x00, 0x00, 0x00, 0x00, 0x02, 0x04, 0x05, 0xb4,
+                      'a' as u8, 'b' as u8, 'c' as u8];
+    let mut tcp_header = MutableTcpHeader::new(packet.as_mut_slice());
+    tcp_header.checksum(Ipv4Addr(192, 168, 1, 100), Ipv4Addr(93, 184, 216, 34));
+    assert_eq!(tcp_header.get_checksum(), 0x699f);
+}
+
+/// TCP option kinds, as defined at:
+/// http://www.iana.org/assignments/tcp-parameters/tcp-parameters.xhtml
+///
+/// FIXME Should include all
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod TcpOptionNumbers {
+    use super::TcpOptionNumber;
+
+    /// End of Option List [RFC793]
+    pub const Eol: TcpOptionNumber           = TcpOptionNumber(0);
+
+    /// No-Operation [RFC793]
+    pub const Nop: TcpOptionNumber           = TcpOptionNumber(1);
+
+    /// Maximum Segment Size [RFC793]
+    pub const Mss: TcpOptionNumber           = TcpOptionNumber(2);
+
+    /// Window Scale [RFC7323]
+    pub const Wscale: TcpOptionNumber        = TcpOptionNumber(3);
+
+    /// SACK Permitted [RFC2018]
+    pub const SackPermitted: TcpOptionNumber = TcpOptionNumber(4);
+
+    /// SACK [RFC2018]
+    pub const Sack: TcpOptionNumber          = TcpOptionNumber(5);
+
+    /// Timestamps [RFC7323]
+    pub const Timestamps: TcpOptionNumber    = TcpOptionNumber(8);
+}
+
+/// Represents the kind field of a TCP option, see `TcpOptionNumbers` for a list of values.
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TcpOptionNumber(pub u8);
+
+/// The bits of the TCP flags field, as defined at:
+/// http://www.iana.org/assignments/tcp-parameters/tcp-parameters.xhtml
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod TcpFlags {
+    /// No more data from sender [RFC793]
+    pub const Fin: u16 = 0x001;
+
+    /// Synchronize sequence numbers [RFC793]
+    pub const Syn: u16 = 0x002;
+
+    /// Reset the connection [RFC793]
+    pub const Rst: u16 = 0x004;
+
+    /// Push function [RFC793]
+    pub const Psh: u16 = 0x008;
+
+    /// Acknowledgment field significant [RFC793]
+    pub const Ack: u16 = 0x010;
+
+    /// Urgent pointer field significant [RFC793]
+    pub const Urg: u16 = 0x020;
+
+    /// ECN-Echo [RFC3168]
+    pub const Ece: u16 = 0x040;
+
+    /// Congestion Window Reduced [RFC3168]
+    pub const Cwr: u16 = 0x080;
+
+    /// ECN-nonce concealment protection [RFC3540]
+    pub const Ns: u16  = 0x100;
+}
@@ -8,11 +8,16 @@
 
 //! UDP packet abstraction
 
+use std::cmp;
 use std::fmt;
 use std::io::net::ip::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 use packet::{Packet, MutablePacket};
 use packet::ip::{IpNextHeaderProtocol};
+use util;
+
+/// The length of a UDP header, in bytes
+pub const UDP_HEADER_LEN: uint = 8;
 
 /// Structure representing a UDP header
 pub struct UdpHeader<'p> {
@@ -124,115 +129,22 @@ pub trait UdpPacket : Packet {
                                ipv4_destination: IpAddr,
                                next_level_protocol: IpNextHeaderProtocol)
         -> u16 {
-        let IpNextHeaderProtocol(next_level_protocol) = next_level_protocol;
-        let mut sum = 0u32;
-
-        // Checksum pseudo-header
-        // IPv4 source
-        match ipv4_source {
-            Ipv4Addr(a, b, c, d) => {
-                sum = sum + (a as u32 << 8 | b as u32);
-                sum = sum + (c as u32 << 8 | d as u32);
-            },
-            _ => ()
-        }
-
-        // IPv4 destination
-        match ipv4_destination {
-            Ipv4Addr(a, b, c, d) => {
-                sum = sum + (a as u32 << 8 | b as u32);
-                sum = sum + (c as u32 << 8 | d as u32);
-            },
-            _ => ()
-        }
-
-        // IPv4 Next level protocol
-        sum = sum + next_level_protocol as u32;
-
-        // UDP Length
-        sum = sum + (self.packet()[4] as u32 << 8 |
-                     self.packet()[5] as u32);
-
-        // Checksum UDP header/packet
-        let mut i = 0;
-        let len = self.get_length() as uint;
-        while i < len && i + 1 < self.packet().len() {
-            let word = self.packet()[i] as u32 << 8 | self.packet()[i + 1] as u32;
-            sum = sum + word;
-            i = i + 2;
-        }
-        // If the length is odd, make sure to checksum the final byte
-        if len & 1 != 0 && len <= self.packet().len() {
-            sum = sum + (self.packet()[len - 1] as u32 << 8);
-        }
-        while sum >> 16 != 0 {
-            sum = (sum >> 16) + (sum & 0xFFFF);
-        }
-
-        return !sum as u16;
+        util::ipv4_checksum(self.checksummed_data(), Some(3), ipv4_source, ipv4_destination,
+                            next_level_protocol)
     }
 
     /// Calculate the checksum for a packet built on IPv6
     fn calculate_ipv6_checksum(&self, ipv6_source: IpAddr, ipv6_destination: IpAddr,
                                next_header: IpNextHeaderProtocol) -> u16 {
-        let IpNextHeaderProtocol(next_header) = next_header;
-        let mut sum = 0u32;
-
-        // Checksum pseudo-header
-        // IPv6 source
-        match ipv6_source {
-            Ipv6Addr(a, b, c, d, e, f, g, h) => {
-                sum = sum + a as u32;
-                sum = sum + b as u32;
-                sum = sum + c as u32;
-                sum = sum + d as u32;
-                sum = sum + e as u32;
-                sum = sum + f as u32;
-                sum = sum + g as u32;
-                sum = sum + h as u32;
-            },
-            _ => ()
-        }
-
-        // IPv6 destination
-        match ipv6_destination {
-            Ipv6Addr(a, b, c, d, e, f, g, h) => {
-                sum = sum + a as u32;
-                sum = sum + b as u32;
-                sum = sum + c as u32;
-                sum = sum + d as u32;
-                sum = sum + e as u32;
-                sum = sum + f as u32;
-                sum = sum + g as u32;
-                sum = sum + h as u32;
-            },
-            _ => ()
-        }
-
-        // IPv6 Next header
-        sum = sum + next_header as u32;
-
-        // UDP Length
-        sum = sum + self.get_length() as u32;
-
-        // Checksum UDP header/packet
-        let mut i = 0;
-        let len = self.get_length() as uint;
-        while i < len && i + 1 < self.packet().len() {
-            let word = self.packet()[i] as u32 << 8 | self.packet()[i + 1] as u32;
-            sum = sum + word;
-            i = i + 2;
-        }
-        // If the length is odd, make sure to checksum the final byte
-        if len & 1 != 0 && len <= self.packet().len() {
-            sum = sum + self.packet()[len - 1] as u32 << 8;
-        }
-
-        while sum >> 16 != 0 {
-            sum = (sum >> 16) + (sum & 0xFFFF);
-        }
+        util::ipv6_checksum(self.checksummed_data(), Some(3), ipv6_source, ipv6_destination,
+                            next_header)
+    }
 
-        return !sum as u16;
+    /// The part of the buffer covered by the checksum - the header and payload, as given by the
+    /// length field
+    fn checksummed_data<'a>(&'a self) -> &'a [u8] {
+        let len = cmp::min(self.get_length() as uint, self.packet().len());
+        self.packet().slice_to(len)
     }
 
     /// Calculate a checksum regardless of IP version
@@ -245,6 +157,24 @@ pub trait UdpPacket : Packet {
         }
     }
 
+    /// Does the checksum field of the packet match the checksum calculated for it?
+    ///
+    /// A checksum field of zero means no checksum was transmitted, which is only permitted over
+    /// IPv4, so is considered valid there.
+    fn is_checksum_valid(&self, source: IpAddr, destination: IpAddr,
+                         next_header: IpNextHeaderProtocol) -> bool {
+        let checksum = self.get_checksum();
+        match (checksum, source) {
+            (0, Ipv4Addr(..)) => return true,
+            _ => ()
+        }
+        // RFC 768, a checksum of zero is transmitted as all ones
+        match self.calculate_checksum(source, destination, next_header) {
+            0 => checksum == 0xFFFF,
+            calculated => checksum == calculated
+        }
+    }
+
 }
 
 impl<'p> UdpPacket for UdpHeader<'p> {}
@@ -256,6 +186,22 @@ impl<'p> UdpHeader<'p> {
     pub fn new(packet: &'p [u8]) -> UdpHeader<'p> {
         UdpHeader { packet: packet }
     }
+
+    /// Construct a new UDP header backed by the given buffer, checking that it is large enough
+    /// to contain a UDP header. Returns None if it is not, or if the length field is less than
+    /// the length of the header or greater than the length of the buffer.
+    pub fn new_checked(packet: &'p [u8]) -> Option<UdpHeader<'p>> {
+        if packet.len() < UDP_HEADER_LEN {
+            return None;
+        }
+        let header = UdpHeader::new(packet);
+        let len = header.get_length() as uint;
+        if len < UDP_HEADER_LEN || len > packet.len() {
+            return None;
+        }
+
+        Some(header)
+    }
 }
 
 impl<'p> MutableUdpHeader<'p> {
@@ -265,6 +211,16 @@ impl<'p> MutableUdpHeader<'p> {
         MutableUdpHeader { packet: packet }
     }
 
+    /// Construct a new mutable UDP header backed by the given buffer, checking that it is large
+    /// enough to contain a UDP header. Returns None if it is not.
+    pub fn new_checked(packet: &'p mut [u8]) -> Option<MutableUdpHeader<'p>> {
+        if packet.len() < UDP_HEADER_LEN {
+            None
+        } else {
+            Some(MutableUdpHeader::new(packet))
+        }
+    }
+
     /// Set the source port for the packet
     pub fn set_source(&mut self, port: u16) {
         self.packet[0] = (port >> 8) as u8;
@@ -391,3 +347,93 @@ fn udp_header_ipv6_test() {
     assert_eq!(ref_packet.as_slice(), packet.slice(40, 48));
 }
 
+#[test]
+fn udp_header_round_trip() {
+    let mut packet = [0u8, ..8 + 5];
+    {
+        let mut udp_header = MutableUdpHeader::new_checked(packet.as_mut_slice()).unwrap();
+        udp_header.set_source(53);
+        udp_header.set_destination(4321);
+        {
+            let data = udp_header.payload_mut();
+            data[0] = 'h' as u8;
+            data[1] = 'e' as u8;
+            data[2] = 'l' as u8;
+            data[3] = 'l' as u8;
+            data[4] = 'o' as u8;
+        }
+        let len = (UDP_HEADER_LEN + udp_header.payload().len()) as u16;
+        udp_header.set_length(len);
+        udp_header.set_checksum(0x1234);
+    }
+
+    let udp_header = UdpHeader::new_checked(packet.as_slice()).unwrap();
+    assert_eq!(udp_header.get_source(), 53);
+    assert_eq!(udp_header.get_destination(), 4321);
+    assert_eq!(udp_header.get_length(), 13);
+    assert_eq!(udp_header.get_length() as uint, UDP_HEADER_LEN + udp_header.payload().len());
+    assert_eq!(udp_header.get_checksum(), 0x1234);
+    assert_eq!(udp_header.payload(), "hello".as_bytes());
+
+    // Every truncation of the packet is shorter than the length field
+    for len in range(0u, packet.len()) {
+        assert!(UdpHeader::new_checked(packet.slice_to(len)).is_none());
+    }
+
+    // Length fields which are shorter than the header, or longer than the buffer
+    for &len in [0u8, 7, 14, 0xff].iter() {
+        let mut inconsistent = packet;
+        inconsistent[5] = len;
+        assert!(UdpHeader::new_checked(inconsistent.as_slice()).is_none());
+    }
+}
+
+#[test]
+fn udp_header_checksum() {
+    use packet::ip::IpNextHeaderProtocols;
+
+    // A DNS query for example.com, which has an odd length
+    let mut packet = [0xcf, 0x08, /* source */
+                      0x00, 0x35, /* destination */
+                      0x00, 0x25, /* length */
+                      0xe3, 0x0e, /* checksum */
+                      0xab, 0xcd, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+                      0x00, 0x00, 0x07, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65,
+                      0x03, 0x63, 0x6f, 0x6d, 0x00, 0x00, 0x01, 0x00, 0x01];
+    let udp = IpNextHeaderProtocols::Udp;
+    let ipv4_source = Ipv4Addr(192, 168, 1, 100);
+    let ipv4_destination = Ipv4Addr(8, 8, 8, 8);
+    let ipv6_source = Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+    let ipv6_destination = Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2);
+    {
+        let udp_header = UdpHeader::new(packet.as_slice());
+        assert_eq!(udp_header.calculate_checksum(ipv4_source, ipv4_destination, udp), 0xe30e);
+        assert!(udp_header.is_checksum_valid(ipv4_source, ipv4_destination, udp));
+        assert_eq!(udp_header.calculate_checksum(ipv6_source, ipv6_destination, udp), 0x59b6);
+        assert!(!udp_header.is_checksum_valid(ipv6_source, ipv6_destination, udp));
+    }
+
+    // The existing checksum is ignored when calculating a new one
+    {
+        let mut udp_header = MutableUdpHeader::new(packet.as_mut_slice());
+        udp_header.checksum(ipv6_source, ipv6_destination, udp);
+        assert_eq!(udp_header.get_checksum(), 0x59b6);
+        assert!(udp_header.is_checksum_valid(ipv6_source, ipv6_destination, udp));
+    }
+
+    // Corrupt the payload
+    packet[20] = 0x00;
+    {
+        let udp_header = UdpHeader::new(packet.as_slice());
+        assert!(!udp_header.is_checksum_valid(ipv6_source, ipv6_destination, udp));
+    }
+
+    // No checksum is only allowed over IPv4
+    packet[6] = 0;
+    packet[7] = 0;
+    {
+        let udp_header = UdpHeader::new(packet.as_slice());
+        assert!(udp_header.is_checksum_valid(ipv4_source, ipv4_destination, udp));
+        assert!(!udp_header.is_checksum_valid(ipv6_source, ipv6_destination, udp));
+    }
+}
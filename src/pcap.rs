@@ -0,0 +1,423 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Support for reading and writing packet captures in the classic pcap file format, as used by
+//! tcpdump and Wireshark
+//!
+//! ```rust,no_run
+//! use pnet::packet::Packet;
+//! use pnet::packet::ethernet::EthernetHeader;
+//! use pnet::pcap::PcapReader;
+//! use std::io::File;
+//!
+//! let data = File::open(&Path::new("capture.pcap")).read_to_end().unwrap();
+//! let reader = PcapReader::new(data.as_slice()).unwrap();
+//! for frame in reader.frames() {
+//!     let (timestamp, packet) = frame.unwrap();
+//!     let ethernet = EthernetHeader::new(packet);
+//!     println!("{}: {} bytes", timestamp, ethernet.packet().len());
+//! }
+//! ```
+
+use std::fmt;
+use std::io::{IoResult, IoError, InvalidInput, BufferedWriter};
+use std::time::Duration;
+
+/// The length of the pcap global header, at the start of the file
+pub const PCAP_HEADER_LEN: uint = 24;
+
+/// The length of the header before each captured packet
+pub const PCAP_RECORD_HEADER_LEN: uint = 16;
+
+// The magic number for files with microsecond timestamps
+const MAGIC_MICROSECONDS: u32 = 0xa1b2c3d4;
+
+// The magic number for files with nanosecond timestamps
+const MAGIC_NANOSECONDS: u32 = 0xa1b23c4d;
+
+/// The snapshot length recorded by `PcapWriter`, the largest packet which can be written
+pub const PCAP_WRITER_SNAPLEN: u32 = 262144;
+
+/// Reads packets from a pcap file which has been loaded into memory
+///
+/// Files written on hosts of either byte order are supported, as are files with nanosecond
+/// timestamps.
+pub struct PcapReader<'p> {
+    data: &'p [u8],
+    big_endian: bool,
+    nanoseconds: bool,
+}
+
+impl<'p> fmt::Show for PcapReader<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt,
+               "PcapReader {{ version: {}, snaplen: {}, link_type: {} }}",
+                self.get_version(),
+                self.get_snaplen(),
+                self.get_link_type()
+        )
+    }
+}
+
+impl<'p> PcapReader<'p> {
+    /// Construct a new reader for the given pcap file contents. Returns an error with kind
+    /// `InvalidInput` if the data is too short to hold the global header, or doesn't start with a
+    /// pcap magic number.
+    pub fn new(data: &'p [u8]) -> IoResult<PcapReader<'p>> {
+        if data.len() < PCAP_HEADER_LEN {
+            return Err(invalid_input("pcap file is too short to hold the global header"));
+        }
+        let le_magic = read_u32(data, false);
+        let be_magic = read_u32(data, true);
+        let (big_endian, nanoseconds) = if le_magic == MAGIC_MICROSECONDS {
+            (false, false)
+        } else if le_magic == MAGIC_NANOSECONDS {
+            (false, true)
+        } else if be_magic == MAGIC_MICROSECONDS {
+            (true, false)
+        } else if be_magic == MAGIC_NANOSECONDS {
+            (true, true)
+        } else {
+            return Err(invalid_input("not a pcap file, the magic number is unknown"));
+        };
+
+        Ok(PcapReader { data: data, big_endian: big_endian, nanoseconds: nanoseconds })
+    }
+
+    /// Get the (major, minor) version of the file format
+    pub fn get_version(&self) -> (u16, u16) {
+        (read_u16(self.data.slice_from(4), self.big_endian),
+         read_u16(self.data.slice_from(6), self.big_endian))
+    }
+
+    /// Get the maximum number of bytes captured from each packet
+    pub fn get_snaplen(&self) -> u32 {
+        read_u32(self.data.slice_from(16), self.big_endian)
+    }
+
+    /// Get the link layer header type of the packets
+    pub fn get_link_type(&self) -> LinkType {
+        LinkType(read_u32(self.data.slice_from(20), self.big_endian))
+    }
+
+    /// Are the timestamps in the file given to nanosecond, rather than microsecond, precision?
+    pub fn has_nanosecond_timestamps(&self) -> bool {
+        self.nanoseconds
+    }
+
+    /// Get an iterator over the packets in the file
+    pub fn frames(&self) -> PcapFrameIterator<'p> {
+        PcapFrameIterator {
+            buf: self.data.slice_from(PCAP_HEADER_LEN),
+            big_endian: self.big_endian,
+            nanoseconds: self.nanoseconds,
+        }
+    }
+}
+
+/// An iterator over the packets in a pcap file
+///
+/// Each item is the time the packet was captured, as a duration since the Unix epoch, and the
+/// captured bytes of the packet. If a record is truncated, an error with kind `InvalidInput` is
+/// returned and iteration finishes.
+pub struct PcapFrameIterator<'p> {
+    buf: &'p [u8],
+    big_endian: bool,
+    nanoseconds: bool,
+}
+
+impl<'p> Iterator<IoResult<(Duration, &'p [u8])>> for PcapFrameIterator<'p> {
+    fn next(&mut self) -> Option<IoResult<(Duration, &'p [u8])>> {
+        if self.buf.len() == 0 {
+            return None;
+        }
+        if self.buf.len() < PCAP_RECORD_HEADER_LEN {
+            self.buf = self.buf.slice_to(0);
+            return Some(Err(invalid_input("pcap record header is truncated")));
+        }
+        let seconds = read_u32(self.buf, self.big_endian);
+        let fraction = read_u32(self.buf.slice_from(4), self.big_endian);
+        let incl_len = read_u32(self.buf.slice_from(8), self.big_endian) as uint;
+        if incl_len > self.buf.len() - PCAP_RECORD_HEADER_LEN {
+            self.buf = self.buf.slice_to(0);
+            return Some(Err(invalid_input("pcap record is longer than the rest of the file")));
+        }
+
+        let fraction = if self.nanoseconds {
+            Duration::nanoseconds(fraction as i64)
+        } else {
+            Duration::microseconds(fraction as i64)
+        };
+        let timestamp = Duration::seconds(seconds as i64) + fraction;
+        let end = PCAP_RECORD_HEADER_LEN + incl_len;
+        let packet = self.buf.slice(PCAP_RECORD_HEADER_LEN, end);
+        self.buf = self.buf.slice_from(end);
+
+        Some(Ok((timestamp, packet)))
+    }
+}
+
+fn invalid_input(desc: &'static str) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: desc,
+        detail: None,
+    }
+}
+
+fn read_u16(buf: &[u8], big_endian: bool) -> u16 {
+    if big_endian {
+        (buf[0] as u16 << 8) | buf[1] as u16
+    } else {
+        (buf[1] as u16 << 8) | buf[0] as u16
+    }
+}
+
+fn read_u32(buf: &[u8], big_endian: bool) -> u32 {
+    if big_endian {
+        (read_u16(buf, true) as u32 << 16) | read_u16(buf.slice_from(2), true) as u32
+    } else {
+        (read_u16(buf.slice_from(2), false) as u32 << 16) | read_u16(buf, false) as u32
+    }
+}
+
+/// Writes packets to a pcap file, with microsecond timestamps
+///
+/// Writes are buffered; call `flush()` to make sure the packets written so far have been passed
+/// to the underlying writer. The buffer is also flushed when the `PcapWriter` is dropped, but any
+/// error is then ignored.
+pub struct PcapWriter<W> {
+    writer: BufferedWriter<W>,
+}
+
+impl<W: Writer> PcapWriter<W> {
+    /// Construct a new pcap writer, writing the global header for packets of the given link type
+    pub fn new(writer: W, link_type: LinkType) -> IoResult<PcapWriter<W>> {
+        let LinkType(link_type) = link_type;
+        let mut writer = BufferedWriter::new(writer);
+        try!(writer.write_le_u32(MAGIC_MICROSECONDS));
+        // Version 2.4, with timestamps in UTC
+        try!(writer.write_le_u16(2));
+        try!(writer.write_le_u16(4));
+        try!(writer.write_le_i32(0));
+        try!(writer.write_le_u32(0));
+        try!(writer.write_le_u32(PCAP_WRITER_SNAPLEN));
+        try!(writer.write_le_u32(link_type));
+
+        Ok(PcapWriter { writer: writer })
+    }
+
+    /// Write a packet, captured at `timestamp` (the duration since the Unix epoch)
+    ///
+    /// The whole packet is written. An error with kind `InvalidInput` is returned if it is longer
+    /// than `PCAP_WRITER_SNAPLEN`, or the timestamp can't be represented in a pcap file.
+    pub fn write(&mut self, timestamp: Duration, packet: &[u8]) -> IoResult<()> {
+        if packet.len() > PCAP_WRITER_SNAPLEN as uint {
+            return Err(invalid_input("packet is longer than the snapshot length"));
+        }
+        let seconds = timestamp.num_seconds();
+        if timestamp < Duration::zero() || seconds > 0xffffffff {
+            return Err(invalid_input("timestamp can't be written to a pcap file"));
+        }
+        let microseconds = (timestamp - Duration::seconds(seconds)).num_microseconds().unwrap();
+
+        try!(self.writer.write_le_u32(seconds as u32));
+        try!(self.writer.write_le_u32(microseconds as u32));
+        try!(self.writer.write_le_u32(packet.len() as u32));
+        try!(self.writer.write_le_u32(packet.len() as u32));
+        self.writer.write(packet)
+    }
+
+    /// Flush any buffered packets to the underlying writer
+    pub fn flush(&mut self) -> IoResult<()> {
+        self.writer.flush()
+    }
+
+    /// Flush any buffered packets, ignoring any error, and return the underlying writer
+    pub fn unwrap(self) -> W {
+        self.writer.unwrap()
+    }
+}
+
+#[cfg(test)]
+fn push_u16(data: &mut Vec<u8>, v: u16, big_endian: bool) {
+    if big_endian {
+        data.push((v >> 8) as u8);
+        data.push(v as u8);
+    } else {
+        data.push(v as u8);
+        data.push((v >> 8) as u8);
+    }
+}
+
+#[cfg(test)]
+fn push_u32(data: &mut Vec<u8>, v: u32, big_endian: bool) {
+    if big_endian {
+        push_u16(data, (v >> 16) as u16, true);
+        push_u16(data, v as u16, true);
+    } else {
+        push_u16(data, v as u16, false);
+        push_u16(data, (v >> 16) as u16, false);
+    }
+}
+
+// A capture of two packets on an Ethernet link, the first 60 bytes long and the second truncated
+// from 100 bytes to 42
+#[cfg(test)]
+fn test_capture(big_endian: bool) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    // Global header
+    push_u32(&mut data, MAGIC_MICROSECONDS, big_endian);
+    push_u16(&mut data, 2, big_endian);
+    push_u16(&mut data, 4, big_endian);
+    push_u32(&mut data, 0, big_endian);
+    push_u32(&mut data, 0, big_endian);
+    push_u32(&mut data, 65535, big_endian);
+    push_u32(&mut data, 1, big_endian);
+
+    // Records
+    for &(seconds, microseconds, incl_len, orig_len) in [(1412000000u32, 250000u32, 60u32, 60u32),
+                                                         (1412000001, 5, 42, 100)].iter() {
+        push_u32(&mut data, seconds, big_endian);
+        push_u32(&mut data, microseconds, big_endian);
+        push_u32(&mut data, incl_len, big_endian);
+        push_u32(&mut data, orig_len, big_endian);
+        for i in range(0, incl_len) {
+            data.push(i as u8);
+        }
+    }
+
+    data
+}
+
+#[test]
+fn pcap_read() {
+    for &big_endian in [false, true].iter() {
+        let data = test_capture(big_endian);
+        let reader = PcapReader::new(data.as_slice()).unwrap();
+        assert_eq!(reader.get_version(), (2, 4));
+        assert_eq!(reader.get_snaplen(), 65535);
+        assert_eq!(reader.get_link_type(), LinkTypes::Ethernet);
+        assert!(!reader.has_nanosecond_timestamps());
+
+        let frames: Vec<(Duration, &[u8])> = reader.frames().map(|f| f.unwrap()).collect();
+        assert_eq!(frames.len(), 2);
+        let (timestamp, packet) = frames[0];
+        assert_eq!(timestamp, Duration::seconds(1412000000) + Duration::milliseconds(250));
+        assert_eq!(packet.len(), 60);
+        assert_eq!(packet[59], 59);
+        let (timestamp, packet) = frames[1];
+        assert_eq!(timestamp, Duration::seconds(1412000001) + Duration::microseconds(5));
+        assert_eq!(packet.len(), 42);
+    }
+}
+
+#[test]
+fn pcap_nanoseconds() {
+    let mut data = test_capture(false);
+    *data.get_mut(0) = 0x4d;
+    *data.get_mut(1) = 0x3c;
+    let reader = PcapReader::new(data.as_slice()).unwrap();
+    assert!(reader.has_nanosecond_timestamps());
+    let (timestamp, _) = reader.frames().next().unwrap().unwrap();
+    assert_eq!(timestamp, Duration::seconds(1412000000) + Duration::microseconds(250));
+}
+
+#[test]
+fn pcap_malformed() {
+    let data = test_capture(false);
+    assert!(PcapReader::new(data.slice_to(PCAP_HEADER_LEN - 1)).is_err());
+    assert!(PcapReader::new([0u8, ..PCAP_HEADER_LEN].as_slice()).is_err());
+
+    // No packets
+    let reader = PcapReader::new(data.slice_to(PCAP_HEADER_LEN)).unwrap();
+    assert!(reader.frames().next().is_none());
+
+    // A truncated record header
+    let reader = PcapReader::new(data.slice_to(PCAP_HEADER_LEN + 10)).unwrap();
+    let mut frames = reader.frames();
+    assert_eq!(frames.next().unwrap().unwrap_err().kind, InvalidInput);
+    assert!(frames.next().is_none());
+
+    // A record which claims to be longer than the rest of the file
+    let reader = PcapReader::new(data.slice_to(data.len() - 1)).unwrap();
+    let mut frames = reader.frames();
+    assert!(frames.next().unwrap().is_ok());
+    assert_eq!(frames.next().unwrap().unwrap_err().kind, InvalidInput);
+    assert!(frames.next().is_none());
+}
+
+#[test]
+fn pcap_write() {
+    use std::io::MemWriter;
+
+    let (first, second, third) = ([0x11u8, ..60], [0x22u8, ..1514], [0x33u8, ..42]);
+    let packets = [(Duration::seconds(1412000000) + Duration::microseconds(250000),
+                    first.as_slice()),
+                   (Duration::seconds(1412000001), second.as_slice()),
+                   (Duration::seconds(1412000001) + Duration::microseconds(1), third.as_slice())];
+
+    let mut writer = PcapWriter::new(MemWriter::new(), LinkTypes::Ethernet).unwrap();
+    for &(timestamp, packet) in packets.iter() {
+        writer.write(timestamp, packet).unwrap();
+    }
+    let data = writer.unwrap().unwrap();
+
+    let reader = PcapReader::new(data.as_slice()).unwrap();
+    assert_eq!(reader.get_version(), (2, 4));
+    assert_eq!(reader.get_snaplen(), PCAP_WRITER_SNAPLEN);
+    assert_eq!(reader.get_link_type(), LinkTypes::Ethernet);
+    let frames: Vec<(Duration, &[u8])> = reader.frames().map(|f| f.unwrap()).collect();
+    assert_eq!(frames.as_slice(), packets.as_slice());
+
+    // Each record's original length matches the captured length
+    let record = data.slice_from(PCAP_HEADER_LEN);
+    assert_eq!(read_u32(record.slice_from(8), false), 60);
+    assert_eq!(read_u32(record.slice_from(12), false), 60);
+}
+
+#[test]
+fn pcap_write_invalid() {
+    use std::io::MemWriter;
+
+    let mut writer = PcapWriter::new(MemWriter::new(), LinkTypes::Raw).unwrap();
+    let packet = Vec::from_elem(PCAP_WRITER_SNAPLEN as uint + 1, 0u8);
+    assert!(writer.write(Duration::zero(), packet.as_slice()).is_err());
+    assert!(writer.write(Duration::seconds(-1), packet.slice_to(20)).is_err());
+    assert!(writer.write(Duration::seconds(1 << 32), packet.slice_to(20)).is_err());
+
+    // Nothing is written for rejected packets
+    writer.flush().unwrap();
+    assert_eq!(writer.unwrap().unwrap().len(), PCAP_HEADER_LEN);
+}
+
+/// Link layer header types, as defined at: http://www.tcpdump.org/linktypes.html
+///
+/// FIXME Should include all
+#[allow(non_snake_case)]
+#[allow(non_uppercase_statics)]
+pub mod LinkTypes {
+    use super::LinkType;
+
+    /// No link layer header, as used by the BSD loopback device
+    pub const Null: LinkType     = LinkType(0);
+
+    /// Ethernet
+    pub const Ethernet: LinkType = LinkType(1);
+
+    /// Raw IPv4 or IPv6 packets, with no link layer header
+    pub const Raw: LinkType      = LinkType(101);
+
+    /// Linux "cooked" capture encapsulation
+    pub const LinuxSll: LinkType = LinkType(113);
+}
+
+/// Represents the link layer header type of the packets in a capture
+#[deriving(Show, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LinkType(pub u32);
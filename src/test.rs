@@ -9,6 +9,8 @@
 extern crate libc;
 
 use std::clone::Clone;
+use std::default::Default;
+use std::io::IoResult;
 use std::result::{Ok, Err};
 use std::option::{Some};
 use std::str::StrSlice;
@@ -16,14 +18,17 @@ use std::task::try_future;
 use std::io::net::ip::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::iter::Iterator;
 
-use datalink::{datalink_channel};
+use datalink::{datalink_channel, datalink_channel_with_options, DataLinkOptions};
+use datalink::{DataLinkSender, DataLinkReceiver};
 use packet::Packet;
 use packet::ethernet::{EtherTypes, EthernetHeader, MutableEthernetHeader, EthernetPacket};
+use packet::icmp::{IcmpTypes, IcmpHeader, MutableIcmpHeader, IcmpPacket};
 use packet::ip::{IpNextHeaderProtocols, IpNextHeaderProtocol};
 use packet::ipv4::{Ipv4Header, MutableIpv4Header, Ipv4Packet};
 use packet::ipv6::{MutableIpv6Header, Ipv6Packet};
 use packet::udp::{UdpHeader, MutableUdpHeader, UdpPacket};
 use transport::{udp_header_iter, ipv4_header_iter, transport_channel, TransportProtocol, Ipv4, Ipv6};
+use transport::icmp_header_iter;
 use transport;
 use datalink;
 use util::NetworkInterface;
@@ -33,6 +38,7 @@ const ETHERNET_HEADER_LEN: uint = 14;
 const IPV4_HEADER_LEN: uint = 20;
 const IPV6_HEADER_LEN: uint = 40;
 const UDP_HEADER_LEN: uint = 8;
+const ICMP_HEADER_LEN: uint = 8;
 const TEST_DATA_LEN: uint = 4;
 
 const IPV4_SOURCE: IpAddr = Ipv4Addr(127, 0, 0, 1);
@@ -110,6 +116,7 @@ fn get_test_interface() -> NetworkInterface {
     use util;
 
     (*util::get_network_interfaces()
+        .unwrap()
         .as_slice().iter()
         .filter(|x| {
             match getenv("PNET_TEST_IFACE") {
@@ -202,6 +209,340 @@ fn layer4_ipv6() {
     layer4(Ipv6Addr(0, 0, 0, 0, 0, 0, 0, 1), IPV6_HEADER_LEN);
 }
 
+// Only run if PNET_TEST_SOURCE_IP is set to a local IPv4 address other than 127.0.0.1, such as
+// 127.0.0.2 on Linux
+#[test]
+fn layer4_bound_source() {
+    use std::os::getenv;
+
+    let source: IpAddr = match getenv("PNET_TEST_SOURCE_IP") {
+        Some(ip) => match from_str(ip.as_slice()) {
+            Some(ip) => ip,
+            None => fail!("layer4_bound_source: invalid PNET_TEST_SOURCE_IP: {}", ip)
+        },
+        None => return
+    };
+
+    let mut packet = [0u8, ..IPV4_HEADER_LEN + UDP_HEADER_LEN + TEST_DATA_LEN];
+    build_udp4_packet(packet.as_mut_slice(), 0, "bind");
+    let udp = UdpHeader::new(packet.slice_from(IPV4_HEADER_LEN));
+
+    let protocol = transport::Layer4(Ipv4(TEST_PROTO));
+    let (mut ttx, _) = match transport_channel(128, protocol) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer4_bound_source: unable to create channel: {}", e),
+    };
+    // The receiving channel is left unbound, so it sees packets to any local address
+    let (_, mut trx) = match transport_channel(128, protocol) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer4_bound_source: unable to create channel: {}", e),
+    };
+
+    match ttx.set_source(source) {
+        Ok(()) => (),
+        Err(e) => fail!("layer4_bound_source: unable to set source {}: {}", source, e)
+    }
+    match ttx.send_to(udp, IPV4_DESTINATION) {
+        Ok(res) => assert_eq!(res as uint, UDP_HEADER_LEN + TEST_DATA_LEN),
+        Err(e) => fail!("layer4_bound_source failed: {}", e)
+    }
+
+    let mut i = 0u;
+    pfor!((header, addr) in udp_header_iter(&mut trx) {
+        if i == 10_000 {
+            fail!("layer4_bound_source: did not receive the packet after 10_000 packets");
+        }
+        if header == udp {
+            assert_eq!(addr, source);
+            break;
+        }
+        i += 1;
+    } on Err(e) {
+        fail!("Receive failed for layer4_bound_source(): {}", e);
+    })
+}
+
+#[test]
+fn layer4_icmp() {
+    let mut packet = [0u8, ..ICMP_HEADER_LEN + TEST_DATA_LEN];
+    packet.slice_from_mut(ICMP_HEADER_LEN).clone_from_slice("ping".as_bytes());
+    {
+        let mut icmp_header = MutableIcmpHeader::new(packet.as_mut_slice());
+        icmp_header.set_icmp_type(IcmpTypes::EchoRequest);
+        icmp_header.set_identifier(0x706e);
+        icmp_header.set_sequence_number(1);
+        icmp_header.checksum();
+    }
+
+    let protocol = transport::Layer4(Ipv4(IpNextHeaderProtocols::Icmp));
+    let (mut ttx, mut trx) = match transport_channel(128, protocol) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer4_icmp: unable to create channel: {}", e),
+    };
+
+    match ttx.send_to(IcmpHeader::new(packet.as_slice()), IPV4_DESTINATION) {
+        Ok(res) => assert_eq!(res as uint, ICMP_HEADER_LEN + TEST_DATA_LEN),
+        Err(e) => fail!("layer4_icmp failed: {}", e)
+    }
+
+    // The echo request itself may be seen before the reply
+    let mut i = 0u;
+    pfor!((header, addr) in icmp_header_iter(&mut trx) {
+        if i == 10_000 {
+            fail!("layer4_icmp: did not receive an echo reply after 10_000 packets");
+        }
+        if header.get_icmp_type() == IcmpTypes::EchoReply &&
+           header.get_identifier() == 0x706e {
+            assert_eq!(addr, IPV4_SOURCE);
+            assert_eq!(header.get_sequence_number(), 1);
+            assert!(header.is_checksum_valid());
+            assert_eq!(header.payload(), "ping".as_bytes());
+            break;
+        }
+        i += 1;
+    } on Err(e) {
+        fail!("Receive failed for layer4_icmp(): {}", e);
+    })
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn layer4_bind_to_device() {
+    let (mut tx, _) = match transport_channel(4096, transport::Layer4(Ipv4(TEST_PROTO))) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer4_bind_to_device: unable to create channel: {}", e)
+    };
+    match tx.bind_to_device("lo") {
+        Ok(()) => (),
+        Err(e) => fail!("layer4_bind_to_device: unable to bind to lo: {}", e)
+    }
+    assert!(tx.bind_to_device("pnetnonexist0").is_err());
+    // An empty name removes the binding
+    assert!(tx.bind_to_device("").is_ok());
+}
+
+// Only run if PNET_TEST_REMOTE_IP is set to an IPv4 address at least two hops away
+#[test]
+fn layer4_ttl() {
+    use std::os::getenv;
+
+    let remote: IpAddr = match getenv("PNET_TEST_REMOTE_IP") {
+        Some(ip) => match from_str(ip.as_slice()) {
+            Some(ip) => ip,
+            None => fail!("layer4_ttl: invalid PNET_TEST_REMOTE_IP: {}", ip)
+        },
+        None => return
+    };
+
+    let mut packet = [0u8, ..ICMP_HEADER_LEN + TEST_DATA_LEN];
+    packet.slice_from_mut(ICMP_HEADER_LEN).clone_from_slice("hops".as_bytes());
+    {
+        let mut icmp_header = MutableIcmpHeader::new(packet.as_mut_slice());
+        icmp_header.set_icmp_type(IcmpTypes::EchoRequest);
+        icmp_header.set_identifier(0x7474);
+        icmp_header.set_sequence_number(1);
+        icmp_header.checksum();
+    }
+
+    let protocol = transport::Layer4(Ipv4(IpNextHeaderProtocols::Icmp));
+    let (mut ttx, mut trx) = match transport_channel(128, protocol) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer4_ttl: unable to create channel: {}", e),
+    };
+    match ttx.set_ttl(1) {
+        Ok(()) => (),
+        Err(e) => fail!("layer4_ttl: unable to set TTL: {}", e)
+    }
+    match ttx.send_to(IcmpHeader::new(packet.as_slice()), remote) {
+        Ok(res) => assert_eq!(res as uint, ICMP_HEADER_LEN + TEST_DATA_LEN),
+        Err(e) => fail!("layer4_ttl failed: {}", e)
+    }
+
+    // The first hop should report that the TTL was exceeded. Its payload is the IPv4 header of
+    // the echo request, followed by the start of the request itself.
+    let mut i = 0u;
+    pfor!((header, _) in icmp_header_iter(&mut trx) {
+        if i == 10_000 {
+            fail!("layer4_ttl: did not receive time exceeded after 10_000 packets");
+        }
+        let payload = header.payload();
+        if header.get_icmp_type() == IcmpTypes::TimeExceeded &&
+           payload.len() >= IPV4_HEADER_LEN + ICMP_HEADER_LEN {
+            let original = Ipv4Header::new(payload);
+            let ihl = original.get_header_length() as uint * 4;
+            if ihl + ICMP_HEADER_LEN <= payload.len() && original.get_destination() == remote &&
+               IcmpHeader::new(payload.slice_from(ihl)).get_identifier() == 0x7474 {
+                break;
+            }
+        }
+        i += 1;
+    } on Err(e) {
+        fail!("Receive failed for layer4_ttl(): {}", e);
+    })
+}
+
+// Ping the given loopback address, checking the reply arrives well within the timeout
+fn check_ping(test: &str, addr: IpAddr) {
+    use std::time::Duration;
+    use transport::ping::ping;
+
+    match ping(addr, Duration::seconds(5)) {
+        Ok(rtt) => assert!(rtt < Duration::seconds(1), "{}: round trip took {}", test, rtt),
+        Err(e) => fail!("{}: ping failed: {}", test, e)
+    }
+}
+
+#[test]
+fn layer4_ipv6_checksum_offset() {
+    let mut packet = [0u8, ..IPV6_HEADER_LEN + UDP_HEADER_LEN + TEST_DATA_LEN];
+    build_udp6_packet(packet.as_mut_slice(), 0, "l4ck");
+    let checksum = UdpHeader::new(packet.slice_from(IPV6_HEADER_LEN)).get_checksum();
+    MutableUdpHeader::new(packet.slice_from_mut(IPV6_HEADER_LEN)).set_checksum(0);
+
+    let (mut ttx, mut trx) = match transport_channel(128, transport::Layer4(Ipv6(TEST_PROTO))) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer4_ipv6_checksum_offset: unable to create channel: {}", e),
+    };
+    // The checksum field of the test packets is where it would be in UDP
+    match ttx.set_checksum_offset(Some(6)) {
+        Ok(()) => (),
+        Err(e) => fail!("layer4_ipv6_checksum_offset: unable to set offset: {}", e)
+    }
+    match ttx.send_to(UdpHeader::new(packet.slice_from(IPV6_HEADER_LEN)), IPV6_DESTINATION) {
+        Ok(res) => assert_eq!(res as uint, UDP_HEADER_LEN + TEST_DATA_LEN),
+        Err(e) => fail!("layer4_ipv6_checksum_offset failed: {}", e)
+    }
+
+    // Other tests use the same protocol over IPv6
+    let mut iter = udp_header_iter(&mut trx);
+    let mut i = 0u;
+    loop {
+        if i == 10_000 {
+            fail!("layer4_ipv6_checksum_offset: did not find packet after 10_000 iterations");
+        }
+        match iter.next() {
+            Ok((header, addr)) => {
+                if header.payload() == "l4ck".as_bytes() {
+                    assert_eq!(addr, IPV6_SOURCE);
+                    assert_eq!(header.get_checksum(), checksum);
+                    break;
+                }
+            },
+            Err(e) => fail!("layer4_ipv6_checksum_offset: receive failed: {}", e)
+        }
+        i += 1;
+    }
+}
+
+#[test]
+fn layer4_checksum_offset_ipv4() {
+    let (mut ttx, _) = match transport_channel(128, transport::Layer4(Ipv4(TEST_PROTO))) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer4_checksum_offset_ipv4: unable to create channel: {}", e),
+    };
+    assert!(ttx.set_checksum_offset(Some(6)).is_err());
+}
+
+#[test]
+fn ping_ipv4() {
+    check_ping("ping_ipv4", IPV4_DESTINATION);
+}
+
+#[test]
+fn ping_ipv6() {
+    check_ping("ping_ipv6", IPV6_DESTINATION);
+}
+
+// Only run if PNET_TEST_GATEWAY_IP is set to the IPv4 address of a host on a directly connected
+// network, such as the default gateway
+#[test]
+fn arp_resolve_gateway() {
+    use std::os::getenv;
+    use std::time::Duration;
+    use datalink::arp::arp_resolve;
+    use util::usable_interfaces;
+
+    let gateway: IpAddr = match getenv("PNET_TEST_GATEWAY_IP") {
+        Some(ip) => match from_str(ip.as_slice()) {
+            Some(ip) => ip,
+            None => fail!("arp_resolve_gateway: invalid PNET_TEST_GATEWAY_IP: {}", ip)
+        },
+        None => return
+    };
+
+    let interfaces = usable_interfaces().unwrap();
+    let interface = match interfaces.iter().find(|iface| iface.is_on_link(gateway)) {
+        Some(iface) => iface,
+        None => fail!("arp_resolve_gateway: no interface is on the same network as {}", gateway)
+    };
+
+    match arp_resolve(interface, gateway, Duration::seconds(2)) {
+        Ok(mac) => assert!(mac.is_unicast()),
+        Err(e) => fail!("arp_resolve_gateway: unable to resolve {}: {}", gateway, e)
+    }
+}
+
+#[test]
+fn layer3_ipv4_icmp() {
+    let send_addr = Ipv4Addr(127, 0, 0, 1);
+    let total_len = IPV4_HEADER_LEN + ICMP_HEADER_LEN + TEST_DATA_LEN;
+    let mut packet = [0u8, ..IPV4_HEADER_LEN + ICMP_HEADER_LEN + TEST_DATA_LEN];
+
+    // Build the whole packet by hand, with an unusual TTL so it can be recognised
+    packet.slice_from_mut(IPV4_HEADER_LEN + ICMP_HEADER_LEN).clone_from_slice("l3ic".as_bytes());
+    {
+        let mut icmp_header = MutableIcmpHeader::new(packet.slice_from_mut(IPV4_HEADER_LEN));
+        icmp_header.set_icmp_type(IcmpTypes::EchoRequest);
+        icmp_header.set_identifier(0x6c33);
+        icmp_header.set_sequence_number(1);
+        icmp_header.checksum();
+    }
+    {
+        let mut ip_header = MutableIpv4Header::new(packet.as_mut_slice());
+        ip_header.set_version(4);
+        ip_header.set_header_length(5);
+        ip_header.set_total_length(total_len as u16);
+        ip_header.set_ttl(17);
+        ip_header.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
+        ip_header.set_source(IPV4_SOURCE);
+        ip_header.set_destination(IPV4_DESTINATION);
+        ip_header.checksum();
+    }
+
+    let tc = transport_channel(128, transport::Layer3(IpNextHeaderProtocols::Icmp));
+    let (mut ttx, mut trx) = match tc {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer3_ipv4_icmp: unable to create channel: {}", e),
+    };
+
+    match ttx.send_to(Ipv4Header::new(packet), send_addr) {
+        Ok(res) => assert_eq!(res as uint, packet.len()),
+        Err(e) => fail!("layer3_ipv4_icmp failed: {}", e)
+    }
+
+    // The echo reply generated by the kernel may also be received
+    let mut i = 0u;
+    pfor!((header, addr) in ipv4_header_iter(&mut trx) {
+        if i == 10_000 {
+            fail!("layer3_ipv4_icmp: did not receive the packet after 10_000 packets");
+        }
+        let icmp_header = IcmpHeader::new(header.packet().slice_from(
+                                           header.get_header_length() as uint * 4u));
+        if icmp_header.get_icmp_type() == IcmpTypes::EchoRequest &&
+           icmp_header.get_identifier() == 0x6c33 {
+            assert_eq!(addr, send_addr);
+            assert_eq!(header.get_ttl(), 17);
+            assert_eq!(header.get_source(), IPV4_SOURCE);
+            assert_eq!(header.get_next_level_protocol(), IpNextHeaderProtocols::Icmp);
+            assert_eq!(icmp_header, IcmpHeader::new(packet.slice_from(IPV4_HEADER_LEN)));
+            break;
+        }
+        i += 1;
+    } on Err(e) {
+        fail!("receive failed for layer3_ipv4_icmp(): {}", e);
+    })
+}
+
 #[test]
 fn layer3_ipv4() {
     let send_addr = Ipv4Addr(127, 0, 0, 1);
@@ -252,7 +593,579 @@ fn layer3_ipv4() {
 #[test]
 fn layer2() {
     let interface = get_test_interface();
+    let dlc = datalink_channel(&interface, MIN_PACKET_SIZE*2, MIN_PACKET_SIZE*2, datalink::Layer2);
+    check_layer2(&interface, dlc);
+}
+
+#[test]
+fn layer2_with_options() {
+    let interface = get_test_interface();
+    let options = DataLinkOptions {
+        write_buffer_size: MIN_PACKET_SIZE*2,
+        read_buffer_size: MIN_PACKET_SIZE*2,
+        ..Default::default()
+    };
+    let dlc = datalink_channel_with_options(&interface, &options);
+    check_layer2(&interface, dlc);
+}
+
+#[test]
+fn layer2_large_buffers() {
+    let interface = get_test_interface();
+    let options = DataLinkOptions {
+        write_buffer_size: 1 << 20,
+        read_buffer_size: 1 << 20,
+        ..Default::default()
+    };
+    let dlc = datalink_channel_with_options(&interface, &options);
+    check_layer2(&interface, dlc);
+}
+
+// NOTE The flags in sysfs include promiscuous mode enabled by packet sockets, unlike those
+//      returned by getifaddrs(), which only include it if set by the administrator
+#[cfg(target_os = "linux")]
+fn is_promiscuous(interface: &NetworkInterface) -> bool {
+    use std::io::File;
+    use std::num::from_str_radix;
+
+    let path = format!("/sys/class/net/{}/flags", interface.name);
+    let flags = match File::open(&Path::new(path.as_slice())).and_then(|mut f| f.read_to_string()) {
+        Ok(flags) => flags,
+        Err(e) => fail!("layer2_promiscuous: unable to read {}: {}", path, e)
+    };
+    // The flags are formatted as 0x1003
+    match from_str_radix::<u32>(flags.as_slice().trim().slice_from(2), 16) {
+        Some(flags) => flags & 0x100 != 0, /* IFF_PROMISC */
+        None => fail!("layer2_promiscuous: invalid interface flags: {}", flags)
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn layer2_promiscuous() {
+    let interface = get_test_interface();
+    let options = DataLinkOptions {
+        promiscuous: true,
+        ..Default::default()
+    };
+    let was_promiscuous = is_promiscuous(&interface);
+    {
+        let _channel = match datalink_channel_with_options(&interface, &options) {
+            Ok(channel) => channel,
+            Err(e) => fail!("layer2_promiscuous: unable to create channel: {}", e)
+        };
+        assert!(is_promiscuous(&interface));
+    }
+    // Closing the channel leaves the interface as it was
+    assert_eq!(is_promiscuous(&interface), was_promiscuous);
+}
+
+#[test]
+fn layer2_read_timeout() {
+    use std::io::TimedOut;
+    use std::time::Duration;
+
+    let interface = get_test_interface();
+    let options = DataLinkOptions {
+        read_timeout: Some(Duration::milliseconds(100)),
+        ..Default::default()
+    };
+    let (_, mut dlrx) = match datalink_channel_with_options(&interface, &options) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_read_timeout: unable to create channel: {}", e)
+    };
+
+    // The test interface should be mostly idle, so a read should time out before long
+    let mut iter = dlrx.iter();
+    for _ in range(0u, 1_000) {
+        match iter.next() {
+            Ok(_) => (),
+            Err(ref e) if e.kind == TimedOut => return,
+            Err(e) => fail!("layer2_read_timeout failed: {}", e)
+        }
+    }
+    fail!("layer2_read_timeout: reads did not time out after 1_000 packets");
+}
+
+#[test]
+fn layer2_iterator() {
+    let interface = get_test_interface();
+    let options: DataLinkOptions = Default::default();
+    let (mut dltx, mut dlrx) = match datalink_channel_with_options(&interface, &options) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_iterator: unable to create channel: {}", e)
+    };
+
+    let mut packets = [[0u8, ..ETHERNET_HEADER_LEN + IPV4_HEADER_LEN + UDP_HEADER_LEN +
+                               TEST_DATA_LEN], ..2];
+    for (packet, msg) in packets.iter_mut().zip(["it01", "it02"].iter()) {
+        {
+            let mut ethernet_header = MutableEthernetHeader::new(packet.as_mut_slice());
+            ethernet_header.set_source(interface.mac_address().unwrap());
+            ethernet_header.set_destination(interface.mac_address().unwrap());
+            ethernet_header.set_ethertype(EtherTypes::Ipv4);
+        }
+        build_udp4_packet(packet.as_mut_slice(), ETHERNET_HEADER_LEN as uint, *msg);
+    }
+
+    for packet in packets.iter() {
+        match dltx.send_to(EthernetHeader::new(packet.as_slice()), None) {
+            Some(Ok(())) => (),
+            Some(Err(e)) => fail!("layer2_iterator failed: {}", e),
+            None => fail!("Provided buffer too small")
+        }
+    }
+
+    // Both frames should be seen, in order, without copying them out of the channel
+    let mut found = 0u;
+    let mut i = 0u;
+    pfor!(eh in dlrx.iter() {
+        if i == 10_000 {
+            fail!("layer2_iterator: did not find matching packets after 10_000 iterations");
+        }
+        if eh == EthernetHeader::new(packets[found].as_slice()) {
+            found += 1;
+            if found == packets.len() {
+                break;
+            }
+        }
+        i += 1;
+    } on Err(e) {
+        fail!("layer2_iterator failed: {}", e);
+    })
+}
+
+#[test]
+fn layer2_send_batch() {
+    let interface = get_test_interface();
+    let options: DataLinkOptions = Default::default();
+    let (mut dltx, mut dlrx) = match datalink_channel_with_options(&interface, &options) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_send_batch: unable to create channel: {}", e)
+    };
+
+    let mut packets = [[0u8, ..ETHERNET_HEADER_LEN + IPV4_HEADER_LEN + UDP_HEADER_LEN +
+                               TEST_DATA_LEN], ..3];
+    for (packet, msg) in packets.iter_mut().zip(["bt01", "bt02", "bt03"].iter()) {
+        {
+            let mut ethernet_header = MutableEthernetHeader::new(packet.as_mut_slice());
+            ethernet_header.set_source(interface.mac_address().unwrap());
+            ethernet_header.set_destination(interface.mac_address().unwrap());
+            ethernet_header.set_ethertype(EtherTypes::Ipv4);
+        }
+        build_udp4_packet(packet.as_mut_slice(), ETHERNET_HEADER_LEN as uint, *msg);
+    }
+
+    let batch: Vec<EthernetHeader> = packets.iter().map(|packet| {
+        EthernetHeader::new(packet.as_slice())
+    }).collect();
+    let mut sent = 0u;
+    while sent < batch.len() {
+        match dltx.send_batch(batch.slice_from(sent)) {
+            Ok(n) => sent += n,
+            Err(e) => fail!("layer2_send_batch failed: {}", e)
+        }
+    }
+
+    let mut found = 0u;
+    let mut i = 0u;
+    pfor!(eh in dlrx.iter() {
+        if i == 10_000 {
+            fail!("layer2_send_batch: did not find all packets after 10_000 iterations");
+        }
+        if eh == batch[found] {
+            found += 1;
+            if found == batch.len() {
+                break;
+            }
+        }
+        i += 1;
+    } on Err(e) {
+        fail!("layer2_send_batch failed: {}", e);
+    })
+}
+
+#[test]
+fn layer2_stats() {
+    let interface = get_test_interface();
+    let options: DataLinkOptions = Default::default();
+    let (mut dltx, dlrx) = match datalink_channel_with_options(&interface, &options) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_stats: unable to create channel: {}", e)
+    };
+
+    let first = match dlrx.stats() {
+        Ok(stats) => stats,
+        Err(e) => fail!("layer2_stats: unable to get statistics: {}", e)
+    };
+
+    let mut packet = [0u8, ..ETHERNET_HEADER_LEN + IPV4_HEADER_LEN + UDP_HEADER_LEN +
+                             TEST_DATA_LEN];
+    {
+        let mut ethernet_header = MutableEthernetHeader::new(packet.as_mut_slice());
+        ethernet_header.set_source(interface.mac_address().unwrap());
+        ethernet_header.set_destination(interface.mac_address().unwrap());
+        ethernet_header.set_ethertype(EtherTypes::Ipv4);
+    }
+    build_udp4_packet(packet.as_mut_slice(), ETHERNET_HEADER_LEN as uint, "stat");
+    match dltx.send_to(EthernetHeader::new(packet.as_slice()), None) {
+        Some(Ok(())) => (),
+        Some(Err(e)) => fail!("layer2_stats failed: {}", e),
+        None => fail!("Provided buffer too small")
+    }
+
+    let second = match dlrx.stats() {
+        Ok(stats) => stats,
+        Err(e) => fail!("layer2_stats: unable to get statistics: {}", e)
+    };
+    assert!(second.received >= first.received);
+    assert!(second.dropped >= first.dropped);
+}
+
+#[test]
+fn layer2_timestamps() {
+    use std::time::Duration;
+
+    let interface = get_test_interface();
+    let options = DataLinkOptions { timestamping: true, ..Default::default() };
+    let (mut dltx, mut dlrx) = match datalink_channel_with_options(&interface, &options) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_timestamps: unable to create channel: {}", e)
+    };
+
+    let mut packets = [[0u8, ..ETHERNET_HEADER_LEN + IPV4_HEADER_LEN + UDP_HEADER_LEN +
+                               TEST_DATA_LEN], ..2];
+    for (packet, msg) in packets.iter_mut().zip(["ts01", "ts02"].iter()) {
+        {
+            let mut ethernet_header = MutableEthernetHeader::new(packet.as_mut_slice());
+            ethernet_header.set_source(interface.mac_address().unwrap());
+            ethernet_header.set_destination(interface.mac_address().unwrap());
+            ethernet_header.set_ethertype(EtherTypes::Ipv4);
+        }
+        build_udp4_packet(packet.as_mut_slice(), ETHERNET_HEADER_LEN as uint, *msg);
+    }
+
+    // The socket is already open, so both frames are queued for us to read back
+    for packet in packets.iter() {
+        match dltx.send_to(EthernetHeader::new(packet.as_slice()), None) {
+            Some(Ok(())) => (),
+            Some(Err(e)) => fail!("layer2_timestamps failed: {}", e),
+            None => fail!("Provided buffer too small")
+        }
+    }
+
+    let mut timestamps = Vec::new();
+    let mut iter = dlrx.iter();
+    for _ in range(0u, 10_000) {
+        let expected = packets[timestamps.len()];
+        match iter.next_with_timestamp() {
+            Ok((ts, ref eh)) if *eh == EthernetHeader::new(expected.as_slice()) => {
+                timestamps.push(ts);
+                if timestamps.len() == packets.len() {
+                    break;
+                }
+            },
+            Ok(_) => (),
+            Err(e) => fail!("layer2_timestamps failed: {}", e)
+        }
+    }
+    assert_eq!(timestamps.len(), 2);
+    assert!(timestamps[0] > Duration::zero());
+    assert!(timestamps[1] >= timestamps[0]);
+}
+
+#[test]
+fn layer2_timestamps_disabled() {
+    use std::io::InvalidInput;
+
+    let interface = get_test_interface();
+    let (_, mut dlrx) = match datalink_channel_with_options(&interface, &Default::default()) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_timestamps_disabled: unable to create channel: {}", e)
+    };
+    match dlrx.iter().next_with_timestamp() {
+        Err(ref e) if e.kind == InvalidInput => (),
+        _ => fail!("layer2_timestamps_disabled: expected an InvalidInput error")
+    }
+}
+
+// NOTE BSD loopback interfaces don't have Ethernet headers, so the filter would need to be
+//      different there
+#[test]
+#[cfg(target_os = "linux")]
+fn layer2_filter() {
+    use std::io::TimedOut;
+    use std::time::Duration;
+    use datalink::BpfInstruction;
+    use packet::MutablePacket;
+    use packet::arp::{MutableArpHeader, ArpHardwareTypes, ArpOperations};
+
+    let interface = get_test_interface();
+    let options = DataLinkOptions {
+        read_timeout: Some(Duration::milliseconds(100)),
+        ..Default::default()
+    };
+    let (mut dltx, mut dlrx) = match datalink_channel_with_options(&interface, &options) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_filter: unable to create channel: {}", e)
+    };
+
+    // Only accept ARP packets - tcpdump -dd arp
+    let filter = [BpfInstruction::new(0x28, 0, 0, 0x0000000c),
+                  BpfInstruction::new(0x15, 0, 1, 0x00000806),
+                  BpfInstruction::new(0x06, 0, 0, 0x0000ffff),
+                  BpfInstruction::new(0x06, 0, 0, 0x00000000)];
+    match dlrx.set_filter(filter.as_slice()) {
+        Ok(()) => (),
+        Err(e) => fail!("layer2_filter: unable to set filter: {}", e)
+    }
+
+    // Drain any packets which were received before the filter was attached
+    {
+        let mut iter = dlrx.iter();
+        loop {
+            match iter.next() {
+                Ok(_) => (),
+                Err(ref e) if e.kind == TimedOut => break,
+                Err(e) => fail!("layer2_filter failed: {}", e)
+            }
+        }
+    }
+
+    let mut udp_packet = [0u8, ..ETHERNET_HEADER_LEN +
+                                 IPV4_HEADER_LEN +
+                                 UDP_HEADER_LEN +
+                                 TEST_DATA_LEN];
+    {
+        let mut ethernet_header = MutableEthernetHeader::new(udp_packet.as_mut_slice());
+        ethernet_header.set_source(interface.mac_address().unwrap());
+        ethernet_header.set_destination(interface.mac_address().unwrap());
+        ethernet_header.set_ethertype(EtherTypes::Ipv4);
+    }
+    build_udp4_packet(udp_packet.as_mut_slice(), ETHERNET_HEADER_LEN, "bpff");
+
+    let mut arp_packet = [0u8, ..ETHERNET_HEADER_LEN + 28];
+    {
+        let mut ethernet_header = MutableEthernetHeader::new(arp_packet.as_mut_slice());
+        ethernet_header.set_source(interface.mac_address().unwrap());
+        ethernet_header.set_destination(interface.mac_address().unwrap());
+        ethernet_header.set_ethertype(EtherTypes::Arp);
+        let mut arp_header = MutableArpHeader::new(ethernet_header.payload_mut());
+        arp_header.set_hardware_type(ArpHardwareTypes::Ethernet);
+        arp_header.set_protocol_type(EtherTypes::Ipv4);
+        arp_header.set_hw_addr_len(6);
+        arp_header.set_proto_addr_len(4);
+        arp_header.set_operation(ArpOperations::Request);
+        arp_header.set_sender_hw_addr(interface.mac_address().unwrap());
+        arp_header.set_sender_proto_addr(IPV4_SOURCE);
+        arp_header.set_target_proto_addr(IPV4_DESTINATION);
+    }
+
+    for packet in [udp_packet.as_slice(), arp_packet.as_slice()].iter() {
+        match dltx.send_to(EthernetHeader::new(*packet), None) {
+            Some(Ok(())) => (),
+            Some(Err(e)) => fail!("layer2_filter failed: {}", e),
+            None => fail!("Provided buffer too small")
+        }
+    }
+
+    // The UDP packet was sent first, so if the filter didn't work it would be received first
+    let mut iter = dlrx.iter();
+    match iter.next() {
+        Ok(eh) => {
+            assert_eq!(eh.get_ethertype(), EtherTypes::Arp);
+            assert!(eh == EthernetHeader::new(arp_packet.as_slice()));
+        },
+        Err(e) => fail!("layer2_filter: didn't receive the ARP packet: {}", e)
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn layer2_ethertype_filter() {
+    use std::time::Duration;
+    use packet::MutablePacket;
+    use packet::arp::{MutableArpHeader, ArpHardwareTypes, ArpOperations};
+
+    let interface = get_test_interface();
+    let options = DataLinkOptions {
+        read_timeout: Some(Duration::milliseconds(100)),
+        ethertype_filter: Some(EtherTypes::Arp),
+        ..Default::default()
+    };
+    let (_, mut dlrx) = match datalink_channel_with_options(&interface, &options) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_ethertype_filter: unable to create channel: {}", e)
+    };
+    let (mut dltx, _) = match datalink_channel_with_options(&interface, &Default::default()) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_ethertype_filter: unable to create channel: {}", e)
+    };
+
+    let mut udp_packet = [0u8, ..ETHERNET_HEADER_LEN +
+                                 IPV4_HEADER_LEN +
+                                 UDP_HEADER_LEN +
+                                 TEST_DATA_LEN];
+    {
+        let mut ethernet_header = MutableEthernetHeader::new(udp_packet.as_mut_slice());
+        ethernet_header.set_source(interface.mac_address().unwrap());
+        ethernet_header.set_destination(interface.mac_address().unwrap());
+        ethernet_header.set_ethertype(EtherTypes::Ipv4);
+    }
+    build_udp4_packet(udp_packet.as_mut_slice(), ETHERNET_HEADER_LEN, "etft");
+
+    let mut arp_packet = [0u8, ..ETHERNET_HEADER_LEN + 28];
+    {
+        let mut ethernet_header = MutableEthernetHeader::new(arp_packet.as_mut_slice());
+        ethernet_header.set_source(interface.mac_address().unwrap());
+        ethernet_header.set_destination(interface.mac_address().unwrap());
+        ethernet_header.set_ethertype(EtherTypes::Arp);
+        let mut arp_header = MutableArpHeader::new(ethernet_header.payload_mut());
+        arp_header.set_hardware_type(ArpHardwareTypes::Ethernet);
+        arp_header.set_protocol_type(EtherTypes::Ipv4);
+        arp_header.set_hw_addr_len(6);
+        arp_header.set_proto_addr_len(4);
+        arp_header.set_operation(ArpOperations::Request);
+        arp_header.set_sender_hw_addr(interface.mac_address().unwrap());
+        arp_header.set_sender_proto_addr(IPV4_SOURCE);
+        arp_header.set_target_proto_addr(Ipv4Addr(127, 0, 0, 2));
+    }
+
+    for packet in [udp_packet.as_slice(), arp_packet.as_slice()].iter() {
+        match dltx.send_to(EthernetHeader::new(*packet), None) {
+            Some(Ok(())) => (),
+            Some(Err(e)) => fail!("layer2_ethertype_filter failed: {}", e),
+            None => fail!("Provided buffer too small")
+        }
+    }
+
+    // The UDP packet was sent first, so it would be seen before the ARP packet if it had been
+    // delivered
+    let mut iter = dlrx.iter();
+    let mut i = 0u;
+    loop {
+        if i == 10_000 {
+            fail!("layer2_ethertype_filter: did not find ARP packet after 10_000 iterations");
+        }
+        match iter.next() {
+            Ok(eh) => {
+                if eh.get_ethertype() != EtherTypes::Arp {
+                    fail!("layer2_ethertype_filter: received a {} packet",
+                          eh.get_ethertype());
+                }
+                if eh == EthernetHeader::new(arp_packet.as_slice()) {
+                    break;
+                }
+            },
+            Err(e) => fail!("layer2_ethertype_filter: didn't receive the ARP packet: {}", e)
+        }
+        i += 1;
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn layer2_fill_source_mac() {
+    use std::time::Duration;
+    use util::MacAddr;
+
+    let interface = get_test_interface();
+    let (_, mut dlrx) = match datalink_channel_with_options(&interface, &DataLinkOptions {
+        read_timeout: Some(Duration::milliseconds(100)),
+        ..Default::default()
+    }) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_fill_source_mac: unable to create channel: {}", e)
+    };
+    let options = DataLinkOptions { fill_source_mac: true, ..Default::default() };
+    let (mut dltx, _) = match datalink_channel_with_options(&interface, &options) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_fill_source_mac: unable to create channel: {}", e)
+    };
+
+    // The loopback interface's address is all zeroes, so the packet is sent with a made up
+    // source address which should be replaced
+    let mut packet = [0u8, ..ETHERNET_HEADER_LEN + IPV4_HEADER_LEN + UDP_HEADER_LEN +
+                             TEST_DATA_LEN];
+    {
+        let mut ethernet_header = MutableEthernetHeader::new(packet.as_mut_slice());
+        ethernet_header.set_source(MacAddr(0x02, 0x00, 0x00, 0x00, 0x00, 0x01));
+        ethernet_header.set_destination(interface.mac_address().unwrap());
+        ethernet_header.set_ethertype(EtherTypes::Ipv4);
+    }
+    build_udp4_packet(packet.as_mut_slice(), ETHERNET_HEADER_LEN, "fsrc");
+    match dltx.send_to(EthernetHeader::new(packet.as_slice()), None) {
+        Some(Ok(())) => (),
+        Some(Err(e)) => fail!("layer2_fill_source_mac failed: {}", e),
+        None => fail!("Provided buffer too small")
+    }
+
+    MutableEthernetHeader::new(packet.as_mut_slice()).set_source(interface.mac_address().unwrap());
+    let mut iter = dlrx.iter();
+    let mut i = 0u;
+    loop {
+        if i == 10_000 {
+            fail!("layer2_fill_source_mac: did not find packet after 10_000 iterations");
+        }
+        match iter.next() {
+            Ok(eh) => {
+                if eh == EthernetHeader::new(packet.as_slice()) {
+                    break;
+                }
+            },
+            Err(e) => fail!("layer2_fill_source_mac: didn't receive the packet: {}", e)
+        }
+        i += 1;
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn layer2_oversized_frame() {
+    use std::io::InvalidInput;
+
+    let interface = get_test_interface();
+    let mtu = match interface.mtu {
+        Some(mtu) => mtu as uint,
+        None => fail!("layer2_oversized_frame: the test interface has no MTU")
+    };
+    let (mut dltx, _) = match datalink_channel_with_options(&interface, &Default::default()) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_oversized_frame: unable to create channel: {}", e)
+    };
+
+    let mut packet = Vec::from_elem(ETHERNET_HEADER_LEN + mtu + 1, 0u8);
+    {
+        let mut ethernet_header = MutableEthernetHeader::new(packet.as_mut_slice());
+        ethernet_header.set_source(interface.mac_address().unwrap());
+        ethernet_header.set_destination(interface.mac_address().unwrap());
+        ethernet_header.set_ethertype(EtherTypes::Ipv4);
+    }
+    build_udp4_packet(packet.as_mut_slice(), ETHERNET_HEADER_LEN, "mtu!");
+
+    // One byte too many is rejected before reaching the kernel
+    match dltx.send_to(EthernetHeader::new(packet.as_slice()), None) {
+        Some(Err(e)) => assert_eq!(e.kind, InvalidInput),
+        Some(Ok(())) => fail!("layer2_oversized_frame: an oversized frame was sent"),
+        None => fail!("Provided buffer too small")
+    }
+    match dltx.send_batch([EthernetHeader::new(packet.as_slice())].as_slice()) {
+        Err(e) => assert_eq!(e.kind, InvalidInput),
+        Ok(n) => fail!("layer2_oversized_frame: {} oversized frames were sent", n)
+    }
 
+    // A frame which fits is sent as normal
+    let fits = packet.slice_to(ETHERNET_HEADER_LEN + mtu);
+    match dltx.send_to(EthernetHeader::new(fits), None) {
+        Some(Ok(())) => (),
+        Some(Err(e)) => fail!("layer2_oversized_frame failed: {}", e),
+        None => fail!("Provided buffer too small")
+    }
+}
+
+// Send a packet over the given channel, and check it can be read back
+fn check_layer2(interface: &NetworkInterface,
+                dlc: IoResult<(DataLinkSender, DataLinkReceiver)>) {
     let mut packet = [0u8, ..ETHERNET_HEADER_LEN +
                              IPV4_HEADER_LEN +
                              UDP_HEADER_LEN +
@@ -260,8 +1173,8 @@ fn layer2() {
 
     {
         let mut ethernet_header = MutableEthernetHeader::new(packet.as_mut_slice());
-        ethernet_header.set_source(interface.mac_address());
-        ethernet_header.set_destination(interface.mac_address());
+        ethernet_header.set_source(interface.mac_address().unwrap());
+        ethernet_header.set_destination(interface.mac_address().unwrap());
         ethernet_header.set_ethertype(EtherTypes::Ipv4);
     }
 
@@ -269,7 +1182,6 @@ fn layer2() {
 
     let (tx, rx) = channel();
 
-    let dlc = datalink_channel(&interface, MIN_PACKET_SIZE*2, MIN_PACKET_SIZE*2, datalink::Layer2);
     let (mut dltx, mut dlrx) = match dlc {
         Ok((tx, rx)) => (tx, rx),
         Err(e) => fail!("layer2: unable to create channel: {}", e)
@@ -304,6 +1216,116 @@ fn layer2() {
     }
 }
 
+#[test]
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+fn layer2_bpf_loopback() {
+    use std::io::net::ip::SocketAddr;
+    use std::io::net::udp::UdpSocket;
+    use util::get_network_interface_by_name;
+
+    let interface = get_network_interface_by_name("lo0").unwrap();
+    let options: DataLinkOptions = Default::default();
+    let (_, mut dlrx) = match datalink_channel_with_options(&interface, &options) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_bpf_loopback: unable to create channel: {}", e)
+    };
+
+    // Generate some traffic on the loopback interface
+    let mut socket = UdpSocket::bind(SocketAddr { ip: IPV4_DESTINATION, port: 0 }).unwrap();
+    let addr = socket.socket_name().unwrap();
+    socket.send_to("bpft".as_bytes(), addr).unwrap();
+
+    let mut iter = dlrx.iter();
+    let mut i = 0u;
+    loop {
+        if i == 10_000 {
+            fail!("layer2_bpf_loopback: did not find matching packet after 10_000 iterations");
+        }
+        match iter.next() {
+            Ok(packet) => if packet.packet().ends_with("bpft".as_bytes()) {
+                break;
+            },
+            Err(e) => fail!("layer2_bpf_loopback failed: {}", e)
+        }
+        i += 1;
+    }
+}
+
+#[test]
+#[cfg(target_os = "macos")]
+fn layer2_bpf_multiple_packets() {
+    use std::io::net::ip::SocketAddr;
+    use std::io::net::udp::UdpSocket;
+    use util::get_network_interface_by_name;
+
+    let interface = get_network_interface_by_name("lo0").unwrap();
+    let options: DataLinkOptions = Default::default();
+    let (_, mut dlrx) = match datalink_channel_with_options(&interface, &options) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_bpf_multiple_packets: unable to create channel: {}", e)
+    };
+
+    // Send several small packets in quick succession, so they're likely to be returned by a
+    // single read from the BPF device
+    let payloads = ["bpf1", "bpf2", "bpf3", "bpf4"];
+    let mut socket = UdpSocket::bind(SocketAddr { ip: IPV4_DESTINATION, port: 0 }).unwrap();
+    let addr = socket.socket_name().unwrap();
+    for payload in payloads.iter() {
+        socket.send_to(payload.as_bytes(), addr).unwrap();
+    }
+
+    // Each should be returned by its own call to next(), in order
+    let mut iter = dlrx.iter();
+    let mut next = 0u;
+    let mut i = 0u;
+    while next < payloads.len() {
+        if i == 10_000 {
+            fail!("layer2_bpf_multiple_packets: only found {} packets after 10_000 iterations",
+                  next);
+        }
+        match iter.next() {
+            Ok(packet) => {
+                let found = payloads.iter().position(|p| packet.packet().ends_with(p.as_bytes()));
+                match found {
+                    Some(n) if n == next => next += 1,
+                    Some(n) => fail!("layer2_bpf_multiple_packets: expected {}, got {}",
+                                     payloads[next], payloads[n]),
+                    None => ()
+                }
+            },
+            Err(e) => fail!("layer2_bpf_multiple_packets failed: {}", e)
+        }
+        i += 1;
+    }
+}
+
+// FIXME [windows] WinPcap doesn't list a loopback adapter, so this uses PNET_TEST_IFACE
+#[test]
+#[cfg(windows)]
+fn layer2_send_windows() {
+    use packet::MutablePacket;
+
+    let interface = get_test_interface();
+    let options: DataLinkOptions = Default::default();
+    let (mut dltx, _) = match datalink_channel_with_options(&interface, &options) {
+        Ok((tx, rx)) => (tx, rx),
+        Err(e) => fail!("layer2_send_windows: unable to create channel: {}", e)
+    };
+
+    let size = ETHERNET_HEADER_LEN + IPV4_HEADER_LEN + UDP_HEADER_LEN + TEST_DATA_LEN;
+    let res = dltx.build_and_send(1, size, |mut ethernet_header| {
+        ethernet_header.set_source(interface.mac_address().unwrap());
+        ethernet_header.set_destination(interface.mac_address().unwrap());
+        ethernet_header.set_ethertype(EtherTypes::Ipv4);
+        build_udp4_packet(ethernet_header.packet_mut(), ETHERNET_HEADER_LEN, "wpct");
+    });
+    match res {
+        Some(Ok(())) => (),
+        Some(Err(e)) => fail!("layer2_send_windows failed: {}", e),
+        None => fail!("Provided buffer too small")
+    }
+}
+
 #[test]
 fn check_test_environment() {
     use std::os;
@@ -325,3 +1347,272 @@ fn check_test_environment() {
     #[cfg(target_os = "linux")]
     fn test_iface() {}
 }
+
+// FIXME [windows] WinPcap doesn't list a loopback adapter
+#[test]
+#[cfg(not(windows))]
+fn network_interface_by_name() {
+    use util::get_network_interface_by_name;
+
+    let name = loopback_name();
+    match get_network_interface_by_name(name) {
+        Some(iface) => {
+            assert_eq!(iface.name.as_slice(), name);
+            assert!(iface.is_loopback());
+        },
+        None => fail!("network_interface_by_name: no interface named {}", name)
+    }
+
+    assert_eq!(get_network_interface_by_name("definitely_not_an_iface"), None);
+
+    #[cfg(target_os = "linux")]
+    fn loopback_name() -> &'static str { "lo" }
+
+    #[cfg(any(target_os = "freebsd", target_os = "macos"))]
+    fn loopback_name() -> &'static str { "lo0" }
+}
+
+#[test]
+fn network_interface_by_index() {
+    use util::{get_network_interfaces, get_network_interface_by_index};
+
+    let interfaces = get_network_interfaces().unwrap();
+    let iface = match interfaces.iter().filter(|iface| iface.index != 0).next() {
+        Some(iface) => iface,
+        None => fail!("network_interface_by_index: no interfaces with an index")
+    };
+    match get_network_interface_by_index(iface.index) {
+        Some(found) => assert_eq!(found.name, iface.name),
+        None => fail!("network_interface_by_index: no interface with index {}", iface.index)
+    }
+
+    assert_eq!(get_network_interface_by_index(0), None);
+}
+
+// FIXME [windows] WinPcap doesn't list a loopback adapter
+#[test]
+#[cfg(not(windows))]
+fn network_interface_flags() {
+    use util::get_network_interfaces;
+
+    let interfaces = get_network_interfaces().unwrap();
+    let loopback = interfaces.iter().filter(|iface| iface.is_loopback()).next().unwrap();
+    assert!(loopback.is_up());
+    assert!(loopback.is_running());
+}
+
+// Loopback interfaces may not have a MAC address, which shouldn't cause a failure
+#[test]
+fn network_interface_mac_address() {
+    use util::get_network_interfaces;
+
+    let interfaces = get_network_interfaces().unwrap();
+    for iface in interfaces.iter().filter(|iface| iface.is_loopback()) {
+        assert_eq!(iface.mac_address(), iface.mac);
+    }
+}
+
+#[test]
+fn network_interface_capabilities() {
+    use util::get_network_interfaces;
+
+    let interfaces = get_network_interfaces().unwrap();
+    let multicast: Vec<&NetworkInterface> = interfaces.iter()
+                                                      .filter(|iface| iface.is_multicast())
+                                                      .collect();
+    assert!(multicast.len() <= interfaces.len());
+    for iface in multicast.iter() {
+        assert!(iface.is_multicast());
+    }
+    for iface in interfaces.iter() {
+        // A broadcast interface can't also be point-to-point
+        assert!(!(iface.is_broadcast() && iface.is_pointopoint()));
+    }
+}
+
+// FIXME [windows] Broadcast addresses aren't reported
+#[test]
+#[cfg(not(windows))]
+fn network_interface_broadcast_addresses() {
+    use util::{get_network_interfaces, ipv4_octets};
+
+    fn to_u32(ip: IpAddr) -> u32 {
+        let o = ipv4_octets(ip).unwrap();
+        (o[0] as u32 << 24) | (o[1] as u32 << 16) | (o[2] as u32 << 8) | o[3] as u32
+    }
+
+    for iface in get_network_interfaces().unwrap().iter().filter(|iface| iface.is_broadcast()) {
+        for broadcast in iface.broadcast_addresses().iter() {
+            if ipv4_octets(*broadcast).is_none() {
+                continue;
+            }
+            // The broadcast address should be in the same subnet as one of the unicast addresses
+            let in_subnet = iface.prefixes().iter().any(|&(ip, len)| {
+                if ipv4_octets(ip).is_none() {
+                    return false;
+                }
+                let mask = if len == 0 { 0 } else { !0u32 << (32 - len as uint) };
+                to_u32(ip) & mask == to_u32(*broadcast) & mask
+            });
+            assert!(in_subnet, "{}: broadcast address {} is not in any subnet of {}",
+                    iface.name, broadcast, iface.prefixes());
+        }
+    }
+}
+
+#[test]
+#[cfg(windows)]
+fn network_interface_index_windows() {
+    use util::get_network_interfaces;
+
+    for iface in get_network_interfaces().unwrap().iter() {
+        assert!(iface.index != 0);
+    }
+}
+
+#[test]
+#[cfg(windows)]
+fn network_interface_ipv6_windows() {
+    use util::{get_network_interfaces, is_ipv6_link_local};
+
+    let interfaces = get_network_interfaces().unwrap();
+    // Not every machine has IPv6 configured, so there's nothing to check if no interface has an
+    // IPv6 address
+    if !interfaces.iter().any(|iface| !iface.ipv6_addresses().is_empty()) {
+        return;
+    }
+    for iface in interfaces.iter() {
+        let ipv6 = iface.ipv6_addresses();
+        if ipv6.is_empty() {
+            continue;
+        }
+        if iface.ipv4_addresses().contains(&Ipv4Addr(127, 0, 0, 1)) {
+            // The loopback pseudo-interface has ::1 alongside 127.0.0.1
+            if !ipv6.contains(&Ipv6Addr(0, 0, 0, 0, 0, 0, 0, 1)) {
+                fail!("network_interface_ipv6_windows: {} has no ::1 address", iface.name);
+            }
+        } else if !ipv6.iter().any(|ip| is_ipv6_link_local(*ip)) {
+            // Windows assigns a link-local address to every adapter with IPv6 enabled, and it
+            // should be reported alongside the adapter's IPv4 addresses
+            fail!("network_interface_ipv6_windows: {} has no link-local address in {}",
+                  iface.name, iface.ips);
+        }
+    }
+}
+
+#[test]
+#[cfg(windows)]
+fn network_interface_unassigned_windows() {
+    use util::get_network_interfaces;
+
+    // Adapters which are disconnected or have no address assigned shouldn't cause enumeration
+    // to fail, and the 0.0.0.0 placeholder they report shouldn't be included
+    for iface in get_network_interfaces().unwrap().iter() {
+        assert!(!iface.ipv4_addresses().contains(&Ipv4Addr(0, 0, 0, 0)));
+    }
+}
+
+// FIXME [windows] The loopback interface isn't listed on Windows
+#[test]
+#[cfg(not(windows))]
+fn network_interface_prefixes() {
+    use util::get_network_interfaces;
+
+    let interfaces = get_network_interfaces().unwrap();
+    let loopback = interfaces.iter().find(|iface| iface.is_loopback()).unwrap();
+    let prefixes = loopback.prefixes();
+    assert!(prefixes.iter().any(|&(ip, len)| match ip {
+        Ipv4Addr(127, _, _, _) => len == 8,
+        Ipv6Addr(0, 0, 0, 0, 0, 0, 0, 1) => len == 128,
+        _ => false
+    }));
+}
+
+// FIXME [windows] The loopback interface isn't listed on Windows
+#[test]
+#[cfg(not(windows))]
+fn network_interface_mtu() {
+    use util::get_network_interfaces;
+
+    let interfaces = get_network_interfaces().unwrap();
+    let loopback = interfaces.iter().find(|iface| iface.is_loopback()).unwrap();
+    match loopback.mtu {
+        // 1280 is the minimum MTU permitted by IPv6
+        Some(mtu) => assert!(mtu >= 1280),
+        None => ()
+    }
+}
+
+#[test]
+fn network_interfaces() {
+    use util::get_network_interfaces;
+
+    match get_network_interfaces() {
+        Ok(interfaces) => assert!(!interfaces.is_empty()),
+        Err(e) => fail!("network_interfaces: unable to list interfaces: {}", e)
+    }
+}
+
+#[test]
+// FIXME [windows] The loopback interface isn't listed
+#[cfg(not(windows))]
+fn network_interfaces_with_ipv4() {
+    use util::interfaces_with_ipv4;
+
+    let interfaces = interfaces_with_ipv4().unwrap();
+    assert!(interfaces.iter().all(|iface| iface.has_ipv4()));
+    // The loopback interface always has 127.0.0.1
+    assert!(interfaces.iter().any(|iface| iface.is_loopback()));
+}
+
+#[test]
+fn network_interfaces_usable() {
+    use util::usable_interfaces;
+
+    let interfaces = usable_interfaces().unwrap();
+    assert!(interfaces.iter().all(|iface| iface.is_up() && !iface.is_loopback()));
+    for pair in interfaces.as_slice().windows(2) {
+        assert!(pair[0].index < pair[1].index);
+    }
+}
+
+// FIXME [windows] WinPcap doesn't list a loopback adapter
+#[test]
+#[cfg(not(windows))]
+fn network_interface_for_ip() {
+    use util::interface_for_ip;
+
+    match interface_for_ip(Ipv4Addr(127, 0, 0, 1)) {
+        Some(iface) => assert!(iface.is_loopback()),
+        None => fail!("network_interface_for_ip: no interface has 127.0.0.1")
+    }
+    // 192.0.2.0/24 is reserved for documentation, so is never assigned
+    assert_eq!(interface_for_ip(Ipv4Addr(192, 0, 2, 123)), None);
+}
+
+// FIXME [windows] Interface changes can't be waited for
+#[test]
+#[cfg(not(windows))]
+fn network_interface_change_timeout() {
+    use std::io::TimedOut;
+    use std::time::Duration;
+    use util::wait_for_interface_change;
+
+    // Either nothing changes and the wait times out, or something did change and the new list of
+    // interfaces is returned; either way it shouldn't block
+    match wait_for_interface_change(Some(Duration::milliseconds(200))) {
+        Ok(interfaces) => assert!(!interfaces.is_empty()),
+        Err(ref e) if e.kind == TimedOut => (),
+        Err(e) => fail!("network_interface_change_timeout: {}", e)
+    }
+}
+
+#[test]
+fn default_interface() {
+    use util::default_interface;
+
+    match default_interface() {
+        Some(iface) => assert!(!iface.is_loopback()),
+        None => ()
+    }
+}
@@ -12,13 +12,18 @@
 //! using IPv4 or IPv6. It also enables layer 3 networking for specific transport protocols, using
 //! IPv4 only.
 //!
+//! Packets received on IPv6 channels don't include the IPv6 header, and are returned with the
+//! source address as an `Ipv6Addr`. The kernel calculates the checksum of ICMPv6 packets sent
+//! on IPv6 channels; for other protocols it can do so if `TransportSender::set_checksum_offset()`
+//! is used.
+//!
 //! Note that this is limited by operating system support - for example, on OS X and FreeBSD, it is
 //! impossible to implement protocols which are already implemented in the kernel such as TCP and
 //! UDP.
 
 #![macro_escape]
 
-use std::io::{IoResult, IoError};
+use std::io::{IoResult, IoError, InvalidInput};
 use std::io::net::ip;
 use std::mem;
 use std::sync::Arc;
@@ -26,12 +31,16 @@ use std::sync::Arc;
 use bindings::libc;
 
 use packet::Packet;
+use packet::icmp::{IcmpHeader};
+use packet::icmpv6::{Icmpv6Header};
 use packet::ip::{IpNextHeaderProtocol};
 use packet::ipv4::{Ipv4Header, Ipv4Packet};
 use packet::udp::{UdpHeader};
 
 use internal;
 
+pub mod ping;
+
 /// Represents a transport layer protocol
 pub enum TransportProtocol {
     /// Represents a transport protocol built on top of IPv4
@@ -42,16 +51,19 @@ pub enum TransportProtocol {
 
 /// Type of transport channel to present
 pub enum TransportChannelType {
-    /// The application will send and receive transport layer packets
+    /// The application will send and receive transport layer packets. The IP header is
+    /// constructed by the operating system when sending.
     Layer4(TransportProtocol),
-    /// The application will send and receive IPv4 packets, with the specified transport protocol
+    /// The application will send and receive IPv4 packets, with the specified transport protocol.
+    /// The socket is configured with `IP_HDRINCL`, so IPv4 headers are sent as provided, allowing
+    /// the TTL and source address to be chosen by the application.
     Layer3(IpNextHeaderProtocol)
 }
 
 /// Structure used for sending at the transport layer. Should be created with transport_channel()
 pub struct TransportSender {
     socket: Arc<internal::FileDesc>,
-    _channel_type: TransportChannelType
+    channel_type: TransportChannelType
 }
 
 /// Structure used for sending at the transport layer. Should be created with transport_channel()
@@ -71,48 +83,34 @@ pub struct TransportReceiver {
 /// allow sending and receiving UDP packets using IPv4; whereas Layer3(IpNextHeaderProtocols::Udp)
 /// would include the IPv4 Header in received values, and require manual construction of an IP
 /// header when sending.
+///
+/// A ping implementation might use `Layer4(Ipv4(IpNextHeaderProtocols::Icmp))`, and receive echo
+/// replies using `icmp_header_iter()`. For simple reachability checks, see `ping::ping()`.
 pub fn transport_channel(buffer_size: uint, channel_type: TransportChannelType)
     -> IoResult<(TransportSender, TransportReceiver)> {
-    let socket = unsafe {
-        match channel_type {
-            Layer4(Ipv4(IpNextHeaderProtocol(proto))) | Layer3(IpNextHeaderProtocol(proto))
-                => libc::socket(libc::AF_INET, libc::SOCK_RAW, proto as libc::c_int),
-            Layer4(Ipv6(IpNextHeaderProtocol(proto)))
-                => libc::socket(libc::AF_INET6, libc::SOCK_RAW, proto as libc::c_int),
-        }
-    };
-    if socket != -1 {
-        if match channel_type { Layer3(_) | Layer4(Ipv4(_)) => true, _ => false } {
-            let hincl: libc::c_int = match channel_type { Layer4(..) => 0, _ => 1 };
-            let res = unsafe {
-                libc::setsockopt(socket,
-                                 libc::IPPROTO_IP,
-                                 libc::IP_HDRINCL,
-                                 (&hincl as *const libc::c_int) as *const libc::c_void,
-                                 mem::size_of::<libc::c_int>() as libc::socklen_t)
-            };
-            if res == -1 {
-                let err = IoError::last_error();
-                unsafe { internal::close(socket); }
-                return Err(err)
-            }
-        }
+    let socket = try!(match channel_type {
+        Layer4(Ipv4(IpNextHeaderProtocol(proto))) | Layer3(IpNextHeaderProtocol(proto))
+            => internal::FileDesc::socket(libc::AF_INET, libc::SOCK_RAW, proto as libc::c_int),
+        Layer4(Ipv6(IpNextHeaderProtocol(proto)))
+            => internal::FileDesc::socket(libc::AF_INET6, libc::SOCK_RAW, proto as libc::c_int),
+    });
+    if match channel_type { Layer3(_) | Layer4(Ipv4(_)) => true, _ => false } {
+        let hincl: libc::c_int = match channel_type { Layer4(..) => 0, _ => 1 };
+        try!(socket.setsockopt(libc::IPPROTO_IP, libc::IP_HDRINCL, &hincl));
+    }
 
-        let sock = Arc::new(internal::FileDesc { fd: socket });
-        let sender = TransportSender {
-            socket: sock.clone(),
-            _channel_type: channel_type,
-        };
-        let receiver = TransportReceiver {
-            socket: sock,
-            buffer: Vec::from_elem(buffer_size, 0u8),
-            channel_type: channel_type,
-        };
+    let sock = Arc::new(socket);
+    let sender = TransportSender {
+        socket: sock.clone(),
+        channel_type: channel_type,
+    };
+    let receiver = TransportReceiver {
+        socket: sock,
+        buffer: Vec::from_elem(buffer_size, 0u8),
+        channel_type: channel_type,
+    };
 
-        Ok((sender, receiver))
-    } else {
-        Err(IoError::last_error())
-    }
+    Ok((sender, receiver))
 }
 
 impl TransportSender {
@@ -124,6 +122,74 @@ impl TransportSender {
         internal::send_to(self.socket.fd, packet.packet(), caddr_ptr, slen)
     }
 
+    /// Set the source address of sent packets, by binding the channel's socket to `source`
+    ///
+    /// This only applies to `Layer4` channels; for `Layer3` channels the source address is taken
+    /// from the IPv4 header, so this has no effect. Since the socket is shared with the
+    /// `TransportReceiver`, only packets sent to `source` will be received once it is bound.
+    pub fn set_source(&mut self, source: ip::IpAddr) -> IoResult<()> {
+        match self.channel_type {
+            Layer3(..) => return Ok(()),
+            Layer4(..) => ()
+        }
+        let mut caddr = unsafe { mem::zeroed() };
+        let slen = internal::addr_to_sockaddr(ip::SocketAddr { ip: source, port: 0 }, &mut caddr);
+        let caddr_ptr = (&caddr as *const libc::sockaddr_storage) as *const libc::sockaddr;
+        if unsafe { libc::bind(self.socket.fd, caddr_ptr, slen) } == -1 {
+            return Err(IoError::last_error());
+        }
+        Ok(())
+    }
+
+    /// Set the TTL (IPv4) or hop limit (IPv6) of sent packets
+    ///
+    /// This only applies to `Layer4` channels; for `Layer3` channels the TTL is taken from the
+    /// IPv4 header, so this has no effect.
+    pub fn set_ttl(&mut self, ttl: u8) -> IoResult<()> {
+        let (level, option) = match self.channel_type {
+            Layer3(..) => return Ok(()),
+            Layer4(Ipv4(..)) => (libc::IPPROTO_IP, libc::IP_TTL),
+            Layer4(Ipv6(..)) => (libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS),
+        };
+        let ttl = ttl as libc::c_int;
+        self.socket.setsockopt(level, option, &ttl)
+    }
+
+    /// Have the kernel calculate the checksum of sent packets, and discard received packets with
+    /// an incorrect checksum, using `IPV6_CHECKSUM`. `offset` is the offset of the 16 bit
+    /// checksum field from the start of the transport header, such as 6 for UDP or 16 for TCP.
+    /// Passing None turns this off again.
+    ///
+    /// This only applies to `Layer4(Ipv6(..))` channels, for other channels an error with kind
+    /// `InvalidInput` is returned. It isn't needed for ICMPv6, since the kernel always calculates
+    /// ICMPv6 checksums, and Linux rejects it for ICMPv6 channels.
+    pub fn set_checksum_offset(&mut self, offset: Option<uint>) -> IoResult<()> {
+        match self.channel_type {
+            Layer4(Ipv6(..)) => (),
+            _ => return Err(IoError {
+                kind: InvalidInput,
+                desc: "checksum offsets can only be set for IPv6 layer 4 channels",
+                detail: None,
+            })
+        }
+        let offset = match offset {
+            Some(offset) => offset as libc::c_int,
+            None => -1
+        };
+        self.socket.setsockopt(libc::IPPROTO_IPV6, libc::IPV6_CHECKSUM, &offset)
+    }
+
+    /// Send packets out of the network interface called `name`, regardless of the routing table,
+    /// using `SO_BINDTODEVICE`. Since the socket is shared with the `TransportReceiver`, only
+    /// packets which arrive on that interface will be received. Binding to an empty name removes
+    /// the binding.
+    ///
+    /// This is only supported on Linux, and usually requires the `CAP_NET_RAW` capability. On
+    /// other platforms an error with kind `IoUnavailable` is returned.
+    pub fn bind_to_device(&mut self, name: &str) -> IoResult<()> {
+        bind_to_device_impl(self.socket.fd, name)
+    }
+
     /// Send a packet to the provided desination
     #[inline]
     pub fn send_to<T : Packet>(&mut self, packet: T, destination: ip::IpAddr) -> IoResult<uint> {
@@ -141,7 +207,7 @@ impl TransportSender {
 
         // FreeBSD and OS X expect total length and fragment offset fields of IPv4 packets to be in
         // host byte order rather than network byte order (man 4 ip/Raw IP Sockets)
-        if match self._channel_type { Layer3(..) => true, _ => false } {
+        if match self.channel_type { Layer3(..) => true, _ => false } {
             let mut mut_slice = Vec::from_elem(packet.packet().len(), 0);
             mut_slice.as_mut_slice().clone_from_slice(packet.packet());
 
@@ -158,6 +224,34 @@ impl TransportSender {
     }
 }
 
+#[cfg(target_os = "linux")]
+fn bind_to_device_impl(socket: internal::CSocket, name: &str) -> IoResult<()> {
+    use bindings::linux;
+
+    let res = unsafe {
+        libc::setsockopt(socket,
+                         linux::SOL_SOCKET,
+                         linux::SO_BINDTODEVICE,
+                         name.as_ptr() as *const libc::c_void,
+                         name.len() as libc::socklen_t)
+    };
+    if res == -1 {
+        return Err(IoError::last_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_to_device_impl(_socket: internal::CSocket, _name: &str) -> IoResult<()> {
+    use std::io::IoUnavailable;
+
+    Err(IoError {
+        kind: IoUnavailable,
+        desc: "binding to a device is only supported on Linux",
+        detail: None,
+    })
+}
+
 /// Create an iterator for some packet type.
 ///
 /// Usage:
@@ -247,3 +341,11 @@ transport_channel_iterator!(UdpHeader,
                             UdpTransportChannelIterator,
                             udp_header_iter)
 
+transport_channel_iterator!(IcmpHeader,
+                            IcmpTransportChannelIterator,
+                            icmp_header_iter)
+
+transport_channel_iterator!(Icmpv6Header,
+                            Icmpv6TransportChannelIterator,
+                            icmpv6_header_iter)
+
@@ -0,0 +1,138 @@
+// Copyright (c) 2014 Robert Clipsham <robert@octarineparrot.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Checking whether a host is reachable using ICMP and ICMPv6 echo requests
+
+extern crate time;
+
+use std::io::{IoResult, IoError, ResourceUnavailable, TimedOut};
+use std::io::net::ip::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+use std::time::Duration;
+
+use bindings::libc;
+#[cfg(target_os = "linux")]
+use bindings::linux::{SOL_SOCKET, SO_RCVTIMEO};
+#[cfg(not(target_os = "linux"))]
+use bindings::bpf::{SOL_SOCKET, SO_RCVTIMEO};
+#[cfg(not(windows))]
+use internal;
+use packet::icmp::{IcmpPacket, IcmpTypes, MutableIcmpHeader};
+use packet::icmpv6::{Icmpv6Packet, Icmpv6Types, MutableIcmpv6Header};
+use packet::ip::IpNextHeaderProtocols;
+use transport::{transport_channel, icmp_header_iter, icmpv6_header_iter};
+use transport::{TransportReceiver, Layer4, Ipv4, Ipv6};
+
+// Echo header, plus some payload so the request isn't unusually small
+const ECHO_REQUEST_LEN: uint = 8 + 56;
+
+// Sequence number for the next echo request, so concurrent calls can tell their replies apart
+static NEXT_SEQUENCE: AtomicUint = INIT_ATOMIC_UINT;
+
+/// Send an echo request to `addr`, using ICMP for IPv4 addresses and ICMPv6 for IPv6 addresses,
+/// and wait for the matching echo reply. Returns the round trip time.
+///
+/// If no reply is received within `timeout`, an error with kind `TimedOut` is returned. As with
+/// any transport channel, permission to open raw sockets is required.
+pub fn ping(addr: IpAddr, timeout: Duration) -> IoResult<Duration> {
+    let channel_type = match addr {
+        Ipv4Addr(..) => Layer4(Ipv4(IpNextHeaderProtocols::Icmp)),
+        Ipv6Addr(..) => Layer4(Ipv6(IpNextHeaderProtocols::Icmpv6)),
+    };
+    let (mut tx, mut rx) = try!(transport_channel(4096, channel_type));
+
+    // Replies are matched to requests by identifier and sequence number
+    let identifier = unsafe { libc::getpid() } as u16;
+    let sequence = NEXT_SEQUENCE.fetch_add(1, SeqCst) as u16;
+
+    let mut buffer = [0u8, ..ECHO_REQUEST_LEN];
+    let start = time::precise_time_ns();
+    match addr {
+        Ipv4Addr(..) => {
+            let mut echo = MutableIcmpHeader::new(buffer.as_mut_slice());
+            echo.set_icmp_type(IcmpTypes::EchoRequest);
+            echo.set_identifier(identifier);
+            echo.set_sequence_number(sequence);
+            echo.checksum();
+            try!(tx.send_to(echo, addr));
+        },
+        Ipv6Addr(..) => {
+            // NOTE The kernel calculates the checksum for ICMPv6 raw sockets
+            let mut echo = MutableIcmpv6Header::new(buffer.as_mut_slice());
+            echo.set_icmpv6_type(Icmpv6Types::EchoRequest);
+            echo.set_identifier(identifier);
+            echo.set_sequence_number(sequence);
+            try!(tx.send_to(echo, addr));
+        }
+    }
+
+    loop {
+        let elapsed = Duration::nanoseconds((time::precise_time_ns() - start) as i64);
+        if elapsed >= timeout {
+            return Err(ping_timed_out());
+        }
+        try!(set_read_timeout(&rx, timeout - elapsed));
+
+        // Other ICMP traffic is received too, including the request itself on loopback
+        let received = match addr {
+            Ipv4Addr(..) => {
+                let mut iter = icmp_header_iter(&mut rx);
+                let res = iter.next().map(|(reply, source)| {
+                    source == addr &&
+                    reply.get_icmp_type() == IcmpTypes::EchoReply &&
+                    reply.get_identifier() == identifier &&
+                    reply.get_sequence_number() == sequence
+                });
+                res
+            },
+            Ipv6Addr(..) => {
+                let mut iter = icmpv6_header_iter(&mut rx);
+                let res = iter.next().map(|(reply, source)| {
+                    source == addr &&
+                    reply.get_icmpv6_type() == Icmpv6Types::EchoReply &&
+                    reply.get_identifier() == identifier &&
+                    reply.get_sequence_number() == sequence
+                });
+                res
+            }
+        };
+        match received {
+            Ok(true) => {
+                return Ok(Duration::nanoseconds((time::precise_time_ns() - start) as i64));
+            },
+            Ok(false) => (),
+            // Reads on a blocking socket only fail with EAGAIN when SO_RCVTIMEO expires
+            Err(ref e) if e.kind == ResourceUnavailable || e.kind == TimedOut => {
+                return Err(ping_timed_out());
+            },
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+fn ping_timed_out() -> IoError {
+    IoError {
+        kind: TimedOut,
+        desc: "timed out waiting for an echo reply",
+        detail: None,
+    }
+}
+
+// Set SO_RCVTIMEO on the receiver's socket
+#[cfg(not(windows))]
+fn set_read_timeout(rx: &TransportReceiver, timeout: Duration) -> IoResult<()> {
+    let tv = internal::duration_to_timeval(timeout);
+    rx.socket.setsockopt(SOL_SOCKET, SO_RCVTIMEO, &tv)
+}
+
+// Windows takes the timeout in milliseconds rather than as a timeval
+#[cfg(windows)]
+fn set_read_timeout(rx: &TransportReceiver, timeout: Duration) -> IoResult<()> {
+    let ms = internal::timeout_millis(timeout) as libc::c_int;
+    rx.socket.setsockopt(SOL_SOCKET, SO_RCVTIMEO, &ms)
+}
@@ -11,22 +11,122 @@
 use bindings::libc;
 
 use std::fmt;
+use std::io::{IoResult, IoError};
+use std::time::Duration;
 use std::from_str;
 use std::mem;
 use std::num::from_str_radix;
-use std::io::net::ip::IpAddr;
+use std::io::net::ip::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-#[cfg(not(windows))] use internal;
+use internal;
+use packet::ip::IpNextHeaderProtocol;
 
-/// A MAC address
-#[deriving(PartialEq, Eq, Clone)]
+/// A MAC address. Addresses are ordered octet by octet, starting with the first.
+#[deriving(PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub struct MacAddr(pub u8, pub u8, pub u8, pub u8, pub u8, pub u8);
 
+impl MacAddr {
+    /// Construct a MAC address from its six octets
+    pub fn from_octets(o: [u8, ..6]) -> MacAddr {
+        MacAddr(o[0], o[1], o[2], o[3], o[4], o[5])
+    }
+
+    /// Construct a MAC address from a slice of octets. Returns None unless the slice is exactly
+    /// six octets long.
+    pub fn from_bytes(bytes: &[u8]) -> Option<MacAddr> {
+        if bytes.len() != 6 {
+            return None;
+        }
+        Some(MacAddr(bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5]))
+    }
+
+    /// Retrieve the six octets of the MAC address, in order
+    pub fn octets(&self) -> [u8, ..6] {
+        match *self {
+            MacAddr(a, b, c, d, e, f) => [a, b, c, d, e, f]
+        }
+    }
+
+    /// Construct a MAC address from the low 48 bits of a u64, with the first octet in bits 40-47
+    /// and the last in bits 0-7. The top 16 bits are ignored.
+    pub fn from_u64(v: u64) -> MacAddr {
+        MacAddr((v >> 40) as u8, (v >> 32) as u8, (v >> 24) as u8,
+                (v >> 16) as u8, (v >> 8) as u8, v as u8)
+    }
+
+    /// Pack the MAC address into the low 48 bits of a u64, with the first octet in bits 40-47
+    /// and the last in bits 0-7. The top 16 bits are zero.
+    pub fn to_u64(&self) -> u64 {
+        self.octets().iter().fold(0u64, |acc, &o| (acc << 8) | o as u64)
+    }
+
+    /// Construct the broadcast MAC address, ff:ff:ff:ff:ff:ff
+    pub fn broadcast() -> MacAddr {
+        BROADCAST
+    }
+
+    /// Is this the broadcast MAC address?
+    pub fn is_broadcast(&self) -> bool {
+        self.octets().iter().all(|&o| o == 0xff)
+    }
+
+    /// Is this a multicast MAC address? This is determined by the group bit, the least
+    /// significant bit of the first octet. Note that the broadcast address is also multicast.
+    pub fn is_multicast(&self) -> bool {
+        self.octets()[0] & 0x01 != 0
+    }
+
+    /// Is this a unicast MAC address?
+    pub fn is_unicast(&self) -> bool {
+        !self.is_multicast()
+    }
+
+    /// Is this a locally administered MAC address? This is determined by the second least
+    /// significant bit of the first octet.
+    pub fn is_local(&self) -> bool {
+        self.octets()[0] & 0x02 != 0
+    }
+
+    /// Is this a universally administered MAC address?
+    pub fn is_universal(&self) -> bool {
+        !self.is_local()
+    }
+
+    /// Set the locally administered bit of the MAC address
+    pub fn set_local(&mut self) {
+        match *self {
+            MacAddr(ref mut a, _, _, _, _, _) => *a = *a | 0x02
+        }
+    }
+
+    /// Format the MAC address with uppercase hex digits, eg. 00:1A:2B:3C:4D:5E
+    pub fn to_string_upper(&self) -> String {
+        match *self {
+            MacAddr(a, b, c, d, e, f) =>
+                format!("{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}", a, b, c, d, e, f)
+        }
+    }
+
+    /// Format the MAC address in Cisco's dotted notation, eg. 001a.2b3c.4d5e
+    pub fn to_cisco_string(&self) -> String {
+        match *self {
+            MacAddr(a, b, c, d, e, f) =>
+                format!("{:02x}{:02x}.{:02x}{:02x}.{:02x}{:02x}", a, b, c, d, e, f)
+        }
+    }
+}
+
+/// The all-zeroes MAC address, 00:00:00:00:00:00
+pub const ZERO: MacAddr = MacAddr(0, 0, 0, 0, 0, 0);
+
+/// The broadcast MAC address, ff:ff:ff:ff:ff:ff
+pub const BROADCAST: MacAddr = MacAddr(0xff, 0xff, 0xff, 0xff, 0xff, 0xff);
+
 impl fmt::Show for MacAddr {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             MacAddr(a, b, c, d, e, f) =>
-                write!(fmt, "{:x}:{:x}:{:x}:{:x}:{:x}:{:x}",
+                write!(fmt, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
                        a, b, c, d, e, f)
         }
     }
@@ -34,8 +134,19 @@ impl fmt::Show for MacAddr {
 
 impl from_str::FromStr for MacAddr {
     fn from_str(s: &str) -> Option<MacAddr> {
+        if s.contains_char('.') {
+            return from_cisco_str(s);
+        }
+
+        // Octets may be separated by either colons or hyphens, but not a mix of both
+        let separator = match (s.contains_char(':'), s.contains_char('-')) {
+            (true, true) => return None,
+            (false, true) => '-',
+            _ => ':'
+        };
+
         let mut parts = [0u8, ..6];
-        let mut splits = s.split(':');
+        let mut splits = s.split(separator);
         let mut i = 0;
         for split in splits {
             if i == 6 {
@@ -56,6 +167,32 @@ impl from_str::FromStr for MacAddr {
     }
 }
 
+/// Parse a MAC address in Cisco's dotted notation - three groups of four hex digits, eg.
+/// 0011.2233.4455
+fn from_cisco_str(s: &str) -> Option<MacAddr> {
+    let mut parts = [0u8, ..6];
+    let mut i = 0;
+    for group in s.split('.') {
+        if i == 6 || group.len() != 4 || !group.chars().all(|c| c.to_digit(16).is_some()) {
+            return None;
+        }
+        match from_str_radix::<u16>(group, 16) {
+            Some(g) => {
+                parts[i] = (g >> 8) as u8;
+                parts[i + 1] = g as u8;
+            },
+            None => return None
+        }
+        i += 2;
+    }
+
+    if i == 6 {
+        Some(MacAddr(parts[0], parts[1], parts[2], parts[3], parts[4], parts[5]))
+    } else {
+        None
+    }
+}
+
 #[test]
 fn mac_addr_from_str() {
     assert_eq!(from_str::<MacAddr>("00:00:00:00:00:00"), Some(MacAddr(0, 0, 0, 0, 0, 0)));
@@ -70,6 +207,545 @@ fn mac_addr_from_str() {
     assert_eq!(from_str::<MacAddr>("12:34:56:78:90:"), None);
     assert_eq!(from_str::<MacAddr>("12:34:56:78:90:00:00"), None);
     assert_eq!(from_str::<MacAddr>("xx:xx:xx:xx:xx:xx"), None);
+    assert_eq!(from_str::<MacAddr>("00-11-22-33-44-55"), Some(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55)));
+    assert_eq!(from_str::<MacAddr>("00-11:22-33-44-55"), None);
+    assert_eq!(from_str::<MacAddr>("00-11-22-33-44"), None);
+    assert_eq!(from_str::<MacAddr>("00-11-22-33-44-"), None);
+    assert_eq!(from_str::<MacAddr>("0011.2233.4455"), Some(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55)));
+    assert_eq!(from_str::<MacAddr>("AABB.ccdd.EEFF"), Some(MacAddr(0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF)));
+    assert_eq!(from_str::<MacAddr>("0011.2233"), None);
+    assert_eq!(from_str::<MacAddr>("00112.233.4455"), None);
+    assert_eq!(from_str::<MacAddr>("0011.2233.4455.6677"), None);
+    assert_eq!(from_str::<MacAddr>("0011.2233.445"), None);
+    assert_eq!(from_str::<MacAddr>("0011.2233.4455."), None);
+    assert_eq!(from_str::<MacAddr>("0011.2233.xx55"), None);
+    assert_eq!(from_str::<MacAddr>("00:11.2233.4455"), None);
+}
+
+#[test]
+fn mac_addr_octets() {
+    let mac = MacAddr(0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc);
+    assert_eq!(mac.octets(), [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]);
+    assert_eq!(MacAddr::from_octets([0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]), mac);
+
+    let macs = [MacAddr(0, 0, 0, 0, 0, 0),
+                MacAddr(0xff, 0xff, 0xff, 0xff, 0xff, 0xff),
+                MacAddr(0xde, 0xad, 0xbe, 0xef, 0x00, 0x01)];
+    for mac in macs.iter() {
+        assert_eq!(MacAddr::from_octets(mac.octets()), *mac);
+    }
+}
+
+#[test]
+fn mac_addr_from_bytes() {
+    let bytes = [0x12u8, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde];
+    assert_eq!(MacAddr::from_bytes(bytes.slice_to(6)),
+               Some(MacAddr(0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc)));
+    assert_eq!(MacAddr::from_bytes(bytes.slice_to(5)), None);
+    assert_eq!(MacAddr::from_bytes(bytes.as_slice()), None);
+    assert_eq!(MacAddr::from_bytes(&[]), None);
+}
+
+#[test]
+fn mac_addr_u64() {
+    let mac = MacAddr(0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc);
+    assert_eq!(mac.to_u64(), 0x123456789abc);
+    assert_eq!(MacAddr::from_u64(0x123456789abc), mac);
+    assert_eq!(MacAddr::from_u64(mac.to_u64()), mac);
+    assert_eq!(MacAddr::from_u64(0xffff123456789abc), mac);
+    assert_eq!(ZERO.to_u64(), 0);
+    assert!(MacAddr::from_u64(0xffffffffffff).is_broadcast());
+    assert_eq!(BROADCAST.to_u64(), 0xffffffffffff);
+}
+
+#[test]
+fn mac_addr_hash() {
+    use std::collections::HashMap;
+
+    let mut map: HashMap<MacAddr, uint> = HashMap::new();
+    map.insert(MacAddr(0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc), 1);
+    map.insert(MacAddr(0xde, 0xf0, 0x12, 0x34, 0x45, 0x67), 2);
+    map.insert(MacAddr(0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc), 3);
+
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.find(&MacAddr(0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc)), Some(&3));
+    assert_eq!(map.find(&MacAddr(0xde, 0xf0, 0x12, 0x34, 0x45, 0x67)), Some(&2));
+}
+
+#[test]
+fn mac_addr_broadcast() {
+    assert!(MacAddr::broadcast().is_broadcast());
+    assert!(!MacAddr(0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc).is_broadcast());
+    assert!(!MacAddr(0xff, 0xff, 0xff, 0xff, 0xff, 0xfe).is_broadcast());
+}
+
+#[test]
+fn mac_addr_multicast() {
+    let mdns = MacAddr(0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb);
+    assert!(mdns.is_multicast());
+    assert!(!mdns.is_unicast());
+
+    let unicast = MacAddr(0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc);
+    assert!(!unicast.is_multicast());
+    assert!(unicast.is_unicast());
+
+    assert!(MacAddr::broadcast().is_multicast());
+    assert!(!MacAddr::broadcast().is_unicast());
+}
+
+#[test]
+fn mac_addr_local() {
+    let universal = MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55);
+    assert!(universal.is_universal());
+    assert!(!universal.is_local());
+
+    let local = MacAddr(0x02, 0x11, 0x22, 0x33, 0x44, 0x55);
+    assert!(local.is_local());
+    assert!(!local.is_universal());
+
+    let mut mac = universal;
+    mac.set_local();
+    assert!(mac.is_local());
+    assert_eq!(mac, local);
+
+    // Setting the bit again should have no further effect
+    mac.set_local();
+    assert_eq!(mac, local);
+}
+
+#[test]
+fn mac_addr_constants() {
+    assert_eq!(format!("{}", ZERO).as_slice(), "00:00:00:00:00:00");
+    assert!(BROADCAST.is_broadcast());
+    assert_eq!(BROADCAST, MacAddr::broadcast());
+}
+
+#[test]
+fn mac_addr_ord() {
+    let mut macs = vec![MacAddr(0x02, 0x00, 0x00, 0x00, 0x00, 0x01),
+                        MacAddr(0xff, 0x00, 0x00, 0x00, 0x00, 0x00),
+                        MacAddr(0x01, 0xff, 0xff, 0xff, 0xff, 0xff),
+                        MacAddr(0x02, 0x00, 0x00, 0x00, 0x00, 0x00)];
+    macs.sort();
+    assert_eq!(macs, vec![MacAddr(0x01, 0xff, 0xff, 0xff, 0xff, 0xff),
+                          MacAddr(0x02, 0x00, 0x00, 0x00, 0x00, 0x00),
+                          MacAddr(0x02, 0x00, 0x00, 0x00, 0x00, 0x01),
+                          MacAddr(0xff, 0x00, 0x00, 0x00, 0x00, 0x00)]);
+}
+
+#[test]
+fn mac_addr_show() {
+    let mac = MacAddr(0, 1, 2, 10, 187, 204);
+    assert_eq!(format!("{}", mac).as_slice(), "00:01:02:0a:bb:cc");
+    assert_eq!(from_str::<MacAddr>(format!("{}", mac).as_slice()), Some(mac));
+}
+
+#[test]
+fn mac_addr_formats() {
+    let mac = MacAddr(0, 0x11, 0x22, 0x33, 0x44, 0x55);
+    assert_eq!(mac.to_string_upper().as_slice(), "00:11:22:33:44:55");
+    assert_eq!(mac.to_cisco_string().as_slice(), "0011.2233.4455");
+
+    let mac = MacAddr(0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff);
+    assert_eq!(mac.to_string_upper().as_slice(), "AA:BB:CC:DD:EE:FF");
+    assert_eq!(mac.to_cisco_string().as_slice(), "aabb.ccdd.eeff");
+    assert_eq!(format!("{}", mac).as_slice(), "aa:bb:cc:dd:ee:ff");
+}
+
+/// Get the octets of an IPv4 address, in network byte order. Returns None if `ip` is an IPv6
+/// address.
+pub fn ipv4_octets(ip: IpAddr) -> Option<[u8, ..4]> {
+    match ip {
+        Ipv4Addr(a, b, c, d) => Some([a, b, c, d]),
+        _ => None
+    }
+}
+
+/// Get the octets of an IPv6 address, in network byte order. Returns None if `ip` is an IPv4
+/// address.
+pub fn ipv6_octets(ip: IpAddr) -> Option<[u8, ..16]> {
+    match ip {
+        Ipv6Addr(a, b, c, d, e, f, g, h) => {
+            let mut octets = [0u8, ..16];
+            for (i, segment) in [a, b, c, d, e, f, g, h].iter().enumerate() {
+                octets[i * 2] = (*segment >> 8) as u8;
+                octets[i * 2 + 1] = (*segment & 0xFF) as u8;
+            }
+            Some(octets)
+        },
+        _ => None
+    }
+}
+
+/// Construct an IPv4 address from its octets, in network byte order
+pub fn ipv4_from_octets(octets: [u8, ..4]) -> IpAddr {
+    Ipv4Addr(octets[0], octets[1], octets[2], octets[3])
+}
+
+/// Construct an IPv6 address from its octets, in network byte order
+pub fn ipv6_from_octets(octets: [u8, ..16]) -> IpAddr {
+    let segment = |i: uint| (octets[i * 2] as u16 << 8) | octets[i * 2 + 1] as u16;
+    Ipv6Addr(segment(0), segment(1), segment(2), segment(3),
+             segment(4), segment(5), segment(6), segment(7))
+}
+
+#[test]
+fn ip_octets() {
+    let ipv4 = Ipv4Addr(192, 168, 0, 1);
+    assert_eq!(ipv4_octets(ipv4), Some([192u8, 168, 0, 1]));
+    assert_eq!(ipv4_from_octets(ipv4_octets(ipv4).unwrap()), ipv4);
+
+    let ipv6 = Ipv6Addr(0x2001, 0xdb8, 0, 0, 0x0102, 0, 0xff00, 0xabcd);
+    let octets = [0x20u8, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00,
+                  0x01, 0x02, 0x00, 0x00, 0xff, 0x00, 0xab, 0xcd];
+    assert_eq!(ipv6_octets(ipv6).unwrap().as_slice(), octets.as_slice());
+    assert_eq!(ipv6_from_octets(octets), ipv6);
+    assert_eq!(ipv6_from_octets(ipv6_octets(ipv6).unwrap()), ipv6);
+
+    // The wrong address family
+    assert!(ipv4_octets(ipv6).is_none());
+    assert!(ipv6_octets(ipv4).is_none());
+}
+
+/// Get an IPv4 address as an integer, so the most significant byte is the first octet. Returns
+/// None if `ip` is an IPv6 address.
+pub fn ipv4_to_u32(ip: IpAddr) -> Option<u32> {
+    match ip {
+        Ipv4Addr(a, b, c, d) => {
+            Some((a as u32 << 24) | (b as u32 << 16) | (c as u32 << 8) | d as u32)
+        },
+        _ => None
+    }
+}
+
+/// Construct an IPv4 address from an integer, as returned by `ipv4_to_u32()`
+pub fn ipv4_from_u32(v: u32) -> IpAddr {
+    Ipv4Addr((v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8)
+}
+
+/// Get an IPv6 address as a pair of integers, holding the high and low 64 bits respectively.
+/// Returns None if `ip` is an IPv4 address.
+pub fn ipv6_to_u64s(ip: IpAddr) -> Option<(u64, u64)> {
+    match ip {
+        Ipv6Addr(a, b, c, d, e, f, g, h) => {
+            let join = |w: u16, x: u16, y: u16, z: u16| {
+                (w as u64 << 48) | (x as u64 << 32) | (y as u64 << 16) | z as u64
+            };
+            Some((join(a, b, c, d), join(e, f, g, h)))
+        },
+        _ => None
+    }
+}
+
+/// Construct an IPv6 address from a pair of integers, as returned by `ipv6_to_u64s()`
+pub fn ipv6_from_u64s((high, low): (u64, u64)) -> IpAddr {
+    Ipv6Addr((high >> 48) as u16, (high >> 32) as u16, (high >> 16) as u16, high as u16,
+             (low >> 48) as u16, (low >> 32) as u16, (low >> 16) as u16, low as u16)
+}
+
+#[test]
+fn ip_integers() {
+    assert_eq!(ipv4_to_u32(Ipv4Addr(255, 255, 255, 0)), Some(0xFFFFFF00));
+    assert_eq!(ipv4_to_u32(Ipv4Addr(192, 168, 0, 1)), Some(0xC0A80001));
+    assert_eq!(ipv4_from_u32(0xC0A80001), Ipv4Addr(192, 168, 0, 1));
+
+    // A prefix can be masked off directly
+    let addr = ipv4_to_u32(Ipv4Addr(192, 168, 17, 5)).unwrap();
+    let mask = ipv4_to_u32(Ipv4Addr(255, 255, 255, 0)).unwrap();
+    assert_eq!(ipv4_from_u32(addr & mask), Ipv4Addr(192, 168, 17, 0));
+
+    // The documentation prefix, 2001:db8::/32, is all in the high half
+    let prefix = Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0);
+    assert_eq!(ipv6_to_u64s(prefix), Some((0x20010db800000000, 0)));
+    let ipv6 = Ipv6Addr(0x2001, 0xdb8, 0, 0, 0x0102, 0, 0xff00, 0xabcd);
+    assert_eq!(ipv6_to_u64s(ipv6), Some((0x20010db800000000, 0x01020000ff00abcd)));
+    assert_eq!(ipv6_from_u64s((0x20010db800000000, 0x01020000ff00abcd)), ipv6);
+
+    // The wrong address family
+    assert!(ipv4_to_u32(ipv6).is_none());
+    assert!(ipv6_to_u64s(Ipv4Addr(127, 0, 0, 1)).is_none());
+}
+
+/// Is `ip` an IPv4 link-local address (169.254.0.0/16)? Returns false for IPv6 addresses.
+pub fn is_ipv4_link_local(ip: IpAddr) -> bool {
+    match ip {
+        Ipv4Addr(169, 254, _, _) => true,
+        _ => false
+    }
+}
+
+/// Is `ip` an IPv6 link-local address (fe80::/10)? Returns false for IPv4 addresses.
+pub fn is_ipv6_link_local(ip: IpAddr) -> bool {
+    match ip {
+        Ipv6Addr(a, _, _, _, _, _, _, _) => a & 0xffc0 == 0xfe80,
+        _ => false
+    }
+}
+
+#[test]
+fn ip_link_local() {
+    assert!(is_ipv4_link_local(Ipv4Addr(169, 254, 1, 1)));
+    assert!(!is_ipv4_link_local(Ipv4Addr(192, 168, 0, 1)));
+    assert!(!is_ipv4_link_local(Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1)));
+
+    assert!(is_ipv6_link_local(Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1)));
+    // The whole of fe80::/10 is link-local
+    assert!(is_ipv6_link_local(Ipv6Addr(0xfebf, 0, 0, 0, 0, 0, 0, 1)));
+    assert!(!is_ipv6_link_local(Ipv6Addr(0xfec0, 0, 0, 0, 0, 0, 0, 1)));
+    assert!(!is_ipv6_link_local(Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)));
+    assert!(!is_ipv6_link_local(Ipv4Addr(169, 254, 1, 1)));
+}
+
+/// Is `ip` an IPv4 private address, as defined in RFC 1918 (10.0.0.0/8, 172.16.0.0/12 or
+/// 192.168.0.0/16)? Returns false for IPv6 addresses.
+pub fn is_ipv4_private(ip: IpAddr) -> bool {
+    match ip {
+        Ipv4Addr(10, _, _, _) => true,
+        Ipv4Addr(172, b, _, _) => b & 0xf0 == 16,
+        Ipv4Addr(192, 168, _, _) => true,
+        _ => false
+    }
+}
+
+/// Is `ip` an IPv4 loopback address (127.0.0.0/8)? Returns false for IPv6 addresses.
+pub fn is_ipv4_loopback(ip: IpAddr) -> bool {
+    match ip {
+        Ipv4Addr(127, _, _, _) => true,
+        _ => false
+    }
+}
+
+/// Is `ip` an IPv4 multicast address (224.0.0.0/4)? Returns false for IPv6 addresses.
+pub fn is_ipv4_multicast(ip: IpAddr) -> bool {
+    match ip {
+        Ipv4Addr(a, _, _, _) => a & 0xf0 == 224,
+        _ => false
+    }
+}
+
+/// Is `ip` in the IPv4 documentation prefix TEST-NET-1 (192.0.2.0/24), as defined in RFC 5737?
+/// Returns false for IPv6 addresses.
+pub fn is_ipv4_documentation(ip: IpAddr) -> bool {
+    match ip {
+        Ipv4Addr(192, 0, 2, _) => true,
+        _ => false
+    }
+}
+
+#[test]
+fn ipv4_classification() {
+    assert!(is_ipv4_private(Ipv4Addr(10, 0, 0, 1)));
+    assert!(is_ipv4_private(Ipv4Addr(10, 255, 255, 255)));
+    assert!(is_ipv4_private(Ipv4Addr(172, 16, 0, 1)));
+    assert!(is_ipv4_private(Ipv4Addr(172, 31, 255, 255)));
+    assert!(is_ipv4_private(Ipv4Addr(192, 168, 0, 1)));
+    // Just outside the private ranges
+    assert!(!is_ipv4_private(Ipv4Addr(11, 0, 0, 1)));
+    assert!(!is_ipv4_private(Ipv4Addr(172, 15, 255, 255)));
+    assert!(!is_ipv4_private(Ipv4Addr(172, 32, 0, 0)));
+    assert!(!is_ipv4_private(Ipv4Addr(192, 169, 0, 1)));
+    assert!(!is_ipv4_private(Ipv4Addr(8, 8, 8, 8)));
+
+    assert!(is_ipv4_loopback(Ipv4Addr(127, 0, 0, 1)));
+    assert!(is_ipv4_loopback(Ipv4Addr(127, 255, 0, 1)));
+    assert!(!is_ipv4_loopback(Ipv4Addr(128, 0, 0, 1)));
+    assert!(!is_ipv4_loopback(Ipv4Addr(10, 0, 0, 1)));
+
+    assert!(is_ipv4_multicast(Ipv4Addr(224, 0, 0, 251)));
+    assert!(is_ipv4_multicast(Ipv4Addr(239, 255, 255, 250)));
+    assert!(!is_ipv4_multicast(Ipv4Addr(223, 255, 255, 255)));
+    assert!(!is_ipv4_multicast(Ipv4Addr(240, 0, 0, 1)));
+
+    assert!(is_ipv4_documentation(Ipv4Addr(192, 0, 2, 1)));
+    assert!(!is_ipv4_documentation(Ipv4Addr(192, 0, 3, 1)));
+    assert!(!is_ipv4_documentation(Ipv4Addr(198, 51, 100, 1)));
+
+    // IPv6 addresses are never classified as IPv4 ones
+    let loopback = Ipv6Addr(0, 0, 0, 0, 0, 0, 0, 1);
+    assert!(!is_ipv4_loopback(loopback));
+    assert!(!is_ipv4_private(Ipv6Addr(0xfd00, 0, 0, 0, 0, 0, 0, 1)));
+    assert!(!is_ipv4_multicast(Ipv6Addr(0xff02, 0, 0, 0, 0, 0, 0, 1)));
+    assert!(!is_ipv4_documentation(Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)));
+}
+
+/// Get the network address of the IPv4 network `ip` is on, given the length of its prefix, by
+/// clearing the host bits. Returns None if `ip` is an IPv6 address, or `prefix_len` is longer
+/// than 32 bits.
+pub fn network_address(ip: IpAddr, prefix_len: u8) -> Option<IpAddr> {
+    match ipv4_to_u32(ip) {
+        Some(v) if prefix_len <= 32 => Some(ipv4_from_u32(v & ipv4_netmask(prefix_len))),
+        _ => None
+    }
+}
+
+/// Get the broadcast address of the IPv4 network `ip` is on, given the length of its prefix, by
+/// setting the host bits. Returns None if `ip` is an IPv6 address, or `prefix_len` is longer
+/// than 32 bits.
+///
+/// /31 networks have no broadcast address, since both addresses are used for hosts (RFC 3021),
+/// and neither do /32 networks, so None is also returned for those.
+pub fn broadcast_address(ip: IpAddr, prefix_len: u8) -> Option<IpAddr> {
+    match ipv4_to_u32(ip) {
+        Some(v) if prefix_len <= 30 => Some(ipv4_from_u32(v | !ipv4_netmask(prefix_len))),
+        _ => None
+    }
+}
+
+// Get the netmask for an IPv4 prefix of at most 32 bits, as an integer
+fn ipv4_netmask(prefix_len: u8) -> u32 {
+    if prefix_len == 0 {
+        0
+    } else {
+        !0u32 << (32 - prefix_len as uint)
+    }
+}
+
+#[test]
+fn ipv4_network_broadcast() {
+    let ip = Ipv4Addr(192, 168, 1, 5);
+    assert_eq!(network_address(ip, 24), Some(Ipv4Addr(192, 168, 1, 0)));
+    assert_eq!(broadcast_address(ip, 24), Some(Ipv4Addr(192, 168, 1, 255)));
+    assert_eq!(network_address(ip, 30), Some(Ipv4Addr(192, 168, 1, 4)));
+    assert_eq!(broadcast_address(ip, 30), Some(Ipv4Addr(192, 168, 1, 7)));
+    assert_eq!(network_address(ip, 0), Some(Ipv4Addr(0, 0, 0, 0)));
+    assert_eq!(broadcast_address(ip, 0), Some(Ipv4Addr(255, 255, 255, 255)));
+
+    // Point to point links have no broadcast address
+    assert_eq!(network_address(ip, 31), Some(Ipv4Addr(192, 168, 1, 4)));
+    assert_eq!(broadcast_address(ip, 31), None);
+    assert_eq!(network_address(ip, 32), Some(ip));
+    assert_eq!(broadcast_address(ip, 32), None);
+
+    // Invalid prefixes, and IPv6 addresses
+    assert_eq!(network_address(ip, 33), None);
+    assert_eq!(broadcast_address(ip, 33), None);
+    let ipv6 = Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+    assert_eq!(network_address(ipv6, 64), None);
+    assert_eq!(broadcast_address(ipv6, 64), None);
+}
+
+/// Calculate the internet checksum (the 16-bit one's complement of the one's complement sum) of
+/// `data`, as described in RFC 1071.
+///
+/// `skip_word` is the index of a 16-bit word to treat as zero - usually the checksum field
+/// itself. If `data` has an odd length, it is padded with a zero byte.
+pub fn checksum(data: &[u8], skip_word: Option<uint>) -> u16 {
+    finish_checksum(sum_be_words(data, skip_word))
+}
+
+/// Calculate the checksum of `data` as for a transport protocol carried by IPv4, including the
+/// IPv4 pseudo-header. The length of `data` is used as the upper-layer length.
+///
+/// Only IPv4 addresses are summed; anything else is ignored.
+pub fn ipv4_checksum(data: &[u8],
+                     skip_word: Option<uint>,
+                     source: IpAddr,
+                     destination: IpAddr,
+                     next_level_protocol: IpNextHeaderProtocol)
+    -> u16 {
+    let IpNextHeaderProtocol(next_level_protocol) = next_level_protocol;
+    let mut sum = 0u32;
+
+    // Checksum pseudo-header
+    sum = sum + ipv4_word_sum(source);
+    sum = sum + ipv4_word_sum(destination);
+    sum = sum + next_level_protocol as u32;
+    sum = sum + data.len() as u32;
+
+    sum = sum + sum_be_words(data, skip_word);
+    finish_checksum(sum)
+}
+
+/// Calculate the checksum of `data` as for a transport protocol carried by IPv6, including the
+/// IPv6 pseudo-header. The length of `data` is used as the upper-layer length.
+///
+/// Only IPv6 addresses are summed; anything else is ignored.
+pub fn ipv6_checksum(data: &[u8],
+                     skip_word: Option<uint>,
+                     source: IpAddr,
+                     destination: IpAddr,
+                     next_header: IpNextHeaderProtocol)
+    -> u16 {
+    let IpNextHeaderProtocol(next_header) = next_header;
+    let mut sum = 0u32;
+
+    // Checksum pseudo-header
+    sum = sum + ipv6_word_sum(source);
+    sum = sum + ipv6_word_sum(destination);
+    sum = sum + next_header as u32;
+    // The upper-layer length is 32 bits for IPv6
+    let len = data.len() as u32;
+    sum = sum + (len >> 16) + (len & 0xFFFF);
+
+    sum = sum + sum_be_words(data, skip_word);
+    finish_checksum(sum)
+}
+
+fn ipv4_word_sum(ip: IpAddr) -> u32 {
+    match ip {
+        Ipv4Addr(a, b, c, d) => (a as u32 << 8 | b as u32) + (c as u32 << 8 | d as u32),
+        _ => 0
+    }
+}
+
+fn ipv6_word_sum(ip: IpAddr) -> u32 {
+    match ip {
+        Ipv6Addr(a, b, c, d, e, f, g, h) => {
+            a as u32 + b as u32 + c as u32 + d as u32 + e as u32 + f as u32 + g as u32 + h as u32
+        },
+        _ => 0
+    }
+}
+
+/// Sum `data` as big endian 16-bit words, without folding the carries
+fn sum_be_words(data: &[u8], skip_word: Option<uint>) -> u32 {
+    let len = data.len();
+    let mut sum = 0u32;
+    let mut i = 0;
+    while i + 1 < len {
+        if skip_word != Some(i / 2) {
+            sum = sum + (data[i] as u32 << 8 | data[i + 1] as u32);
+        }
+        i = i + 2;
+    }
+    // If the length is odd, make sure to checksum the final byte
+    if len & 1 != 0 && skip_word != Some(len / 2) {
+        sum = sum + (data[len - 1] as u32 << 8);
+    }
+
+    sum
+}
+
+/// Fold the carries into the low 16 bits, then take the one's complement
+fn finish_checksum(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum >> 16) + (sum & 0xFFFF);
+    }
+
+    !sum as u16
+}
+
+#[test]
+fn checksum_rfc1071() {
+    // The example from section 3 of RFC 1071, which sums to 0xddf2
+    let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+    assert_eq!(checksum(data.as_slice(), None), 0x220d);
+
+    // The skipped word is treated as zero
+    let zeroed = [0x00, 0x01, 0x00, 0x00, 0xf4, 0xf5, 0xf6, 0xf7];
+    assert_eq!(checksum(data.as_slice(), Some(1)), 0x1411);
+    assert_eq!(checksum(zeroed.as_slice(), None), 0x1411);
+
+    assert_eq!(checksum(&[], None), 0xFFFF);
+}
+
+#[test]
+fn checksum_odd_length() {
+    let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6];
+    assert_eq!(checksum(data.as_slice(), None), 0x2304);
+
+    // The trailing byte can be skipped too
+    assert_eq!(checksum(data.as_slice(), Some(3)), checksum(data.slice_to(6), None));
 }
 
 /// Represents a network interface and its associated addresses
@@ -83,22 +759,379 @@ pub struct NetworkInterface {
     pub mac: Option<MacAddr>,
     /// An IP addresses for the interface
     pub ips: Option<Vec<IpAddr>>,
+    /// The IP addresses for the interface, along with their prefix lengths
+    pub ip_prefixes: Option<Vec<(IpAddr, u8)>>,
+    /// The broadcast addresses for the interface, or for point-to-point links, the address of
+    /// the peer
+    pub broadcast_ips: Option<Vec<IpAddr>>,
+    /// The maximum transmission unit of the interface, if known
+    pub mtu: Option<u32>,
     /// Operating system specific flags for the interface
     pub flags: u32,
 }
 
-impl NetworkInterface {
-    /// Retreive the MAC address associated with the interface
-    pub fn mac_address(&self) -> MacAddr {
-        self.mac.unwrap()
+impl NetworkInterface {
+    /// Retrieve the MAC address associated with the interface, if it has one. Interfaces such as
+    /// tunnels and PPP links, and loopback interfaces on some platforms, have no MAC address.
+    pub fn mac_address(&self) -> Option<MacAddr> {
+        self.mac
+    }
+
+    /// Is the interface a loopback interface?
+    pub fn is_loopback(&self) -> bool {
+        self.flags & (libc::IFF_LOOPBACK as u32) != 0
+    }
+
+    /// Is the interface administratively up?
+    pub fn is_up(&self) -> bool {
+        self.flags & (libc::IFF_UP as u32) != 0
+    }
+
+    /// Is the interface operationally running?
+    #[cfg(not(windows))]
+    pub fn is_running(&self) -> bool {
+        self.flags & (libc::IFF_RUNNING as u32) != 0
+    }
+
+    /// Is the interface operationally running?
+    // FIXME [windows] There is no IFF_RUNNING, and flags aren't populated
+    #[cfg(windows)]
+    pub fn is_running(&self) -> bool {
+        false
+    }
+
+    /// Does the interface support multicast?
+    pub fn is_multicast(&self) -> bool {
+        self.flags & (libc::IFF_MULTICAST as u32) != 0
+    }
+
+    /// Does the interface support broadcast?
+    pub fn is_broadcast(&self) -> bool {
+        self.flags & (libc::IFF_BROADCAST as u32) != 0
+    }
+
+    /// Is the interface a point-to-point link, such as a tunnel?
+    pub fn is_pointopoint(&self) -> bool {
+        self.flags & (libc::IFF_POINTOPOINT as u32) != 0
+    }
+
+    /// Retrieve the IPv4 addresses associated with the interface
+    pub fn ipv4_addresses(&self) -> Vec<IpAddr> {
+        match self.ips {
+            Some(ref ips) => ips.iter().filter(|ip| match **ip {
+                                 Ipv4Addr(..) => true,
+                                 _ => false
+                             }).map(|ip| *ip).collect(),
+            None => Vec::new()
+        }
+    }
+
+    /// Retrieve the IPv6 addresses associated with the interface
+    pub fn ipv6_addresses(&self) -> Vec<IpAddr> {
+        match self.ips {
+            Some(ref ips) => ips.iter().filter(|ip| match **ip {
+                                 Ipv6Addr(..) => true,
+                                 _ => false
+                             }).map(|ip| *ip).collect(),
+            None => Vec::new()
+        }
+    }
+
+    /// Retrieve the IPv6 addresses associated with the interface, excluding link-local addresses
+    pub fn global_ipv6_addresses(&self) -> Vec<IpAddr> {
+        self.ipv6_addresses().into_iter().filter(|ip| !is_ipv6_link_local(*ip)).collect()
+    }
+
+    /// Does the interface have an IPv4 address?
+    pub fn has_ipv4(&self) -> bool {
+        match self.ips {
+            Some(ref ips) => ips.iter().any(|ip| match *ip {
+                                 Ipv4Addr(..) => true,
+                                 _ => false
+                             }),
+            None => false
+        }
+    }
+
+    /// Does the interface have an IPv6 address?
+    pub fn has_ipv6(&self) -> bool {
+        match self.ips {
+            Some(ref ips) => ips.iter().any(|ip| match *ip {
+                                 Ipv6Addr(..) => true,
+                                 _ => false
+                             }),
+            None => false
+        }
+    }
+
+    /// Retrieve the IP addresses associated with the interface, along with the length of the
+    /// prefix for the subnet each belongs to
+    pub fn prefixes(&self) -> Vec<(IpAddr, u8)> {
+        match self.ip_prefixes {
+            Some(ref prefixes) => prefixes.clone(),
+            None => Vec::new()
+        }
+    }
+
+    /// Is `addr` directly reachable from the interface, ie. within one of the subnets it has an
+    /// address in? Addresses are only compared against prefixes of the same family.
+    pub fn is_on_link(&self, addr: IpAddr) -> bool {
+        self.on_link_address(addr).is_some()
+    }
+
+    /// Get the address of the interface in the subnet containing `addr`, if `addr` is on-link.
+    /// This is the address to use when sending to `addr` directly from the interface.
+    pub fn on_link_address(&self, addr: IpAddr) -> Option<IpAddr> {
+        self.prefixes().iter().find(|&&(ip, len)| in_prefix(addr, ip, len)).map(|&(ip, _)| ip)
+    }
+
+    /// Retrieve the broadcast addresses associated with the interface. For point-to-point
+    /// interfaces, the address of the peer is returned instead.
+    pub fn broadcast_addresses(&self) -> Vec<IpAddr> {
+        match self.broadcast_ips {
+            Some(ref ips) => ips.clone(),
+            None => Vec::new()
+        }
+    }
+}
+
+#[test]
+fn network_interface_addresses() {
+    let mut iface = NetworkInterface {
+        name: "test0".to_string(),
+        index: 1,
+        mac: None,
+        ips: Some(vec![Ipv4Addr(192, 168, 0, 1), Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1)]),
+        ip_prefixes: Some(vec![(Ipv4Addr(192, 168, 0, 1), 24),
+                               (Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1), 64)]),
+        broadcast_ips: Some(vec![Ipv4Addr(192, 168, 0, 255)]),
+        mtu: Some(1500),
+        flags: 0,
+    };
+    assert_eq!(iface.prefixes(), vec![(Ipv4Addr(192, 168, 0, 1), 24),
+                                      (Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1), 64)]);
+    assert_eq!(iface.ipv4_addresses(), vec![Ipv4Addr(192, 168, 0, 1)]);
+    assert_eq!(iface.ipv6_addresses(), vec![Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1)]);
+    assert_eq!(iface.global_ipv6_addresses(), vec![]);
+    assert_eq!(iface.broadcast_addresses(), vec![Ipv4Addr(192, 168, 0, 255)]);
+
+    iface.ips = Some(vec![Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+                          Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)]);
+    assert_eq!(iface.global_ipv6_addresses(), vec![Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)]);
+
+    iface.ips = None;
+    iface.ip_prefixes = None;
+    iface.broadcast_ips = None;
+    assert_eq!(iface.ipv4_addresses(), vec![]);
+    assert_eq!(iface.ipv6_addresses(), vec![]);
+    assert_eq!(iface.prefixes(), vec![]);
+    assert_eq!(iface.broadcast_addresses(), vec![]);
+
+    iface.ips = Some(vec![]);
+    assert_eq!(iface.ipv4_addresses(), vec![]);
+    assert_eq!(iface.ipv6_addresses(), vec![]);
+}
+
+/// Does `addr` share the first `len` bits of `prefix`? False if they are of different families.
+fn in_prefix(addr: IpAddr, prefix: IpAddr, len: u8) -> bool {
+    fn compare(a: &[u8], b: &[u8], len: uint) -> bool {
+        if len > a.len() * 8 {
+            return false;
+        }
+        let whole = len / 8;
+        let bits = len % 8;
+        if a.slice_to(whole) != b.slice_to(whole) {
+            return false;
+        }
+        let mask = if bits == 0 { 0 } else { 0xffu8 << (8 - bits) };
+        bits == 0 || a[whole] & mask == b[whole] & mask
+    }
+
+    let len = len as uint;
+    match (addr, prefix) {
+        (Ipv4Addr(..), Ipv4Addr(..)) =>
+            compare(ipv4_octets(addr).unwrap().as_slice(),
+                    ipv4_octets(prefix).unwrap().as_slice(), len),
+        (Ipv6Addr(..), Ipv6Addr(..)) =>
+            compare(ipv6_octets(addr).unwrap().as_slice(),
+                    ipv6_octets(prefix).unwrap().as_slice(), len),
+        _ => false
+    }
+}
+
+#[test]
+fn network_interface_on_link() {
+    let iface = NetworkInterface {
+        name: "test0".to_string(),
+        index: 1,
+        mac: None,
+        ips: Some(vec![Ipv4Addr(192, 168, 1, 1), Ipv6Addr(0x2001, 0xdb8, 0, 1, 0, 0, 0, 1)]),
+        ip_prefixes: Some(vec![(Ipv4Addr(192, 168, 1, 1), 24),
+                               (Ipv6Addr(0x2001, 0xdb8, 0, 1, 0, 0, 0, 1), 64)]),
+        broadcast_ips: None,
+        mtu: None,
+        flags: 0,
+    };
+    assert!(iface.is_on_link(Ipv4Addr(192, 168, 1, 5)));
+    assert!(iface.is_on_link(Ipv4Addr(192, 168, 1, 255)));
+    assert!(!iface.is_on_link(Ipv4Addr(192, 168, 2, 1)));
+    assert!(!iface.is_on_link(Ipv4Addr(10, 0, 0, 1)));
+
+    assert!(iface.is_on_link(Ipv6Addr(0x2001, 0xdb8, 0, 1, 0xabcd, 0, 0, 1)));
+    assert!(!iface.is_on_link(Ipv6Addr(0x2001, 0xdb8, 0, 2, 0, 0, 0, 1)));
+
+    assert_eq!(iface.on_link_address(Ipv4Addr(192, 168, 1, 5)), Some(Ipv4Addr(192, 168, 1, 1)));
+    assert_eq!(iface.on_link_address(Ipv6Addr(0x2001, 0xdb8, 0, 1, 0xabcd, 0, 0, 1)),
+               Some(Ipv6Addr(0x2001, 0xdb8, 0, 1, 0, 0, 0, 1)));
+    assert_eq!(iface.on_link_address(Ipv4Addr(10, 0, 0, 1)), None);
+
+    // Mixed families never match, even with a zero length prefix
+    assert!(in_prefix(Ipv4Addr(10, 0, 0, 1), Ipv4Addr(192, 168, 0, 1), 0));
+    assert!(!in_prefix(Ipv6Addr(0, 0, 0, 0, 0, 0, 0, 1), Ipv4Addr(192, 168, 0, 1), 0));
+
+    // Prefix lengths which aren't a multiple of eight
+    assert!(in_prefix(Ipv4Addr(10, 0, 0, 1), Ipv4Addr(10, 0, 0, 7), 29));
+    assert!(!in_prefix(Ipv4Addr(10, 0, 0, 9), Ipv4Addr(10, 0, 0, 7), 29));
+    assert!(!in_prefix(Ipv4Addr(10, 0, 0, 9), Ipv4Addr(10, 0, 0, 9), 33));
+
+    let no_prefixes = NetworkInterface { ip_prefixes: None, .. iface };
+    assert!(!no_prefixes.is_on_link(Ipv4Addr(192, 168, 1, 5)));
+}
+
+/// An IPv4 or IPv6 network in CIDR notation, such as 192.168.0.0/24 or 2001:db8::/32
+#[deriving(PartialEq, Eq, Clone, Hash)]
+pub struct Cidr {
+    /// The address of the network. Bits after the prefix are kept as given, so this may also be
+    /// the address of a host on the network.
+    pub address: IpAddr,
+    /// The length of the prefix, in bits
+    pub prefix_len: u8,
+}
+
+impl Cidr {
+    /// Construct a new Cidr. Returns None if `prefix_len` is longer than the address.
+    pub fn new(address: IpAddr, prefix_len: u8) -> Option<Cidr> {
+        let max_len = match address {
+            Ipv4Addr(..) => 32,
+            Ipv6Addr(..) => 128,
+        };
+        if prefix_len > max_len {
+            None
+        } else {
+            Some(Cidr { address: address, prefix_len: prefix_len })
+        }
+    }
+
+    /// Is `addr` on this network? Always false if it is of a different address family.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        in_prefix(addr, self.address, self.prefix_len)
+    }
+}
+
+impl fmt::Show for Cidr {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}/{}", self.address, self.prefix_len)
     }
+}
 
-    /// Is the interface a loopback interface?
-    pub fn is_loopback(&self) -> bool {
-        self.flags & (libc::IFF_LOOPBACK as u32) != 0
+impl from_str::FromStr for Cidr {
+    fn from_str(s: &str) -> Option<Cidr> {
+        let mut parts = s.split('/');
+        let address = parts.next().and_then(|a| from_str::<IpAddr>(a));
+        let prefix_len = parts.next().and_then(|p| from_str::<u8>(p));
+        match (address, prefix_len, parts.next()) {
+            (Some(address), Some(prefix_len), None) => Cidr::new(address, prefix_len),
+            _ => None
+        }
     }
 }
 
+#[test]
+fn cidr_from_str() {
+    let v4: Cidr = from_str("192.168.0.0/24").unwrap();
+    assert_eq!(v4.address, Ipv4Addr(192, 168, 0, 0));
+    assert_eq!(v4.prefix_len, 24);
+    assert_eq!(format!("{}", v4).as_slice(), "192.168.0.0/24");
+
+    let v6: Cidr = from_str("2001:db8::/32").unwrap();
+    assert_eq!(v6.address, Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0));
+    assert_eq!(v6.prefix_len, 32);
+
+    assert_eq!(from_str::<Cidr>("0.0.0.0/0"), Cidr::new(Ipv4Addr(0, 0, 0, 0), 0));
+    assert_eq!(from_str::<Cidr>("::1/128"), Cidr::new(Ipv6Addr(0, 0, 0, 0, 0, 0, 0, 1), 128));
+
+    // Prefixes longer than the address
+    assert_eq!(from_str::<Cidr>("192.168.0.0/33"), None);
+    assert_eq!(from_str::<Cidr>("2001:db8::/129"), None);
+    assert_eq!(Cidr::new(Ipv4Addr(10, 0, 0, 0), 33), None);
+
+    // Malformed
+    assert_eq!(from_str::<Cidr>("192.168.0.0"), None);
+    assert_eq!(from_str::<Cidr>("192.168.0.0/"), None);
+    assert_eq!(from_str::<Cidr>("/24"), None);
+    assert_eq!(from_str::<Cidr>("192.168.0.0/24/8"), None);
+    assert_eq!(from_str::<Cidr>("192.168.0/24"), None);
+    assert_eq!(from_str::<Cidr>("192.168.0.0/-1"), None);
+    assert_eq!(from_str::<Cidr>("192.168.0.0/x"), None);
+    assert_eq!(from_str::<Cidr>(""), None);
+}
+
+#[test]
+fn cidr_contains() {
+    let v4: Cidr = from_str("192.168.0.0/24").unwrap();
+    assert!(v4.contains(Ipv4Addr(192, 168, 0, 0)));
+    assert!(v4.contains(Ipv4Addr(192, 168, 0, 123)));
+    assert!(v4.contains(Ipv4Addr(192, 168, 0, 255)));
+    assert!(!v4.contains(Ipv4Addr(192, 168, 1, 0)));
+    assert!(!v4.contains(Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)));
+
+    let v6: Cidr = from_str("2001:db8::/32").unwrap();
+    assert!(v6.contains(Ipv6Addr(0x2001, 0xdb8, 0xffff, 0, 0, 0, 0, 1)));
+    assert!(!v6.contains(Ipv6Addr(0x2001, 0xdb9, 0, 0, 0, 0, 0, 1)));
+    assert!(!v6.contains(Ipv4Addr(192, 168, 0, 1)));
+
+    // Everything of the same family is on the default route
+    let default: Cidr = from_str("0.0.0.0/0").unwrap();
+    assert!(default.contains(Ipv4Addr(8, 8, 8, 8)));
+    assert!(!default.contains(Ipv6Addr(0, 0, 0, 0, 0, 0, 0, 1)));
+
+    // Host bits in the address are ignored
+    let host: Cidr = from_str("10.1.2.3/8").unwrap();
+    assert!(host.contains(Ipv4Addr(10, 200, 0, 1)));
+}
+
+#[test]
+fn network_interface_has_address_family() {
+    let mut iface = NetworkInterface {
+        name: "test0".to_string(),
+        index: 1,
+        mac: None,
+        ips: Some(vec![Ipv4Addr(192, 168, 0, 1), Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1)]),
+        ip_prefixes: None,
+        broadcast_ips: None,
+        mtu: None,
+        flags: 0,
+    };
+    assert!(iface.has_ipv4());
+    assert!(iface.has_ipv6());
+
+    iface.ips = Some(vec![Ipv4Addr(10, 0, 0, 1), Ipv4Addr(10, 0, 0, 2)]);
+    assert!(iface.has_ipv4());
+    assert!(!iface.has_ipv6());
+
+    iface.ips = Some(vec![Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)]);
+    assert!(!iface.has_ipv4());
+    assert!(iface.has_ipv6());
+
+    iface.ips = Some(vec![]);
+    assert!(!iface.has_ipv4());
+    assert!(!iface.has_ipv6());
+
+    iface.ips = None;
+    assert!(!iface.has_ipv4());
+    assert!(!iface.has_ipv6());
+}
+
 #[cfg(target_os = "linux")]
 fn sockaddr_to_network_addr(sa: *const libc::sockaddr) -> (Option<MacAddr>, Option<IpAddr>) {
     unsafe {
@@ -149,31 +1182,596 @@ fn sockaddr_to_network_addr(sa: *const libc::sockaddr) -> (Option<MacAddr>, Opti
     }
 }
 
+/// Get the MTU of an interface, given its name and one of its entries from getifaddrs.
+#[cfg(target_os = "linux")]
+unsafe fn get_mtu(addr: *const libc::ifaddrs, name: &str) -> Option<u32> {
+    use bindings::linux;
+
+    // The MTU isn't part of any of the entries, so has to be requested separately. Only do so for
+    // the AF_PACKET entry, so it's only requested once per interface.
+    let sa = (*addr).ifa_addr;
+    if sa.is_null() || (*sa).sa_family as libc::c_int != libc::AF_PACKET {
+        return None;
+    }
+    let mut ifreq: linux::ifreq = mem::zeroed();
+    if name.len() >= ifreq.ifr_name.len() {
+        return None;
+    }
+    let mut i = 0;
+    for c in name.bytes() {
+        ifreq.ifr_name[i] = c as libc::c_char;
+        i += 1;
+    }
+
+    let socket = match internal::FileDesc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) {
+        Ok(socket) => socket,
+        Err(_) => return None
+    };
+    if linux::ioctl(socket.fd, linux::SIOCGIFMTU, &mut ifreq) == -1 {
+        return None;
+    }
+
+    Some(ifreq.ifr_mtu as u32)
+}
+
+/// Get the MTU of an interface, given its name and one of its entries from getifaddrs.
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+unsafe fn get_mtu(addr: *const libc::ifaddrs, _name: &str) -> Option<u32> {
+    use bindings::bpf;
+
+    // The AF_LINK entry carries an if_data, which includes the MTU
+    let sa = (*addr).ifa_addr;
+    if sa.is_null() || (*sa).sa_family as libc::c_int != bpf::AF_LINK ||
+       (*addr).ifa_data.is_null() {
+        return None;
+    }
+    let data: *const bpf::if_data = mem::transmute((*addr).ifa_data);
+
+    Some((*data).ifi_mtu as u32)
+}
+
+/// Get the broadcast address of a getifaddrs entry, or the address of the peer for
+/// point-to-point interfaces. Null is returned if the entry has neither.
+#[cfg(target_os = "linux")]
+unsafe fn broadcast_sockaddr(addr: *const libc::ifaddrs) -> *const libc::sockaddr {
+    use std::ptr;
+
+    // ifa_ifu is a union of ifa_broadaddr and ifa_dstaddr; the flags say which is set
+    let flags = (*addr).ifa_flags as libc::c_int;
+    if flags & (libc::IFF_BROADCAST | libc::IFF_POINTOPOINT) == 0 {
+        return ptr::null();
+    }
+    (*addr).ifa_ifu as *const libc::sockaddr
+}
+
+/// Get the broadcast address of a getifaddrs entry, or the address of the peer for
+/// point-to-point interfaces. Null is returned if the entry has neither.
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+unsafe fn broadcast_sockaddr(addr: *const libc::ifaddrs) -> *const libc::sockaddr {
+    use std::ptr;
+
+    // ifa_broadaddr is defined as ifa_dstaddr; the flags say which it holds
+    let flags = (*addr).ifa_flags as libc::c_int;
+    if flags & (libc::IFF_BROADCAST | libc::IFF_POINTOPOINT) == 0 {
+        return ptr::null();
+    }
+    (*addr).ifa_dstaddr as *const libc::sockaddr
+}
+
+/// Convert a netmask to a prefix length. The netmask is interpreted according to the family of
+/// the address it belongs to, since not all platforms set the family of the netmask.
+#[cfg(not(windows))]
+fn netmask_to_prefix(ip: IpAddr, netmask: *const libc::sockaddr) -> Option<u8> {
+    use std::num::Int;
+
+    if netmask.is_null() {
+        return None;
+    }
+    unsafe {
+        match ip {
+            Ipv4Addr(..) => {
+                let sin: *const libc::sockaddr_in = mem::transmute(netmask);
+                Some(((*sin).sin_addr.s_addr as u32).count_ones() as u8)
+            },
+            Ipv6Addr(..) => {
+                let sin6: *const libc::sockaddr_in6 = mem::transmute(netmask);
+                Some((*sin6).sin6_addr.s6_addr.iter()
+                                                .fold(0u, |acc, x| acc + x.count_ones()) as u8)
+            }
+        }
+    }
+}
+
 /// Get a list of available network interfaces for the current machine.
+///
+/// An error is returned if the interfaces can't be enumerated; a machine with no interfaces
+/// gives an empty list.
 #[inline]
-pub fn get_network_interfaces() -> Vec<NetworkInterface> {
+pub fn get_network_interfaces() -> IoResult<Vec<NetworkInterface>> {
     get_network_interfaces_impl()
 }
 
+/// Get a list of the network interfaces which have at least one IPv4 address.
+pub fn interfaces_with_ipv4() -> IoResult<Vec<NetworkInterface>> {
+    get_network_interfaces().map(|interfaces| {
+        interfaces.into_iter().filter(|iface| iface.has_ipv4()).collect()
+    })
+}
+
+/// Get a list of the network interfaces which are up, aren't loopback interfaces, and have at
+/// least one IPv4 or IPv6 address which isn't link-local. These are the interfaces which can
+/// usually reach other hosts. The list is sorted by interface index.
+pub fn usable_interfaces() -> IoResult<Vec<NetworkInterface>> {
+    get_network_interfaces().map(filter_usable)
+}
+
+fn filter_usable(interfaces: Vec<NetworkInterface>) -> Vec<NetworkInterface> {
+    let mut usable: Vec<NetworkInterface> = interfaces.into_iter().filter(|iface| {
+        iface.is_up() && !iface.is_loopback() && match iface.ips {
+            Some(ref ips) => ips.iter().any(|&ip| {
+                !is_ipv4_link_local(ip) && !is_ipv6_link_local(ip)
+            }),
+            None => false
+        }
+    }).collect();
+    usable.sort_by(|a, b| a.index.cmp(&b.index));
+    usable
+}
+
+#[test]
+fn network_interfaces_usable() {
+    let iface = |name: &str, index: u32, ips: Vec<IpAddr>, flags: u32| {
+        NetworkInterface {
+            name: name.to_string(),
+            index: index,
+            mac: None,
+            ips: Some(ips),
+            ip_prefixes: None,
+            broadcast_ips: None,
+            mtu: None,
+            flags: flags,
+        }
+    };
+    let up = libc::IFF_UP as u32;
+    let interfaces = vec![
+        iface("eth1", 3, vec![Ipv4Addr(192, 168, 1, 2)], up),
+        iface("lo", 1, vec![Ipv4Addr(127, 0, 0, 1)], up | libc::IFF_LOOPBACK as u32),
+        iface("eth0", 2, vec![Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+                              Ipv6Addr(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)], up),
+        iface("eth2", 4, vec![Ipv4Addr(10, 0, 0, 1)], 0),
+        iface("eth3", 5, vec![Ipv4Addr(169, 254, 0, 1),
+                              Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 2)], up),
+        iface("eth4", 6, vec![], up),
+    ];
+    let names: Vec<String> = filter_usable(interfaces).into_iter().map(|i| i.name).collect();
+    assert_eq!(names, vec!["eth0".to_string(), "eth1".to_string()]);
+}
+
+#[test]
+fn network_interface_mac_address() {
+    let mut iface = NetworkInterface {
+        name: "tun0".to_string(),
+        index: 1,
+        mac: None,
+        ips: None,
+        ip_prefixes: None,
+        broadcast_ips: None,
+        mtu: None,
+        flags: 0,
+    };
+    assert_eq!(iface.mac_address(), None);
+    iface.mac = Some(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55));
+    assert_eq!(iface.mac_address(), Some(MacAddr(0x00, 0x11, 0x22, 0x33, 0x44, 0x55)));
+}
+
+/// Find the network interface with the given name, if there is one.
+///
+/// None is also returned if the network interfaces can't be enumerated.
+pub fn get_network_interface_by_name(name: &str) -> Option<NetworkInterface> {
+    match get_network_interfaces() {
+        Ok(interfaces) => interfaces.into_iter().find(|iface| iface.name.as_slice() == name),
+        Err(_) => None
+    }
+}
+
+/// Find the network interface with the given index, if there is one.
+///
+/// Indices are operating system specific, and zero is never a valid index, so None is returned
+/// if `index` is zero. None is also returned if the network interfaces can't be enumerated.
+pub fn get_network_interface_by_index(index: u32) -> Option<NetworkInterface> {
+    if index == 0 {
+        return None;
+    }
+    match get_network_interfaces() {
+        Ok(interfaces) => interfaces.into_iter().find(|iface| iface.index == index),
+        Err(_) => None
+    }
+}
+
+/// Find the network interface which has the address `addr` assigned to it, if there is one.
+///
+/// Only exact matches are found, an address which is merely in the same subnet as an interface
+/// doesn't match. None is also returned if the network interfaces can't be enumerated.
+pub fn interface_for_ip(addr: IpAddr) -> Option<NetworkInterface> {
+    match get_network_interfaces() {
+        Ok(interfaces) => interfaces.into_iter().find(|iface| {
+            match iface.ips {
+                Some(ref ips) => ips.contains(&addr),
+                None => false
+            }
+        }),
+        Err(_) => None
+    }
+}
+
+/// Find the network interface used by the default route, if there is one.
+///
+/// If the routing table can't be queried, the first interface which is up, isn't a loopback
+/// interface and has an IPv4 address is used instead. None is returned if the network
+/// interfaces can't be enumerated.
+pub fn default_interface() -> Option<NetworkInterface> {
+    let interfaces = match get_network_interfaces() {
+        Ok(interfaces) => interfaces,
+        Err(_) => return None
+    };
+    let default = default_route_interface().and_then(|name| {
+        interfaces.iter().find(|iface| iface.name == name).map(|iface| iface.clone())
+    });
+    match default {
+        Some(_) => default,
+        None => interfaces.into_iter().find(|iface| {
+            iface.is_up() && !iface.is_loopback() && iface.has_ipv4()
+        })
+    }
+}
+
+/// Block until a network interface is added, removed or changes address, then return the new
+/// list of network interfaces.
+///
+/// If `timeout` is given and no change happens within it, an error of kind `TimedOut` is
+/// returned. Changes which happen before this is called aren't reported.
+#[inline]
+pub fn wait_for_interface_change(timeout: Option<Duration>) -> IoResult<Vec<NetworkInterface>> {
+    try!(wait_for_interface_change_impl(timeout));
+    get_network_interfaces()
+}
+
+#[cfg(not(windows))]
+fn interface_change_timed_out() -> IoError {
+    use std::io::TimedOut;
+
+    IoError {
+        kind: TimedOut,
+        desc: "timed out waiting for an interface change",
+        detail: None,
+    }
+}
+
+// Listen on a netlink socket subscribed to link and address changes; any message on it is a
+// change.
+#[cfg(target_os = "linux")]
+fn wait_for_interface_change_impl(timeout: Option<Duration>) -> IoResult<()> {
+    use std::io::ResourceUnavailable;
+
+    use bindings::linux;
+
+    let socket = try!(internal::FileDesc::socket(linux::AF_NETLINK,
+                                                 libc::SOCK_RAW,
+                                                 linux::NETLINK_ROUTE));
+
+    let mut addr: linux::sockaddr_nl = unsafe { mem::zeroed() };
+    addr.nl_family = linux::AF_NETLINK as libc::sa_family_t;
+    addr.nl_groups = linux::RTMGRP_LINK | linux::RTMGRP_IPV4_IFADDR | linux::RTMGRP_IPV6_IFADDR;
+    if unsafe { libc::bind(socket.fd,
+                           (&addr as *const linux::sockaddr_nl) as *const libc::sockaddr,
+                           mem::size_of::<linux::sockaddr_nl>() as libc::socklen_t) } == -1 {
+        return Err(IoError::last_error());
+    }
+    try!(set_receive_timeout(&socket, linux::SOL_SOCKET, linux::SO_RCVTIMEO, timeout));
+
+    let mut buf = [0u8, ..4096];
+    let mut caddr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    match internal::recv_from(socket.fd, buf.as_mut_slice(), &mut caddr) {
+        Ok(_) => Ok(()),
+        // Reads on a blocking socket only fail with EAGAIN when SO_RCVTIMEO expires
+        Err(ref e) if e.kind == ResourceUnavailable => Err(interface_change_timed_out()),
+        Err(e) => Err(e)
+    }
+}
+
+// Listen on a routing socket. This also carries routing table changes, which are skipped.
+//
+// NOTE The timeout applies to each read, so a steady stream of routing table changes can make
+//      this wait for longer than `timeout`
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+fn wait_for_interface_change_impl(timeout: Option<Duration>) -> IoResult<()> {
+    use std::io::ResourceUnavailable;
+
+    use bindings::bpf;
+
+    let socket = try!(internal::FileDesc::socket(bpf::PF_ROUTE, libc::SOCK_RAW, 0));
+    try!(set_receive_timeout(&socket, bpf::SOL_SOCKET, bpf::SO_RCVTIMEO, timeout));
+
+    let mut buf = [0u8, ..4096];
+    loop {
+        let mut caddr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let len = match internal::recv_from(socket.fd, buf.as_mut_slice(), &mut caddr) {
+            Ok(len) => len,
+            Err(ref e) if e.kind == ResourceUnavailable => {
+                return Err(interface_change_timed_out())
+            },
+            Err(e) => return Err(e)
+        };
+        // Every message starts with its length (u16), version (u8) and type (u8)
+        if len >= 4 && is_interface_message(buf[3]) {
+            return Ok(());
+        }
+    }
+
+    #[cfg(target_os = "freebsd")]
+    fn is_interface_message(typ: u8) -> bool {
+        typ == bpf::RTM_NEWADDR || typ == bpf::RTM_DELADDR || typ == bpf::RTM_IFINFO ||
+        typ == bpf::RTM_IFANNOUNCE
+    }
+
+    #[cfg(target_os = "macos")]
+    fn is_interface_message(typ: u8) -> bool {
+        typ == bpf::RTM_NEWADDR || typ == bpf::RTM_DELADDR || typ == bpf::RTM_IFINFO
+    }
+}
+
+// FIXME [windows] Use NotifyIpInterfaceChange
+#[cfg(windows)]
+fn wait_for_interface_change_impl(_timeout: Option<Duration>) -> IoResult<()> {
+    use std::io::IoUnavailable;
+
+    Err(IoError {
+        kind: IoUnavailable,
+        desc: "waiting for interface changes is not supported on Windows",
+        detail: None,
+    })
+}
+
+#[cfg(not(windows))]
+fn set_receive_timeout(socket: &internal::FileDesc, level: libc::c_int, option: libc::c_int,
+                       timeout: Option<Duration>) -> IoResult<()> {
+    match timeout {
+        Some(timeout) => socket.setsockopt(level, option, &internal::duration_to_timeval(timeout)),
+        None => Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn default_route_interface() -> Option<String> {
+    use std::io::File;
+
+    match File::open(&Path::new("/proc/net/route")).and_then(|mut f| f.read_to_string()) {
+        Ok(routes) => parse_default_route(routes.as_slice()),
+        Err(_) => None
+    }
+}
+
+// Ask the routing socket for the route to 0.0.0.0/0, including the interface it uses
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+fn default_route_interface() -> Option<String> {
+    use std::cmp;
+
+    use bindings::bpf;
+
+    struct RouteMessage {
+        hdr: bpf::rt_msghdr,
+        addrs: [u8, ..512],
+    }
+
+    let socket = match internal::FileDesc::socket(bpf::PF_ROUTE, libc::SOCK_RAW, 0) {
+        Ok(socket) => socket,
+        Err(_) => return None
+    };
+    // Don't wait forever if the reply is lost
+    let timeout = Some(Duration::seconds(1));
+    if set_receive_timeout(&socket, bpf::SOL_SOCKET, bpf::SO_RCVTIMEO, timeout).is_err() {
+        return None;
+    }
+
+    // The destination and netmask are both 0.0.0.0, and are followed by an empty link address
+    // which asks for the interface to be included in the reply, as route(8) does
+    let mut request: RouteMessage = unsafe { mem::zeroed() };
+    let sin_len = mem::size_of::<libc::sockaddr_in>();
+    let sdl_len = mem::size_of::<bpf::sockaddr_dl>();
+    let mut addrs_len = 0u;
+    for _ in range(0u, 2) {
+        request.addrs[addrs_len] = sin_len as u8;
+        request.addrs[addrs_len + 1] = libc::AF_INET as u8;
+        addrs_len += bpf::RT_ROUNDUP(sin_len);
+    }
+    request.addrs[addrs_len] = sdl_len as u8;
+    request.addrs[addrs_len + 1] = bpf::AF_LINK as u8;
+    addrs_len += bpf::RT_ROUNDUP(sdl_len);
+
+    let hdr_len = mem::size_of::<bpf::rt_msghdr>();
+    let pid = unsafe { libc::getpid() };
+    let seq = 1;
+    request.hdr.rtm_msglen = (hdr_len + addrs_len) as libc::c_ushort;
+    request.hdr.rtm_version = bpf::RTM_VERSION;
+    request.hdr.rtm_type = bpf::RTM_GET;
+    request.hdr.rtm_flags = bpf::RTF_UP | bpf::RTF_GATEWAY;
+    request.hdr.rtm_addrs = bpf::RTA_DST | bpf::RTA_NETMASK | bpf::RTA_IFP;
+    request.hdr.rtm_seq = seq;
+    let written = unsafe {
+        ::libc::write(socket.fd,
+                      (&request as *const RouteMessage) as *const libc::c_void,
+                      (hdr_len + addrs_len) as libc::size_t)
+    };
+    // Fails with ESRCH if there is no default route
+    if written != (hdr_len + addrs_len) as ::libc::ssize_t {
+        return None;
+    }
+
+    // Every routing message is received, so skip any which aren't the reply
+    loop {
+        let mut reply: RouteMessage = unsafe { mem::zeroed() };
+        let len = unsafe {
+            ::libc::read(socket.fd,
+                         (&mut reply as *mut RouteMessage) as *mut libc::c_void,
+                         mem::size_of::<RouteMessage>() as libc::size_t)
+        };
+        if len < 0 {
+            return None;
+        }
+        let len = cmp::min(len as uint, reply.hdr.rtm_msglen as uint);
+        if len < hdr_len || reply.hdr.rtm_type != bpf::RTM_GET || reply.hdr.rtm_pid != pid ||
+           reply.hdr.rtm_seq != seq {
+            continue;
+        }
+        if reply.hdr.rtm_errno != 0 {
+            return None;
+        }
+        return match route_interface(reply.hdr.rtm_addrs, reply.addrs.slice_to(len - hdr_len)) {
+            Some(name) => Some(name),
+            None => {
+                get_network_interface_by_index(reply.hdr.rtm_index as u32).map(|iface| iface.name)
+            }
+        };
+    }
+}
+
+/// Find the name of the interface in the RTA_IFP address of a routing message, given the
+/// `rtm_addrs` field of the message and the addresses which follow its header
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+fn route_interface(rtm_addrs: libc::c_int, addrs: &[u8]) -> Option<String> {
+    use bindings::bpf;
+
+    let mut addrs = addrs;
+    for i in range(0u, bpf::RTAX_MAX) {
+        if rtm_addrs & (1 << i) == 0 {
+            continue;
+        }
+        if addrs.len() < 2 {
+            return None;
+        }
+        let sa_len = addrs[0] as uint;
+        if i == bpf::RTAX_IFP {
+            // A sockaddr_dl, with the length of the name at offset 5 and the name at offset 8
+            if addrs[1] as libc::c_int != bpf::AF_LINK || sa_len < 8 || sa_len > addrs.len() {
+                return None;
+            }
+            let name_len = addrs[5] as uint;
+            if name_len == 0 || 8 + name_len > sa_len {
+                return None;
+            }
+            return String::from_utf8(addrs.slice(8, 8 + name_len).to_vec()).ok();
+        }
+        let size = bpf::RT_ROUNDUP(sa_len);
+        if size > addrs.len() {
+            return None;
+        }
+        addrs = addrs.slice_from(size);
+    }
+    None
+}
+
+#[test]
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+fn route_interface_parsing() {
+    use bindings::bpf;
+
+    // A destination and gateway, followed by the interface em0 with index 1
+    let sin_len = mem::size_of::<libc::sockaddr_in>();
+    let mut addrs = Vec::from_elem(2 * bpf::RT_ROUNDUP(sin_len), 0u8);
+    *addrs.get_mut(0) = sin_len as u8;
+    *addrs.get_mut(1) = libc::AF_INET as u8;
+    *addrs.get_mut(bpf::RT_ROUNDUP(sin_len)) = sin_len as u8;
+    *addrs.get_mut(bpf::RT_ROUNDUP(sin_len) + 1) = libc::AF_INET as u8;
+    addrs.push_all([20u8, bpf::AF_LINK as u8, 1, 0, 6, 3, 6, 0, /* sockaddr_dl header */
+                    'e' as u8, 'm' as u8, '0' as u8, /* name */
+                    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, /* address */
+                    0x00, 0x00, 0x00, 0x00].as_slice());
+    let rtm_addrs = bpf::RTA_DST | bpf::RTA_GATEWAY | bpf::RTA_IFP;
+    assert_eq!(route_interface(rtm_addrs, addrs.as_slice()), Some("em0".to_string()));
+
+    // No interface in the reply, or a truncated one
+    assert_eq!(route_interface(bpf::RTA_DST | bpf::RTA_GATEWAY, addrs.as_slice()), None);
+    let truncated = addrs.len() - 12;
+    assert_eq!(route_interface(rtm_addrs, addrs.slice_to(truncated)), None);
+    assert_eq!(route_interface(rtm_addrs, [].as_slice()), None);
+}
+
+// FIXME [windows] Use GetBestInterface
+#[cfg(windows)]
+fn default_route_interface() -> Option<String> {
+    None
+}
+
+/// Find the name of the interface used by the default route, given the contents of
+/// /proc/net/route. If there are several default routes, the one with the lowest metric is used.
+#[cfg(target_os = "linux")]
+fn parse_default_route(routes: &str) -> Option<String> {
+    // The first line is a header. The interface name is the first column, followed by the
+    // destination; the metric is the seventh column, and the mask the eighth. Addresses are in
+    // hex, the metric in decimal.
+    routes.lines().skip(1).filter_map(|line| {
+        let fields: Vec<&str> = line.words().collect();
+        if fields.len() >= 8 && fields[1] == "00000000" && fields[7] == "00000000" {
+            from_str::from_str::<u32>(fields[6]).map(|metric| (metric, fields[0].to_string()))
+        } else {
+            None
+        }
+    }).min_by(|&(metric, _)| metric).map(|(_, name)| name)
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn default_route_parsing() {
+    let header = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\t\
+                  Window\tIRTT\n";
+    let routes = format!("{}{}{}",
+        header,
+        "eth1\t0002A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0\n",
+        "eth0\t00000000\t0100A8C0\t0003\t0\t0\t0\t00000000\t0\t0\t0\n");
+    assert_eq!(parse_default_route(routes.as_slice()), Some("eth0".to_string()));
+
+    // The default route with the lowest metric wins, wherever it is in the table
+    let metrics = format!("{}{}{}{}",
+        header,
+        "wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0\n",
+        "eth0\t00000000\t0100A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n",
+        "eth1\t00000000\t0102A8C0\t0003\t0\t0\t200\t00000000\t0\t0\t0\n");
+    assert_eq!(parse_default_route(metrics.as_slice()), Some("eth0".to_string()));
+
+    let no_default = format!("{}{}",
+        header,
+        "eth1\t0002A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0\n");
+    assert_eq!(parse_default_route(no_default.as_slice()), None);
+    assert_eq!(parse_default_route(header), None);
+    assert_eq!(parse_default_route(""), None);
+}
+
 #[cfg(not(windows))]
-fn get_network_interfaces_impl() -> Vec<NetworkInterface> {
+fn get_network_interfaces_impl() -> IoResult<Vec<NetworkInterface>> {
     use std::string::raw as strraw;
 
     let mut ifaces: Vec<NetworkInterface> = Vec::new();
     unsafe {
         let mut addrs: *mut libc::ifaddrs = mem::uninitialized();
         if libc::getifaddrs(&mut addrs) != 0 {
-            return ifaces;
+            return Err(IoError::last_error());
         }
         let mut addr = addrs;
         while addr.is_not_null() {
             let name = strraw::from_buf((*addr).ifa_name as *const u8);
             let (mac, ip) = sockaddr_to_network_addr((*addr).ifa_addr as *const libc::sockaddr);
+            let netmask = (*addr).ifa_netmask as *const libc::sockaddr;
+            let prefix = ip.and_then(|ip| netmask_to_prefix(ip, netmask).map(|len| (ip, len)));
+            let (_, broadcast) =
+                sockaddr_to_network_addr(broadcast_sockaddr(addr as *const libc::ifaddrs));
             let ni = NetworkInterface {
                 name: name.clone(),
                 index: 0,
                 mac: mac,
                 ips: ip.map(|ip| [ip].to_vec()),
+                ip_prefixes: prefix.map(|prefix| [prefix].to_vec()),
+                broadcast_ips: broadcast.map(|ip| [ip].to_vec()),
+                mtu: get_mtu(addr as *const libc::ifaddrs, name.as_slice()),
                 flags: (*addr).ifa_flags
             };
             let mut found: bool = false;
@@ -196,79 +1794,183 @@ fn get_network_interfaces_impl() -> Vec<NetworkInterface> {
                 |name| libc::if_nametoindex(name)
             );
         }
-        return ifaces;
+        Ok(ifaces)
     }
+}
 
-    fn merge(old: &mut NetworkInterface, new: &NetworkInterface) {
-        old.mac = match new.mac {
-            None => old.mac,
-            _ => new.mac
-        };
-        match (&mut old.ips, &new.ips) {
-            (&Some(ref mut old_ips), &Some(ref new_ips)) => old_ips.push_all(new_ips.as_slice()),
-            _ => {}
-        };
-        old.flags = old.flags | new.flags;
-    }
+/// Merge the details from another getifaddrs entry for the same interface into `old`.
+#[cfg(not(windows))]
+fn merge(old: &mut NetworkInterface, new: &NetworkInterface) {
+    old.mac = match new.mac {
+        None => old.mac,
+        _ => new.mac
+    };
+    merge_addresses(&mut old.ips, &new.ips);
+    merge_addresses(&mut old.ip_prefixes, &new.ip_prefixes);
+    merge_addresses(&mut old.broadcast_ips, &new.broadcast_ips);
+    old.mtu = old.mtu.or(new.mtu);
+    old.flags = old.flags | new.flags;
+}
+
+/// Add any addresses in `new` which aren't already in `old`.
+#[cfg(not(windows))]
+fn merge_addresses<T: PartialEq + Clone>(old: &mut Option<Vec<T>>, new: &Option<Vec<T>>) {
+    // The first entry for an interface may not have had any addresses (eg. an AF_PACKET
+    // entry), so make sure addresses from later entries aren't dropped
+    match *new {
+        Some(ref new_addrs) => {
+            if old.is_none() {
+                *old = Some(Vec::new());
+            }
+            let old_addrs = old.as_mut().unwrap();
+            for addr in new_addrs.iter() {
+                if !old_addrs.contains(addr) {
+                    old_addrs.push(addr.clone());
+                }
+            }
+        },
+        None => {}
+    };
+}
+
+#[test]
+#[cfg(not(windows))]
+fn network_interface_merge() {
+    let mut iface = NetworkInterface {
+        name: "test0".to_string(),
+        index: 0,
+        mac: Some(MacAddr(0, 1, 2, 3, 4, 5)),
+        ips: None,
+        ip_prefixes: None,
+        broadcast_ips: None,
+        mtu: Some(1500),
+        flags: 0x1,
+    };
+    let entry = NetworkInterface {
+        name: "test0".to_string(),
+        index: 0,
+        mac: None,
+        ips: Some(vec![Ipv4Addr(192, 168, 0, 1)]),
+        ip_prefixes: Some(vec![(Ipv4Addr(192, 168, 0, 1), 24)]),
+        broadcast_ips: Some(vec![Ipv4Addr(192, 168, 0, 255)]),
+        mtu: None,
+        flags: 0x2,
+    };
+    merge(&mut iface, &entry);
+    // The same address can be listed more than once
+    merge(&mut iface, &entry);
+
+    assert_eq!(iface.mac, Some(MacAddr(0, 1, 2, 3, 4, 5)));
+    assert_eq!(iface.ips, Some(vec![Ipv4Addr(192, 168, 0, 1)]));
+    assert_eq!(iface.prefixes(), vec![(Ipv4Addr(192, 168, 0, 1), 24)]);
+    assert_eq!(iface.broadcast_addresses(), vec![Ipv4Addr(192, 168, 0, 255)]);
+    assert_eq!(iface.mtu, Some(1500));
+    assert_eq!(iface.flags, 0x3);
 
+    let entry = NetworkInterface {
+        ips: Some(vec![Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1), Ipv4Addr(192, 168, 0, 1)]),
+        ip_prefixes: Some(vec![(Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1), 64)]),
+        .. entry
+    };
+    merge(&mut iface, &entry);
+    assert_eq!(iface.ips, Some(vec![Ipv4Addr(192, 168, 0, 1),
+                                    Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1)]));
+    assert_eq!(iface.prefixes(), vec![(Ipv4Addr(192, 168, 0, 1), 24),
+                                      (Ipv6Addr(0xfe80, 0, 0, 0, 0, 0, 0, 1), 64)]);
 }
 
 #[cfg(windows)]
-fn get_network_interfaces_impl() -> Vec<NetworkInterface> {
+fn get_network_interfaces_impl() -> IoResult<Vec<NetworkInterface>> {
+    use std::ptr;
     use std::str::from_utf8;
     use std::string::raw;
 
     use bindings::winpcap;
 
     let mut adapters_size = 0u32;
+    let flags = winpcap::GAA_FLAG_SKIP_ANYCAST |
+                winpcap::GAA_FLAG_SKIP_MULTICAST |
+                winpcap::GAA_FLAG_SKIP_DNS_SERVER;
 
+    // The first call fails with ERROR_BUFFER_OVERFLOW, and tells us how much space is needed
     unsafe {
-        let mut tmp: winpcap::IP_ADAPTER_INFO = mem::zeroed();
-        // FIXME [windows] This only gets IPv4 addresses - should use GetAdaptersAddresses
-        winpcap::GetAdaptersInfo(
-            &mut tmp,
-            &mut adapters_size
-        );
+        winpcap::GetAdaptersAddresses(winpcap::AF_UNSPEC,
+                                      flags,
+                                      ptr::null_mut(),
+                                      ptr::null_mut(),
+                                      &mut adapters_size);
     }
 
+    // Allocate in units of IP_ADAPTER_ADDRESSES so the buffer is suitably aligned
+    let vec_size = adapters_size as uint / mem::size_of::<winpcap::IP_ADAPTER_ADDRESSES>() + 1;
 
-    let vec_size = adapters_size / mem::size_of::<winpcap::IP_ADAPTER_INFO>() as u32;
-
-    let mut adapters = Vec::with_capacity(vec_size as uint);
+    let mut adapters: Vec<winpcap::IP_ADAPTER_ADDRESSES> = Vec::with_capacity(vec_size);
 
-    // FIXME [windows] Check return code
-    unsafe {
-        winpcap::GetAdaptersInfo(adapters.as_mut_ptr(), &mut adapters_size);
+    let ret = unsafe {
+        winpcap::GetAdaptersAddresses(winpcap::AF_UNSPEC,
+                                      flags,
+                                      ptr::null_mut(),
+                                      adapters.as_mut_ptr(),
+                                      &mut adapters_size)
+    };
+    // GetAdaptersAddresses returns the error code rather than setting the last error
+    if ret != 0 {
+        return Err(IoError::from_errno(ret as uint, true));
     }
 
     // Create a complete list of NetworkInterfaces for the machine
     let mut cursor = adapters.as_mut_ptr();
-    let mut all_ifaces = Vec::with_capacity(vec_size as uint);
+    let mut all_ifaces = Vec::new();
     while cursor.is_not_null() {
         let mac = unsafe {
-                    MacAddr((*cursor).Address[0],
-                            (*cursor).Address[1],
-                            (*cursor).Address[2],
-                            (*cursor).Address[3],
-                            (*cursor).Address[4],
-                            (*cursor).Address[5])
-                  };
-        let mut ip_cursor = unsafe { &mut (*cursor).IpAddressList as winpcap::PIP_ADDR_STRING};
+            if (*cursor).PhysicalAddressLength as uint >= 6 {
+                Some(MacAddr((*cursor).PhysicalAddress[0],
+                             (*cursor).PhysicalAddress[1],
+                             (*cursor).PhysicalAddress[2],
+                             (*cursor).PhysicalAddress[3],
+                             (*cursor).PhysicalAddress[4],
+                             (*cursor).PhysicalAddress[5]))
+            } else {
+                None
+            }
+        };
+        let mut ip_cursor = unsafe { (*cursor).FirstUnicastAddress };
         let mut ips: Vec<IpAddr> = Vec::new();
+        let mut prefixes: Vec<(IpAddr, u8)> = Vec::new();
         while ip_cursor.is_not_null() {
-            let ip_str = unsafe {
-                            raw::from_buf((*ip_cursor).IpAddress.String.as_ptr() as *const u8)
-                         };
-            ips.push(from_str(ip_str.as_slice()).unwrap());
+            // Both IPv4 and IPv6 addresses are returned here, as sockaddrs
+            let addr = unsafe {
+                let sa = &(*ip_cursor).Address;
+                internal::sockaddr_to_addr(mem::transmute(sa.lpSockaddr),
+                                           sa.iSockaddrLength as uint)
+            };
+            match addr {
+                // Adapters without an assigned address report 0.0.0.0 as a placeholder
+                Ok(sa) if sa.ip == Ipv4Addr(0, 0, 0, 0) => (),
+                Ok(sa) => {
+                    ips.push(sa.ip);
+                    prefixes.push((sa.ip, unsafe { (*ip_cursor).OnLinkPrefixLength }));
+                },
+                // Skip addresses which can't be converted rather than failing the whole
+                // enumeration
+                Err(_) => ()
+            }
             ip_cursor = unsafe { (*ip_cursor).Next };
         }
         unsafe {
             all_ifaces.push(NetworkInterface {
-                        name: raw::from_buf((*cursor).AdapterName.as_ptr() as *const u8),
-                        index: (*cursor).Index,
-                        mac: Some(mac),
+                        name: raw::from_buf((*cursor).AdapterName as *const u8),
+                        // NOTE This is the same IfIndex used by the rest of the IP helper API,
+                        //      and is carried through when filtering supported adapters below
+                        index: (*cursor).IfIndex,
+                        mac: mac,
                         ips: Some(ips),
-                        //flags: (*cursor).Type, // FIXME [windows]
+                        ip_prefixes: Some(prefixes),
+                        // FIXME [windows] The broadcast address isn't reported, it would need
+                        //                 to be derived from the prefix
+                        broadcast_ips: None,
+                        mtu: Some((*cursor).Mtu as u32),
+                        //flags: (*cursor).Flags, // FIXME [windows]
                         flags: 0,
                      });
 
@@ -276,18 +1978,8 @@ fn get_network_interfaces_impl() -> Vec<NetworkInterface> {
         }
     }
 
-    let mut buf = [0u8, ..4096];
-    let mut buflen = buf.len() as u32;
-
-    // Gets list of supported adapters in form:
-    // adapter1\0adapter2\0\0desc1\0desc2\0\0
-    if unsafe { winpcap::PacketGetAdapterNames(buf.as_mut_ptr() as *mut i8, &mut buflen) } == 0 {
-        // FIXME [windows] Should allocate a buffer big enough and try again
-        //        - size should be buf.len() + buflen (buflen is overwritten)
-        fail!("FIXME [windows] unable to get interface list");
-    }
-
-    let buf_str = from_utf8(buf).unwrap();
+    let buf = try!(get_adapter_names(4096));
+    let buf_str = from_utf8(buf.as_slice()).unwrap();
     let iface_names = buf_str.split_str("\0\0").next();
     let mut vec = Vec::new();
 
@@ -309,6 +2001,44 @@ fn get_network_interfaces_impl() -> Vec<NetworkInterface> {
         None => ()
     };
 
-    vec
+    Ok(vec)
+}
+
+/// Gets the list of adapters supported by WinPcap, in the form:
+/// adapter1\0adapter2\0\0desc1\0desc2\0\0
+///
+/// `initial_size` is only a hint - if it's too small a larger buffer is allocated and the call
+/// is retried.
+#[cfg(windows)]
+fn get_adapter_names(initial_size: uint) -> IoResult<Vec<u8>> {
+    use bindings::winpcap;
+
+    let mut buf = Vec::from_elem(initial_size, 0u8);
+    let mut buflen = buf.len() as u32;
+
+    if unsafe { winpcap::PacketGetAdapterNames(buf.as_mut_ptr() as *mut i8, &mut buflen) } == 0 {
+        // buflen is overwritten with the size of buffer required
+        buf = Vec::from_elem(buflen as uint, 0u8);
+        if unsafe {
+            winpcap::PacketGetAdapterNames(buf.as_mut_ptr() as *mut i8, &mut buflen)
+        } == 0 {
+            return Err(IoError::last_error());
+        }
+    }
+
+    Ok(buf)
 }
 
+#[test]
+#[cfg(windows)]
+fn adapter_names_small_buffer() {
+    use std::str::from_utf8;
+
+    // A single byte is never enough to hold the adapter list, so this forces the retry path
+    let small = get_adapter_names(1).unwrap();
+    let large = get_adapter_names(4096).unwrap();
+
+    let small_names = from_utf8(small.as_slice()).unwrap().split_str("\0\0").next();
+    let large_names = from_utf8(large.as_slice()).unwrap().split_str("\0\0").next();
+    assert_eq!(small_names, large_names);
+}